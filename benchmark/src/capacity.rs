@@ -0,0 +1,73 @@
+extern crate lockfree;
+
+use lockfree::map::Map;
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+// How many entries are loaded into each map.
+const KEYS: u64 = 10_000_000;
+
+// How many times each load is measured.
+const RUNS: u32 = 3;
+
+// Loads the entries into the map created by `make`, each thread inserting its
+// own range of keys, and returns how long it took, creating the map included.
+fn measure<F>(nthread: u64, make: F) -> Duration
+where
+    F: FnOnce() -> Map<u64, u64>,
+{
+    let then = Instant::now();
+    let map = Arc::new(make());
+
+    let threads = (0 .. nthread)
+        .map(|i| {
+            let map = map.clone();
+            thread::spawn(move || {
+                let start = i * KEYS / nthread;
+                let end = (i + 1) * KEYS / nthread;
+                for key in start .. end {
+                    map.insert(key, key);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().expect("thread failed");
+    }
+
+    // Dropping the map is not measured.
+    let ret = then.elapsed();
+    drop(map);
+    ret
+}
+
+fn main() {
+    println!("A program bulk-loading {} entries into a map.", KEYS);
+
+    for &nthread in &[1, 2, 4, 8] {
+        println!();
+
+        let mut plain = Duration::default();
+        let mut reserved = Duration::default();
+
+        for _ in 0 .. RUNS {
+            plain += measure(nthread, Map::new);
+            reserved += measure(nthread, || Map::with_capacity(KEYS as usize));
+        }
+
+        println!(
+            "Map::new with {} threads mean time: {:?}",
+            nthread,
+            plain / RUNS
+        );
+        println!(
+            "Map::with_capacity with {} threads mean time: {:?}",
+            nthread,
+            reserved / RUNS
+        );
+    }
+}
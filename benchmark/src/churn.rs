@@ -0,0 +1,106 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::Map;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Arc,
+        Mutex,
+    },
+};
+
+// How many keys the maps go through.
+const KEYS: u64 = 0x1000;
+
+// How many rounds are counted by `allocs_per_round`.
+const COUNTED_ROUNDS: u64 = 0x100000;
+
+// Counts every allocation, so the allocator traffic of a workload can be told.
+struct Counting;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[derive(Debug, Clone, Default)]
+struct MutexChurn {
+    inner: Arc<Mutex<HashMap<u64, u64>>>,
+    i: u64,
+}
+
+impl Target for MutexChurn {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        let mut map = self.inner.lock().unwrap();
+        map.insert(i, i);
+        map.remove(&i);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LockfreeChurn {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for LockfreeChurn {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        self.inner.insert(i, i);
+        self.inner.remove(&i);
+    }
+}
+
+// Runs the given target alone, returning how many allocations each round
+// makes on average once the target is warm.
+fn allocs_per_round<T>(mut target: T) -> f64
+where
+    T: Target,
+{
+    for _ in 0 .. KEYS {
+        target.round();
+    }
+    let before = ALLOCS.load(Relaxed);
+    for _ in 0 .. COUNTED_ROUNDS {
+        target.round();
+    }
+    (ALLOCS.load(Relaxed) - before) as f64 / COUNTED_ROUNDS as f64
+}
+
+fn main() {
+    // Inserts a key and removes it right away, so buckets and their nodes are
+    // created and destroyed in every round.
+    bench! {
+        levels 1, 2, 4, 8;
+        "mutex insert + remove" => MutexChurn::default(),
+        "lockfree insert + remove" => LockfreeChurn::default(),
+    }
+
+    println!();
+    println!(
+        "allocations per round: mutex {:.2}, lockfree {:.2}",
+        allocs_per_round(MutexChurn::default()),
+        allocs_per_round(LockfreeChurn::default())
+    );
+}
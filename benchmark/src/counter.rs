@@ -0,0 +1,86 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::Map;
+use std::sync::{
+    atomic::{AtomicU64, Ordering::*},
+    Arc,
+};
+
+// How many keys share the increments. Few keys make them hot.
+const KEYS: u64 = 4;
+
+#[derive(Debug, Clone)]
+struct UpdateIncrement {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for UpdateIncrement {
+    #[inline(always)]
+    fn round(&mut self) {
+        let key = self.i % KEYS;
+        self.i += 1;
+        self.inner.update(&key, |&val| val + 1);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct InPlaceIncrement {
+    inner: Arc<Map<u64, AtomicU64>>,
+    i: u64,
+}
+
+impl Target for InPlaceIncrement {
+    #[inline(always)]
+    fn round(&mut self) {
+        let key = self.i % KEYS;
+        self.i += 1;
+        self.inner.update_in_place(&key, |val| val + 1);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FetchAddIncrement {
+    inner: Arc<Map<u64, AtomicU64>>,
+    i: u64,
+}
+
+impl Target for FetchAddIncrement {
+    #[inline(always)]
+    fn round(&mut self) {
+        let key = self.i % KEYS;
+        self.i += 1;
+        if let Some(guard) = self.inner.get(&key) {
+            guard.val().fetch_add(1, AcqRel);
+        }
+    }
+}
+
+fn counters() -> Arc<Map<u64, u64>> {
+    Arc::new((0 .. KEYS).map(|key| (key, 0)).collect())
+}
+
+fn atomic_counters() -> Arc<Map<u64, AtomicU64>> {
+    Arc::new((0 .. KEYS).map(|key| (key, AtomicU64::new(0))).collect())
+}
+
+fn main() {
+    bench! {
+        levels 1, 2, 4, 8;
+        "lockfree update" => UpdateIncrement {
+            inner: counters(),
+            i: 0,
+        },
+        "lockfree update_in_place" => InPlaceIncrement {
+            inner: atomic_counters(),
+            i: 0,
+        },
+        "lockfree get + fetch_add" => FetchAddIncrement {
+            inner: atomic_counters(),
+            i: 0,
+        },
+    }
+}
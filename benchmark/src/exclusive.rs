@@ -0,0 +1,127 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::Map;
+use std::hint::black_box;
+
+// How many keys the maps hold, or go through.
+const KEYS: u64 = 0x10000;
+
+#[derive(Debug, Clone)]
+struct SharedInsert {
+    inner: Map<u64, u64>,
+    i: u64,
+}
+
+impl Target for SharedInsert {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        self.inner.insert(i, i);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MutInsert {
+    inner: Map<u64, u64>,
+    i: u64,
+}
+
+impl Target for MutInsert {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        self.inner.insert_mut(i, i);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SharedGet {
+    inner: Map<u64, u64>,
+    i: u64,
+}
+
+impl Target for SharedGet {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        black_box(self.inner.get(&i).map(|guard| *guard.val()));
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MutGet {
+    inner: Map<u64, u64>,
+    i: u64,
+}
+
+impl Target for MutGet {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        black_box(self.inner.get_mut(&i).map(|val| *val));
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct SharedChurn {
+    inner: Map<u64, u64>,
+    i: u64,
+}
+
+impl Target for SharedChurn {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        self.inner.insert(i, i);
+        black_box(self.inner.remove(&i).map(|removed| *removed.val()));
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct MutChurn {
+    inner: Map<u64, u64>,
+    i: u64,
+}
+
+impl Target for MutChurn {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        self.inner.insert_mut(i, i);
+        black_box(self.inner.remove_mut(&i).map(|(_, val)| val));
+    }
+}
+
+fn filled() -> Map<u64, u64> {
+    (0 .. KEYS).map(|i| (i, i)).collect()
+}
+
+fn main() {
+    // Exclusive access cannot be shared, so only a single thread is measured.
+    bench! {
+        levels 1;
+        "lockfree insert" => SharedInsert { inner: filled(), i: 0 },
+        "lockfree insert_mut" => MutInsert { inner: filled(), i: 0 },
+    }
+
+    bench! {
+        levels 1;
+        "lockfree get" => SharedGet { inner: filled(), i: 0 },
+        "lockfree get_mut" => MutGet { inner: filled(), i: 0 },
+    }
+
+    bench! {
+        levels 1;
+        "lockfree insert + remove" => SharedChurn::default(),
+        "lockfree insert_mut + remove_mut" => MutChurn::default(),
+    }
+}
@@ -0,0 +1,67 @@
+extern crate lockfree;
+extern crate rayon;
+
+use lockfree::map::Map;
+use rayon::{
+    iter::{IntoParallelIterator, ParallelExtend, ParallelIterator},
+    ThreadPoolBuilder,
+};
+use std::time::{Duration, Instant};
+
+// How many entries are loaded into each map.
+const KEYS: u64 = 10_000_000;
+
+// How many times each load is measured.
+const RUNS: u32 = 3;
+
+// Loads the entries into a new map through `load`, and returns how long it
+// took.
+fn measure<F>(load: F) -> Duration
+where
+    F: FnOnce(&Map<u64, u64>),
+{
+    let map = Map::new();
+    let then = Instant::now();
+    load(&map);
+
+    // Dropping the map is not measured.
+    let ret = then.elapsed();
+    assert_eq!(map.len(), KEYS as usize);
+    drop(map);
+    ret
+}
+
+fn main() {
+    println!("A program bulk-loading {} entries into a map.", KEYS);
+
+    let mut serial = Duration::default();
+    for _ in 0 .. RUNS {
+        serial += measure(|map| map.extend((0 .. KEYS).map(|i| (i, i))));
+    }
+    println!();
+    println!("Map::extend mean time: {:?}", serial / RUNS);
+
+    for &nthread in &[1, 2, 4, 8] {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(nthread)
+            .build()
+            .expect("failed to build the thread pool");
+
+        let mut parallel = Duration::default();
+        for _ in 0 .. RUNS {
+            parallel += measure(|map| {
+                pool.install(|| {
+                    let mut map = map;
+                    map.par_extend((0 .. KEYS).into_par_iter().map(|i| (i, i)))
+                })
+            });
+        }
+
+        println!();
+        println!(
+            "Map::par_extend with {} threads mean time: {:?}",
+            nthread,
+            parallel / RUNS
+        );
+    }
+}
@@ -0,0 +1,108 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::{AHashMap, FxMap, IntMap, Map};
+use std::{hash::BuildHasher, hint::black_box, sync::Arc};
+
+// How many keys the maps of the `get` benchmarks hold.
+const KEYS: u64 = 0x100000;
+
+#[derive(Debug)]
+struct Insert<H> {
+    inner: Arc<Map<u64, u64, H>>,
+    i: u64,
+}
+
+impl<H> Clone for Insert<H> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), i: self.i }
+    }
+}
+
+impl<H> Target for Insert<H>
+where
+    H: BuildHasher,
+{
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i += 1;
+        self.inner.insert(i, i);
+    }
+}
+
+#[derive(Debug)]
+struct Get<H> {
+    inner: Arc<Map<u64, u64, H>>,
+    i: u64,
+}
+
+impl<H> Clone for Get<H> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), i: self.i }
+    }
+}
+
+impl<H> Target for Get<H>
+where
+    H: BuildHasher,
+{
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        black_box(self.inner.get(&i).map(|guard| *guard.val()));
+    }
+}
+
+fn filled<H>(map: Map<u64, u64, H>) -> Arc<Map<u64, u64, H>>
+where
+    H: BuildHasher,
+{
+    map.extend((0 .. KEYS).map(|i| (i, i)));
+    Arc::new(map)
+}
+
+fn main() {
+    bench! {
+        levels 1, 2, 4, 8;
+        "RandomState insert" => Insert {
+            inner: Arc::new(Map::new()),
+            i: 0,
+        },
+        "aHash insert" => Insert {
+            inner: Arc::new(AHashMap::default()),
+            i: 0,
+        },
+        "FxHash insert" => Insert {
+            inner: Arc::new(FxMap::default()),
+            i: 0,
+        },
+        "Identity insert" => Insert {
+            inner: Arc::new(IntMap::default()),
+            i: 0,
+        },
+    }
+
+    bench! {
+        levels 1, 2, 4, 8;
+        "RandomState get" => Get {
+            inner: filled(Map::new()),
+            i: 0,
+        },
+        "aHash get" => Get {
+            inner: filled(AHashMap::default()),
+            i: 0,
+        },
+        "FxHash get" => Get {
+            inner: filled(FxMap::default()),
+            i: 0,
+        },
+        "Identity get" => Get {
+            inner: filled(IntMap::default()),
+            i: 0,
+        },
+    }
+}
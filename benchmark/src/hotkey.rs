@@ -0,0 +1,81 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::Map;
+use std::sync::Arc;
+
+// How many keys the uncontended target goes through.
+const KEYS: u64 = 0x10000;
+
+#[derive(Debug, Clone)]
+struct HotInsert {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for HotInsert {
+    #[inline(always)]
+    fn round(&mut self) {
+        self.i += 1;
+        self.inner.insert(0, self.i);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct HotChurn {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for HotChurn {
+    #[inline(always)]
+    fn round(&mut self) {
+        self.i += 1;
+        if self.i % 2 == 0 {
+            self.inner.insert(0, self.i);
+        } else {
+            self.inner.remove(&0);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SpreadInsert {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for SpreadInsert {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        self.inner.insert(i, i);
+    }
+}
+
+fn main() {
+    // Every thread hammers the same key.
+    bench! {
+        levels 1, 4, 16, 64;
+        "lockfree insert (one key)" => HotInsert {
+            inner: Arc::new(Map::new()),
+            i: 0,
+        },
+        "lockfree insert + remove (one key)" => HotChurn {
+            inner: Arc::new(Map::new()),
+            i: 0,
+        },
+    }
+
+    // The threads go through many keys, and seldom collide.
+    bench! {
+        levels 1, 4;
+        "lockfree insert (many keys)" => SpreadInsert {
+            inner: Arc::new((0 .. KEYS).map(|i| (i, i)).collect()),
+            i: 0,
+        },
+    }
+}
@@ -0,0 +1,73 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::{DefaultHashBuilder, Map};
+use std::sync::Arc;
+
+// How many keys the maps go through, enough that most entries are out of
+// the cache.
+const KEYS: u64 = 0x100000;
+
+// Odd, so stepping by it goes through every key, but far apart, so the next
+// key is rarely in the cache.
+const STEP: u64 = 0x9E3779B1;
+
+// Fills a map with every key, its pairs inline or not.
+fn filled(inline: bool) -> Arc<Map<u64, u64>> {
+    let map = if inline {
+        Map::with_inline_pairs(DefaultHashBuilder::default())
+    } else {
+        Map::new()
+    };
+    assert_eq!(map.has_inline_pairs(), inline);
+    map.extend((0 .. KEYS).map(|i| (i, i)));
+    Arc::new(map)
+}
+
+#[derive(Debug, Clone)]
+struct Get {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for Get {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + STEP) % KEYS;
+        assert!(self.inner.get(&i).is_some());
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Churn {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for Churn {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + STEP) % KEYS;
+        self.inner.remove(&i);
+        self.inner.insert(i, i);
+    }
+}
+
+fn main() {
+    // The same `u64` to `u64` workloads, with pairs behind a pointer of their
+    // own, and with pairs kept inline in their entries.
+    bench! {
+        levels 1, 2, 4, 8;
+        "get (out of line)" => Get { inner: filled(false), i: 0 },
+        "get (inline)" => Get { inner: filled(true), i: 0 },
+        "remove + insert (out of line)" => Churn {
+            inner: filled(false),
+            i: 0,
+        },
+        "remove + insert (inline)" => Churn { inner: filled(true), i: 0 },
+    }
+}
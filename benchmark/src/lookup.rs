@@ -0,0 +1,127 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::{FrozenMap, Map};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+};
+
+// How many keys the maps are filled with.
+const KEYS: u64 = 0x10000;
+
+// Hashes a key with its lowest bit cleared, so keys come in colliding pairs
+// and every bucket holds two entries.
+#[derive(Debug, Clone, Default)]
+struct PairState {
+    inner: RandomState,
+}
+
+impl BuildHasher for PairState {
+    type Hasher = PairHasher<<RandomState as BuildHasher>::Hasher>;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        PairHasher { inner: self.inner.build_hasher() }
+    }
+}
+
+struct PairHasher<H> {
+    inner: H,
+}
+
+impl<H> Hasher for PairHasher<H>
+where
+    H: Hasher,
+{
+    fn finish(&self) -> u64 {
+        self.inner.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.inner.write(bytes)
+    }
+
+    fn write_u64(&mut self, word: u64) {
+        self.inner.write_u64(word & !1)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MutexGet {
+    inner: Arc<Mutex<HashMap<u64, u64>>>,
+    i: u64,
+}
+
+impl Target for MutexGet {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        assert!(self.inner.lock().unwrap().get(&i).is_some());
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LockfreeGet<S> {
+    inner: Arc<Map<u64, u64, S>>,
+    i: u64,
+}
+
+impl<S> Target for LockfreeGet<S>
+where
+    S: BuildHasher + Send + Sync,
+{
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        assert!(self.inner.get(&i).is_some());
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FrozenGet {
+    inner: Arc<FrozenMap<u64, u64>>,
+    i: u64,
+}
+
+impl Target for FrozenGet {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i = (self.i + 1) % KEYS;
+        assert!(self.inner.get(&i).is_some());
+    }
+}
+
+fn main() {
+    // Every round looks up a key present in the map, so the cost is that of
+    // reaching the entry once its bucket is found.
+    bench! {
+        levels 1, 2, 4, 8;
+        "mutex get" => MutexGet {
+            inner: Arc::new(Mutex::new((0 .. KEYS).map(|i| (i, i)).collect())),
+            i: 0,
+        },
+        "lockfree get" => LockfreeGet {
+            inner: Arc::new((0 .. KEYS).map(|i| (i, i)).collect::<Map<_, _>>()),
+            i: 0,
+        },
+        "lockfree get (colliding pairs)" => LockfreeGet {
+            inner: Arc::new(Map::from_iter_with_hasher(
+                (0 .. KEYS).map(|i| (i, i)),
+                PairState::default(),
+            )),
+            i: 0,
+        },
+        "frozen get" => FrozenGet {
+            inner: Arc::new(
+                (0 .. KEYS).map(|i| (i, i)).collect::<Map<_, _>>().freeze(),
+            ),
+            i: 0,
+        },
+    }
+}
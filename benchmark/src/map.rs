@@ -1,9 +1,11 @@
 #[macro_use]
 extern crate benchsuite;
 extern crate lockfree;
+extern crate rayon;
 
 use benchsuite::exec::Target;
 use lockfree::map::Map;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::{
     collections::HashMap,
     hash::{Hash, Hasher},
@@ -191,6 +193,109 @@ impl Target for LockfreeMixed {
     }
 }
 
+type BatchInner = Arc<Map<u64, u64>>;
+
+const BATCH_LEN: u64 = 64;
+
+#[derive(Debug, Clone, Default)]
+struct LockfreeInsertLoop {
+    inner: BatchInner,
+    i: u64,
+}
+
+impl Target for LockfreeInsertLoop {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i += BATCH_LEN;
+        for j in i .. i + BATCH_LEN {
+            self.inner.insert(j, j);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LockfreeInsertAll {
+    inner: BatchInner,
+    i: u64,
+}
+
+impl Target for LockfreeInsertAll {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i += BATCH_LEN;
+        prevent_opt(
+            self.inner.insert_all((i .. i + BATCH_LEN).map(|j| (j, j))),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LockfreeGetLoop {
+    inner: BatchInner,
+    i: u64,
+}
+
+impl Target for LockfreeGetLoop {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i += BATCH_LEN;
+        for j in i .. i + BATCH_LEN {
+            prevent_opt(self.inner.get(&j).map(|guard| *guard.val()));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LockfreeGetMany {
+    inner: BatchInner,
+    keys: Vec<u64>,
+    i: u64,
+}
+
+impl Target for LockfreeGetMany {
+    #[inline(always)]
+    fn round(&mut self) {
+        let i = self.i;
+        self.i += BATCH_LEN;
+        self.keys.clear();
+        self.keys.extend(i .. i + BATCH_LEN);
+        prevent_opt(self.inner.get_many(&self.keys, |_, &val| val));
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LockfreeSum {
+    inner: BatchInner,
+}
+
+impl Target for LockfreeSum {
+    #[inline(always)]
+    fn round(&mut self) {
+        let mut sum = 0u64;
+        self.inner.for_each(|_, &val| sum = sum.wrapping_add(val));
+        prevent_opt(sum);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LockfreeParSum {
+    inner: BatchInner,
+}
+
+impl Target for LockfreeParSum {
+    #[inline(always)]
+    fn round(&mut self) {
+        let sum = (&*self.inner)
+            .into_par_iter()
+            .map(|(_, val)| val)
+            .reduce(|| 0, u64::wrapping_add);
+        prevent_opt(sum);
+    }
+}
+
 fn main() {
     let mutex = MutexInner::default();
     let lockfree = LockfreeInner::default();
@@ -231,6 +336,44 @@ fn main() {
         },
     }
 
+    bench! {
+        levels 1, 2, 4, 8;
+        "lockfree insert (64 per round)" => LockfreeInsertLoop {
+            inner: BatchInner::default(),
+            i: 0,
+        },
+        "lockfree insert_all (64 per round)" => LockfreeInsertAll {
+            inner: BatchInner::default(),
+            i: 0,
+        },
+    }
+
+    let batch_get = BatchInner::default();
+    batch_get.extend((0 .. 0x100000).map(|i| (i, i)));
+
+    bench! {
+        levels 1, 2, 4, 8;
+        "lockfree get (64 per round)" => LockfreeGetLoop {
+            inner: batch_get.clone(),
+            i: 0,
+        },
+        "lockfree get_many (64 per round)" => LockfreeGetMany {
+            inner: batch_get.clone(),
+            keys: Vec::new(),
+            i: 0,
+        },
+    }
+
+    bench! {
+        levels 1;
+        "lockfree for_each sum (whole map per round)" => LockfreeSum {
+            inner: batch_get.clone(),
+        },
+        "lockfree parallel sum (whole map per round)" => LockfreeParSum {
+            inner: batch_get,
+        },
+    }
+
     bench! {
         levels 1, 2, 4, 8;
         "mutex mixed" => MutexMixed {
@@ -0,0 +1,71 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::Map;
+use std::{hint::black_box, sync::Arc};
+
+// How many operations a round performs, under a single pin or not.
+const OPS: u64 = 1000;
+
+// How many keys the maps hold, so that rounds mix updates and lookups of
+// present keys.
+const KEYS: u64 = 0x10000;
+
+#[derive(Debug, Clone)]
+struct Unpinned {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for Unpinned {
+    #[inline(always)]
+    fn round(&mut self) {
+        for _ in 0 .. OPS {
+            let key = self.i % KEYS;
+            self.i += 1;
+            if key % 4 == 0 {
+                self.inner.insert(key, self.i);
+            } else {
+                black_box(self.inner.get(&key).map(|guard| *guard.val()));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pinned {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for Pinned {
+    #[inline(always)]
+    fn round(&mut self) {
+        let pinned = self.inner.pin();
+        for _ in 0 .. OPS {
+            let key = self.i % KEYS;
+            self.i += 1;
+            if key % 4 == 0 {
+                pinned.insert(key, self.i);
+            } else {
+                black_box(pinned.get(&key).map(|&(_, val)| val));
+            }
+        }
+    }
+}
+
+fn filled() -> Arc<Map<u64, u64>> {
+    Arc::new((0 .. KEYS).map(|i| (i, i)).collect())
+}
+
+fn main() {
+    // Each round is a loop of 1000 operations, a quarter of them insertions,
+    // either pausing the incinerator once per operation or once per loop.
+    bench! {
+        levels 1, 2, 4, 8;
+        "unpinned 1000 ops" => Unpinned { inner: filled(), i: 0 },
+        "pinned 1000 ops" => Pinned { inner: filled(), i: 0 },
+    }
+}
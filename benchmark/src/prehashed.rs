@@ -0,0 +1,181 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::Map;
+use std::{
+    hash::{BuildHasher, Hasher},
+    hint::black_box,
+    sync::Arc,
+};
+
+// How many keys the maps of the `get` benchmarks hold.
+const KEYS: u64 = 0x100000;
+
+// A content digest made up from the index of a key, spread like the output of
+// a good hash function.
+fn digest(i: u64) -> u64 {
+    i.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ i >> 29
+}
+
+// Takes a digest as its own hash, which is as cheap as a hasher can get.
+#[derive(Debug, Clone, Copy, Default)]
+struct DigestState;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct DigestHasher(u64);
+
+impl BuildHasher for DigestState {
+    type Hasher = DigestHasher;
+
+    fn build_hasher(&self) -> DigestHasher {
+        DigestHasher(0)
+    }
+}
+
+impl Hasher for DigestHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = self.0 << 8 | byte as u64;
+        }
+    }
+
+    fn write_u64(&mut self, word: u64) {
+        self.0 = word;
+    }
+}
+
+#[derive(Debug)]
+struct Insert<H> {
+    inner: Arc<Map<u64, u64, H>>,
+    i: u64,
+}
+
+impl<H> Clone for Insert<H> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), i: self.i }
+    }
+}
+
+impl<H> Target for Insert<H>
+where
+    H: BuildHasher,
+{
+    #[inline(always)]
+    fn round(&mut self) {
+        let key = digest(self.i);
+        self.i += 1;
+        self.inner.insert(key, key);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct InsertHashed {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for InsertHashed {
+    #[inline(always)]
+    fn round(&mut self) {
+        let key = digest(self.i);
+        self.i += 1;
+        self.inner.insert_hashed(key, key, key);
+    }
+}
+
+#[derive(Debug)]
+struct Get<H> {
+    inner: Arc<Map<u64, u64, H>>,
+    i: u64,
+}
+
+impl<H> Clone for Get<H> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone(), i: self.i }
+    }
+}
+
+impl<H> Target for Get<H>
+where
+    H: BuildHasher,
+{
+    #[inline(always)]
+    fn round(&mut self) {
+        let key = digest(self.i);
+        self.i = (self.i + 1) % KEYS;
+        black_box(self.inner.get(&key).map(|guard| *guard.val()));
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GetHashed {
+    inner: Arc<Map<u64, u64>>,
+    i: u64,
+}
+
+impl Target for GetHashed {
+    #[inline(always)]
+    fn round(&mut self) {
+        let key = digest(self.i);
+        self.i = (self.i + 1) % KEYS;
+        black_box(self.inner.get_hashed(key, &key).map(|guard| *guard.val()));
+    }
+}
+
+fn filled<H>(map: Map<u64, u64, H>) -> Arc<Map<u64, u64, H>>
+where
+    H: BuildHasher,
+{
+    map.extend((0 .. KEYS).map(|i| (digest(i), digest(i))));
+    Arc::new(map)
+}
+
+fn filled_hashed() -> Arc<Map<u64, u64>> {
+    let map = Map::new();
+    for i in 0 .. KEYS {
+        map.insert_hashed(digest(i), digest(i), digest(i));
+    }
+    Arc::new(map)
+}
+
+fn main() {
+    // Keys are digests already, so hashing them again is wasted work, which
+    // is either skipped or made as cheap as possible.
+    bench! {
+        levels 1, 2, 4, 8;
+        "RandomState insert" => Insert {
+            inner: Arc::new(Map::new()),
+            i: 0,
+        },
+        "pass-through insert" => Insert {
+            inner: Arc::new(Map::with_hasher(DigestState)),
+            i: 0,
+        },
+        "insert_hashed" => InsertHashed {
+            inner: Arc::new(Map::new()),
+            i: 0,
+        },
+    }
+
+    bench! {
+        levels 1, 2, 4, 8;
+        "RandomState get" => Get {
+            inner: filled(Map::new()),
+            i: 0,
+        },
+        "pass-through get" => Get {
+            inner: filled(Map::with_hasher(DigestState)),
+            i: 0,
+        },
+        "get_hashed" => GetHashed {
+            inner: filled_hashed(),
+            i: 0,
+        },
+    }
+}
@@ -0,0 +1,84 @@
+#[macro_use]
+extern crate benchsuite;
+extern crate lockfree;
+
+use benchsuite::exec::Target;
+use lockfree::map::Map;
+use std::sync::Arc;
+
+// How many keys each map holds.
+const KEYS: usize = 0x1000;
+
+// How long each key is, in bytes.
+const KEY_LEN: usize = 256;
+
+fn key(i: usize) -> String {
+    let mut key = format!("{:08x}", i);
+    while key.len() < KEY_LEN {
+        key.push('.');
+    }
+    key
+}
+
+fn filled() -> Map<String, usize> {
+    (0 .. KEYS).map(|i| (key(i), i)).collect()
+}
+
+#[derive(Debug, Clone)]
+struct Reinsert {
+    inner: Arc<Map<String, usize>>,
+    keys: Arc<Vec<String>>,
+    i: usize,
+}
+
+impl Target for Reinsert {
+    #[inline(always)]
+    fn round(&mut self) {
+        let key = &self.keys[self.i];
+        self.i = (self.i + 1) % KEYS;
+        if let Some(removed) = self.inner.remove(key) {
+            self.inner.reinsert(removed);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Move {
+    source: Arc<Map<String, usize>>,
+    target: Arc<Map<String, usize>>,
+    keys: Arc<Vec<String>>,
+    i: usize,
+}
+
+impl Target for Move {
+    #[inline(always)]
+    fn round(&mut self) {
+        let key = &self.keys[self.i];
+        self.i = (self.i + 1) % KEYS;
+        if let Some(removed) = self.source.remove(key) {
+            self.target.reinsert(removed);
+        } else if let Some(removed) = self.target.remove(key) {
+            self.source.reinsert(removed);
+        }
+    }
+}
+
+fn main() {
+    let keys = Arc::new((0 .. KEYS).map(key).collect::<Vec<_>>());
+
+    // Removes the entries with 256-byte keys and reinserts them.
+    bench! {
+        levels 1, 4;
+        "lockfree remove + reinsert (same map)" => Reinsert {
+            inner: Arc::new(filled()),
+            keys: keys.clone(),
+            i: 0,
+        },
+        "lockfree remove + reinsert (other hasher)" => Move {
+            source: Arc::new(filled()),
+            target: Arc::new(Map::new()),
+            keys: keys.clone(),
+            i: 0,
+        },
+    }
+}
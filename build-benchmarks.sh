@@ -45,6 +45,66 @@ cargo run --bin map --release >> $FILE || exit 1
 echo '```' >> $FILE
 echo '' >> $FILE
 
+echo '## MAP HASHERS' >> $FILE
+echo '```' >> $FILE
+cargo run --bin hashers --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
+echo '## MAP PRE-HASHED KEYS' >> $FILE
+echo '```' >> $FILE
+cargo run --bin prehashed --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
+echo '## MAP PINNED BATCHES' >> $FILE
+echo '```' >> $FILE
+cargo run --bin pinned --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
+echo '## MAP HOT COUNTERS' >> $FILE
+echo '```' >> $FILE
+cargo run --bin counter --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
+echo '## MAP EXCLUSIVE ACCESS' >> $FILE
+echo '```' >> $FILE
+cargo run --bin exclusive --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
+echo '## MAP HOT KEY' >> $FILE
+echo '```' >> $FILE
+cargo run --bin hotkey --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
+echo '## MAP REINSERT' >> $FILE
+echo '```' >> $FILE
+cargo run --bin reinsert --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
+echo '## MAP CHURN' >> $FILE
+echo '```' >> $FILE
+cargo run --bin churn --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
+echo '## MAP LOOKUP' >> $FILE
+echo '```' >> $FILE
+cargo run --bin lookup --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
+echo '## MAP WITH CAPACITY' >> $FILE
+echo '```' >> $FILE
+cargo run --bin capacity --release >> $FILE || exit 1
+echo '```' >> $FILE
+echo '' >> $FILE
+
 echo '## MPSC CHANNEL' >> $FILE
 echo '```' >> $FILE
 cargo run --bin mpsc --release >> $FILE || exit 1
@@ -2,18 +2,16 @@ pub use super::{
     NoRecv,
     RecvErr::{self, *},
 };
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicPtr, Ordering::*},
+};
 use incin::Pause;
 use owned_alloc::OwnedAlloc;
 use ptr::{bypass_null, check_null_align};
 use removable::Removable;
-use std::{
-    fmt,
-    ptr::{null_mut, NonNull},
-    sync::{
-        atomic::{AtomicPtr, Ordering::*},
-        Arc,
-    },
-};
 
 /// Creates an asynchronous lock-free Multi-Producer-Multi-Consumer (MPMC)
 /// channel. In order to allow multiple producers and multiple receivers,
@@ -491,6 +489,7 @@ mod test {
         },
         thread,
     };
+    use std::prelude::v1::*;
 
     #[test]
     fn correct_numbers() {
@@ -2,16 +2,14 @@ pub use super::{
     NoRecv,
     RecvErr::{self, *},
 };
-use owned_alloc::OwnedAlloc;
-use ptr::{bypass_null, check_null_align};
-use std::{
+use alloc::sync::Arc;
+use core::{
     fmt,
     ptr::{null_mut, NonNull},
-    sync::{
-        atomic::{AtomicPtr, Ordering::*},
-        Arc,
-    },
+    sync::atomic::{AtomicPtr, Ordering::*},
 };
+use owned_alloc::OwnedAlloc;
+use ptr::{bypass_null, check_null_align};
 
 /// Creates an asynchronous lock-free Multi-Producer-Single-Consumer (MPSC)
 /// channel. In order to allow multiple producers, [`Sender`] is clonable and
@@ -446,6 +444,7 @@ unsafe fn delete_before_last<T>(
 mod test {
     use channel::mpsc;
     use std::thread;
+    use std::prelude::v1::*;
 
     #[test]
     fn correct_numbers() {
@@ -2,18 +2,16 @@ pub use super::{
     NoRecv,
     RecvErr::{self, *},
 };
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicPtr, Ordering::*},
+};
 use incin::Pause;
 use owned_alloc::OwnedAlloc;
 use ptr::{bypass_null, check_null_align};
 use removable::Removable;
-use std::{
-    fmt,
-    ptr::{null_mut, NonNull},
-    sync::{
-        atomic::{AtomicPtr, Ordering::*},
-        Arc,
-    },
-};
 
 /// Creates an asynchronous lock-free Single-Producer-Multi-Consumer (SPMC)
 /// channel. In order to allow multiple consumers, [`Receiver`] is clonable and
@@ -341,6 +339,7 @@ mod test {
         },
         thread,
     };
+    use std::prelude::v1::*;
 
     #[test]
     fn correct_numbers() {
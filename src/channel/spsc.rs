@@ -2,13 +2,13 @@ pub use super::{
     NoRecv,
     RecvErr::{self, *},
 };
-use owned_alloc::OwnedAlloc;
-use ptr::check_null_align;
-use std::{
+use core::{
     fmt,
     ptr::{null_mut, NonNull},
     sync::atomic::{AtomicPtr, Ordering::*},
 };
+use owned_alloc::OwnedAlloc;
+use ptr::check_null_align;
 
 /// Creates an asynchronous lock-free Single-Producer-Single-Consumer (SPSC)
 /// channel.
@@ -255,6 +255,7 @@ struct Node<T> {
 mod test {
     use channel::spsc;
     use std::thread;
+    use std::prelude::v1::*;
 
     #[test]
     fn correct_sequence() {
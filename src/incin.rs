@@ -1,9 +1,20 @@
-use std::{
-    cell::Cell,
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::cell::Cell;
+use core::{
     fmt,
     marker::PhantomData,
     sync::atomic::{AtomicUsize, Ordering::*},
 };
+#[cfg(not(feature = "std"))]
+use core::ptr::{null_mut, NonNull};
+#[cfg(not(feature = "std"))]
+use owned_alloc::OwnedAlloc;
+use primitive;
+#[cfg(not(feature = "std"))]
+use primitive::PtrMut;
+#[cfg(feature = "std")]
 use tls::ThreadLocal;
 
 /// The incinerator. It is an API used to solve the infamous ABA problem. It
@@ -19,6 +30,10 @@ use tls::ThreadLocal;
 /// When the incinerator is dropped, all the garbage is automatically dropped
 /// too.
 ///
+/// With the `std` feature, each thread has its own garbage list. Without it,
+/// there is no thread-local storage, so the garbage list is shared by every
+/// thread, and it is only cleared when the pause counter is zero.
+///
 /// C11 Implementation: <https://gitlab.com/bzim/c11-incinerator/>
 ///
 /// # Example
@@ -65,14 +80,34 @@ use tls::ThreadLocal;
 /// ```
 #[derive(Debug)]
 pub struct Incinerator<T> {
-    counter: AtomicUsize,
+    counter: primitive::AtomicUsize,
+    #[cfg(feature = "std")]
     tls_list: ThreadLocal<GarbageList<T>>,
+    // How many values are saved in the thread-local garbage lists.
+    #[cfg(feature = "std")]
+    pending: AtomicUsize,
+    #[cfg(not(feature = "std"))]
+    shared_list: GarbageStack<T>,
 }
 
 impl<T> Incinerator<T> {
     /// Creates a new incinerator, with no pauses and empty garbage list.
+    #[cfg(feature = "std")]
     pub fn new() -> Self {
-        Self { counter: AtomicUsize::new(0), tls_list: ThreadLocal::new() }
+        Self {
+            counter: primitive::AtomicUsize::new(0),
+            tls_list: ThreadLocal::new(),
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    /// Creates a new incinerator, with no pauses and empty garbage list.
+    #[cfg(not(feature = "std"))]
+    pub fn new() -> Self {
+        Self {
+            counter: primitive::AtomicUsize::new(0),
+            shared_list: GarbageStack::new(),
+        }
     }
 
     /// Increments the pause counter and creates a pause associated with this
@@ -95,14 +130,22 @@ impl<T> Incinerator<T> {
                 AcqRel,
                 Relaxed,
             ) {
+                // Also count the pauses of this thread, so we can tell if the
+                // current thread is pausing the incinerator. The list of this
+                // thread is created right away, so it is looked up only once,
+                // and the counter is only ever shared with the threads its
+                // pauses are sent to.
+                #[cfg(feature = "std")]
                 Ok(_) => {
-                    break Pause {
-                        incin: self,
-                        had_list: self.tls_list.get().is_some(),
-                        _unsync: PhantomData,
-                    };
+                    let local =
+                        &self.tls_list.with_init(GarbageList::new).pauses;
+                    local.fetch_add(1, Relaxed);
+                    break Pause { incin: self, local, _unsync: PhantomData };
                 },
 
+                #[cfg(not(feature = "std"))]
+                Ok(_) => break Pause { incin: self, _unsync: PhantomData },
+
                 Err(new) => count = new,
             }
         }
@@ -127,41 +170,124 @@ impl<T> Incinerator<T> {
     /// the counter is zero. If the counter is zero when the method is called,
     /// the value is immediately dropped and the garbage list is cleared. You
     /// must remove the resource from shared context before calling this method.
-    /// This operation performs [`Acquire`] on the pause counter.
+    /// This operation performs [`AcqRel`] on the pause counter.
+    #[cfg(feature = "std")]
     pub fn add(&self, val: T) {
-        if self.counter.load(Acquire) == 0 {
+        if read_counter(&self.counter) == 0 {
             // Safe to drop it all. Note that we check the counter after the
             // resource was removed from shared context. Since we use Thread
             // Local Storage, nobody can add something to the list meanwhile
             // besides us.
-            self.tls_list.get().map(GarbageList::clear);
+            self.clear_local();
             drop(val);
         } else {
             // Not safe to drop. We have to save the value in the garbage list.
-            self.tls_list.with_init(GarbageList::new).add(val);
+            self.save(val);
+        }
+    }
+
+    /// Adds the given value to the garbage list. The value is only dropped when
+    /// the counter is zero. If the counter is zero when the method is called,
+    /// the value is immediately dropped and the garbage list is cleared. You
+    /// must remove the resource from shared context before calling this method.
+    /// This operation performs [`AcqRel`] on the pause counter.
+    #[cfg(not(feature = "std"))]
+    pub fn add(&self, val: T) {
+        if read_counter(&self.counter) == 0 {
+            // Safe to drop the value, since it was removed from shared context
+            // before we checked the counter. The shared list is checked again
+            // after it is taken, since other threads may pause meanwhile.
+            self.shared_list.clear_unpaused(&self.counter);
+            drop(val);
+        } else {
+            // Not safe to drop. We have to save the value in the garbage list.
+            self.shared_list.push(val);
         }
     }
 
     /// Tries to delete the garbage list associated with this thread. The
     /// garbage list is only cleared if the counter is zero. In case of success,
-    /// `true` is returned. This operation performs [`Acquire`] on the pause
+    /// `true` is returned. This operation performs [`AcqRel`] on the pause
     /// counter.
+    #[cfg(feature = "std")]
     pub fn try_clear(&self) -> bool {
-        if self.counter.load(Acquire) == 0 {
+        if read_counter(&self.counter) == 0 {
             // It is only safe to drop if there are no active pauses. Remember
             // nobody can add something to this specific list besides us because
             // it is thread local.
-            self.tls_list.get().map(GarbageList::clear);
+            self.clear_local();
             true
         } else {
             false
         }
     }
 
+    /// Tries to delete the shared garbage list. The garbage list is only
+    /// cleared if the counter is zero. In case of success, `true` is returned.
+    /// This operation performs [`AcqRel`] on the pause counter.
+    #[cfg(not(feature = "std"))]
+    pub fn try_clear(&self) -> bool {
+        read_counter(&self.counter) == 0
+            && self.shared_list.clear_unpaused(&self.counter)
+    }
+
+    /// Tests whether the current thread has any active pause on this
+    /// incinerator. A pause sent to another thread still counts for the thread
+    /// which created it. Only available with the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn is_paused_locally(&self) -> bool {
+        self.tls_list.get().is_some_and(|list| list.pauses.load(Relaxed) > 0)
+    }
+
+    // Tests whether nothing is paused, for users keeping garbage of their own,
+    // which they may only drop if this holds after removing it from shared
+    // context. It performs `AcqRel` on the pause counter, just like `add`.
+    pub(crate) fn is_unpaused(&self) -> bool {
+        read_counter(&self.counter) == 0
+    }
+
     /// Clears everything that is in the inicinerator regardless of pauses.
     /// Exclusive reference is required.
+    #[cfg(feature = "std")]
     pub fn clear(&mut self) {
         self.tls_list.clear();
+        *self.pending.get_mut() = 0;
+    }
+
+    /// Clears everything that is in the inicinerator regardless of pauses.
+    /// Exclusive reference is required.
+    #[cfg(not(feature = "std"))]
+    pub fn clear(&mut self) {
+        self.shared_list.clear();
+    }
+
+    /// The number of values waiting in the garbage lists to be dropped. It is
+    /// only approximate if the incinerator is used concurrently.
+    #[cfg(feature = "std")]
+    pub fn pending(&self) -> usize {
+        self.pending.load(Relaxed)
+    }
+
+    /// The number of values waiting in the garbage list to be dropped. It is
+    /// only approximate if the incinerator is used concurrently.
+    #[cfg(not(feature = "std"))]
+    pub fn pending(&self) -> usize {
+        self.shared_list.len.load(Relaxed)
+    }
+
+    // Saves the value in the garbage list of the current thread.
+    #[cfg(feature = "std")]
+    fn save(&self, val: T) {
+        self.pending.fetch_add(1, Relaxed);
+        self.tls_list.with_init(GarbageList::new).add(val);
+    }
+
+    // Drops the garbage list of the current thread, if there is one.
+    #[cfg(feature = "std")]
+    fn clear_local(&self) {
+        if let Some(list) = self.tls_list.get() {
+            self.pending.fetch_sub(list.clear(), Relaxed);
+        }
     }
 }
 
@@ -180,7 +306,9 @@ where
     T: 'incin,
 {
     incin: &'incin Incinerator<T>,
-    had_list: bool,
+    // The pause counter of the thread which created this pause.
+    #[cfg(feature = "std")]
+    local: &'incin AtomicUsize,
     _unsync: PhantomData<*mut ()>,
 }
 
@@ -193,22 +321,38 @@ impl<'incin, T> Pause<'incin, T> {
     /// Adds the given value to the garbage list of the incinerator but if the
     /// counter is `1` (i.e. this is the only active pause) data is immediately
     /// dropped. See documention for [`Incinerator::add`] for more. This
-    /// operation performs [`Acquire`] on the pause counter.
+    /// operation performs [`AcqRel`] on the pause counter.
+    #[cfg(feature = "std")]
     pub fn add_to_incin(&self, val: T) {
-        if self.incin.counter.load(Acquire) == 1 {
+        if read_counter(&self.incin.counter) == 1 {
             // We are the only pause active in this case.
             //
             // Safe to drop it all. Note that we check the counter after the
             // resource was removed from shared context. Since we use Thread
             // Local Storage, nobody can add something to the list meanwhile
             // besides us.
-            if self.had_list {
-                self.incin.tls_list.get().map(GarbageList::clear);
-            }
+            self.incin.clear_local();
             drop(val);
         } else {
             // Not safe to drop. We have to save the value in the garbage list.
-            self.incin.tls_list.with_init(GarbageList::new).add(val);
+            self.incin.save(val);
+        }
+    }
+
+    /// Adds the given value to the garbage list of the incinerator but if the
+    /// counter is `1` (i.e. this is the only active pause) data is immediately
+    /// dropped. See documention for [`Incinerator::add`] for more. This
+    /// operation performs [`AcqRel`] on the pause counter.
+    #[cfg(not(feature = "std"))]
+    pub fn add_to_incin(&self, val: T) {
+        if read_counter(&self.incin.counter) == 1 {
+            // We are the only pause active in this case, so the value can be
+            // dropped. The shared list is left alone, though: it may hold
+            // garbage added by other threads which this very pause still sees.
+            drop(val);
+        } else {
+            // Not safe to drop. We have to save the value in the garbage list.
+            self.incin.shared_list.push(val);
         }
     }
 
@@ -221,11 +365,22 @@ impl<'incin, T> Pause<'incin, T> {
 }
 
 impl<'incin, T> Drop for Pause<'incin, T> {
+    #[cfg(feature = "std")]
     fn drop(&mut self) {
+        self.local.fetch_sub(1, Relaxed);
         if self.incin.counter.fetch_sub(1, AcqRel) == 1 {
             // If the previous value was 1, this means now it is 0 and... we can
             // delete our local list.
-            self.incin.tls_list.get().map(GarbageList::clear);
+            self.incin.clear_local();
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn drop(&mut self) {
+        if self.incin.counter.fetch_sub(1, AcqRel) == 1 {
+            // If the previous value was 1, this means now it is 0 and... we can
+            // delete the shared list, unless someone paused meanwhile.
+            self.incin.shared_list.clear_unpaused(&self.incin.counter);
         }
     }
 }
@@ -238,13 +393,29 @@ impl<'incin, T> Clone for Pause<'incin, T> {
 
 unsafe impl<'incin, T> Send for Pause<'incin, T> where T: Send {}
 
+// Reads the pause counter after a value was removed from shared context, to
+// tell whether it can be dropped. A plain load would not do: a thread pausing
+// meanwhile increments the counter and then loads the shared pointer, while
+// this one stores the pointer and then loads the counter, and both loads may
+// miss the other's store. An RMW is ordered with the increment in the
+// modification order of the counter, so either it sees the pause, or the pause
+// synchronizes with it and then cannot load the removed value.
+#[inline]
+fn read_counter(counter: &primitive::AtomicUsize) -> usize {
+    counter.fetch_add(0, AcqRel)
+}
+
+#[cfg(feature = "std")]
 struct GarbageList<T> {
     list: Cell<Vec<T>>,
+    // Pauses created by the thread owning this list.
+    pauses: AtomicUsize,
 }
 
+#[cfg(feature = "std")]
 impl<T> GarbageList<T> {
     fn new() -> Self {
-        Self { list: Cell::new(Vec::new()) }
+        Self { list: Cell::new(Vec::new()), pauses: AtomicUsize::new(0) }
     }
 
     fn add(&self, val: T) {
@@ -253,11 +424,14 @@ impl<T> GarbageList<T> {
         self.list.replace(list);
     }
 
-    fn clear(&self) {
-        self.list.replace(Vec::new());
+    // Drops the saved values, returning how many there were.
+    fn clear(&self) -> usize {
+        let list = self.list.replace(Vec::new());
+        list.len()
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> fmt::Debug for GarbageList<T>
 where
     T: fmt::Debug,
@@ -278,6 +452,127 @@ where
     }
 }
 
+// The garbage list shared by every thread when there is no thread-local
+// storage. It is a lock-free stack whose nodes are only ever taken all at once,
+// so it does not suffer from the ABA problem itself.
+#[cfg(not(feature = "std"))]
+struct GarbageStack<T> {
+    top: primitive::AtomicPtr<GarbageNode<T>>,
+    // How many values are in the stack, including taken nodes being checked
+    // by `clear_unpaused`.
+    len: AtomicUsize,
+}
+
+#[cfg(not(feature = "std"))]
+struct GarbageNode<T> {
+    // Only held to be dropped along with the node.
+    #[allow(dead_code)]
+    val: T,
+    next: *mut GarbageNode<T>,
+}
+
+#[cfg(not(feature = "std"))]
+impl<T> GarbageStack<T> {
+    fn new() -> Self {
+        Self {
+            top: primitive::AtomicPtr::new(null_mut()),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, val: T) {
+        self.len.fetch_add(1, Relaxed);
+        let node = OwnedAlloc::new(GarbageNode { val, next: null_mut() });
+        let nnptr = node.into_raw();
+        self.push_chain(nnptr, nnptr);
+    }
+
+    // Pushes a chain of nodes linked through `next`, given its first and last
+    // nodes, which must not be shared.
+    fn push_chain(
+        &self,
+        first: NonNull<GarbageNode<T>>,
+        last: NonNull<GarbageNode<T>>,
+    ) {
+        let mut top = self.top.load(Relaxed);
+        loop {
+            // Safe because the chain is still ours.
+            unsafe { (*last.as_ptr()).next = top }
+            let new = first.as_ptr();
+            match self.top.compare_exchange(top, new, Release, Relaxed) {
+                Ok(_) => break,
+                Err(new) => top = new,
+            }
+        }
+    }
+
+    // Takes the whole list and drops it if the given pause counter is zero
+    // after that. Otherwise, the list is pushed back, since whoever paused
+    // meanwhile may be reading the garbage. Returns whether it was dropped.
+    fn clear_unpaused(&self, counter: &primitive::AtomicUsize) -> bool {
+        let first = self.top.swap(null_mut(), AcqRel);
+
+        if read_counter(counter) == 0 {
+            // Safe because we took the nodes and nobody is paused.
+            let count = unsafe { Self::drop_chain(first) };
+            self.len.fetch_sub(count, Relaxed);
+            return true;
+        }
+
+        if let Some(first) = NonNull::new(first) {
+            let mut last = first;
+            // Safe because the taken nodes are ours.
+            while let Some(next) = NonNull::new(unsafe { last.as_ref().next }) {
+                last = next;
+            }
+            self.push_chain(first, last);
+        }
+
+        false
+    }
+
+    fn clear(&mut self) {
+        let first = self.top.replace_mut(null_mut());
+        // Safe because we have exclusive access.
+        unsafe { Self::drop_chain(first) };
+        *self.len.get_mut() = 0;
+    }
+
+    // Unsafe because the chain must not be shared. Returns how many nodes were
+    // dropped.
+    unsafe fn drop_chain(mut ptr: *mut GarbageNode<T>) -> usize {
+        let mut count = 0;
+        while let Some(nnptr) = NonNull::new(ptr) {
+            let node = OwnedAlloc::from_raw(nnptr);
+            ptr = node.next;
+            count += 1;
+        }
+        count
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T> Drop for GarbageStack<T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T> fmt::Debug for GarbageStack<T> {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("GarbageStack")
+            .field("top", &self.top)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+unsafe impl<T> Send for GarbageStack<T> where T: Send {}
+#[cfg(not(feature = "std"))]
+unsafe impl<T> Sync for GarbageStack<T> where T: Send {}
+
 macro_rules! doc {
     ($doc:expr ; $($target:tt)*) => {
         #[doc = $doc]
@@ -300,7 +595,7 @@ macro_rules! make_shared_incin {
                      were used.");
             $(#[$meta])*
             $vis struct $name<$($params),*> {
-                inner: ::std::sync::Arc<::incin::Incinerator<$garbage>>,
+                inner: ::alloc::sync::Arc<::incin::Incinerator<$garbage>>,
             }
         }
 
@@ -308,7 +603,7 @@ macro_rules! make_shared_incin {
             doc! {
                 concat!("Creates a new shared incinerator for ", $target, ".");
                 $vis fn new() -> Self {
-                    use std::sync::Arc;
+                    use alloc::sync::Arc;
                     use incin::Incinerator;
                     Self {
                         inner: Arc::new(Incinerator::new()),
@@ -321,10 +616,8 @@ macro_rules! make_shared_incin {
                          best possible way given the runtime status of this \
                          incinerator.");
                 $vis fn clear(&mut self) {
-                    use std::{
-                        mem::replace,
-                        sync::Arc,
-                    };
+                    use alloc::sync::Arc;
+                    use core::mem::replace;
                     // use incin::Incinerator;
 
                     // I know this sounds weird. This is because Arc::get_mut
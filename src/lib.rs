@@ -1,4 +1,5 @@
 #![warn(missing_docs)]
+#![no_std]
 //! A crate providing lock-free data structures and a solution for the "ABA
 //! problem" related to pointers.
 //!
@@ -28,8 +29,75 @@
 //! # Performance Guide
 //! In order to achieve a better time performance with lockfree, it is
 //! recommended to avoid global locking stuff like heap allocation.
+//!
+//! # Features
+//! - `std` (default): links the standard library. Without it, the crate only
+//!   needs `core` and `alloc`, so it works on bare-metal targets with a global
+//!   allocator. Each incinerator then keeps a single shared garbage list
+//!   instead of one per thread, [`tls`] is not available, and the default
+//!   hasher of [`Map`](map::Map) and [`Set`](set::Set) is
+//!   [`FixedState`](map::FixedState), whose hashes are predictable.
+//! - `serde`: implements serialization for [`Map`](map::Map).
+//! - `rayon`: parallel iteration over, and parallel extension of,
+//!   [`Map`](map::Map). Requires `std`.
+//! - `hash128`: hashes keys of [`Map`](map::Map) to 128 bits.
+//! - `ahash`: the aliases [`AHashMap`](map::AHashMap) and
+//!   [`AHashSet`](set::AHashSet), which hash with [aHash](::ahash). It is
+//!   faster than the default hasher, and still randomly seeded. Requires
+//!   `std`.
+//! - `fxhash`: the aliases [`FxMap`](map::FxMap) and [`FxSet`](set::FxSet),
+//!   which hash with [FxHash](::fxhash). It is very fast for small keys such
+//!   as integers, but it is not seeded, so an adversary who controls the keys
+//!   can make them collide. Requires `std`.
+//! - `tracing`: emits [tracing](::tracing) events about the contention in
+//!   [`Map`](map::Map): failed compare-and-swaps which make an operation
+//!   retry, operations retrying too many times, sub-tables created and empty
+//!   buckets collapsed. The events carry the depth in the tree and the retry
+//!   count, never the keys. With `std`, it also warns about guards which kept
+//!   memory reclamation stalled for more than a second. Without this feature,
+//!   the instrumentation is compiled out.
+//! - `debug-validate`: enables `Map::validate`, which checks the internal
+//!   invariants of a [`Map`](map::Map), for debugging the crate.
+//! - `snapshot`: enables `Map::snapshot`, which takes copy-on-write
+//!   snapshots of a [`Map`](map::Map) without copying its entries. Every
+//!   [`Map`](map::Map) then copies the tables and buckets it shares with
+//!   snapshots before changing them, so changes are slightly slower, and
+//!   more so while snapshots are alive. Writers never wait for snapshots.
+//!
+//! These hasher features do not change the default hasher: features are
+//! additive, so enabling one anywhere in a build would change the hasher of
+//! every [`Map`](map::Map) in it.
+
+#[cfg(any(feature = "std", test))]
+#[macro_use]
+extern crate std;
+
+#[macro_use]
+extern crate alloc;
+
+#[cfg(feature = "serde")]
+extern crate serde;
+
+#[cfg(feature = "rayon")]
+extern crate rayon;
 
-extern crate owned_alloc;
+#[cfg(feature = "ahash")]
+extern crate ahash;
+
+#[cfg(feature = "fxhash")]
+extern crate fxhash;
+
+#[cfg(feature = "tracing")]
+extern crate tracing;
+
+#[cfg(loom)]
+extern crate loom;
+
+#[cfg(all(test, feature = "serde"))]
+extern crate bincode;
+
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 /// Provides convenient re-exports.
 pub mod prelude;
@@ -40,7 +108,9 @@ pub mod prelude;
 #[macro_use]
 pub mod incin;
 
-/// A wait-free per-object Thread Local Storage (TLS).
+/// A wait-free per-object Thread Local Storage (TLS). Only available with the
+/// `std` feature.
+#[cfg(feature = "std")]
 pub mod tls;
 
 /// A lock-free queue.
@@ -68,3 +138,7 @@ pub mod removable;
 
 #[allow(dead_code)]
 mod ptr;
+
+mod primitive;
+
+mod owned_alloc;
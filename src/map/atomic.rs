@@ -0,0 +1,93 @@
+use core::sync::atomic::Ordering::*;
+#[cfg(target_has_atomic = "64")]
+use core::sync::atomic::{AtomicI64, AtomicU64};
+use core::sync::atomic::{
+    AtomicBool,
+    AtomicI16,
+    AtomicI32,
+    AtomicI8,
+    AtomicIsize,
+    AtomicU16,
+    AtomicU32,
+    AtomicU8,
+    AtomicUsize,
+};
+
+/// A value of a [`Map`](super::Map) which can be changed in place, through a
+/// shared reference, such as
+/// [`AtomicU64`](core::sync::atomic::AtomicU64). Changing it in place does not
+/// allocate a new entry, nor swap the entry in its bucket, unlike
+/// [`update`](super::Map::update). See
+/// [`update_in_place`](super::Map::update_in_place).
+pub trait AtomicValue {
+    /// The plain value held.
+    type Value: Copy;
+
+    /// Creates an atomic value holding the given plain value.
+    fn new(val: Self::Value) -> Self;
+
+    /// Loads the plain value, with [`Acquire`] ordering.
+    fn load(&self) -> Self::Value;
+
+    /// Replaces the plain value with the one computed from it by the given
+    /// closure, with [`AcqRel`] ordering, returning the previous value. The
+    /// closure is called again if the value is concurrently changed.
+    fn fetch_update<F>(&self, update: F) -> Self::Value
+    where
+        F: FnMut(Self::Value) -> Self::Value;
+}
+
+macro_rules! impl_atomic_value {
+    ($($atomic:ty => $val:ty),* $(,)*) => {
+        $(
+            impl AtomicValue for $atomic {
+                type Value = $val;
+
+                #[inline]
+                fn new(val: $val) -> Self {
+                    <$atomic>::new(val)
+                }
+
+                #[inline]
+                fn load(&self) -> $val {
+                    <$atomic>::load(self, Acquire)
+                }
+
+                #[inline]
+                fn fetch_update<F>(&self, mut update: F) -> $val
+                where
+                    F: FnMut($val) -> $val,
+                {
+                    let res = <$atomic>::fetch_update(
+                        self,
+                        AcqRel,
+                        Acquire,
+                        |val| Some(update(val)),
+                    );
+                    // The closure never gives up.
+                    match res {
+                        Ok(prev) | Err(prev) => prev,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_atomic_value! {
+    AtomicBool => bool,
+    AtomicU8 => u8,
+    AtomicI8 => i8,
+    AtomicU16 => u16,
+    AtomicI16 => i16,
+    AtomicU32 => u32,
+    AtomicI32 => i32,
+    AtomicUsize => usize,
+    AtomicIsize => isize,
+}
+
+#[cfg(target_has_atomic = "64")]
+impl_atomic_value! {
+    AtomicU64 => u64,
+    AtomicI64 => i64,
+}
@@ -0,0 +1,46 @@
+use core::hint::spin_loop;
+#[cfg(feature = "std")]
+use std::thread;
+
+// The last step which only spins, waiting for `1 << SPIN_STEPS` spin hints,
+// i.e. at most `64`, which is about the time of a contended compare-and-swap
+// on a cache line bouncing between a few cores.
+const SPIN_STEPS: u32 = 6;
+
+// Bounded exponential backoff for the retry loops of a map. It is created
+// before the loop, which costs nothing, and `snooze` is only called after a
+// compare-and-swap failed, so the uncontended path never waits. Each failure
+// doubles the spinning, up to `1 << SPIN_STEPS` spin hints; past that, the
+// thread yields to the others instead (or keeps spinning the maximum without
+// the `std` feature), since the other threads are probably preempted while
+// holding the cache line.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self { step: 0 }
+    }
+
+    // Waits after a failure, longer after each one.
+    #[inline(never)]
+    pub fn snooze(&mut self) {
+        if self.step <= SPIN_STEPS {
+            for _ in 0 .. 1u32 << self.step {
+                spin_loop();
+            }
+            self.step += 1;
+        } else {
+            #[cfg(feature = "std")]
+            thread::yield_now();
+
+            #[cfg(not(feature = "std"))]
+            for _ in 0 .. 1u32 << SPIN_STEPS {
+                spin_loop();
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use super::{
+    insertion::InsertPair,
+    DefaultHashBuilder,
+    Insertion,
+    Iter,
+    Map,
+    ReadGuard,
+    Removed,
+};
+use core::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash},
+    sync::atomic::{AtomicUsize, Ordering::*},
+};
+
+/// A [`Map`] holding at most a given number of entries, e.g. to bound the
+/// memory taken by each tenant of a service. Insertions which would create an
+/// entry fail with [`CapacityExceeded`] once the limit is reached, giving the
+/// key and value back, while replacements of stored entries always succeed.
+///
+/// The limit is never exceeded, not even for a moment. Before an insertion
+/// which might create an entry, a slot is reserved by incrementing a counter,
+/// in a compare-and-swap loop which fails if every slot is taken. If the
+/// insertion turns out to replace an entry, the slot is released. A removal
+/// releases the slot of the removed entry. Therefore, an insertion might be
+/// rejected while a concurrent replacement holds a slot for a short time, even
+/// though the number of entries is below the limit.
+pub struct BoundedMap<K, V, H = DefaultHashBuilder> {
+    inner: Map<K, V, H>,
+    limit: usize,
+    // Slots taken by entries, and by insertions which might create one.
+    reserved: AtomicUsize,
+}
+
+impl<K, V> BoundedMap<K, V> {
+    /// Creates a [`BoundedMap`] holding at most `limit` entries, with the
+    /// default hasher builder.
+    pub fn new(limit: usize) -> Self {
+        Self::with_hasher(limit, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, H> BoundedMap<K, V, H> {
+    /// The maximum number of entries of this [`BoundedMap`].
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The number of entries in this [`BoundedMap`]. The same considerations
+    /// of [`Map::len`] apply.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns whether this [`BoundedMap`] has no entries. The same
+    /// considerations of [`Map::len`] apply.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Creates an iterator over guarded references to the key-value entries.
+    pub fn iter<'map>(&'map self) -> Iter<'map, K, V> {
+        self.inner.iter()
+    }
+
+    /// Consumes this [`BoundedMap`] into the unbounded [`Map`] holding its
+    /// entries.
+    pub fn into_inner(self) -> Map<K, V, H> {
+        self.inner
+    }
+
+    // Takes a slot for an entry, unless every slot is taken.
+    fn reserve(&self) -> bool {
+        let limit = self.limit;
+        self.reserved
+            .fetch_update(AcqRel, Acquire, |reserved| {
+                if reserved < limit {
+                    Some(reserved + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+
+    // Gives back a slot taken by `reserve`.
+    fn release(&self) {
+        self.reserved.fetch_sub(1, AcqRel);
+    }
+}
+
+impl<K, V, H> BoundedMap<K, V, H>
+where
+    H: BuildHasher,
+{
+    /// Creates a [`BoundedMap`] holding at most `limit` entries, using the
+    /// given hasher builder.
+    pub fn with_hasher(limit: usize, builder: H) -> Self {
+        Self {
+            inner: Map::with_hasher(builder),
+            limit,
+            reserved: AtomicUsize::new(0),
+        }
+    }
+
+    /// Searches for the entry identified by the given key. See [`Map::get`].
+    pub fn get<'map, Q>(&'map self, key: &Q) -> Option<ReadGuard<'map, K, V>>
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        self.inner.get(key)
+    }
+
+    /// Tests if the entry identified by the given key is present. See
+    /// [`Map::contains_key`].
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        self.inner.contains_key(key)
+    }
+
+    /// Inserts unconditionally the given key and value, returning the
+    /// previously stored entry, if any. If there was no entry with the key
+    /// and the [`BoundedMap`] is full, nothing is inserted and the key and
+    /// value are given back.
+    pub fn insert(
+        &self,
+        key: K,
+        val: V,
+    ) -> Result<Option<Removed<K, V>>, CapacityExceeded<K, V>>
+    where
+        K: Hash + Ord,
+    {
+        if self.reserve() {
+            let removed = self.inner.insert(key, val);
+            if removed.is_some() {
+                self.release();
+            }
+            return Ok(removed);
+        }
+
+        // Every slot is taken, so only a replacement may go on.
+        let hash = self.inner.hash_of(&key);
+        let events = self.inner.events();
+        let pause = self.inner.pause();
+        // Safe because we paused properly.
+        let insertion = unsafe {
+            self.inner.insert_top(
+                InsertPair::new(|found| found.is_some(), (key, val)),
+                hash,
+                &pause,
+                &events,
+            )
+        };
+
+        match insertion {
+            Insertion::Updated(removed) => Ok(Some(removed)),
+            // The closure never accepts a missing entry.
+            Insertion::Created => unreachable!(),
+            Insertion::Failed(inserter) => {
+                let (key, val) = inserter.into_pair();
+                Err(CapacityExceeded { key, val })
+            },
+        }
+    }
+
+    /// Inserts the given key and value only if no entry with the key is
+    /// present, just like [`Map::try_insert`]. Fails with
+    /// [`TryInsertErr::CapacityExceeded`] if the [`BoundedMap`] is full, even
+    /// if an entry with the key is present.
+    pub fn try_insert(&self, key: K, val: V) -> Result<(), TryInsertErr<K, V>>
+    where
+        K: Hash + Ord,
+    {
+        if !self.reserve() {
+            let err = CapacityExceeded { key, val };
+            return Err(TryInsertErr::CapacityExceeded(err));
+        }
+
+        self.inner.try_insert(key, val).map_err(|(key, val)| {
+            self.release();
+            TryInsertErr::Occupied(key, val)
+        })
+    }
+
+    /// Removes unconditionally the entry identified by the given key, freeing
+    /// its slot. See [`Map::remove`].
+    pub fn remove<Q>(&self, key: &Q) -> Option<Removed<K, V>>
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        let removed = self.inner.remove(key);
+        if removed.is_some() {
+            self.release();
+        }
+        removed
+    }
+}
+
+impl<'map, K, V, H> IntoIterator for &'map BoundedMap<K, V, H> {
+    type Item = ReadGuard<'map, K, V>;
+
+    type IntoIter = Iter<'map, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V, H> fmt::Debug for BoundedMap<K, V, H>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("BoundedMap")
+            .field("limit", &self.limit)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+/// The error of an insertion into a full [`BoundedMap`] which would create an
+/// entry. The key and value are given back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityExceeded<K, V> {
+    /// The key which was not inserted.
+    pub key: K,
+    /// The value which was not inserted.
+    pub val: V,
+}
+
+/// The error of a [`try_insert`](BoundedMap::try_insert) operation on a
+/// [`BoundedMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryInsertErr<K, V> {
+    /// An entry with the key is already present. The key and value are given
+    /// back.
+    Occupied(K, V),
+    /// The [`BoundedMap`] is full.
+    CapacityExceeded(CapacityExceeded<K, V>),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::{Arc, Barrier},
+        thread,
+    };
+    use std::prelude::v1::*;
+
+    #[test]
+    fn limit_and_replacements() {
+        let map = BoundedMap::new(3);
+        for i in 0 .. 3 {
+            assert!(map.insert(i, i).unwrap().is_none());
+        }
+        assert_eq!(
+            map.insert(3, 3).unwrap_err(),
+            CapacityExceeded { key: 3, val: 3 }
+        );
+        assert_eq!(
+            map.try_insert(3, 30),
+            Err(TryInsertErr::CapacityExceeded(CapacityExceeded {
+                key: 3,
+                val: 30
+            }))
+        );
+
+        // Replacements are allowed when full.
+        assert_eq!(*map.insert(1, 10).unwrap().unwrap().val(), 1);
+        assert_eq!(*map.get(&1).unwrap().val(), 10);
+        assert_eq!(map.len(), 3);
+
+        assert_eq!(*map.remove(&0).unwrap().val(), 0);
+        assert!(map.remove(&0).is_none());
+        assert_eq!(map.try_insert(1, 1), Err(TryInsertErr::Occupied(1, 1)));
+        assert_eq!(map.try_insert(3, 3), Ok(()));
+        assert!(map.insert(4, 4).is_err());
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.into_inner().len(), 3);
+    }
+
+    #[test]
+    fn boundary_multithreaded() {
+        const THREADS: usize = 16;
+        const LIMIT: usize = 100;
+        const ROUNDS: usize = 2000;
+
+        let map = Arc::new(BoundedMap::new(LIMIT));
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for i in 0 .. ROUNDS {
+                    let key = (t * ROUNDS + i) % (LIMIT * 3);
+                    let inserted = if i % 2 == 0 {
+                        map.insert(key, t).is_ok()
+                    } else {
+                        map.try_insert(key, t).is_ok()
+                    };
+                    assert!(map.len() <= LIMIT);
+                    if inserted && i % 3 == 0 {
+                        map.remove(&key);
+                    }
+                }
+            }));
+        }
+
+        // Watches the count while the threads hammer the limit.
+        barrier.wait();
+        while threads.iter().any(|thread| !thread.is_finished()) {
+            assert!(map.reserved.load(Acquire) <= LIMIT);
+            assert!(map.len() <= LIMIT);
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        let count = map.iter().count();
+        assert!(count <= LIMIT);
+        assert_eq!(count, map.len());
+        assert_eq!(count, map.reserved.load(Acquire));
+        map.inner.validate();
+    }
+}
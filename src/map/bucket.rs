@@ -1,128 +1,388 @@
+#[cfg(feature = "snapshot")]
+use super::cow::Keeper;
 use super::{
-    guard::{ReadGuard, Removed},
+    backoff::Backoff,
+    cow::Snapshots,
+    guard::Removed,
     insertion::Inserter,
+    order::BucketOrder,
+    salt::Salt,
+    slab::Slab,
+    table::{Restart, Table},
+    trace::Probe,
+    HashCode,
 };
-use incin::{Incinerator, Pause};
-use owned_alloc::OwnedAlloc;
-use ptr::non_zero_null;
-use std::{
+#[cfg(feature = "snapshot")]
+use alloc::sync::Weak;
+use alloc::{sync::Arc, vec::Vec};
+use core::{
     borrow::Borrow,
     cmp::Ordering,
     fmt,
-    mem,
-    ptr::{null_mut, NonNull},
-    sync::{
-        atomic::{AtomicPtr, Ordering::*},
-        Arc,
-    },
+    mem::{self, MaybeUninit},
+    ptr::{self, addr_of_mut, null, null_mut, NonNull},
+    sync::atomic::{AtomicUsize, Ordering::*},
 };
+#[cfg(feature = "snapshot")]
+use core::sync::atomic::AtomicBool;
+use incin::{Incinerator, Pause};
+use owned_alloc::{AllocFailed, OwnedAlloc};
+use primitive::{AtomicPtr, PtrMut};
+use ptr::non_zero_null;
 
-#[repr(align(/* at least */ 2))]
+// The entries of a bucket form a linked list. The first one is kept right in
+// the bucket, so a lookup whose key is alone in its bucket takes no more than
+// a hop from the bucket to the entry. Only the entries after it, i.e. those
+// brought by collisions, get intermediate nodes of their own. When the first
+// entry is removed while other entries follow it, a "sentinel" "root" entry
+// takes its place, since the entries following it cannot be moved safely
+// under concurrent access. Exclusive access moves them forward again.
+//
+// With snapshots, a bucket may be held by many tables, see `Snapshots`. A
+// bucket is copied before it is changed then, and the copy freezes each one of
+// its intermediate nodes, setting the lower bit of its pointer, so the entries
+// it copies are never replaced afterwards.
+//
+// Lookups clean removed entries up on the way, unless snapshots are enabled:
+// a reader may then walk a frozen bucket held by a snapshot, which must never
+// change, so only writers clean up, having found the bucket owned.
+pub const CLEANS: bool = !cfg!(feature = "snapshot");
+
+#[cfg_attr(not(feature = "snapshot"), repr(align(/* at least */ 2)))]
+// Tables mark their nodes holding buckets with two more bits, see `COPIED`.
+#[cfg_attr(feature = "snapshot", repr(align(/* at least */ 8)))]
 pub struct Bucket<K, V> {
-    hash: u64,
+    hash: HashCode,
     list: List<K, V>,
+    // Where the nodes of this bucket come from, and where they go back.
+    slabs: NonNull<Slabs<K, V>>,
+    // How many tables hold this bucket, see `Snapshots`.
+    #[cfg(feature = "snapshot")]
+    refs: AtomicUsize,
+    // Set when this bucket was copied for a snapshot, so its pairs belong to
+    // the copy and are not dropped along with it.
+    #[cfg(feature = "snapshot")]
+    shell: AtomicBool,
 }
 
 impl<K, V> Bucket<K, V> {
-    pub fn new(hash: u64, pair: NonNull<(K, V)>) -> Self {
-        // We create a bucket with a single entry.
+    // Takes a bucket holding a single entry with the given pair from the
+    // slabs. If allocation fails, every node taken is given back, and the
+    // pair still belongs to the caller. Unsafe because the incinerator of the
+    // map needs to be paused, or the slabs exclusively accessed.
+    pub unsafe fn try_alloc(
+        hash: HashCode,
+        pair: NonNull<(K, V)>,
+        shared: bool,
+        slabs: &Slabs<K, V>,
+    ) -> Result<NonNull<Self>, AllocFailed> {
+        let bucket = Self::new(hash, pair, shared, slabs)?;
+        slabs.buckets.try_alloc(bucket).map_err(|(mut bucket, err)| {
+            // The pair is not ours to drop along with the bucket.
+            bucket.take_first();
+            err
+        })
+    }
 
-        // First we create an entry for the pair whose next node is null.
-        let entry = Entry { pair, next: null_mut() };
+    // Whether snapshots may read the pair is told by `shared`, see `Entry`.
+    // Unsafe for the same reasons as `try_alloc`.
+    unsafe fn new(
+        hash: HashCode,
+        pair: NonNull<(K, V)>,
+        shared: bool,
+        slabs: &Slabs<K, V>,
+    ) -> Result<Self, AllocFailed> {
+        // We create a bucket with a single entry, kept right in the bucket,
+        // whose next node is null.
+        let version = slabs.next_version();
+        let entry = Entry::new(pair, null_mut(), version, shared);
+        let list = List::new(entry, slabs)?;
+        Ok(Self {
+            hash,
+            list,
+            slabs: NonNull::from(slabs),
+            #[cfg(feature = "snapshot")]
+            refs: AtomicUsize::new(1),
+            #[cfg(feature = "snapshot")]
+            shell: AtomicBool::new(false),
+        })
+    }
 
-        // Then we create an intermediate node to keep the entry.
-        let list = List::new(entry);
-        let list_ptr = OwnedAlloc::new(list).into_raw().as_ptr();
+    // Gives the bucket back to its slab. It is dropped, along with its nodes,
+    // once it is recycled. Unsafe because the bucket must come from the slabs
+    // it knows, and it must not be read afterwards, except under pauses
+    // started before it was detached.
+    pub unsafe fn retire(bucket: NonNull<Self>) {
+        bucket.as_ref().slabs().buckets.retire(bucket);
+    }
 
-        Self {
-            hash,
-            // Then we make the "sentinel" "root" entry (never deleted from the
-            // bucket).
-            list: List::new(Entry::root(list_ptr)),
-        }
+    // Moves the bucket out of its slab. Unsafe for the same reasons as
+    // `retire`.
+    pub unsafe fn take(bucket: NonNull<Self>) -> Self {
+        bucket.as_ref().slabs().buckets.take(bucket)
     }
 
-    pub fn hash(&self) -> u64 {
+    pub fn hash(&self) -> HashCode {
         self.hash
     }
 
+    // Whether the pairs of this bucket belong to a copy of it.
+    #[cfg(feature = "snapshot")]
+    fn is_shell(&self) -> bool {
+        self.shell.load(Relaxed)
+    }
+
+    #[cfg(not(feature = "snapshot"))]
+    fn is_shell(&self) -> bool {
+        false
+    }
+
+    // Whether this bucket is held by a single table, and was never copied,
+    // so it can be changed in place. Unsafe because the incinerator of the map
+    // must be paused.
+    #[cfg(feature = "snapshot")]
+    pub unsafe fn is_owned(&self) -> bool {
+        self.refs.load(Acquire) == 1
+            && self.list.atomic.load(Acquire) as usize & 1 == 0
+    }
+
+    // Without snapshots, a bucket is only held by its table.
+    #[cfg(not(feature = "snapshot"))]
+    #[inline(always)]
+    pub unsafe fn is_owned(&self) -> bool {
+        true
+    }
+
+    // Counts one more table holding this bucket, unless no table holds it
+    // anymore, in which case it is being retired and false is returned.
+    #[cfg(feature = "snapshot")]
+    pub fn share(&self) -> bool {
+        let mut refs = self.refs.load(Relaxed);
+        loop {
+            if refs == 0 {
+                break false;
+            }
+            // Nothing is published by a new holder.
+            match self.refs.compare_exchange_weak(
+                refs,
+                refs + 1,
+                Relaxed,
+                Relaxed,
+            ) {
+                Ok(_) => break true,
+                Err(new) => refs = new,
+            }
+        }
+    }
+
+    #[cfg(not(feature = "snapshot"))]
+    #[inline(always)]
+    pub fn share(&self) -> bool {
+        true
+    }
+
+    // Counts one table less holding the given bucket, giving it back to its
+    // slab as a shell once no table holds it: its pairs belong to a copy of
+    // it, since the map detaches a bucket shared with snapshots by copying it.
+    // Unsafe for the same reasons as `retire`.
+    #[cfg(feature = "snapshot")]
+    pub unsafe fn release(bucket: NonNull<Self>) {
+        // `AcqRel` makes every use of the bucket by its holders happen before
+        // it is retired.
+        if bucket.as_ref().refs.fetch_sub(1, AcqRel) == 1 {
+            Self::retire_shell(bucket);
+        }
+    }
+
+    #[cfg(not(feature = "snapshot"))]
+    #[inline(always)]
+    pub unsafe fn release(bucket: NonNull<Self>) {
+        Self::retire(bucket);
+    }
+
+    // Takes from the slabs a copy of this bucket, holding the same pairs, with
+    // the same versions, and held by a single table. Returns `None` if the
+    // bucket is empty. Each intermediate node of this bucket is frozen before
+    // its entry is copied, so operations which found this bucket earlier
+    // cannot change it anymore, and start again from the top. The pairs still
+    // belong to this bucket until the copy takes its place, and snapshots may
+    // read them afterwards, see `Entry`. If allocation fails, every node taken
+    // is given back, and this bucket is left frozen. Unsafe because the
+    // incinerator of the map must be paused.
+    #[cfg(feature = "snapshot")]
+    pub unsafe fn try_copy(
+        &self,
+    ) -> Result<Option<NonNull<Self>>, AllocFailed> {
+        let slabs = self.slabs();
+        let mut entries = Vec::new();
+        let mut list = &self.list;
+        loop {
+            // A frozen entry is never replaced, nor is the node after it
+            // unlinked, so it can be followed.
+            let entry = list.freeze().as_ref();
+            let next = entry.next as usize;
+            if !entry.is_root() && next & 1 == 0 {
+                // Pairs kept inline are copied along with their entries, so
+                // snapshots never read them from the copy.
+                let shared = !slabs.inline;
+                let copy =
+                    Entry::new(entry.pair, null_mut(), entry.version, shared);
+                entries.push(copy);
+            }
+            match NonNull::new((next & !1) as *mut List<K, V>) {
+                Some(next) => list = &*next.as_ptr(),
+                None => break,
+            }
+        }
+        let (first, rest) = match entries.split_first() {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+
+        let bucket = Self {
+            hash: self.hash,
+            list: List::new(*first, slabs)?,
+            slabs: self.slabs,
+            refs: AtomicUsize::new(1),
+            shell: AtomicBool::new(true),
+        };
+        let bucket = slabs.buckets.try_alloc(bucket).map_err(|(_, err)| err)?;
+        let mut last = bucket.as_ref().list.load();
+        for entry in rest {
+            let list = match List::try_alloc(*entry, slabs) {
+                Ok(list) => list,
+                Err(err) => {
+                    Self::retire(bucket);
+                    return Err(err);
+                },
+            };
+            // Safe because the copy is not shared yet.
+            (*last.as_ptr()).next = list.as_ptr();
+            last = list.as_ref().load();
+        }
+        // The copy is complete, so it owns the pairs once shared.
+        bucket.as_ref().shell.store(false, Relaxed);
+        Ok(Some(bucket))
+    }
+
+    // Gives back to its slab a bucket whose pairs belong to a copy of it, or
+    // a copy which did not take the place of the bucket. Unsafe for the same
+    // reasons as `retire`.
+    #[cfg(feature = "snapshot")]
+    pub unsafe fn retire_shell(bucket: NonNull<Self>) {
+        bucket.as_ref().shell.store(true, Relaxed);
+        Self::retire(bucket);
+    }
+
+    fn slabs<'slabs>(&self) -> &'slabs Slabs<K, V> {
+        // Safe because the slabs outlive every node of the map.
+        unsafe { &*self.slabs.as_ptr() }
+    }
+
+    // The memory taken by a bucket and its first entry, not counting its pair,
+    // unless it is kept inline.
+    pub fn byte_size(slabs: &Slabs<K, V>) -> usize {
+        Slab::<Self>::slot_size() + slabs.entry_size()
+    }
+
+    // The memory taken by each entry of a bucket after the first one, not
+    // counting its pair, unless it is kept inline.
+    pub fn entry_byte_size(slabs: &Slabs<K, V>) -> usize {
+        Slab::<List<K, V>>::slot_size() + slabs.entry_size()
+    }
+
+    // The first entry of the bucket. Unsafe because the incinerator of the map
+    // must be paused.
+    pub unsafe fn head(&self) -> NonNull<Entry<K, V>> {
+        self.list.load()
+    }
+
     // Unsafe because it might need incinerator's pause.
     pub unsafe fn is_empty(&self) -> bool {
-        (*self.list.atomic.load(Acquire)).is_empty()
+        self.list.load().as_ref().is_empty()
     }
 
-    pub fn take_first(&mut self) -> Option<OwnedAlloc<Entry<K, V>>> {
-        // First let's load the root entry.
-        //
+    // Takes the first entry out of the bucket, leaving a root entry in its
+    // place. The pair of the entry is not dropped with the bucket anymore.
+    pub fn take_first(&mut self) -> Option<Entry<K, V>> {
+        let head = self.head_mut();
         // Safe because of exclusive reference. We are the only ones accessing
-        // it. We also *do not* store null pointers in list's AtomicPtr!
-        let entry = unsafe { &mut *self.list.atomic.load(Relaxed) };
-        let prev = entry.next;
-        // Let's set the root entry's next field to null.
-        entry.next = null_mut();
-
-        NonNull::new(prev).map(|nnptr| {
-            // It's safe because we only store properly allocated nodes. Also,
-            // we have removed the node.
-            let list = unsafe { OwnedAlloc::from_raw(nnptr) };
-            let ptr = list.atomic.load(Relaxed);
-            // Safe to by-pass null check because we never store null pointers
-            // in list's AtomicPtr! Safe to deallocate because we removed the
-            // node.
-            unsafe { OwnedAlloc::from_raw(NonNull::new_unchecked(ptr)) }
-        })
+        // it.
+        let head = unsafe { &mut *head.as_ptr() };
+        if head.is_root() {
+            None
+        } else {
+            let root = Entry::root(head.next);
+            Some(mem::replace(head, root))
+        }
     }
 
-    // Unsafe because it might need incinerator's pause and there is no
-    // guarantee the passed pause by this thread comes from the same incinerator
-    // from which other threads pass pauses.
-    pub unsafe fn get<'map, Q>(
-        &self,
-        key: &Q,
-        pause: Pause<'map, Garbage<K, V>>,
-    ) -> GetRes<'map, K, V>
+    // Unsafe because the bucket must be kept alive while it is read, e.g. by
+    // a pause of the incinerator of the map.
+    pub unsafe fn get<'map, O, Q>(&'map self, key: &Q) -> GetRes<'map, K, V>
     where
-        Q: ?Sized + Ord,
+        O: BucketOrder<Q>,
+        Q: ?Sized,
         K: Borrow<Q>,
     {
-        match self.find(key, &pause) {
+        match self.find::<O, Q>(key, CLEANS) {
             // The table must delete the whole bucket.
-            FindRes::Delete => GetRes::Delete(pause),
+            FindRes::Delete => GetRes::Delete,
+
+            FindRes::NoMemory(err) => err.handle(),
+
+            // Only found by clean-ups, which lookups do only when nothing is
+            // ever frozen.
+            FindRes::Frozen => unreachable!(),
 
             // We found the entry.
-            FindRes::Exact { curr, .. } => GetRes::Found(ReadGuard::new(
+            FindRes::Exact { curr, .. } => GetRes::Found(
                 &*curr.as_ref().pair.as_ptr(),
-                pause,
-            )),
+                curr.as_ref().version,
+            ),
 
             // We found no entry.
-            FindRes::After { .. } => GetRes::NotFound,
+            FindRes::Before { .. } | FindRes::After { .. } => GetRes::NotFound,
         }
     }
 
+    // If allocation fails, the inserter is given back and the bucket is left
+    // unchanged.
+    //
     // Unsafe because it might need incinerator's pause and there is no
     // guarantee the passed pause by this thread comes from the same incinerator
     // from which other threads pass pauses. Also because the inserter must be
     // implemented correctly and must yield valid pointers.
-    pub unsafe fn insert<I>(
+    pub unsafe fn insert<O, I>(
         &self,
         mut inserter: I,
         pause: &Pause<Garbage<K, V>>,
         incin: &Arc<Incinerator<Garbage<K, V>>>,
+        probe: &mut Probe,
     ) -> InsertRes<I, K, V>
     where
+        O: BucketOrder<K>,
         I: Inserter<K, V>,
-        K: Ord,
     {
+        let slabs = self.slabs();
+        let mut backoff = Backoff::new();
         loop {
-            match self.find(inserter.key(), pause) {
+            match self.find::<O, K>(inserter.key(), true) {
                 // The table must delete the whole bucket.
                 FindRes::Delete => break InsertRes::Delete(inserter),
 
+                FindRes::NoMemory(err) => {
+                    break InsertRes::NoMemory(inserter, err);
+                },
+
+                // The bucket was copied, and the copy must be found again.
+                FindRes::Frozen => break InsertRes::Frozen(inserter),
+
                 // We found an entry with equal key.
                 FindRes::Exact { curr_list, curr } => {
                     // Let's test the found conditions. Let's test if the
                     // inserter "approves" it.
+                    inserter.found_version(curr.as_ref().version);
                     inserter.input(Some(curr.as_ref().pair.as_ref()));
                     // Then we try to extract the pair pointer.
                     let pair = match inserter.pointer() {
@@ -132,21 +392,31 @@ impl<K, V> Bucket<K, V> {
                         None => break InsertRes::Failed(inserter),
                     };
                     // Create a new entry with a new pair but same next field.
-                    let new_entry = Entry { pair, next: curr.as_ref().next };
-                    let new_ptr = OwnedAlloc::new(new_entry).into_raw();
+                    let new_entry = Entry::new(
+                        pair,
+                        curr.as_ref().next,
+                        slabs.next_version(),
+                        inserter.shared(),
+                    );
+                    let new_ptr = match slabs.try_alloc_entry(new_entry) {
+                        Ok(nnptr) => nnptr,
+                        Err(err) => break InsertRes::NoMemory(inserter, err),
+                    };
 
-                    // We extract the old pair.
-                    let old_pair = curr.as_ref().pair;
+                    // We keep the old entry, which is retired on success.
+                    let old = *curr.as_ref();
                     // And now we try to update the place where the old entry
                     // was.
-                    if curr_list.try_update(curr, new_ptr, pause) {
-                        // Remember to prevent the inserter from deallocating.
-                        inserter.take_pointer();
+                    if curr_list.try_update(curr, new_ptr, slabs) {
+                        // The pair is ours now.
+                        slabs.publish(inserter, new_ptr);
                         // Create a removed entry from the old pair.
-                        let pair = OwnedAlloc::from_raw(old_pair);
-                        let removed = Removed::new(pair, incin);
+                        let removed =
+                            slabs.removed(&old, incin, self.hash, pause);
                         break InsertRes::Updated(removed);
                     }
+                    probe.retry("bucket");
+                    backoff.snooze();
                 },
 
                 // We found a spot to insert at.
@@ -163,28 +433,95 @@ impl<K, V> Bucket<K, V> {
                     };
 
                     // Create a new entry with the next field.
-                    let curr_entry = Entry { pair, next: prev.as_ref().next };
+                    let curr_entry = Entry::new(
+                        pair,
+                        prev.as_ref().next,
+                        slabs.next_version(),
+                        inserter.shared(),
+                    );
                     // Make an intermediate node for it.
-                    let curr_list = List::new(curr_entry);
-                    let curr_nnptr = OwnedAlloc::new(curr_list).into_raw();
+                    let curr_nnptr = match List::try_alloc(curr_entry, slabs) {
+                        Ok(nnptr) => nnptr,
+                        Err(err) => break InsertRes::NoMemory(inserter, err),
+                    };
+                    let curr_ptr = curr_nnptr.as_ref().load();
 
                     // Create a new predecessor for our freshly created entry.
                     let new_prev = Entry {
-                        pair: prev.as_ref().pair,
                         next: curr_nnptr.as_ptr(),
+                        ..*prev.as_ref()
+                    };
+                    let new_ptr = match slabs.try_alloc_entry(new_prev) {
+                        Ok(nnptr) => nnptr,
+                        Err(err) => {
+                            slabs.retire_entry(curr_ptr);
+                            slabs.lists.retire(curr_nnptr);
+                            break InsertRes::NoMemory(inserter, err);
+                        },
                     };
-                    let new_ptr = OwnedAlloc::new(new_prev).into_raw();
 
                     // And try to update.
-                    if prev_list.try_update(prev, new_ptr, pause) {
-                        // Remember to prevent the inserter from deallocating.
-                        inserter.take_pointer();
+                    if prev_list.try_update(prev, new_ptr, slabs) {
+                        // The pair is ours now.
+                        slabs.publish(inserter, curr_ptr);
+                        break InsertRes::Created;
+                    }
+
+                    // Clean-up in case of failure. These nodes were never
+                    // shared, but they are retired anyway, since only
+                    // recycling may free slots.
+                    slabs.retire_entry(curr_ptr);
+                    slabs.lists.retire(curr_nnptr);
+                    probe.retry("bucket");
+                    backoff.snooze();
+                },
+
+                // The key fits before the first entry, kept in the bucket.
+                FindRes::Before { head } => {
+                    inserter.input(None);
+                    let pair = match inserter.pointer() {
+                        Some(nnptr) => nnptr,
+                        None => break InsertRes::Failed(inserter),
+                    };
+
+                    // The first entry moves to an intermediate node of its
+                    // own, which comes after the new first entry.
+                    let head_list = match List::try_alloc_holding(head, slabs)
+                    {
+                        Ok(nnptr) => nnptr,
+                        Err(err) => break InsertRes::NoMemory(inserter, err),
+                    };
+                    let new_head = Entry::new(
+                        pair,
+                        head_list.as_ptr(),
+                        slabs.next_version(),
+                        inserter.shared(),
+                    );
+                    let new_ptr = match slabs.try_alloc_entry(new_head) {
+                        Ok(nnptr) => nnptr,
+                        Err(err) => {
+                            slabs.lists.retire(head_list);
+                            break InsertRes::NoMemory(inserter, err);
+                        },
+                    };
+
+                    // Unlike in `List::try_update`, the replaced entry is not
+                    // retired, since it is still linked, from its new node.
+                    let res = self.list.atomic.compare_exchange(
+                        head.as_ptr(),
+                        new_ptr.as_ptr(),
+                        Release,
+                        Relaxed,
+                    );
+                    if res.is_ok() {
+                        slabs.publish(inserter, new_ptr);
                         break InsertRes::Created;
                     }
 
-                    // Clean-up in case of failure.
-                    OwnedAlloc::from_raw(curr_nnptr.as_ref().load());
-                    OwnedAlloc::from_raw(curr_nnptr);
+                    slabs.retire_entry(new_ptr);
+                    slabs.lists.retire(head_list);
+                    probe.retry("bucket");
+                    backoff.snooze();
                 },
             }
         }
@@ -193,66 +530,134 @@ impl<K, V> Bucket<K, V> {
     // Unsafe because it might need incinerator's pause and there is no
     // guarantee the passed pause by this thread comes from the same incinerator
     // from which other threads pass pauses.
-    pub unsafe fn remove<Q, F>(
+    pub unsafe fn remove<O, Q, F>(
         &self,
         key: &Q,
         mut interactive: F,
-        pause: &Pause<Garbage<K, V>>,
-        incin: &Arc<Incinerator<Garbage<K, V>>>,
+        probe: &mut Probe,
     ) -> RemoveRes<K, V>
     where
-        Q: ?Sized + Ord,
+        O: BucketOrder<Q>,
+        Q: ?Sized,
         K: Borrow<Q>,
         F: FnMut(&(K, V)) -> bool,
     {
+        let slabs = self.slabs();
+        let mut backoff = Backoff::new();
         loop {
-            match self.find(key, pause) {
+            match self.find::<O, Q>(key, true) {
                 // The table must delete the whole bucket.
-                FindRes::Delete => break RemoveRes { pair: None, delete: true },
+                FindRes::Delete => break RemoveRes::none(true, false),
+
+                FindRes::NoMemory(err) => err.handle(),
+
+                // The bucket was copied, and the copy must be found again.
+                FindRes::Frozen => break RemoveRes::none(false, true),
 
                 // We found an entry whose key matches the input.
                 FindRes::Exact { curr_list, curr } => {
                     // Let's test if the met conditions are ok!
                     if !interactive(curr.as_ref().pair.as_ref()) {
-                        break RemoveRes { pair: None, delete: false };
+                        break RemoveRes::none(false, false);
                     }
 
-                    // Let's first remove it logically. Let's create an entry
-                    // with same data... but marked!
-                    let pair_ptr = curr.as_ref().pair;
-                    let new_entry = Entry {
-                        pair: pair_ptr,
-                        next: (curr.as_ref().next as usize | 1) as *mut _,
-                    };
-                    let new_ptr = OwnedAlloc::new(new_entry).into_raw();
-
-                    // Then we try to update where it was before.
-                    if curr_list.try_update(curr, new_ptr, pause) {
-                        let pair = OwnedAlloc::from_raw(pair_ptr);
+                    // Let's first remove it logically.
+                    let old = *curr.as_ref();
+                    if curr_list.try_mark(curr, slabs) {
                         break RemoveRes {
-                            pair: Some(Removed::new(pair, incin)),
+                            removed: Some(slabs.removed_pair(&old)),
                             // Just some clean up.
-                            delete: self.try_clear_first(pause),
+                            delete: self.try_clear_first(),
+                            restart: false,
                         };
                     }
+                    probe.retry("bucket");
+                    backoff.snooze();
                 },
 
                 // This means the entry was not found.
-                FindRes::After { .. } => {
-                    break RemoveRes { pair: None, delete: false };
+                FindRes::Before { .. } | FindRes::After { .. } => {
+                    break RemoveRes::none(false, false);
                 },
             }
         }
     }
 
-    // Unsafe because it might need incinerator's pause and there is no
-    // guarantee the passed pause by this thread comes from the same incinerator
-    // from which other threads pass pauses.
-    pub unsafe fn collect<'map>(
-        &'map self,
-        pause: &Pause<'map, Garbage<K, V>>,
-        out: &mut Vec<ReadGuard<'map, K, V>>,
-    ) {
+    // Removes the first entry of the bucket, if any. Fails if the bucket was
+    // copied, since the copy must be found again. Unsafe because it might need
+    // incinerator's pause and there is no guarantee the passed pause by this
+    // thread comes from the same incinerator from which other threads pass
+    // pauses.
+    pub unsafe fn pop_first(
+        &self,
+        pause: &Pause<Garbage<K, V>>,
+        incin: &Arc<Incinerator<Garbage<K, V>>>,
+    ) -> Result<Option<Removed<K, V>>, Restart> {
+        let slabs = self.slabs();
+        loop {
+            let (list, entry) = match self.load_head(true) {
+                HeadRes::NoMemory(err) => err.handle(),
+                HeadRes::Frozen => break Err(Restart),
+                HeadRes::Inline(head) => (&self.list, head),
+                HeadRes::Root(root) => {
+                    match self.list.load_next(root, slabs, true) {
+                        LoadNextRes::Failed | LoadNextRes::Cleared { .. } => {
+                            continue
+                        },
+                        LoadNextRes::NoMemory(err) => err.handle(),
+                        LoadNextRes::End => break Ok(None),
+                        LoadNextRes::Ok { list, entry } => {
+                            (&*list.as_ptr(), entry)
+                        },
+                    }
+                },
+            };
+            let old = *entry.as_ref();
+            if list.try_mark(entry, slabs) {
+                break Ok(Some(slabs.removed(&old, incin, self.hash, pause)));
+            }
+        }
+    }
+
+    // The salted hash shared by the keys of this bucket, taken from its first
+    // live key, if the map has flood protection. An empty bucket has none, but
+    // it never holds a key again either. Unsafe because the bucket must be
+    // kept alive while it is read, e.g. by a pause of the incinerator of the
+    // map, or exclusively accessed.
+    pub unsafe fn salted(&self) -> Option<u64> {
+        let salt = self.slabs().salt.as_ref()?;
+        let (key, _) = self.first()?;
+        Some(salt.hash_stored(key))
+    }
+
+    // Returns the first live pair of the bucket, if any. Unsafe for the same
+    // reasons as `salted`.
+    pub unsafe fn first(&self) -> Option<&(K, V)> {
+        loop {
+            let root = match self.load_head(CLEANS) {
+                HeadRes::NoMemory(err) => err.handle(),
+                // Only found by clean-ups, just like in `get`.
+                HeadRes::Frozen => unreachable!(),
+                HeadRes::Inline(head) => {
+                    break Some(head.as_ref().pair.as_ref())
+                },
+                HeadRes::Root(root) => root,
+            };
+            match self.list.load_next(root, self.slabs(), CLEANS) {
+                LoadNextRes::Failed | LoadNextRes::Cleared { .. } => (),
+                LoadNextRes::NoMemory(err) => err.handle(),
+                LoadNextRes::End => break None,
+                LoadNextRes::Ok { entry, .. } => {
+                    break Some(entry.as_ref().pair.as_ref())
+                },
+            }
+        }
+    }
+
+    // Pushes every live pair of the bucket into the given vector, in order.
+    // Unsafe because the bucket must be kept alive while the pairs are used,
+    // e.g. by a pause of the incinerator of the map.
+    pub unsafe fn collect<'map>(&'map self, out: &mut Vec<&'map (K, V)>) {
         // The length to which we will truncate the vector at each retry.
         let trunc = out.len();
 
@@ -260,18 +665,25 @@ impl<K, V> Bucket<K, V> {
             // Clean-up previous try.
             out.truncate(trunc);
             let mut prev_list = &self.list;
-            let mut prev = prev_list.load();
+            let mut prev = match self.load_head(CLEANS) {
+                HeadRes::NoMemory(err) => err.handle(),
+                // Only found by clean-ups, just like in `get`.
+                HeadRes::Frozen => unreachable!(),
+                HeadRes::Root(root) => root,
+                HeadRes::Inline(head) => {
+                    out.push(&*head.as_ref().pair.as_ptr());
+                    head
+                },
+            };
 
             loop {
-                match prev_list.load_next(prev, pause) {
+                match prev_list.load_next(prev, self.slabs(), CLEANS) {
                     LoadNextRes::Failed => continue 'retry,
+                    LoadNextRes::NoMemory(err) => err.handle(),
                     LoadNextRes::End => break 'retry,
                     LoadNextRes::Cleared { new_prev } => prev = new_prev,
                     LoadNextRes::Ok { list, entry } => {
-                        out.push(ReadGuard::new(
-                            &*entry.as_ref().pair.as_ptr(),
-                            pause.clone(),
-                        ));
+                        out.push(&*entry.as_ref().pair.as_ptr());
                         prev_list = &*list.as_ptr();
                         prev = entry;
                     },
@@ -280,15 +692,64 @@ impl<K, V> Bucket<K, V> {
         }
     }
 
-    // Returns whether the bucket is empty. Unsafe because it might need
-    // incinerator's pause and there is no guarantee the passed pause by
-    // this thread comes from the same incinerator from which other threads
-    // pass pauses.
-    unsafe fn try_clear_first(&self, pause: &Pause<Garbage<K, V>>) -> bool {
-        let mut prev = self.list.load();
+    // Checks the invariants of this bucket, panicking if one is broken: only
+    // the first entry may be a root entry, and the keys of the entries not
+    // removed are distinct, and strictly increasing if the bucket is ordered.
+    // The key of each one of these entries is passed to the closure. Nothing
+    // is cleaned up on the way, so the bucket is read just as it is. Unsafe
+    // because the incinerator needs to be paused.
+    #[cfg(any(test, feature = "debug-validate"))]
+    pub unsafe fn validate<O, F>(&self, mut on_key: F)
+    where
+        O: BucketOrder<K>,
+        F: FnMut(&K),
+    {
+        let mut entry = self.list.load();
+        let mut first = true;
+        let mut prev_keys = Vec::<&K>::new();
+
+        loop {
+            let next = entry.as_ref().next;
+
+            if entry.as_ref().is_root() {
+                assert!(first, "root entry not first in its bucket");
+                assert!(next as usize & 1 == 0, "root entry removed");
+            } else if next as usize & 1 == 0 {
+                // Marked entries were removed, and are just waiting to be
+                // cleaned up.
+                let (key, _) = &*entry.as_ref().pair.as_ptr();
+                // A search for any previous key would stop before this one.
+                for prev_key in &prev_keys {
+                    assert!(
+                        O::compare(key, prev_key) == Ordering::Greater,
+                        "bucket keys out of order or repeated"
+                    );
+                }
+                on_key(key);
+                prev_keys.push(key);
+            }
+
+            first = false;
+            match NonNull::new((next as usize & !1) as *mut List<K, V>) {
+                Some(list) => entry = list.as_ref().load(),
+                None => break,
+            }
+        }
+    }
+
+    // Returns whether the bucket is empty. A frozen bucket is never taken as
+    // empty, since its copy must be detached instead. Unsafe because the
+    // incinerator of the map must be paused.
+    pub unsafe fn try_clear_first(&self) -> bool {
+        let mut prev = match self.load_head(true) {
+            HeadRes::NoMemory(err) => err.handle(),
+            HeadRes::Frozen | HeadRes::Inline(_) => return false,
+            HeadRes::Root(root) => root,
+        };
         loop {
-            match self.list.load_next(prev, pause) {
+            match self.list.load_next(prev, self.slabs(), true) {
                 LoadNextRes::Failed => break false,
+                LoadNextRes::NoMemory(err) => err.handle(),
                 LoadNextRes::End => break true,
                 LoadNextRes::Cleared { new_prev } => prev = new_prev,
                 LoadNextRes::Ok { .. } => break false,
@@ -296,26 +757,327 @@ impl<K, V> Bucket<K, V> {
         }
     }
 
-    // Unsafe because it might need incinerator's pause and there is no
-    // guarantee the passed pause by this thread comes from the same incinerator
-    // from which other threads pass pauses.
-    unsafe fn find<'map, Q>(
+    // Just like `get`, but with exclusive access to the bucket, so no pause is
+    // needed.
+    pub fn get_mut<O, Q>(&mut self, key: &Q) -> Option<&mut (K, V)>
+    where
+        O: BucketOrder<Q>,
+        Q: ?Sized,
+        K: Borrow<Q>,
+    {
+        match self.find_mut::<O, Q>(key) {
+            // Safe because we have exclusive access to the bucket, and the
+            // found entry was not removed.
+            FindMutRes::Head { entry } | FindMutRes::Exact { entry, .. } => {
+                Some(unsafe { &mut *entry.as_ref().pair.as_ptr() })
+            },
+
+            FindMutRes::Before | FindMutRes::After { .. } => None,
+        }
+    }
+
+    // Just like `insert`, but with exclusive access to the bucket. An entry
+    // with an equal key has its pair replaced in place, and the old pair is
+    // given back.
+    pub fn insert_mut<O>(&mut self, pair: (K, V)) -> Option<(K, V)>
+    where
+        O: BucketOrder<K>,
+    {
+        match self.find_mut::<O, K>(&pair.0) {
+            // Safe because we have exclusive access to the bucket, and the
+            // found entry was not removed.
+            FindMutRes::Head { entry } | FindMutRes::Exact { entry, .. } => {
+                let entry = unsafe { &mut *entry.as_ptr() };
+                entry.version = self.slabs().next_version();
+                let stored = unsafe { &mut *entry.pair.as_ptr() };
+                Some(mem::replace(stored, pair))
+            },
+
+            FindMutRes::Before => {
+                let slabs = self.slabs();
+                let pair = OwnedAlloc::new(pair).into_raw();
+                // Safe because we have exclusive access to the bucket, so the
+                // first entry can be changed in place, and nodes can be taken
+                // from the slabs.
+                unsafe {
+                    let head = self.list.load_mut();
+                    let next = if head.as_ref().is_root() {
+                        // Only an empty bucket starts with a root entry here.
+                        null_mut()
+                    } else {
+                        // The first entry moves to a node of its own.
+                        List::try_alloc(*head.as_ref(), slabs)
+                            .unwrap_or_else(|err| err.handle())
+                            .as_ptr()
+                    };
+                    let version = slabs.next_version();
+                    let entry = Entry::new(pair, next, version, false);
+                    slabs.write_entry(head, entry);
+                    slabs.release(pair);
+                }
+                None
+            },
+
+            FindMutRes::After { prev } => {
+                let slabs = self.slabs();
+                let pair = OwnedAlloc::new(pair).into_raw();
+                // Safe because we have exclusive access to the bucket, so the
+                // previous entry can be changed in place, and nodes can be
+                // taken from the slabs.
+                unsafe {
+                    let prev = &mut *prev.as_ptr();
+                    let version = slabs.next_version();
+                    let entry = Entry::new(pair, prev.next, version, false);
+                    let list = List::try_alloc(entry, slabs)
+                        .unwrap_or_else(|err| err.handle());
+                    prev.next = list.as_ptr();
+                    slabs.release(pair);
+                }
+                None
+            },
+        }
+    }
+
+    // Just like `remove`, but with exclusive access to the bucket, so the
+    // entry is unlinked and deallocated right away, and its pair is moved
+    // out.
+    pub fn remove_mut<O, Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        O: BucketOrder<Q>,
+        Q: ?Sized,
+        K: Borrow<Q>,
+    {
+        match self.find_mut::<O, Q>(key) {
+            FindMutRes::Exact { prev, list, entry } => unsafe {
+                // Safe because we have exclusive access to the bucket and we
+                // only store properly allocated nodes. The entry is unlinked
+                // before being given back.
+                let slabs = self.slabs();
+                let pair = slabs.read_pair(entry.as_ref().pair);
+                (*prev.as_ptr()).next = entry.as_ref().next;
+                slabs.lists.retire(list);
+                slabs.retire_entry(entry);
+                Some(pair)
+            },
+
+            FindMutRes::Head { entry } => unsafe {
+                // Safe because we have exclusive access to the bucket. Marking
+                // the entry lets `head_mut` clean it up like any entry removed
+                // through a shared reference. The pair is moved out first,
+                // since an inline one is overwritten by the next entry.
+                let head = &mut *entry.as_ptr();
+                let pair = self.slabs().read_pair(head.pair);
+                head.next = (head.next as usize | 1) as *mut _;
+                self.head_mut();
+                Some(pair)
+            },
+
+            FindMutRes::Before | FindMutRes::After { .. } => None,
+        }
+    }
+
+    // Tests whether the bucket has no entry left, with exclusive access to the
+    // bucket. Entries removed through a shared reference found at the start of
+    // the bucket are cleaned up.
+    pub fn is_empty_mut(&mut self) -> bool {
+        // Safe because we never store null pointers in list's AtomicPtr.
+        unsafe { self.head_mut().as_ref().is_root() }
+    }
+
+    // Returns the first entry of the bucket, with exclusive access. Entries
+    // removed through a shared reference found at the start of the bucket are
+    // cleaned up, and a root entry is replaced by the entry following it, so
+    // the first entry is kept in the bucket again. Hence, the returned entry
+    // is a root entry only if the bucket is empty.
+    fn head_mut(&mut self) -> NonNull<Entry<K, V>> {
+        let slabs = self.slabs();
+        // Safe because we never store null pointers in list's AtomicPtr.
+        let head = unsafe { self.list.load_mut() };
+
+        // Safe because we have exclusive access to the bucket and we only
+        // store properly allocated nodes. The entry following the first one is
+        // copied into it before being given back, along with its intermediate
+        // node.
+        unsafe {
+            loop {
+                let entry = head.as_ref();
+                let next = entry.next as usize;
+                if !entry.is_root() && next & 1 == 0 {
+                    break;
+                }
+                let list = match NonNull::new((next & !1) as *mut List<K, V>) {
+                    Some(list) => list,
+                    None => {
+                        *head.as_ptr() = Entry::root(null_mut());
+                        break;
+                    },
+                };
+                let following = list.as_ref().load();
+                slabs.write_entry(head, *following.as_ref());
+                slabs.lists.retire(list);
+                slabs.retire_entry(following);
+            }
+        }
+
+        head
+    }
+
+    // Walks the bucket with exclusive access, looking for the given key, just
+    // like `find`. Entries removed through a shared reference are unlinked and
+    // deallocated on the way, instead of being replaced.
+    fn find_mut<O, Q>(&mut self, key: &Q) -> FindMutRes<K, V>
+    where
+        O: BucketOrder<Q>,
+        Q: ?Sized,
+        K: Borrow<Q>,
+    {
+        let mut prev = self.head_mut();
+
+        // Safe because we have exclusive access to the bucket and we only
+        // store properly allocated nodes.
+        unsafe {
+            if prev.as_ref().is_root() {
+                return FindMutRes::Before;
+            }
+            let (stored_key, _) = prev.as_ref().pair.as_ref();
+            match O::compare(key, stored_key.borrow()) {
+                Ordering::Equal => return FindMutRes::Head { entry: prev },
+                Ordering::Less => return FindMutRes::Before,
+                Ordering::Greater => (),
+            }
+        }
+
+        loop {
+            // Safe because we have exclusive access to the bucket and we only
+            // store properly allocated nodes.
+            unsafe {
+                let list = match NonNull::new(prev.as_ref().next) {
+                    Some(list) => list,
+                    None => break FindMutRes::After { prev },
+                };
+                let entry = list.as_ref().load();
+
+                if self.unlink_removed(&mut *prev.as_ptr(), list, entry) {
+                    continue;
+                }
+
+                let (stored_key, _) = entry.as_ref().pair.as_ref();
+                match O::compare(key, stored_key.borrow()) {
+                    Ordering::Equal => {
+                        break FindMutRes::Exact { prev, list, entry };
+                    },
+                    Ordering::Less => break FindMutRes::After { prev },
+                    Ordering::Greater => prev = entry,
+                }
+            }
+        }
+    }
+
+    // Unlinks the given entry, which comes right after the given previous
+    // entry, and gives it and its intermediate node back, if it was removed.
+    // Its pair belongs to whoever removed it. Returns whether it was removed.
+    // Unsafe because the bucket must be exclusively accessed, and the pointers
+    // must come from it.
+    unsafe fn unlink_removed(
+        &self,
+        prev: &mut Entry<K, V>,
+        list: NonNull<List<K, V>>,
+        entry: NonNull<Entry<K, V>>,
+    ) -> bool {
+        let next = entry.as_ref().next as usize;
+        if next & 1 == 0 {
+            return false;
+        }
+        prev.next = (next & !1) as *mut _;
+        let slabs = self.slabs();
+        slabs.lists.retire(list);
+        slabs.retire_entry(entry);
+        true
+    }
+
+    // Loads the first entry of the bucket. If it was removed, it is replaced
+    // by a root entry first, since the entry following it cannot be moved in
+    // its place: it might be concurrently updated through its own intermediate
+    // node. Without clean-up, a removed first entry is just passed over, as a
+    // root entry would be, and a frozen bucket is read as it is. Unsafe
+    // because the bucket must be kept alive while it is read, e.g. by a pause
+    // of the incinerator of the map, which must be paused to clean up.
+    unsafe fn load_head(&self, clean: bool) -> HeadRes<K, V> {
+        let slabs = self.slabs();
+        loop {
+            let loaded = self.list.atomic.load(Acquire);
+            if clean && loaded as usize & 1 == 1 {
+                break HeadRes::Frozen;
+            }
+            let head = NonNull::new_unchecked(unfrozen(loaded));
+            let next = head.as_ref().next as usize;
+            if head.as_ref().is_root() || (!clean && next & 1 == 1) {
+                break HeadRes::Root(head);
+            }
+            if next & 1 == 0 {
+                break HeadRes::Inline(head);
+            }
+
+            let root = match slabs
+                .try_alloc_entry(Entry::root((next & !1) as *mut _))
+            {
+                Ok(nnptr) => nnptr,
+                Err(err) => break HeadRes::NoMemory(err),
+            };
+            if self.list.try_update(head, root, slabs) {
+                break HeadRes::Root(root);
+            }
+        }
+    }
+
+    // Cleans up removed entries on the way if told to, see `CLEANS`. Unsafe
+    // for the same reasons as `load_head`.
+    unsafe fn find<'map, O, Q>(
         &'map self,
         key: &Q,
-        pause: &Pause<Garbage<K, V>>,
+        clean: bool,
     ) -> FindRes<'map, K, V>
     where
-        Q: ?Sized + Ord,
+        O: BucketOrder<Q>,
+        Q: ?Sized,
         K: Borrow<Q>,
     {
         'retry: loop {
             let mut prev_list = &self.list;
-            let mut prev = prev_list.load();
+            let mut prev = match self.load_head(clean) {
+                HeadRes::NoMemory(err) => break FindRes::NoMemory(err),
+
+                HeadRes::Frozen => break FindRes::Frozen,
+
+                HeadRes::Root(root) => root,
+
+                HeadRes::Inline(head) => {
+                    let comparison = {
+                        let (stored_key, _) = head.as_ref().pair.as_ref();
+                        O::compare(key, stored_key.borrow())
+                    };
+
+                    match comparison {
+                        Ordering::Equal => {
+                            break FindRes::Exact {
+                                curr_list: &self.list,
+                                curr: head,
+                            };
+                        },
+                        Ordering::Less => break FindRes::Before { head },
+                        Ordering::Greater => head,
+                    }
+                },
+            };
 
             loop {
-                match prev_list.load_next(prev, pause) {
+                match prev_list.load_next(prev, self.slabs(), clean) {
                     LoadNextRes::Failed => continue 'retry,
 
+                    LoadNextRes::NoMemory(err) => {
+                        break 'retry FindRes::NoMemory(err);
+                    },
+
                     LoadNextRes::End => {
                         // If the previous is the root and we reached the end we
                         // should delete the whole bucket.
@@ -333,7 +1095,7 @@ impl<K, V> Bucket<K, V> {
                     LoadNextRes::Ok { list, entry } => {
                         let comparison = {
                             let (stored_key, _) = entry.as_ref().pair.as_ref();
-                            key.cmp(stored_key.borrow())
+                            O::compare(key, stored_key.borrow())
                         };
 
                         match comparison {
@@ -368,19 +1130,16 @@ impl<K, V> IntoIterator for Bucket<K, V> {
 
     type IntoIter = IntoIter<K, V>;
 
-    fn into_iter(self) -> Self::IntoIter {
-        // By-passing this null check is ok because we never store null pointer
-        // on the list's AomticPtr.
-        let nnptr =
-            unsafe { NonNull::new_unchecked(self.list.atomic.load(Relaxed)) };
-        let head = unsafe { OwnedAlloc::from_raw(nnptr) };
-        mem::forget(self);
-        // Making an owned allocation is safe because we have ownership over the
+    fn into_iter(mut self) -> Self::IntoIter {
+        let nnptr = self.head_mut();
+        // Taking the first entry is safe because we have ownership over the
         // bucket.
-        IntoIter {
-            curr: NonNull::new(head.next)
-                .map(|nnptr| unsafe { OwnedAlloc::from_raw(nnptr) }),
-        }
+        let head = unsafe { self.slabs().take_entry(nnptr) };
+        let slabs = self.slabs;
+        mem::forget(self);
+        // Only an empty bucket starts with a root entry here.
+        let next = if head.is_root() { None } else { Some(head) };
+        IntoIter { next, slabs: slabs.as_ptr() }
     }
 }
 
@@ -390,84 +1149,149 @@ impl<'map, K, V> IntoIterator for &'map mut Bucket<K, V> {
     type IntoIter = IterMut<'map, K, V>;
 
     fn into_iter(self) -> Self::IntoIter {
-        // By-passing this null check is ok because we never store null pointer
-        // on the list's AomticPtr.
-        let head = unsafe { &mut *self.list.atomic.load(Relaxed) };
         // This dereferral is ok because we have exclusive reference to the
         // bucket.
-        IterMut { curr: unsafe { head.next.as_mut() } }
+        let head = unsafe { &mut *self.head_mut().as_ptr() };
+        // Only an empty bucket starts with a root entry here.
+        IterMut { curr: if head.is_root() { None } else { Some(head) } }
     }
 }
 
 impl<K, V> Drop for Bucket<K, V> {
     fn drop(&mut self) {
         unsafe {
-            let ptr = self.list.atomic.load(Relaxed);
-            let sentinel = NonNull::new_unchecked(ptr);
-            let mut top = sentinel.as_ref().next;
-            // Ok to deallocate it now since we already retrieved information.
-            // Note that we have exclusive access to the bucket.
-            OwnedAlloc::from_raw(sentinel);
-
-            while let Some(list) = NonNull::new(top) {
-                let ptr = list.as_ref().atomic.load(Relaxed);
-                // By-passing this null check is ok because we never store null
-                // pointer on the list's AomticPtr.
-                let entry = NonNull::new_unchecked(ptr);
-                // Ok to deallocate it now since we already retrieved
-                // information. Note that we have exclusive
-                // access to the bucket.
-                OwnedAlloc::from_raw(list);
-
-                let next = if entry.as_ref().next as usize & 1 == 0 {
+            let slabs = self.slabs();
+            let shell = self.is_shell();
+            // By-passing this null check is ok because we never store null
+            // pointer on the list's AomticPtr.
+            let mut entry = self.list.load_mut();
+
+            loop {
+                let next = entry.as_ref().next as usize;
+                if !shell && !entry.as_ref().is_root() && next & 1 == 0 {
                     // If the node is *not* marked, this entry was not removed
                     // and the pair needs to be deallocated. Ok to deallocate
                     // since we have exclusive reference.
-                    OwnedAlloc::from_raw(entry.as_ref().pair);
-                    entry.as_ref().next
-                } else {
-                    (entry.as_ref().next as usize & !1) as *mut _
+                    slabs.drop_pair(entry.as_ref().pair);
+                }
+                // Ok to give it back now since we already retrieved
+                // information. Note that we have exclusive access to the
+                // bucket.
+                slabs.retire_entry(entry);
+
+                let list = match NonNull::new((next & !1) as *mut List<K, V>) {
+                    Some(list) => list,
+                    None => break,
                 };
-                // Ok to deallocate it now since we already retrieved
-                // information. Note that we have exclusive
-                // access to the bucket.
-                OwnedAlloc::from_raw(entry);
-                top = next;
+                // Same as above, and again we never store null pointers.
+                entry = (*list.as_ptr()).load_mut();
+                slabs.lists.retire(list);
             }
         }
     }
 }
 
+// Entries are never changed in place: marking one, or unlinking the entry
+// after it, replaces it with a copy. The pair is kept out of line by default,
+// so its address stays the same across copies: `Removed` hands the very
+// allocation over after the pause ends, and values like atomics are updated in
+// place through it. Pairs of `Copy` keys and values may be kept inline
+// instead, see `InlineEntry`.
+//
+// The version is given whenever a pair is published, and kept by the copies.
+// Unlike the address of the pair, which may be reused once the pair is
+// dropped, or published again by `Reinsert`, it tells whether the very same
+// publication is still there.
+//
+// With snapshots, an entry is shared when snapshots may read its pair, i.e.
+// when its bucket is a copy, or its pair was removed while shared, see
+// `Keeper`.
 pub struct Entry<K, V> {
     pair: NonNull<(K, V)>,
     next: *mut List<K, V>,
+    version: usize,
+    #[cfg(feature = "snapshot")]
+    shared: bool,
 }
 
 impl<K, V> Entry<K, V> {
     #[inline]
-    pub fn root(next: *mut List<K, V>) -> Self {
+    pub fn new(
+        pair: NonNull<(K, V)>,
+        next: *mut List<K, V>,
+        version: usize,
+        _shared: bool,
+    ) -> Self {
         Self {
-            // Use this dangling pointer to mark an entry as the "sentinel"
-            // "root" entry.
-            pair: non_zero_null(),
+            pair,
             next,
+            version,
+            #[cfg(feature = "snapshot")]
+            shared: _shared,
         }
     }
 
+    #[inline]
+    pub fn root(next: *mut List<K, V>) -> Self {
+        // Use this dangling pointer to mark an entry as the "sentinel" "root"
+        // entry.
+        Self::new(non_zero_null(), next, 0, false)
+    }
+
+    // Whether snapshots may read the pair of this entry.
+    #[cfg(feature = "snapshot")]
+    #[inline]
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    #[cfg(not(feature = "snapshot"))]
+    #[inline]
+    pub fn is_shared(&self) -> bool {
+        false
+    }
+
     #[inline]
     pub fn is_root(&self) -> bool {
         self.pair == non_zero_null()
     }
 
+    // Whether a bucket starting with this entry is empty, i.e. nothing
+    // follows it, and it is either a root entry or a removed one.
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.is_root() && self.next == null_mut()
+        let next = self.next as usize;
+        next & !1 == 0 && (self.is_root() || next & 1 == 1)
+    }
+}
+
+// An entry along with its pair, taken from the slabs of a map whose pairs are
+// kept inline, saving an allocation and a hop per entry. The pair of the entry
+// points into its own slot, so copies of the entry copy the pair too, which is
+// why only pairs of `Copy` keys and values are kept inline: values updated in
+// place do not need to be shared, and bit copies need no drop. The address of
+// such a pair no longer identifies it across copies, but the version of the
+// entry still does. The entry comes first, so a pointer to the slot is a
+// pointer to the entry.
+#[repr(C)]
+struct InlineEntry<K, V> {
+    entry: Entry<K, V>,
+    pair: MaybeUninit<(K, V)>,
+}
+
+impl<K, V> InlineEntry<K, V> {
+    // The version of the entry holding the given pair. Unsafe because the pair
+    // must be kept inline, in a slot not recycled yet.
+    unsafe fn version_of(pair: &(K, V)) -> usize {
+        let offset = mem::offset_of!(Self, pair);
+        let slot = (pair as *const (K, V) as *const u8).sub(offset);
+        (*(slot as *const Self)).entry.version
     }
 }
 
 impl<K, V> Clone for Entry<K, V> {
     fn clone(&self) -> Self {
-        Self { pair: self.pair, next: self.next }
+        *self
     }
 }
 
@@ -487,28 +1311,90 @@ pub struct List<K, V> {
 }
 
 impl<K, V> List<K, V> {
+    // Unsafe because the incinerator of the map must be paused, or the slabs
+    // exclusively accessed.
     #[inline]
-    fn new(entry: Entry<K, V>) -> Self {
-        let ptr = OwnedAlloc::new(entry).into_raw().as_ptr();
-        Self { atomic: AtomicPtr::new(ptr) }
+    unsafe fn new(
+        entry: Entry<K, V>,
+        slabs: &Slabs<K, V>,
+    ) -> Result<Self, AllocFailed> {
+        let nnptr = slabs.try_alloc_entry(entry)?;
+        Ok(Self { atomic: AtomicPtr::new(nnptr.as_ptr()) })
     }
 
-    // Unsafe because `Bucket` needs to store entries correctly.
+    // Just like `new`, but the intermediate node is taken from the slabs too.
+    // Nothing is left taken if allocation fails. Unsafe for the same reasons
+    // as `new`.
+    unsafe fn try_alloc(
+        entry: Entry<K, V>,
+        slabs: &Slabs<K, V>,
+    ) -> Result<NonNull<Self>, AllocFailed> {
+        let list = Self::new(entry, slabs)?;
+        slabs.lists.try_alloc(list).map_err(|(list, err)| {
+            slabs.retire_entry(list.load());
+            err
+        })
+    }
+
+    // Takes from the slabs an intermediate node keeping an entry which was
+    // already taken from them. Unsafe for the same reasons as `new`.
+    unsafe fn try_alloc_holding(
+        entry: NonNull<Entry<K, V>>,
+        slabs: &Slabs<K, V>,
+    ) -> Result<NonNull<Self>, AllocFailed> {
+        let list = Self { atomic: AtomicPtr::new(entry.as_ptr()) };
+        slabs.lists.try_alloc(list).map_err(|(_, err)| err)
+    }
+
+    // Loads the entry, whether this node was frozen or not. Unsafe because
+    // `Bucket` needs to store entries correctly.
     unsafe fn load(&self) -> NonNull<Entry<K, V>> {
-        NonNull::new_unchecked(self.atomic.load(Acquire))
+        NonNull::new_unchecked(unfrozen(self.atomic.load(Acquire)))
+    }
+
+    // Just like `load`, with exclusive access. Unsafe for the same reasons.
+    unsafe fn load_mut(&mut self) -> NonNull<Entry<K, V>> {
+        NonNull::new_unchecked(unfrozen(self.atomic.read_mut()))
+    }
+
+    // Freezes this node, so its entry is never replaced anymore, and returns
+    // the entry. Unsafe for the same reasons as `load`.
+    #[cfg(feature = "snapshot")]
+    unsafe fn freeze(&self) -> NonNull<Entry<K, V>> {
+        let mut loaded = self.atomic.load(Acquire);
+        loop {
+            if loaded as usize & 1 == 1 {
+                break NonNull::new_unchecked(unfrozen(loaded));
+            }
+            // `Acquire` on failure reads the entry which replaced the loaded
+            // one. Nothing is published by freezing.
+            match self.atomic.compare_exchange_weak(
+                loaded,
+                (loaded as usize | 1) as *mut _,
+                Acquire,
+                Acquire,
+            ) {
+                Ok(_) => break NonNull::new_unchecked(loaded),
+                Err(new) => loaded = new,
+            }
+        }
     }
 
-    // Loads the next and do clean-up if necessary. Unsafe because it might need
-    // incinerator's pause and there is no guarantee the passed pause by
-    // this thread comes from the same incinerator from which other threads
-    // pass pauses. Also, `Bucket` needs to store entries correctly.
+    // Loads the next and do clean-up if told to, see `CLEANS`. Without
+    // clean-up, a removed entry is just passed over. Unsafe because the
+    // incinerator of the map must be paused to clean up, and the slabs must
+    // be the ones of the bucket. Also, `Bucket` needs to store entries
+    // correctly.
     unsafe fn load_next(
         &self,
         prev: NonNull<Entry<K, V>>,
-        pause: &Pause<Garbage<K, V>>,
+        slabs: &Slabs<K, V>,
+        clean: bool,
     ) -> LoadNextRes<K, V> {
         // Loading the previous node's next field (e.g. the "current" node).
-        let list = match NonNull::new(prev.as_ref().next) {
+        // The previous may be removed if nothing is cleaned up.
+        let next = prev.as_ref().next as usize & !1;
+        let list = match NonNull::new(next as *mut List<K, V>) {
             Some(nnptr) => nnptr,
             // The next is null; there is no next.
             None => return LoadNextRes::End,
@@ -517,6 +1403,10 @@ impl<K, V> List<K, V> {
         let entry = list.as_ref().load();
         let next = entry.as_ref().next as usize;
 
+        if next & 1 == 1 && !clean {
+            return LoadNextRes::Cleared { new_prev: entry };
+        }
+
         // If the next field was marked, this node was logically removed. Time
         // to remove it physically.
         if next & 1 == 1 {
@@ -524,14 +1414,19 @@ impl<K, V> List<K, V> {
             // previous, but with next field pointing to current node's the
             // intermediate list.
             let new_entry =
-                Entry { pair: prev.as_ref().pair, next: (next & !1) as *mut _ };
-            let new_ptr = OwnedAlloc::new(new_entry).into_raw();
+                Entry { next: (next & !1) as *mut _, ..*prev.as_ref() };
+            // Without a new previous node, the removed one cannot be unlinked,
+            // nor passed over, since updating it would bring it back.
+            let new_ptr = match slabs.try_alloc_entry(new_entry) {
+                Ok(nnptr) => nnptr,
+                Err(err) => return LoadNextRes::NoMemory(err),
+            };
 
             // Then we try to update the previous node.
-            if self.try_update(prev, new_ptr, pause) {
-                // This is shared data. Must be deleted through the incinerator.
-                pause.add_to_incin(Garbage::List(OwnedAlloc::from_raw(list)));
-                pause.add_to_incin(Garbage::Entry(OwnedAlloc::from_raw(entry)));
+            if self.try_update(prev, new_ptr, slabs) {
+                // This is shared data. Must be retired, not freed.
+                slabs.lists.retire(list);
+                slabs.retire_entry(entry);
                 LoadNextRes::Cleared { new_prev: new_ptr }
             } else {
                 LoadNextRes::Failed
@@ -541,63 +1436,379 @@ impl<K, V> List<K, V> {
         }
     }
 
+    // Tries to remove logically the given entry, by updating this intermediate
+    // node with an entry with same data... but marked! Unsafe because of the
+    // same reasons of `try_update`.
+    unsafe fn try_mark(
+        &self,
+        loaded: NonNull<Entry<K, V>>,
+        slabs: &Slabs<K, V>,
+    ) -> bool {
+        let new_entry = Entry {
+            next: (loaded.as_ref().next as usize | 1) as *mut _,
+            ..*loaded.as_ref()
+        };
+        let new_ptr = slabs.alloc_entry(new_entry);
+        self.try_update(loaded, new_ptr, slabs)
+    }
+
     // Tries to update this intermediate node and does clean-up of the passed
-    // pointers. Unsafe because it might need incinerator's pause and there is
-    // no guarantee the passed pause by this thread comes from the same
-    // incinerator from which other threads pass pauses. Also, `Bucket`
-    // needs to store entries correctly.
+    // pointers. Unsafe because the incinerator of the map must be paused, and
+    // the slabs must be the ones of the bucket. Also, `Bucket` needs to store
+    // entries correctly.
     unsafe fn try_update(
         &self,
         loaded: NonNull<Entry<K, V>>,
         new: NonNull<Entry<K, V>>,
-        pause: &Pause<Garbage<K, V>>,
+        slabs: &Slabs<K, V>,
     ) -> bool {
+        // `Release` publishes the new entry to readers loading it with
+        // `Acquire`. Nothing is read through the replaced pointer, which was
+        // already loaded with `Acquire`, nor through the one found on failure,
+        // so both may be `Relaxed` otherwise. A failure sends callers back to
+        // `find` or makes them give up a clean-up, so this is not a weak
+        // exchange: a spurious failure is not worth that.
         let res = self.atomic.compare_exchange(
             loaded.as_ptr(),
             new.as_ptr(),
             Release,
-            Relaxed
+            Relaxed,
         );
 
         if res == Ok(loaded.as_ptr()) {
             // Clean-up of the old pointer.
-            let alloc = OwnedAlloc::from_raw(loaded);
-            pause.add_to_incin(Garbage::Entry(alloc));
+            slabs.retire_entry(loaded);
             true
         } else {
-            // Clean-up of the tried new pointer.
-            OwnedAlloc::from_raw(new);
+            // Clean-up of the tried new pointer. It was never shared, but it
+            // is retired anyway, since only recycling may free slots.
+            slabs.retire_entry(new);
             false
         }
     }
 }
 
+// Buckets and their nodes are not garbage: they go back to the slabs.
 pub enum Garbage<K, V> {
     Pair(OwnedAlloc<(K, V)>),
-    Entry(OwnedAlloc<Entry<K, V>>),
-    List(OwnedAlloc<List<K, V>>),
-    Bucket(OwnedAlloc<Bucket<K, V>>),
+    Table(OwnedAlloc<Table<K, V>>),
+    // A keeper no longer the latest one, see `Snapshots`.
+    #[cfg(feature = "snapshot")]
+    Keeper(Weak<Keeper<K, V>>),
 }
 
 impl<K, V> fmt::Debug for Garbage<K, V> {
     fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Garbage::Pair(ptr) => write!(fmtr, "Garbage::Pair({:?})", ptr),
-            Garbage::List(ptr) => write!(fmtr, "Garbage::List({:?})", ptr),
-            Garbage::Bucket(ptr) => write!(fmtr, "Garbage::Bucket({:?})", ptr),
-            Garbage::Entry(ptr) => write!(fmtr, "Garbage::Entry({:?})", ptr),
+            Garbage::Table(ptr) => write!(fmtr, "Garbage::Table({:?})", ptr),
+            #[cfg(feature = "snapshot")]
+            Garbage::Keeper(weak) => {
+                write!(fmtr, "Garbage::Keeper({:?})", weak.as_ptr())
+            },
         }
     }
 }
 
+// Clears the mark left on a pointer to an entry by `List::freeze`.
+#[inline]
+fn unfrozen<K, V>(ptr: *mut Entry<K, V>) -> *mut Entry<K, V> {
+    (ptr as usize & !1) as *mut _
+}
+
+// The slabs of a map, one per node type. Buckets come first so that, when the
+// slabs are recycled or dropped, the nodes of dropped buckets are given back
+// before the other slabs are.
+pub struct Slabs<K, V> {
+    pub buckets: Slab<Bucket<K, V>>,
+    pub lists: Slab<List<K, V>>,
+    pub entries: Slab<Entry<K, V>>,
+    // Used instead of `entries` if pairs are kept inline.
+    inline_entries: Slab<InlineEntry<K, V>>,
+    // Whether pairs are kept inline, see `InlineEntry`. Only set for keys and
+    // values which are `Copy`, before anything is inserted.
+    pub inline: bool,
+    // The version of the next pair published, see `Entry`. Root entries have
+    // version zero.
+    versions: AtomicUsize,
+    // Tells apart the keys whose plain hashes are equal, with flood
+    // protection. Kept here since every bucket reaches it.
+    pub salt: Option<Salt<K>>,
+    // The epochs and keepers of the snapshots of the map, see `Snapshots`.
+    // Kept here since every bucket reaches it too.
+    pub snapshots: Snapshots<K, V>,
+}
+
+impl<K, V> Slabs<K, V> {
+    pub fn new() -> Self {
+        Self {
+            buckets: Slab::new(),
+            lists: Slab::new(),
+            entries: Slab::new(),
+            inline_entries: Slab::new(),
+            inline: false,
+            versions: AtomicUsize::new(1),
+            salt: None,
+            snapshots: Snapshots::new(),
+        }
+    }
+
+    // A version no pair of the map was published with. Only its uniqueness
+    // matters, so no ordering is needed.
+    pub fn next_version(&self) -> usize {
+        self.versions.fetch_add(1, Relaxed)
+    }
+
+    // The memory taken by each entry, along with its pair if it is kept
+    // inline.
+    pub fn entry_size(&self) -> usize {
+        if self.inline {
+            Slab::<InlineEntry<K, V>>::slot_size()
+        } else {
+            Slab::<Entry<K, V>>::slot_size()
+        }
+    }
+
+    // Takes a slot for the entry. If pairs are kept inline, the pair of the
+    // entry is copied into the slot, wherever it was. If allocation fails,
+    // nothing is taken. Unsafe because the incinerator of the map must be
+    // paused, or the slabs exclusively accessed, and the pair of the entry
+    // must be readable.
+    pub unsafe fn try_alloc_entry(
+        &self,
+        entry: Entry<K, V>,
+    ) -> Result<NonNull<Entry<K, V>>, AllocFailed> {
+        if !self.inline {
+            return self.entries.try_alloc(entry).map_err(|(_, err)| err);
+        }
+        let slot = InlineEntry { entry, pair: MaybeUninit::uninit() };
+        let slot = self.inline_entries.try_alloc(slot).map_err(|(_, err)| err)?;
+        let entry = slot.cast::<Entry<K, V>>();
+        self.write_entry(entry, *entry.as_ref());
+        Ok(entry)
+    }
+
+    // Just like `try_alloc_entry`, but calls the global allocation error
+    // handler if allocation fails.
+    pub unsafe fn alloc_entry(
+        &self,
+        entry: Entry<K, V>,
+    ) -> NonNull<Entry<K, V>> {
+        self.try_alloc_entry(entry).unwrap_or_else(|err| err.handle())
+    }
+
+    // Writes the entry over the one in the given slot, copying its pair into
+    // the slot if pairs are kept inline. Unsafe because the slot must be
+    // exclusively accessed, and the pair of the entry must be readable.
+    pub unsafe fn write_entry(
+        &self,
+        slot: NonNull<Entry<K, V>>,
+        mut entry: Entry<K, V>,
+    ) {
+        if self.inline && !entry.is_root() {
+            let inline = slot.cast::<InlineEntry<K, V>>().as_ptr();
+            let pair = addr_of_mut!((*inline).pair) as *mut (K, V);
+            // The pair may already be in the slot.
+            ptr::copy(entry.pair.as_ptr(), pair, 1);
+            entry.pair = NonNull::new_unchecked(pair);
+        }
+        slot.as_ptr().write(entry);
+    }
+
+    // See `Slab::retire`.
+    pub unsafe fn retire_entry(&self, entry: NonNull<Entry<K, V>>) {
+        if self.inline {
+            self.inline_entries.retire(entry.cast());
+        } else {
+            self.entries.retire(entry);
+        }
+    }
+
+    // See `Slab::take`. A pair kept inline is left in the slot, which is not
+    // reused until the slabs are recycled.
+    pub unsafe fn take_entry(
+        &self,
+        entry: NonNull<Entry<K, V>>,
+    ) -> Entry<K, V> {
+        if self.inline {
+            self.inline_entries.take(entry.cast()).entry
+        } else {
+            self.entries.take(entry)
+        }
+    }
+
+    // Takes ownership of the pair of an entry which was removed. A pair kept
+    // inline is copied into an allocation of its own. Unsafe because the pair
+    // must be found in the map, and nobody else may take it.
+    pub unsafe fn own_pair(
+        &self,
+        pair: NonNull<(K, V)>,
+    ) -> OwnedAlloc<(K, V)> {
+        if self.inline {
+            OwnedAlloc::new(pair.as_ptr().read())
+        } else {
+            OwnedAlloc::from_raw(pair)
+        }
+    }
+
+    // Takes ownership of the pair of an entry just removed from a bucket,
+    // noting whether snapshots may still read it. Unsafe for the same reasons
+    // as `own_pair`.
+    pub unsafe fn removed_pair(
+        &self,
+        entry: &Entry<K, V>,
+    ) -> RemovedPair<K, V> {
+        RemovedPair {
+            pair: self.own_pair(entry.pair),
+            // A pair kept inline was just copied, and snapshots read the
+            // original.
+            shared: !self.inline && entry.is_shared(),
+        }
+    }
+
+    // Makes a removed pair taken from a bucket with the given hash, letting
+    // the keeper of the snapshots keep it if they may still read it. Unsafe
+    // because the pause must come from the given incinerator, the one of the
+    // map.
+    pub unsafe fn wrap_removed(
+        &self,
+        removed: RemovedPair<K, V>,
+        incin: &Arc<Incinerator<Garbage<K, V>>>,
+        hash: HashCode,
+        pause: &Pause<Garbage<K, V>>,
+    ) -> Removed<K, V> {
+        let RemovedPair { pair, shared } = removed;
+        let mut removed = Removed::new(pair, incin, hash);
+        if shared {
+            self.snapshots.hold(&mut removed, pause);
+        }
+        removed
+    }
+
+    // Just like `removed_pair` followed by `wrap_removed`.
+    pub unsafe fn removed(
+        &self,
+        entry: &Entry<K, V>,
+        incin: &Arc<Incinerator<Garbage<K, V>>>,
+        hash: HashCode,
+        pause: &Pause<Garbage<K, V>>,
+    ) -> Removed<K, V> {
+        self.wrap_removed(self.removed_pair(entry), incin, hash, pause)
+    }
+
+    // Just like `own_pair`, but moves the pair out, with exclusive access.
+    pub unsafe fn read_pair(&self, pair: NonNull<(K, V)>) -> (K, V) {
+        if self.inline {
+            pair.as_ptr().read()
+        } else {
+            let (pair, _) = OwnedAlloc::from_raw(pair).move_inner();
+            pair
+        }
+    }
+
+    // Drops the pair of an entry which was not removed, with exclusive
+    // access. A pair kept inline needs no drop.
+    pub unsafe fn drop_pair(&self, pair: NonNull<(K, V)>) {
+        if !self.inline {
+            drop(OwnedAlloc::from_raw(pair));
+        }
+    }
+
+    // Frees the allocation of a pair given to a new entry, if the entry took a
+    // copy of it, i.e. if pairs are kept inline. Unsafe because the pair must
+    // come from `OwnedAlloc`, and it must not be used afterwards.
+    pub unsafe fn release(&self, pair: NonNull<(K, V)>) {
+        if self.inline {
+            drop(OwnedAlloc::from_raw(pair));
+        }
+    }
+
+    // Lets the inserter know that its pair was published in the given entry,
+    // and takes the allocation of the pair from it. Unsafe because the entry
+    // must have been taken with the pair of the inserter, and the incinerator
+    // of the map must be paused.
+    pub unsafe fn publish<I>(
+        &self,
+        mut inserter: I,
+        entry: NonNull<Entry<K, V>>,
+    ) where
+        I: Inserter<K, V>,
+    {
+        inserter.stored(entry.as_ref().pair);
+        let given = inserter.pointer();
+        inserter.take_pointer();
+        if let Some(given) = given {
+            self.release(given);
+        }
+    }
+
+    // Tells whether the given pairs, found in the map under the same pause,
+    // were published by the same insertion. The copies of an entry keep the
+    // address of its pair, unless the pair is kept inline, in which case the
+    // version of the entry tells it instead. Unsafe because the pairs must be
+    // found in the map while the incinerator is paused.
+    pub unsafe fn same_pair(&self, left: &(K, V), right: &(K, V)) -> bool {
+        if self.inline {
+            InlineEntry::version_of(left) == InlineEntry::version_of(right)
+        } else {
+            ptr::eq(left, right)
+        }
+    }
+
+    // Tells apart, along with the version of its entry, the pair found in the
+    // map: its address, unless it is kept inline. Then, the version alone
+    // tells it apart, and the address of the slabs tells the map apart.
+    pub fn pair_tag(&self, pair: &(K, V)) -> usize {
+        if self.inline {
+            self as *const Self as usize
+        } else {
+            pair as *const (K, V) as usize
+        }
+    }
+
+    // See `Slab::recycle`.
+    pub fn recycle<F>(&self, unpaused: F)
+    where
+        F: Fn() -> bool,
+    {
+        self.buckets.recycle(&unpaused);
+        self.lists.recycle(&unpaused);
+        self.entries.recycle(&unpaused);
+        self.inline_entries.recycle(&unpaused);
+    }
+
+    pub fn recycle_mut(&mut self) {
+        self.buckets.recycle_mut();
+        self.lists.recycle_mut();
+        self.entries.recycle_mut();
+        self.inline_entries.recycle_mut();
+    }
+}
+
+// The slabs only move nodes, and pairs with them, between threads.
+unsafe impl<K, V> Send for Slabs<K, V>
+where
+    K: Send,
+    V: Send,
+{
+}
+
+unsafe impl<K, V> Sync for Slabs<K, V>
+where
+    K: Send,
+    V: Send,
+{
+}
+
 pub enum GetRes<'map, K, V>
 where
     K: 'map,
     V: 'map,
 {
-    Found(ReadGuard<'map, K, V>),
+    // The pair along with the version of its entry.
+    Found(&'map (K, V), usize),
     NotFound,
-    Delete(Pause<'map, Garbage<K, V>>),
+    Delete,
 }
 
 pub enum InsertRes<I, K, V> {
@@ -605,11 +1816,30 @@ pub enum InsertRes<I, K, V> {
     Updated(Removed<K, V>),
     Failed(I),
     Delete(I),
+    // The bucket was copied, and the copy must be found again from the top.
+    Frozen(I),
+    NoMemory(I, AllocFailed),
+}
+
+// The pair of an entry just removed from a bucket, see `Slabs::removed_pair`.
+pub struct RemovedPair<K, V> {
+    pair: OwnedAlloc<(K, V)>,
+    // Whether snapshots may still read the pair.
+    shared: bool,
 }
 
 pub struct RemoveRes<K, V> {
-    pub pair: Option<Removed<K, V>>,
+    pub removed: Option<RemovedPair<K, V>>,
+    // The table must delete the whole bucket.
     pub delete: bool,
+    // The bucket was copied, and the copy must be found again from the top.
+    pub restart: bool,
+}
+
+impl<K, V> RemoveRes<K, V> {
+    fn none(delete: bool, restart: bool) -> Self {
+        Self { removed: None, delete, restart }
+    }
 }
 
 enum FindRes<'map, K, V>
@@ -619,14 +1849,52 @@ where
 {
     Delete,
 
+    NoMemory(AllocFailed),
+
+    // Only found by clean-ups, see `Bucket::try_copy`.
+    Frozen,
+
     Exact { curr_list: &'map List<K, V>, curr: NonNull<Entry<K, V>> },
 
+    // The key fits before the first entry, which is kept in the bucket.
+    Before { head: NonNull<Entry<K, V>> },
+
     After { prev_list: &'map List<K, V>, prev: NonNull<Entry<K, V>> },
 }
 
+enum HeadRes<K, V> {
+    NoMemory(AllocFailed),
+
+    // Only found by clean-ups, see `Bucket::try_copy`.
+    Frozen,
+
+    Root(NonNull<Entry<K, V>>),
+
+    // The first entry is kept in the bucket, and it was not removed.
+    Inline(NonNull<Entry<K, V>>),
+}
+
+enum FindMutRes<K, V> {
+    // The key is the one of the first entry, kept in the bucket.
+    Head { entry: NonNull<Entry<K, V>> },
+
+    Exact {
+        prev: NonNull<Entry<K, V>>,
+        list: NonNull<List<K, V>>,
+        entry: NonNull<Entry<K, V>>,
+    },
+
+    // The key fits before the first entry, if any.
+    Before,
+
+    After { prev: NonNull<Entry<K, V>> },
+}
+
 enum LoadNextRes<K, V> {
     Failed,
 
+    NoMemory(AllocFailed),
+
     End,
 
     Cleared { new_prev: NonNull<Entry<K, V>> },
@@ -635,12 +1903,16 @@ enum LoadNextRes<K, V> {
 }
 
 pub struct IntoIter<K, V> {
-    curr: Option<OwnedAlloc<List<K, V>>>,
+    // The next entry, already taken from the slabs.
+    next: Option<Entry<K, V>>,
+    // The slabs of the map, which outlive this iterator. Null when it is
+    // empty.
+    slabs: *const Slabs<K, V>,
 }
 
 impl<K, V> IntoIter<K, V> {
     pub fn empty() -> Self {
-        Self { curr: None }
+        Self { next: None, slabs: null() }
     }
 }
 
@@ -649,19 +1921,31 @@ impl<K, V> Iterator for IntoIter<K, V> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let list = self.curr.take()?;
-            // Safe because we only store non-null nodes.
-            let entry_nnptr = unsafe { list.load() };
-            // Safe because we have ownership over the nodes.
-            let entry = unsafe { OwnedAlloc::from_raw(entry_nnptr) };
-            // Safe because we have ownership over the nodes *and* we clear the
-            // bit that may be set.
-            self.curr = NonNull::new((entry.next as usize & !1) as *mut _)
-                .map(|nnptr| unsafe { OwnedAlloc::from_raw(nnptr) });
-
-            // Safe because, again, we have ownership over the nodes.
-            if entry.next as usize & 1 == 0 {
-                break Some(unsafe { OwnedAlloc::from_raw(entry.pair) });
+            let entry = self.next.take()?;
+            // Safe because there is an entry, so the slabs are not null.
+            let slabs = unsafe { &*self.slabs };
+            // Safe because we have ownership over the nodes. A pair kept
+            // inline is still in the slot of the entry, which is not reused,
+            // since the slabs are not recycled while they are iterated.
+            let pair = if entry.next as usize & 1 == 0 {
+                Some(unsafe { slabs.own_pair(entry.pair) })
+            } else {
+                None
+            };
+
+            // We clear the bit that may be set.
+            let cleared = entry.next as usize & !1;
+            let next = NonNull::new(cleared as *mut List<K, V>);
+            // Safe because we have ownership over the nodes, and we only store
+            // non-null nodes.
+            self.next = next.map(|list| unsafe {
+                let entry_nnptr = list.as_ref().load();
+                slabs.lists.retire(list);
+                slabs.take_entry(entry_nnptr)
+            });
+
+            if pair.is_some() {
+                break pair;
             }
         }
     }
@@ -675,7 +1959,7 @@ impl<K, V> Drop for IntoIter<K, V> {
 
 impl<K, V> fmt::Debug for IntoIter<K, V> {
     fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmtr, "{:?}", self.curr)
+        write!(fmtr, "{:?}", self.next.map(|entry| entry.pair))
     }
 }
 
@@ -684,7 +1968,7 @@ where
     K: 'map,
     V: 'map,
 {
-    curr: Option<&'map mut List<K, V>>,
+    curr: Option<&'map mut Entry<K, V>>,
 }
 
 impl<'map, K, V> IterMut<'map, K, V> {
@@ -698,16 +1982,16 @@ impl<'map, K, V> Iterator for IterMut<'map, K, V> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let list = self.curr.take()?;
-            let ptr = list.atomic.load(Relaxed);
-            // Safe because we never store non-null nodes in list's AtomicPtr.
-            let entry = unsafe { &mut *ptr };
+            let entry = self.curr.take()?;
 
             // Safe because we clear the only bit we mark. Also, we only store
-            // properly allocated nodes.
+            // properly allocated nodes, and never null pointers in list's
+            // AtomicPtr.
             self.curr = unsafe {
                 let cleared = entry.next as usize & !1;
-                (cleared as *mut List<K, V>).as_mut()
+                (cleared as *mut List<K, V>)
+                    .as_mut()
+                    .map(|list| &mut *list.load_mut().as_ptr())
             };
 
             if entry.next as usize & 1 == 0 {
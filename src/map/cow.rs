@@ -0,0 +1,375 @@
+// Copy-on-write of the tables and buckets of a map, which lets
+// `Map::snapshot` share them with the map instead of copying them. Tables and
+// buckets count how many tables hold them. Whatever is held more than once is
+// shared, so an operation changing the map first makes every table and bucket
+// on its way belong to the map alone, from the top table down, copying the
+// shared ones, see `Node::own`. Once the snapshots sharing them are dropped,
+// they are held once again, and nothing is copied anymore.
+//
+// A snapshot copies the top table of the map, which is never replaced, so it
+// counts one more hold on every table and bucket the top table holds.
+// Operations which found them held once before that may still change them in
+// place, so a snapshot waits for them to be done: every operation changing
+// the map registers in the current epoch before loading the top table, and a
+// snapshot starts a new epoch once it copied the top table, then waits until
+// no operation is left in the old one. Operations never wait for snapshots,
+// though. Copying a table marks everything it holds as copied, and copying a
+// bucket freezes it, so operations which loaded them before fail to change
+// them afterwards, and start again from the top table.
+//
+// Pairs are not copied, so a pair removed from the map may still be read by
+// snapshots. Removed entries hand such pairs to a keeper, which keeps them
+// until the snapshots which may read them are dropped, see `Keeper`.
+//
+// Without the `snapshot` feature, `Snapshots` and `Writing` are zero-sized
+// types whose methods do nothing, and everything belongs to the map alone.
+
+#[cfg(feature = "snapshot")]
+use super::backoff::Backoff;
+use super::{bucket::Garbage, guard::Removed};
+#[cfg(feature = "snapshot")]
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+};
+use core::marker::PhantomData;
+#[cfg(feature = "snapshot")]
+use core::{
+    mem::ManuallyDrop,
+    ptr::null_mut,
+    sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering::*},
+};
+#[cfg(feature = "snapshot")]
+use incin::Incinerator;
+use incin::Pause;
+#[cfg(feature = "snapshot")]
+use owned_alloc::OwnedAlloc;
+
+// The epochs of a map and the keepers of its snapshots.
+pub struct Snapshots<K, V> {
+    // Moved to the next epoch by each snapshot.
+    #[cfg(feature = "snapshot")]
+    epoch: AtomicUsize,
+    // How many operations changing the map are in progress in the epochs of
+    // each parity. Snapshots are taken one at a time, and each one waits for
+    // the epoch before it, so only two epochs have operations in progress.
+    #[cfg(feature = "snapshot")]
+    writers: [AtomicUsize; 2],
+    // A raw `Weak` to the keeper of the latest snapshot, or null before the
+    // first snapshot. Only replaced while a snapshot is taken.
+    #[cfg(feature = "snapshot")]
+    latest: AtomicPtr<Keeper<K, V>>,
+    // Set while a snapshot is taken.
+    #[cfg(feature = "snapshot")]
+    taking: AtomicBool,
+    _marker: PhantomData<(K, V)>,
+}
+
+#[cfg(feature = "snapshot")]
+impl<K, V> Snapshots<K, V> {
+    pub fn new() -> Self {
+        Self {
+            epoch: AtomicUsize::new(0),
+            writers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            latest: AtomicPtr::new(null_mut()),
+            taking: AtomicBool::new(false),
+            _marker: PhantomData,
+        }
+    }
+
+    // Registers an operation changing the map in the current epoch. It never
+    // waits: if a snapshot starts a new epoch meanwhile, the operation
+    // registers again, in the new one.
+    pub fn write(&self) -> Writing<'_> {
+        loop {
+            // `SeqCst` orders the registration with the start of a new epoch:
+            // either the snapshot starting it waits for this operation, or
+            // this operation sees the new epoch, and then the top table held
+            // by the snapshot.
+            let epoch = self.epoch.load(SeqCst);
+            let writers = &self.writers[epoch & 1];
+            writers.fetch_add(1, SeqCst);
+            if self.epoch.load(SeqCst) == epoch {
+                break Writing { writers };
+            }
+            writers.fetch_sub(1, Release);
+        }
+    }
+
+    // Takes a snapshot: a new keeper is made the latest one, the closure
+    // copies the top table of the map, and a new epoch starts, whose
+    // operations see the tables and buckets shared. Then, the operations of
+    // the old epoch are waited for. Returns what the closure gave, along with
+    // the keeper of the snapshot. This must not be called by an operation
+    // changing the map, which would be waited for forever.
+    pub fn take<T, F>(
+        &self,
+        incin: &Arc<Incinerator<Garbage<K, V>>>,
+        share: F,
+    ) -> (T, Arc<Keeper<K, V>>)
+    where
+        F: FnOnce() -> T,
+    {
+        let _taking = Taking::lock(self);
+        let keeper = self.push(incin);
+        // Orders the new keeper before the shared nodes: an operation which
+        // finds a node shared, and then removes a pair from it, sees the new
+        // keeper in `hold`.
+        fence(SeqCst);
+        let shared = share();
+
+        // `SeqCst` just like in `write`.
+        let epoch = self.epoch.fetch_add(1, SeqCst);
+        let writers = &self.writers[epoch & 1];
+        let mut backoff = Backoff::new();
+        // `SeqCst` synchronizes with the operations leaving the old epoch, so
+        // their changes are seen by the snapshot.
+        while writers.load(SeqCst) != 0 {
+            backoff.snooze();
+        }
+        (shared, keeper)
+    }
+
+    // Lets the keeper of the latest snapshot keep a pair just removed from
+    // the map which snapshots may still read, unless every snapshot is
+    // dropped already. Unsafe because the pause must come from the
+    // incinerator of the map, which the replaced keepers are given to.
+    pub unsafe fn hold(
+        &self,
+        removed: &mut Removed<K, V>,
+        _pause: &Pause<Garbage<K, V>>,
+    ) {
+        // Pairs the fence of `take`: either the snapshot shares the node
+        // after the pair was removed from it, or the new keeper is seen.
+        fence(SeqCst);
+        let latest = self.latest.load(Acquire);
+        if latest.is_null() {
+            return;
+        }
+        // Safe because the latest `Weak` is only dropped by the incinerator,
+        // and we are paused.
+        let latest = ManuallyDrop::new(Weak::from_raw(latest));
+        if latest.strong_count() > 0 {
+            Removed::hold(removed, Weak::clone(&latest));
+        }
+    }
+
+    // Whether the given keeper keeps pairs for the snapshots of this map.
+    pub fn is_keeper(&self, keeper: &Keeper<K, V>) -> bool {
+        keeper.snapshots == self as *const Self as usize
+    }
+
+    // Makes a new keeper the latest one, kept alive by the previous one, if
+    // alive. Only called while a snapshot is taken.
+    fn push(
+        &self,
+        incin: &Arc<Incinerator<Garbage<K, V>>>,
+    ) -> Arc<Keeper<K, V>> {
+        let keeper = Arc::new(Keeper {
+            pairs: AtomicPtr::new(null_mut()),
+            newer: AtomicPtr::new(null_mut()),
+            incin: Arc::downgrade(incin),
+            snapshots: self as *const Self as usize,
+        });
+
+        let latest = self.latest.load(Acquire);
+        if !latest.is_null() {
+            // Safe because only snapshots being taken replace the latest
+            // `Weak`, and only one is taken at a time.
+            let latest = ManuallyDrop::new(unsafe { Weak::from_raw(latest) });
+            if let Some(prev) = latest.upgrade() {
+                let newer = Arc::into_raw(keeper.clone()) as *mut _;
+                prev.newer.store(newer, Release);
+            }
+        }
+
+        let weak = Weak::into_raw(Arc::downgrade(&keeper)) as *mut _;
+        let old = self.latest.swap(weak, AcqRel);
+        if !old.is_null() {
+            // Readers of the latest `Weak` may still clone the old one, so
+            // it is dropped through the incinerator. Safe because it came
+            // from `Weak::into_raw`.
+            incin.add(Garbage::Keeper(unsafe { Weak::from_raw(old) }));
+        }
+        keeper
+    }
+}
+
+#[cfg(feature = "snapshot")]
+impl<K, V> Drop for Snapshots<K, V> {
+    fn drop(&mut self) {
+        let latest = *self.latest.get_mut();
+        if !latest.is_null() {
+            // Safe because it came from `Weak::into_raw`, and nobody reads it
+            // anymore.
+            drop(unsafe { Weak::from_raw(latest) });
+        }
+    }
+}
+
+#[cfg(not(feature = "snapshot"))]
+impl<K, V> Snapshots<K, V> {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self { _marker: PhantomData }
+    }
+
+    #[inline(always)]
+    pub fn write(&self) -> Writing<'_> {
+        Writing { _writers: PhantomData }
+    }
+
+    #[inline(always)]
+    pub unsafe fn hold(
+        &self,
+        _removed: &mut Removed<K, V>,
+        _pause: &Pause<Garbage<K, V>>,
+    ) {
+    }
+}
+
+// An operation changing the map, registered in its epoch until dropped.
+pub struct Writing<'snapshots> {
+    #[cfg(feature = "snapshot")]
+    writers: &'snapshots AtomicUsize,
+    #[cfg(not(feature = "snapshot"))]
+    _writers: PhantomData<&'snapshots ()>,
+}
+
+#[cfg(feature = "snapshot")]
+impl<'snapshots> Drop for Writing<'snapshots> {
+    fn drop(&mut self) {
+        // `Release` publishes the changes to a snapshot waiting for the
+        // epoch.
+        self.writers.fetch_sub(1, Release);
+    }
+}
+
+// A snapshot being taken, allowing no other one until dropped.
+#[cfg(feature = "snapshot")]
+struct Taking<'snapshots> {
+    taking: &'snapshots AtomicBool,
+}
+
+#[cfg(feature = "snapshot")]
+impl<'snapshots> Taking<'snapshots> {
+    fn lock<K, V>(snapshots: &'snapshots Snapshots<K, V>) -> Self {
+        let taking = &snapshots.taking;
+        let mut backoff = Backoff::new();
+        // `Acquire` synchronizes with the snapshot taken before.
+        while taking
+            .compare_exchange_weak(false, true, Acquire, Relaxed)
+            .is_err()
+        {
+            backoff.snooze();
+        }
+        Self { taking }
+    }
+}
+
+#[cfg(feature = "snapshot")]
+impl<'snapshots> Drop for Taking<'snapshots> {
+    fn drop(&mut self) {
+        self.taking.store(false, Release);
+    }
+}
+
+// Keeps the pairs removed from a map while its snapshots may still read them.
+// Each snapshot has a keeper, which keeps the keeper of the next snapshot
+// alive, so the keeper of a snapshot lives as long as the snapshot or any
+// snapshot taken before it. A pair removed from the map goes to the latest
+// keeper, so it is kept until every snapshot taken before its removal is
+// dropped. Then, its pairs go to the incinerator of the map, since the map
+// may still read them just like any removed pair.
+#[cfg(feature = "snapshot")]
+pub struct Keeper<K, V> {
+    // A stack of the pairs kept, only popped when the keeper is dropped.
+    pairs: AtomicPtr<Kept<K, V>>,
+    // A raw `Arc` of the keeper of the next snapshot, or null.
+    newer: AtomicPtr<Keeper<K, V>>,
+    incin: Weak<Incinerator<Garbage<K, V>>>,
+    // The address of the `Snapshots` of the map, telling the map apart.
+    snapshots: usize,
+}
+
+#[cfg(feature = "snapshot")]
+struct Kept<K, V> {
+    pair: OwnedAlloc<(K, V)>,
+    next: *mut Kept<K, V>,
+}
+
+#[cfg(feature = "snapshot")]
+impl<K, V> Keeper<K, V> {
+    // Keeps the given pair until this keeper is dropped.
+    pub fn keep(&self, pair: OwnedAlloc<(K, V)>) {
+        let kept = Box::into_raw(Box::new(Kept { pair, next: null_mut() }));
+        let mut next = self.pairs.load(Relaxed);
+        loop {
+            // Safe because the node is not shared until it is pushed.
+            unsafe { (*kept).next = next };
+            // The pairs are only read by the destructor, which has exclusive
+            // access, so nothing needs to be published.
+            match self.pairs.compare_exchange_weak(
+                next,
+                kept,
+                Relaxed,
+                Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(new) => next = new,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "snapshot")]
+impl<K, V> Drop for Keeper<K, V> {
+    fn drop(&mut self) {
+        let incin = self.incin.upgrade();
+        let mut kept = *self.pairs.get_mut();
+        while !kept.is_null() {
+            // Safe because the nodes come from `Box::into_raw`, and we have
+            // exclusive access.
+            let node = unsafe { Box::from_raw(kept) };
+            kept = node.next;
+            match &incin {
+                Some(incin) => incin.add(Garbage::Pair(node.pair)),
+                // Nobody reads the map anymore.
+                None => drop(node.pair),
+            }
+        }
+
+        // The keepers of the next snapshots are dropped one by one, instead
+        // of recursively.
+        let mut newer = *self.newer.get_mut();
+        while !newer.is_null() {
+            // Safe because it came from `Arc::into_raw`.
+            let arc = unsafe { Arc::from_raw(newer) };
+            newer = match Arc::into_inner(arc) {
+                Some(mut keeper) => {
+                    let next = *keeper.newer.get_mut();
+                    *keeper.newer.get_mut() = null_mut();
+                    next
+                },
+                None => null_mut(),
+            };
+        }
+    }
+}
+
+// The pairs kept are only moved between threads.
+#[cfg(feature = "snapshot")]
+unsafe impl<K, V> Send for Keeper<K, V>
+where
+    K: Send,
+    V: Send,
+{
+}
+
+#[cfg(feature = "snapshot")]
+unsafe impl<K, V> Sync for Keeper<K, V>
+where
+    K: Send,
+    V: Send,
+{
+}
@@ -0,0 +1,236 @@
+use super::{BucketOrder, Map};
+use core::hash::{BuildHasher, Hash};
+
+/// A difference between two maps found by [`Map::diff`], where the left
+/// [`Map`] is the one `diff` is called on and the right [`Map`] is the other
+/// one. `K` and `V` are references to the keys and values found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiffEntry<K, V> {
+    /// The key and value of an entry of the left [`Map`] whose key is not in
+    /// the right one.
+    OnlyLeft(K, V),
+    /// The key and value of an entry of the right [`Map`] whose key is not in
+    /// the left one.
+    OnlyRight(K, V),
+    /// A key found in both maps, with the different values of the left
+    /// [`Map`] and of the right one, in this order.
+    Changed(K, V, V),
+}
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+{
+    /// Calls the given closure on every difference between this [`Map`] and
+    /// the other one: entries whose keys are only in one of them, and keys in
+    /// both with different values. This [`Map`] is traversed first, and each
+    /// of its keys is looked up in the other one; then the other [`Map`] is
+    /// traversed, and each of its keys is looked up in this one. Just like in
+    /// [`for_each`](Map::for_each), the incinerators are paused only while
+    /// small chunks of the maps are read, and the closure is called while
+    /// they are paused.
+    ///
+    /// The result is only a snapshot if none of the maps are being
+    /// concurrently mutated. Otherwise, it is a best effort: entries left
+    /// unchanged during the whole diff are reported correctly, but an entry
+    /// inserted, changed or removed meanwhile may be reported as in neither
+    /// state, or not reported at all.
+    pub fn diff<H2, const OTHER_BITS: usize, O2, F>(
+        &self,
+        other: &Map<K, V, H2, OTHER_BITS, O2>,
+        mut visitor: F,
+    ) where
+        K: Hash + Eq,
+        V: PartialEq,
+        O: BucketOrder<K>,
+        H2: BuildHasher,
+        O2: BucketOrder<K>,
+        F: FnMut(DiffEntry<&K, &V>),
+    {
+        self.for_each(|key, val| match other.get(key) {
+            Some(found) if *found.val() == *val => (),
+            Some(found) => visitor(DiffEntry::Changed(key, val, found.val())),
+            None => visitor(DiffEntry::OnlyLeft(key, val)),
+        });
+
+        other.for_each(|key, val| {
+            if !self.contains_key(key) {
+                visitor(DiffEntry::OnlyRight(key, val));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::map::{FixedState, Unordered};
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Arc,
+        thread,
+    };
+    use std::prelude::v1::*;
+
+    type Diff = (
+        HashMap<u32, u32>,
+        HashMap<u32, u32>,
+        HashMap<u32, (u32, u32)>,
+    );
+
+    fn diff_of<H, const BITS: usize, O, H2, const OTHER_BITS: usize, O2>(
+        left: &Map<u32, u32, H, BITS, O>,
+        right: &Map<u32, u32, H2, OTHER_BITS, O2>,
+    ) -> Diff
+    where
+        H: BuildHasher,
+        O: BucketOrder<u32>,
+        H2: BuildHasher,
+        O2: BucketOrder<u32>,
+    {
+        let mut diff = Diff::default();
+        left.diff(right, |entry| match entry {
+            DiffEntry::OnlyLeft(&key, &val) => {
+                assert!(diff.0.insert(key, val).is_none());
+            },
+            DiffEntry::OnlyRight(&key, &val) => {
+                assert!(diff.1.insert(key, val).is_none());
+            },
+            DiffEntry::Changed(&key, &left, &right) => {
+                assert!(diff.2.insert(key, (left, right)).is_none());
+            },
+        });
+        diff
+    }
+
+    #[test]
+    fn three_kinds() {
+        let left = Map::new();
+        let right = Map::new();
+        for (key, val) in [(1, 10), (2, 20), (3, 30), (4, 40)].iter().copied() {
+            left.insert(key, val);
+        }
+        for (key, val) in [(2, 20), (3, 33), (5, 50)].iter().copied() {
+            right.insert(key, val);
+        }
+
+        let (only_left, only_right, changed) = diff_of(&left, &right);
+        assert_eq!(only_left, [(1, 10), (4, 40)].iter().copied().collect());
+        assert_eq!(only_right, [(5, 50)].iter().copied().collect());
+        assert_eq!(changed, [(3, (30, 33))].iter().copied().collect());
+
+        let (only_left, only_right, changed) = diff_of(&right, &left);
+        assert_eq!(only_left, [(5, 50)].iter().copied().collect());
+        assert_eq!(only_right.len(), 2);
+        assert_eq!(changed, [(3, (33, 30))].iter().copied().collect());
+    }
+
+    #[test]
+    fn equal_and_empty() {
+        let left = Map::<u32, u32>::new();
+        let right = Map::new();
+        assert_eq!(diff_of(&left, &right), Diff::default());
+
+        for i in 0 .. 1000 {
+            left.insert(i, i);
+            right.insert(i, i);
+        }
+        assert_eq!(diff_of(&left, &right), Diff::default());
+
+        let empty = Map::new();
+        let (only_left, only_right, changed) = diff_of(&left, &empty);
+        assert_eq!(only_left.len(), 1000);
+        assert!(only_right.is_empty() && changed.is_empty());
+    }
+
+    #[test]
+    fn randomized_against_hash_maps() {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        // The maps differ in hasher, fanout and order, which must not matter.
+        let left = Map::<u32, u32>::new();
+        let right =
+            Map::<u32, u32, FixedState, 4, Unordered>::with_fanout(FixedState);
+        let mut left_std = HashMap::new();
+        let mut right_std = HashMap::new();
+        for _ in 0 .. 20_000 {
+            // The high bits of xorshift are the most random ones.
+            let key = (next() >> 51) as u32;
+            let val = (next() >> 62) as u32;
+            if next() >> 63 == 0 {
+                left.insert(key, val);
+                left_std.insert(key, val);
+            } else {
+                right.insert(key, val);
+                right_std.insert(key, val);
+            }
+        }
+
+        let mut expected = Diff::default();
+        for (&key, &val) in &left_std {
+            match right_std.get(&key) {
+                Some(&found) if found == val => (),
+                Some(&found) => {
+                    expected.2.insert(key, (val, found));
+                },
+                None => {
+                    expected.0.insert(key, val);
+                },
+            }
+        }
+        for (&key, &val) in &right_std {
+            if !left_std.contains_key(&key) {
+                expected.1.insert(key, val);
+            }
+        }
+        assert!(!expected.0.is_empty());
+        assert!(!expected.1.is_empty());
+        assert!(!expected.2.is_empty());
+        assert_eq!(diff_of(&left, &right), expected);
+    }
+
+    #[test]
+    fn concurrent_changes() {
+        const STABLE: u32 = 1000;
+
+        // Keys below `STABLE` are never touched during the diff, so they are
+        // reported exactly; the others come and go.
+        let left = Arc::new(Map::new());
+        let right = Map::new();
+        for i in 0 .. STABLE {
+            left.insert(i, i);
+            right.insert(i, if i % 2 == 0 { i } else { i + 1 });
+        }
+
+        let writer = {
+            let left = left.clone();
+            thread::spawn(move || {
+                for i in 0 .. 20_000 {
+                    let key = STABLE + i % 500;
+                    left.insert(key, i);
+                    left.remove(&(STABLE + (i + 250) % 500));
+                }
+            })
+        };
+
+        let mut changed = HashSet::new();
+        left.diff(&right, |entry| match entry {
+            DiffEntry::OnlyLeft(&key, _) => assert!(key >= STABLE),
+            DiffEntry::OnlyRight(&key, _) => {
+                unreachable!("key {} only in the right map", key)
+            },
+            DiffEntry::Changed(&key, &left, &right) => {
+                assert_eq!((left, right), (key, key + 1));
+                assert!(changed.insert(key));
+            },
+        });
+        writer.join().expect("writer failed");
+        assert_eq!(changed, (0 .. STABLE).filter(|i| i % 2 == 1).collect());
+    }
+}
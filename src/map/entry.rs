@@ -0,0 +1,206 @@
+use super::{
+    hooks::Events,
+    insertion::{InsertNew, Insertion, Located, Preview},
+    order::{BucketOrder, Ordered},
+    HashCode,
+    Map,
+    ReadGuard,
+};
+use core::{cell::Cell, hash::Hash, ptr, sync::atomic::Ordering::*};
+
+/// A view into a single entry of a [`Map`], returned by
+/// [`entry`](Map::entry). The key is hashed only once, when the entry is
+/// created. Each operation is a single interactive insertion retried over the
+/// bucket list, never a lookup followed by an insertion, so no concurrent
+/// modification can slip between testing the entry and writing it.
+#[derive(Debug)]
+pub struct Entry<'map, K, V, H, const BITS: usize = 8, O = Ordered>
+where
+    K: 'map,
+    V: 'map,
+    H: 'map,
+{
+    map: &'map Map<K, V, H, BITS, O>,
+    hash: HashCode,
+    state: State<'map, K, V>,
+}
+
+#[derive(Debug)]
+enum State<'map, K, V>
+where
+    K: 'map,
+    V: 'map,
+{
+    Key(K),
+    Modified(ReadGuard<'map, K, V>),
+}
+
+impl<'map, K, V, H, const BITS: usize, O> Entry<'map, K, V, H, BITS, O> {
+    pub(super) fn new(
+        map: &'map Map<K, V, H, BITS, O>,
+        key: K,
+        hash: HashCode,
+    ) -> Self {
+        Self { map, hash, state: State::Key(key) }
+    }
+
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        match &self.state {
+            State::Key(key) => key,
+            State::Modified(guard) => guard.key(),
+        }
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> Entry<'map, K, V, H, BITS, O>
+where
+    K: Hash + Eq,
+    O: BucketOrder<K>,
+{
+    /// Inserts the given value if no entry with this key is present. Returns
+    /// a guarded reference to the inserted or to the found entry. If the entry
+    /// was previously modified by [`and_modify`](Entry::and_modify), the
+    /// modified entry is returned.
+    pub fn or_insert(self, val: V) -> ReadGuard<'map, K, V> {
+        self.or_insert_with(|| val)
+    }
+
+    /// Inserts the value returned by the given closure if no entry with this
+    /// key is present. The closure is called at most once, and only if no
+    /// entry was found; even so, its value might be discarded if another
+    /// thread concurrently inserts an entry with this key first. Returns a
+    /// guarded reference to the inserted or to the found entry. If the entry
+    /// was previously modified by [`and_modify`](Entry::and_modify), the
+    /// modified entry is returned.
+    pub fn or_insert_with<F>(self, init: F) -> ReadGuard<'map, K, V>
+    where
+        F: FnOnce() -> V,
+    {
+        let events = self.map.events();
+        self.or_insert_noted(init, &events)
+    }
+
+    // Inserts just like `or_insert_with`, recording the inserted pair in the
+    // given events.
+    pub(super) fn or_insert_noted<F>(
+        self,
+        init: F,
+        events: &Events<K, V>,
+    ) -> ReadGuard<'map, K, V>
+    where
+        F: FnOnce() -> V,
+    {
+        let key = match self.state {
+            State::Key(key) => key,
+            State::Modified(guard) => return guard,
+        };
+
+        let mut init = Some(init);
+        let found = Cell::new(ptr::null());
+        let inserter = InsertNew::with_key(
+            |_: &K, val: Option<&mut V>, stored: Option<&(K, V)>| match stored {
+                Some(pair) => {
+                    found.set(pair as *const (K, V));
+                    Preview::Discard
+                },
+                None if val.is_some() => Preview::Keep,
+                None => match init.take() {
+                    Some(init) => Preview::New(init()),
+                    None => Preview::Discard,
+                },
+            },
+            key,
+        );
+        let stored = Cell::new(None);
+        let inserter = Located::new(inserter, &stored);
+
+        let pause = self.map.pause();
+        // Safe because we paused properly.
+        let insertion = unsafe {
+            self.map.insert_top(inserter, self.hash, &pause, events)
+        };
+
+        let pair = match (insertion, stored.get()) {
+            (Insertion::Created, Some(stored)) => {
+                self.map.len.fetch_add(1, Relaxed);
+                stored.as_ptr() as *const (K, V)
+            },
+            (Insertion::Failed(inserter), _) => {
+                drop(inserter);
+                found.get()
+            },
+            // The closure never accepts an existing entry, and a created one
+            // is always stored.
+            _ => unreachable!(),
+        };
+
+        // This is safe because:
+        // 1. The pair was either inserted by us or found in the map, and so
+        // it was reachable while we were paused.
+        // 2. We keep the pause alive in the guard, so the pair is not
+        // deallocated while the guard lives.
+        ReadGuard::new(unsafe { &*pair }, pause)
+    }
+
+    /// Inserts the default value if no entry with this key is present. See
+    /// [`or_insert_with`](Entry::or_insert_with) for details.
+    pub fn or_default(self) -> ReadGuard<'map, K, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// If an entry with this key is present, replaces it with a new entry whose
+    /// value is generated by the given closure from the current value. The new
+    /// entry is atomically swapped in only if the current entry was not
+    /// concurrently replaced, otherwise the closure is called again with the
+    /// newer value. If no entry is present, nothing is done.
+    pub fn and_modify<F>(self, mut modify: F) -> Self
+    where
+        F: FnMut(&V) -> V,
+    {
+        let key = match self.state {
+            State::Key(key) => key,
+            State::Modified(_) => return self,
+        };
+
+        let inserter = InsertNew::with_key(
+            |_: &K, _: Option<&mut V>, stored: Option<&(K, V)>| match stored {
+                Some((_, val)) => Preview::New(modify(val)),
+                None => Preview::Discard,
+            },
+            key,
+        );
+        let stored = Cell::new(None);
+        let inserter = Located::new(inserter, &stored);
+
+        let events = self.map.events();
+        let pause = self.map.pause();
+        // Safe because we paused properly.
+        let insertion = unsafe {
+            self.map.insert_top(inserter, self.hash, &pause, &events)
+        };
+
+        let state = match (insertion, stored.get()) {
+            (Insertion::Updated(_), Some(stored)) => {
+                // This is safe because:
+                // 1. The pair was inserted by us while we were paused.
+                // 2. We keep the pause alive in the guard, so the pair is not
+                // deallocated while the guard lives.
+                let pair = unsafe { &*stored.as_ptr() };
+                State::Modified(ReadGuard::new(pair, pause))
+            },
+            (Insertion::Failed(inserter), _) => {
+                let (key, _) = inserter.into_inner().into_pair();
+                State::Key(key)
+            },
+            // The closure never accepts an absent entry, and an updated one is
+            // always stored.
+            _ => unreachable!(),
+        };
+
+        Self { map: self.map, hash: self.hash, state }
+    }
+}
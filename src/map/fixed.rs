@@ -0,0 +1,56 @@
+#![allow(deprecated)]
+
+use core::hash::{BuildHasher, Hasher, SipHasher};
+
+// The keys of the SipHash used by `FixedState`. Any constant works, these are
+// just the first digits of pi.
+const KEY0: u64 = 0x243f_6a88_85a3_08d3;
+const KEY1: u64 = 0x1319_8a2e_0370_7344;
+
+/// A hasher builder whose hashers are SipHash-2-4 with a fixed key. It is the
+/// default hasher builder of [`Map`](super::Map) and [`Set`](::set::Set)
+/// without the `std` feature, where
+/// [`RandomState`](https://doc.rust-lang.org/std/collections/hash_map/struct.RandomState.html)
+/// is not available. Since the key is the same for every map and every run,
+/// an adversary who controls the keys can make them collide; use a randomly
+/// seeded hasher builder if that is a concern.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FixedState;
+
+impl FixedState {
+    /// Creates the hasher builder.
+    pub fn new() -> Self {
+        FixedState
+    }
+}
+
+impl BuildHasher for FixedState {
+    type Hasher = FixedHasher;
+
+    fn build_hasher(&self) -> FixedHasher {
+        FixedHasher { inner: SipHasher::new_with_keys(KEY0, KEY1) }
+    }
+}
+
+/// The hasher built by [`FixedState`].
+#[derive(Debug, Clone)]
+pub struct FixedHasher {
+    inner: SipHasher,
+}
+
+impl FixedHasher {
+    // A SipHash-2-4 hasher with the given keys instead of the fixed ones.
+    pub(super) fn with_keys(key0: u64, key1: u64) -> Self {
+        Self { inner: SipHasher::new_with_keys(key0, key1) }
+    }
+}
+
+impl Hasher for FixedHasher {
+    fn finish(&self) -> u64 {
+        self.inner.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.inner.write(bytes)
+    }
+}
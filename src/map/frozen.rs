@@ -0,0 +1,296 @@
+use super::{order::BucketOrder, DefaultHashBuilder, Map, Ordered, DEBUG_LEN};
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::FusedIterator,
+    marker::PhantomData,
+    slice,
+};
+
+// The index of an empty slot.
+const EMPTY: usize = usize::MAX;
+
+/// An immutable snapshot of a [`Map`], made by [`Map::freeze`] for maps which
+/// are only read once filled. The entries are kept in a flat open-addressing
+/// table, so lookups need no atomic operations nor incinerator pauses, and
+/// they return plain references. [`thaw`](FrozenMap::thaw) turns it back into
+/// a [`Map`].
+pub struct FrozenMap<
+    K,
+    V,
+    H = DefaultHashBuilder,
+    const BITS: usize = 8,
+    O = Ordered,
+> {
+    pairs: Box<[(K, V)]>,
+    // Linear probing over a power of two of slots, at least a quarter of them
+    // empty, so probe sequences stay short and always end.
+    slots: Box<[Slot]>,
+    builder: H,
+    _order: PhantomData<O>,
+}
+
+// The hash of a key, which spares comparing keys in most probes, and where
+// its pair is.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    hash: u64,
+    index: usize,
+}
+
+impl<K, V, H, const BITS: usize, O> FrozenMap<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+{
+    pub(super) fn new(pairs: Vec<(K, V)>, builder: H) -> Self
+    where
+        K: Hash,
+    {
+        let pairs = pairs.into_boxed_slice();
+        let len = pairs.len() + pairs.len() / 3 + 1;
+        let empty = Slot { hash: 0, index: EMPTY };
+        let mut slots = vec![empty; len.next_power_of_two()].into_boxed_slice();
+        let mask = slots.len() - 1;
+
+        for (index, (key, _)) in pairs.iter().enumerate() {
+            let hash = builder.hash_one(key);
+            let mut pos = hash as usize & mask;
+            while slots[pos].index != EMPTY {
+                pos = (pos + 1) & mask;
+            }
+            slots[pos] = Slot { hash, index };
+        }
+
+        Self { pairs, slots, builder, _order: PhantomData }
+    }
+
+    /// Searches for the entry identified by the given key, returning a
+    /// reference to its value. Just like [`Map::get`], [`Hash`] and [`Eq`]
+    /// must be implemented in the same way for the borrowed type and the
+    /// stored type.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: ?Sized + Hash + Eq,
+        K: Borrow<Q>,
+    {
+        self.get_key_value(key).map(|(_, val)| val)
+    }
+
+    /// Just like [`get`](FrozenMap::get), but the stored key is returned
+    /// along with the value.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        Q: ?Sized + Hash + Eq,
+        K: Borrow<Q>,
+    {
+        let hash = self.builder.hash_one(key);
+        let mask = self.slots.len() - 1;
+        let mut pos = hash as usize & mask;
+
+        loop {
+            let slot = self.slots[pos];
+            if slot.index == EMPTY {
+                break None;
+            }
+            if slot.hash == hash {
+                let (stored, val) = &self.pairs[slot.index];
+                if stored.borrow() == key {
+                    break Some((stored, val));
+                }
+            }
+            pos = (pos + 1) & mask;
+        }
+    }
+
+    /// Tests whether an entry is identified by the given key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Eq,
+        K: Borrow<Q>,
+    {
+        self.get_key_value(key).is_some()
+    }
+
+    /// Moves the entries back into a [`Map`], with a new incinerator and no
+    /// hooks. The pairs are reinserted through exclusive access, so nothing
+    /// is cloned.
+    pub fn thaw(self) -> Map<K, V, H, BITS, O>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let mut map = Map::with_fanout(self.builder);
+        for (key, val) in self.pairs.into_vec() {
+            map.insert_mut(key, val);
+        }
+        map
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> FrozenMap<K, V, H, BITS, O> {
+    /// The number of entries in this [`FrozenMap`].
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Returns whether this [`FrozenMap`] has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Creates an iterator over references to the key-value entries, in no
+    /// particular order.
+    pub fn iter(&self) -> FrozenIter<'_, K, V> {
+        FrozenIter { inner: self.pairs.iter() }
+    }
+
+    /// The hasher buider used by this [`FrozenMap`], i.e. the one of the
+    /// [`Map`] it was made from.
+    pub fn hasher(&self) -> &H {
+        &self.builder
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> IntoIterator
+    for &'map FrozenMap<K, V, H, BITS, O>
+{
+    type Item = (&'map K, &'map V);
+
+    type IntoIter = FrozenIter<'map, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> fmt::Debug for FrozenMap<K, V, H, BITS, O>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    /// Prints at most `128` entries, followed by the count of the omitted
+    /// ones, just like the `Debug` implementation of [`Map`].
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.write_str("FrozenMap {")?;
+        for (count, (key, val)) in self.iter().take(DEBUG_LEN).enumerate() {
+            let sep = if count == 0 { "" } else { ", " };
+            write!(fmtr, "{}{:?}: {:?}", sep, key, val)?;
+        }
+        if self.len() > DEBUG_LEN {
+            write!(fmtr, ", ... ({} more)", self.len() - DEBUG_LEN)?;
+        }
+        fmtr.write_str("}")
+    }
+}
+
+/// An iterator over references to the key-value entries of a [`FrozenMap`].
+#[derive(Debug, Clone)]
+pub struct FrozenIter<'map, K, V>
+where
+    K: 'map,
+    V: 'map,
+{
+    inner: slice::Iter<'map, (K, V)>,
+}
+
+impl<'map, K, V> Iterator for FrozenIter<'map, K, V> {
+    type Item = (&'map K, &'map V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, val)| (key, val))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'map, K, V> ExactSizeIterator for FrozenIter<'map, K, V> {}
+
+impl<'map, K, V> FusedIterator for FrozenIter<'map, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use map::{support::ConstState, Unordered};
+    use std::{
+        collections::hash_map::RandomState,
+        string::{String, ToString},
+    };
+    use std::prelude::v1::*;
+
+    #[test]
+    fn round_trip() {
+        let map = Map::new();
+        for i in 0 .. 1000u64 {
+            map.insert(i, i * 2);
+        }
+
+        let frozen = map.freeze();
+        assert_eq!(frozen.len(), 1000);
+        for i in 0 .. 1000 {
+            assert_eq!(frozen.get(&i), Some(&(i * 2)));
+        }
+        assert!(frozen.get(&1000).is_none());
+        assert_eq!(frozen.get_key_value(&7), Some((&7, &14)));
+        let mut keys = frozen.iter().map(|(&key, _)| key).collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, (0 .. 1000).collect::<Vec<_>>());
+
+        let map = frozen.thaw();
+        assert_eq!(map.len(), 1000);
+        for i in 0 .. 1000 {
+            assert_eq!(map.get_cloned(&i), Some(i * 2));
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn colliding_round_trip() {
+        let map = Map::with_hasher(ConstState);
+        for i in 0 .. 300u64 {
+            map.insert(i.to_string(), i);
+        }
+        assert_eq!(map.stats().max_bucket_len(), 300);
+
+        let frozen = map.freeze();
+        for i in 0 .. 300u64 {
+            assert_eq!(frozen.get(i.to_string().as_str()), Some(&i));
+        }
+        assert!(!frozen.contains_key("300"));
+
+        let map = frozen.thaw();
+        assert_eq!(map.len(), 300);
+        assert_eq!(map.stats().max_bucket_len(), 300);
+        for i in 0 .. 300u64 {
+            assert_eq!(map.get_cloned(i.to_string().as_str()), Some(i));
+        }
+        map.validate();
+
+        let map = Map::<String, u64, _, 8, Unordered>::with_fanout(ConstState);
+        for i in 0 .. 10u64 {
+            map.insert(i.to_string(), i);
+        }
+        let map = map.freeze().thaw();
+        for i in 0 .. 10u64 {
+            assert_eq!(map.get_cloned(i.to_string().as_str()), Some(i));
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn empty_and_debug() {
+        let frozen = Map::<u64, u64>::new().freeze();
+        assert!(frozen.is_empty());
+        assert!(frozen.get(&0).is_none());
+        assert_eq!(format!("{:?}", frozen), "FrozenMap {}");
+
+        let map = Map::with_hasher(RandomState::new());
+        map.insert(1, 'a');
+        let frozen = map.freeze();
+        assert_eq!(format!("{:?}", frozen), "FrozenMap {1: 'a'}");
+        assert!(frozen.thaw().get(&1).is_some());
+    }
+}
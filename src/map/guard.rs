@@ -1,7 +1,8 @@
-use super::bucket::Garbage;
-use incin::{Incinerator, Pause};
-use owned_alloc::OwnedAlloc;
-use std::{
+#[cfg(feature = "snapshot")]
+use super::cow::Keeper;
+use super::{bucket::Garbage, cow::Snapshots, trace::Hold, HashCode};
+use alloc::sync::{Arc, Weak};
+use core::{
     borrow::Borrow,
     cmp::Ordering,
     fmt,
@@ -9,8 +10,12 @@ use std::{
     mem::forget,
     ops::Deref,
     ptr::NonNull,
-    sync::{Arc, Weak},
 };
+use incin::{Incinerator, Pause};
+use owned_alloc::OwnedAlloc;
+
+#[cfg(feature = "std")]
+use std::thread;
 
 /// A read-operation guard. This ensures no entry allocation is
 /// mutated or freed while potential reads are performed.
@@ -126,10 +131,13 @@ impl<'map, K, V> Borrow<(K, V)> for ReadGuard<'map, K, V> {
     }
 }
 
+// A guard is a shared reference to the entry, and the same entry might be
+// guarded by another thread, so sending it requires `Sync`, just like sending
+// a `&(K, V)`.
 unsafe impl<'map, K, V> Send for ReadGuard<'map, K, V>
 where
-    K: Send,
-    V: Send,
+    K: Sync,
+    V: Sync,
 {
 }
 
@@ -140,6 +148,57 @@ where
 {
 }
 
+/// A guarded reference to the value of an entry, returned by
+/// [`get_guarded`](super::Map::get_guarded). It dereferences to the value,
+/// while the key is available through [`key`](ValueGuard::key).
+///
+/// Just like [`ReadGuard`], it keeps the incinerator paused while alive. No
+/// memory detached from the [`Map`](super::Map), by any thread, is reclaimed
+/// until every guard is dropped, so holding a guard for long makes garbage
+/// pile up globally. With the `tracing` and `std` features, a warning event is
+/// emitted if a guard is dropped more than a second after it was created.
+#[must_use = "the value is only guarded while the guard is alive"]
+#[derive(Debug)]
+pub struct ValueGuard<'map, K, V>
+where
+    K: 'map,
+    V: 'map,
+{
+    inner: ReadGuard<'map, K, V>,
+    hold: Hold,
+}
+
+impl<'map, K, V> ValueGuard<'map, K, V> {
+    pub(super) fn new(inner: ReadGuard<'map, K, V>) -> Self {
+        Self { inner, hold: Hold::new() }
+    }
+
+    /// The key of the guarded entry.
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+}
+
+impl<'map, K, V> Deref for ValueGuard<'map, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.inner.val()
+    }
+}
+
+impl<'map, K, V> AsRef<V> for ValueGuard<'map, K, V> {
+    fn as_ref(&self) -> &V {
+        self.inner.val()
+    }
+}
+
+impl<'map, K, V> Drop for ValueGuard<'map, K, V> {
+    fn drop(&mut self) {
+        self.hold.release("ValueGuard");
+    }
+}
+
 /// A removed entry. It can be reinserted at the same [`Map`](super::Map) it was
 /// removed. It can also be inserted on another [`Map`](super::Map), but only if
 /// either the [`Map`](super::Map) is dropped, there are no sensitive reads
@@ -148,14 +207,68 @@ where
 pub struct Removed<K, V> {
     nnptr: NonNull<(K, V)>,
     origin: Weak<Incinerator<Garbage<K, V>>>,
+    // The hash of the key, as stored in the bucket the entry was removed
+    // from. It is only trusted by maps with the given stamp, i.e. maps hashing
+    // keys the same way as the original map. A zero stamp trusts no map.
+    hash: HashCode,
+    stamp: usize,
+    // The keeper of the snapshots which may still read the pair, see
+    // `Keeper`. The pair goes to it when dropped, unless it is dropped
+    // already.
+    #[cfg(feature = "snapshot")]
+    keeper: Option<Weak<Keeper<K, V>>>,
 }
 
 impl<K, V> Removed<K, V> {
     pub(super) fn new(
         alloc: OwnedAlloc<(K, V)>,
         origin: &Arc<Incinerator<Garbage<K, V>>>,
+        hash: HashCode,
     ) -> Self {
-        Self { nnptr: alloc.into_raw(), origin: Arc::downgrade(origin) }
+        Self {
+            nnptr: alloc.into_raw(),
+            origin: Arc::downgrade(origin),
+            hash,
+            stamp: 0,
+            #[cfg(feature = "snapshot")]
+            keeper: None,
+        }
+    }
+
+    // Lets the given keeper keep the pair for the snapshots which may still
+    // read it.
+    #[cfg(feature = "snapshot")]
+    pub(super) fn hold(this: &mut Self, keeper: Weak<Keeper<K, V>>) {
+        this.keeper = Some(keeper);
+    }
+
+    // Whether snapshots may still read the pair, in which case it cannot be
+    // moved out, nor reinserted into another map.
+    #[cfg(feature = "snapshot")]
+    pub(super) fn is_held(this: &Self) -> bool {
+        match &this.keeper {
+            Some(keeper) => keeper.strong_count() > 0,
+            None => false,
+        }
+    }
+
+    #[cfg(not(feature = "snapshot"))]
+    pub(super) fn is_held(_this: &Self) -> bool {
+        false
+    }
+
+    // Records the stamp of the map whose hash is cached.
+    pub(super) fn stamp(this: &mut Self, stamp: usize) {
+        this.stamp = stamp;
+    }
+
+    // The cached hash of the key, if the map with the given stamp can trust it.
+    pub(super) fn hash_for(this: &Self, stamp: usize) -> Option<HashCode> {
+        if stamp != 0 && this.stamp == stamp {
+            Some(this.hash)
+        } else {
+            None
+        }
     }
 
     pub(super) fn into_alloc(mut this: Self) -> OwnedAlloc<(K, V)> {
@@ -164,6 +277,10 @@ impl<K, V> Removed<K, V> {
         // There is no other way of dropping the weak and forgetting ourselves.
         // Rust does not let us move fields of a `Drop` struct.
         unsafe { (&mut this.origin as *mut Weak<_>).drop_in_place() }
+        #[cfg(feature = "snapshot")]
+        unsafe {
+            (&mut this.keeper as *mut Option<Weak<_>>).drop_in_place()
+        }
         forget(this);
         alloc
     }
@@ -172,10 +289,20 @@ impl<K, V> Removed<K, V> {
         this.nnptr
     }
 
+    // Whether the pair may be inserted into the map with the given
+    // incinerator and snapshots. A pair held for snapshots may only go back
+    // to their map.
     pub(super) fn is_usable_by(
         this: &mut Self,
         origin: &Arc<Incinerator<Garbage<K, V>>>,
+        _snapshots: &Snapshots<K, V>,
     ) -> bool {
+        #[cfg(feature = "snapshot")]
+        if let Some(keeper) = this.keeper.as_ref().and_then(Weak::upgrade) {
+            if !_snapshots.is_keeper(&keeper) {
+                return false;
+            }
+        }
         match &this.origin.upgrade() {
             None => true,
             Some(arc) if Arc::ptr_eq(arc, origin) => true,
@@ -204,10 +331,25 @@ impl<K, V> Removed<K, V> {
         v
     }
 
+    /// Clones the key and value into an owned pair. Unlike
+    /// [`Removed::try_unwrap`], this is always available, regardless of
+    /// pauses.
+    pub fn to_pair(&self) -> (K, V)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        (self.key().clone(), self.val().clone())
+    }
+
     /// Tries to acquire a mutable reference to the pair. Succeeds only if
     /// either the original [`Map`](super::Map) was dropped or no sensitive
-    /// reads are being performed.
+    /// reads are being performed, and no snapshot of the map taken before the
+    /// removal is alive.
     pub fn try_as_mut(this: &mut Self) -> Option<&mut (K, V)> {
+        if Self::is_held(this) {
+            return None;
+        }
         let success = match this.origin.upgrade() {
             None => true,
             Some(arc) => {
@@ -221,6 +363,8 @@ impl<K, V> Removed<K, V> {
         };
 
         if success {
+            // The key may be changed, so its hash can no longer be trusted.
+            this.stamp = 0;
             // We own the allocation. This must be safe.
             Some(unsafe { this.nnptr.as_mut() })
         } else {
@@ -230,33 +374,119 @@ impl<K, V> Removed<K, V> {
 
     /// Tries to convert this wrapper into the pair. Succeeds only if either the
     /// original [`Map`](super::Map) was dropped or no sensitive reads are being
-    /// performed.
+    /// performed. See [`Removed::try_unwrap`].
     pub fn try_into(this: Self) -> Result<(K, V), Self> {
+        Self::try_unwrap(this)
+    }
+
+    /// Tries to move the pair out of this wrapper, freeing the allocation
+    /// immediately. On failure, the wrapper is given back so the caller can
+    /// retry later.
+    ///
+    /// This succeeds only if the original [`Map`](super::Map) was dropped or
+    /// its incinerator has no active pauses, and, with the `snapshot`
+    /// feature, no snapshot of the map taken before the removal is alive,
+    /// since snapshots may still read the pair. This is sound because:
+    /// 1. The entry was logically removed before this wrapper was created, and
+    ///    no operation started after that dereferences the pair of a logically
+    ///    removed entry.
+    /// 2. Every operation which might have loaded the pair before the removal
+    ///    did it while the incinerator was paused, and keeps it paused while it
+    ///    uses the pair (e.g. through a [`ReadGuard`]).
+    /// 3. Therefore, if the pause counter is zero after the removal, nobody can
+    ///    still hold the pair.
+    pub fn try_unwrap(this: Self) -> Result<(K, V), Self> {
+        if Self::is_held(&this) {
+            return Err(this);
+        }
         let success = match this.origin.upgrade() {
             None => true,
             Some(arc) => arc.try_clear(),
         };
 
         if success {
-            // We own the allocation. This must be safe.
-            let (ret, _) =
-                unsafe { OwnedAlloc::from_raw(this.nnptr) }.move_inner();
-            forget(this);
+            let (ret, _) = Self::into_alloc(this).move_inner();
             Ok(ret)
         } else {
             Err(this)
         }
     }
+
+    /// Moves the pair out of this wrapper, yielding the current thread until
+    /// [`Removed::try_unwrap`] succeeds, i.e. until the incinerator of the
+    /// original [`Map`](super::Map) has no active pauses, and the snapshots
+    /// which may read the pair are dropped. Intended for shutdown paths and
+    /// tests, where blocking briefly is fine. Only available with the `std`
+    /// feature.
+    ///
+    /// # Panics
+    /// Panics if the current thread itself pauses the incinerator (e.g. by
+    /// holding a [`ReadGuard`]), since it would wait forever otherwise.
+    /// Waiting for a snapshot held by the current thread is not checked, and
+    /// never ends either.
+    #[cfg(feature = "std")]
+    pub fn into_inner(mut this: Self) -> (K, V) {
+        loop {
+            this = match Self::try_unwrap(this) {
+                Ok(pair) => break pair,
+                Err(this) => this,
+            };
+
+            if let Some(incin) = this.origin.upgrade() {
+                if incin.is_paused_locally() {
+                    panic!(
+                        "Removed::into_inner called while the current thread \
+                         pauses the incinerator"
+                    );
+                }
+            }
+            thread::yield_now();
+        }
+    }
 }
 
 impl<K, V> Drop for Removed<K, V> {
     fn drop(&mut self) {
         // We own the allocation. This must be safe.
         let alloc = unsafe { OwnedAlloc::from_raw(self.nnptr) };
+        #[cfg(feature = "snapshot")]
+        if let Some(keeper) = self.keeper.as_ref().and_then(Weak::upgrade) {
+            return keeper.keep(alloc);
+        }
         self.origin.upgrade().map(|incin| incin.add(Garbage::Pair(alloc)));
     }
 }
 
+impl<K, V> Clone for Removed<K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    /// Clones the pair into a new allocation. The clone was never shared, so it
+    /// is not bound to any incinerator and can be inserted on any
+    /// [`Map`](super::Map).
+    fn clone(&self) -> Self {
+        Self {
+            nnptr: OwnedAlloc::new(self.to_pair()).into_raw(),
+            origin: Weak::new(),
+            hash: self.hash,
+            stamp: self.stamp,
+            #[cfg(feature = "snapshot")]
+            keeper: None,
+        }
+    }
+}
+
+impl<'removed, K, V> From<&'removed Removed<K, V>> for (K, V)
+where
+    K: Clone,
+    V: Clone,
+{
+    fn from(removed: &'removed Removed<K, V>) -> Self {
+        removed.to_pair()
+    }
+}
+
 impl<K, V> Deref for Removed<K, V> {
     type Target = (K, V);
 
@@ -346,13 +576,26 @@ impl<K, V> Borrow<(K, V)> for Removed<K, V> {
     }
 }
 
+// A removed entry is owned by the `Removed` alone, but readers which found it
+// before it was removed may still reference it while their pauses last, and
+// they may be in the sending thread itself, e.g. through a guard kept across
+// the removal. Reading the entry from the receiving thread meanwhile shares it
+// between threads, hence `Sync`, just like for `Arc<(K, V)>`. The entry is
+// dropped by the receiving thread, or by whichever thread clears the garbage,
+// hence `Send`. Dropping a `Removed` from any thread is sound: the entry was
+// detached before it was sent, so the pause counter checked by the receiving
+// thread covers every reader. If some reader is still paused, the entry is kept
+// in the garbage list of the receiving thread, until that thread finds the
+// incinerator unpaused or the incinerator is dropped.
 unsafe impl<K, V> Send for Removed<K, V>
 where
-    K: Send,
-    V: Send,
+    K: Send + Sync,
+    V: Send + Sync,
 {
 }
 
+// Sharing a removed entry only lets other threads read it, since taking it
+// out or changing it requires ownership or an exclusive reference.
 unsafe impl<K, V> Sync for Removed<K, V>
 where
     K: Sync,
@@ -0,0 +1,456 @@
+use super::{
+    BucketOrder,
+    DefaultHashBuilder,
+    Iter,
+    Map,
+    MapStats,
+    MemoryUsage,
+    Ordered,
+    ReadGuard,
+    ScanCursor,
+    ValueGuard,
+    Version,
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    borrow::Borrow,
+    cmp,
+    fmt,
+    hash::{BuildHasher, Hash},
+    ops::Deref,
+};
+
+/// A shared [`Map`] which may be read and written, made by
+/// [`Map::handles`]. It dereferences to the [`Map`], so every operation is
+/// available through it, and it makes [`ReadHandle`]s of the same [`Map`].
+pub struct WriteHandle<
+    K,
+    V,
+    H = DefaultHashBuilder,
+    const BITS: usize = 8,
+    O = Ordered,
+> {
+    map: Arc<Map<K, V, H, BITS, O>>,
+}
+
+/// A shared [`Map`] which may only be read, made by [`Map::handles`] or
+/// [`WriteHandle::read_handle`], e.g. for parts of a program which must never
+/// change the [`Map`]. Only the operations which do not change the [`Map`]
+/// are available, with the same guarantees as the ones of [`Map`].
+pub struct ReadHandle<
+    K,
+    V,
+    H = DefaultHashBuilder,
+    const BITS: usize = 8,
+    O = Ordered,
+> {
+    map: Arc<Map<K, V, H, BITS, O>>,
+}
+
+// The handles made by `Map::handles`.
+type Handles<K, V, H, const BITS: usize, O> =
+    (WriteHandle<K, V, H, BITS, O>, ReadHandle<K, V, H, BITS, O>);
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O> {
+    /// Splits a shared [`Map`] into a handle which may write to it and one
+    /// which may only read it. Both can be cloned, and more read handles can
+    /// be made from the write handle, so the type system tells which parts
+    /// of a program may change the [`Map`].
+    pub fn handles(self: Arc<Self>) -> Handles<K, V, H, BITS, O> {
+        let read = ReadHandle { map: self.clone() };
+        (WriteHandle { map: self }, read)
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> WriteHandle<K, V, H, BITS, O> {
+    /// Makes a handle which may only read the [`Map`] of this handle.
+    pub fn read_handle(&self) -> ReadHandle<K, V, H, BITS, O> {
+        ReadHandle { map: self.map.clone() }
+    }
+
+    /// Gives the shared [`Map`] of this handle back.
+    pub fn into_inner(self) -> Arc<Map<K, V, H, BITS, O>> {
+        self.map
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Deref for WriteHandle<K, V, H, BITS, O> {
+    type Target = Map<K, V, H, BITS, O>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Clone for WriteHandle<K, V, H, BITS, O> {
+    fn clone(&self) -> Self {
+        Self { map: self.map.clone() }
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> fmt::Debug for WriteHandle<K, V, H, BITS, O>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    H: BuildHasher,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("WriteHandle").field("map", &self.map).finish()
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> ReadHandle<K, V, H, BITS, O> {
+    /// The number of entries. See [`Map::len`].
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns whether there are no entries. See [`Map::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Creates an iterator over guarded references to the entries. See
+    /// [`Map::iter`].
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.map.iter()
+    }
+
+    /// Computes structural statistics. See [`Map::stats`].
+    pub fn stats(&self) -> MapStats {
+        self.map.stats()
+    }
+
+    /// Estimates the memory used. See [`Map::memory_usage`].
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.map.memory_usage()
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> ReadHandle<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+{
+    /// The hasher builder of the [`Map`]. See [`Map::hasher`].
+    pub fn hasher(&self) -> &H {
+        self.map.hasher()
+    }
+
+    /// Searches for the entry identified by the given key. See [`Map::get`].
+    pub fn get<'map, Q>(&'map self, key: &Q) -> Option<ReadGuard<'map, K, V>>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        self.map.get(key)
+    }
+
+    /// Searches for the entry identified by the given key, guarding only its
+    /// value. See [`Map::get_guarded`].
+    #[must_use]
+    pub fn get_guarded<'map, Q>(
+        &'map self,
+        key: &Q,
+    ) -> Option<ValueGuard<'map, K, V>>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        self.map.get_guarded(key)
+    }
+
+    /// Searches for the entry identified by the given key and clones its
+    /// value. See [`Map::get_cloned`].
+    pub fn get_cloned<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.map.get_cloned(key)
+    }
+
+    /// Searches for the entry identified by the given key and clones its key
+    /// and value. See [`Map::get_pair_cloned`].
+    pub fn get_pair_cloned<Q>(&self, key: &Q) -> Option<(K, V)>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q> + Clone,
+        V: Clone,
+    {
+        self.map.get_pair_cloned(key)
+    }
+
+    /// Searches for the entry identified by the given key and clones its
+    /// stored key. See [`Map::get_key_cloned`].
+    pub fn get_key_cloned<Q>(&self, key: &Q) -> Option<K>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q> + Clone,
+    {
+        self.map.get_key_cloned(key)
+    }
+
+    /// Searches for the entries identified by each of the given keys, calling
+    /// the given closure on the found ones. See [`Map::get_many`].
+    pub fn get_many<'key, Q, I, F, T>(
+        &self,
+        keys: I,
+        reader: F,
+    ) -> Vec<Option<T>>
+    where
+        Q: ?Sized + Hash + Eq + 'key,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        I: IntoIterator<Item = &'key Q>,
+        F: FnMut(&K, &V) -> T,
+    {
+        self.map.get_many(keys, reader)
+    }
+
+    /// Searches for the entry identified by the given key, reading it along
+    /// with its version. See [`Map::get_versioned`].
+    pub fn get_versioned<Q, F, T>(
+        &self,
+        key: &Q,
+        reader: F,
+    ) -> Option<(Version, T)>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        F: FnOnce(&K, &V) -> T,
+    {
+        self.map.get_versioned(key, reader)
+    }
+
+    /// Tests if the entry identified by the given key is present. See
+    /// [`Map::contains_key`].
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Calls the given closure on some entry, if any. See [`Map::get_any`].
+    pub fn get_any<F, T>(&self, reader: F) -> Option<T>
+    where
+        F: FnOnce(&K, &V) -> T,
+    {
+        self.map.get_any(reader)
+    }
+
+    /// Calls the given closure on every entry. See [`Map::for_each`].
+    pub fn for_each<F>(&self, visitor: F)
+    where
+        F: FnMut(&K, &V),
+    {
+        self.map.for_each(visitor)
+    }
+
+    /// Calls the given closure on a batch of entries, resuming from the given
+    /// cursor. See [`Map::scan`].
+    pub fn scan<F>(
+        &self,
+        cursor: ScanCursor,
+        batch: usize,
+        visitor: F,
+    ) -> Option<ScanCursor>
+    where
+        F: FnMut(&K, &V),
+    {
+        self.map.scan(cursor, batch, visitor)
+    }
+
+    /// Calls the given closure on every key. See [`Map::keys`].
+    pub fn keys<F>(&self, visitor: F)
+    where
+        F: FnMut(&K),
+    {
+        self.map.keys(visitor)
+    }
+
+    /// Collects clones of every key. See [`Map::keys_cloned`].
+    pub fn keys_cloned(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.map.keys_cloned()
+    }
+
+    /// Calls the given closure on every value. See [`Map::values`].
+    pub fn values<F>(&self, visitor: F)
+    where
+        F: FnMut(&V),
+    {
+        self.map.values(visitor)
+    }
+
+    /// Collects clones of every value. See [`Map::values_cloned`].
+    pub fn values_cloned(&self) -> Vec<V>
+    where
+        V: Clone,
+    {
+        self.map.values_cloned()
+    }
+
+    /// Collects clones of every entry, sorted by key. See
+    /// [`Map::to_sorted_vec`].
+    pub fn to_sorted_vec(&self) -> Vec<(K, V)>
+    where
+        K: Ord + Clone,
+        V: Clone,
+    {
+        self.map.to_sorted_vec()
+    }
+
+    /// Collects clones of every entry, sorted with the given comparator. See
+    /// [`Map::to_vec_by`].
+    pub fn to_vec_by<F>(&self, compare: F) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+        F: FnMut(&(K, V), &(K, V)) -> cmp::Ordering,
+    {
+        self.map.to_vec_by(compare)
+    }
+
+    /// Folds every entry into an accumulator. See [`Map::fold`].
+    pub fn fold<B, F>(&self, init: B, fold: F) -> B
+    where
+        F: FnMut(B, &K, &V) -> B,
+    {
+        self.map.fold(init, fold)
+    }
+
+    /// Counts the entries matching the given predicate. See
+    /// [`Map::count_matching`].
+    pub fn count_matching<F>(&self, predicate: F) -> usize
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.map.count_matching(predicate)
+    }
+}
+
+impl<'handle, K, V, H, const BITS: usize, O> IntoIterator
+    for &'handle ReadHandle<K, V, H, BITS, O>
+{
+    type Item = ReadGuard<'handle, K, V>;
+
+    type IntoIter = Iter<'handle, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Clone for ReadHandle<K, V, H, BITS, O> {
+    fn clone(&self) -> Self {
+        Self { map: self.map.clone() }
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> fmt::Debug for ReadHandle<K, V, H, BITS, O>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+    H: BuildHasher,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("ReadHandle").field("map", &self.map).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering::*},
+        thread,
+    };
+    use std::prelude::v1::*;
+
+    #[test]
+    fn read_handle_sees_writes() {
+        let (write, read) = Arc::new(Map::new()).handles();
+        write.insert("one", 1);
+        write.insert("two", 2);
+        assert_eq!(read.len(), 2);
+        assert_eq!(read.get("one").map(|guard| *guard.val()), Some(1));
+        assert_eq!(read.get_cloned("two"), Some(2));
+        assert!(read.contains_key("two"));
+        assert!(!read.contains_key("three"));
+        assert_eq!(read.to_sorted_vec(), vec![("one", 1), ("two", 2)]);
+        assert_eq!((&read).into_iter().count(), 2);
+
+        let other = write.read_handle();
+        write.remove("one");
+        assert_eq!(other.keys_cloned(), vec!["two"]);
+        assert_eq!(read.fold(0, |acc, _, val| acc + val), 2);
+        assert_eq!(write.into_inner().len(), 1);
+    }
+
+    #[test]
+    fn many_readers_one_writer() {
+        const KEYS: u64 = 1000;
+
+        let map = Arc::new(Map::new());
+        for i in 0 .. KEYS {
+            map.insert(i, i * 2);
+        }
+        let (write, read) = map.handles();
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers = (0 .. 4)
+            .map(|_| {
+                let read = read.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    while !done.load(Acquire) {
+                        // Stable keys are always there, with either value.
+                        for i in 0 .. KEYS {
+                            let val = read.get_cloned(&i).expect("stable key");
+                            assert!(val == i * 2 || val == i * 3);
+                        }
+                        read.for_each(|key, val| {
+                            assert!(*key >= KEYS || *val % *key.max(&1) == 0)
+                        });
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let writer = {
+            let write = write.clone();
+            thread::spawn(move || {
+                for round in 0 .. 20 {
+                    for i in 0 .. KEYS {
+                        write.insert(i, i * (2 + round % 2));
+                        write.insert(KEYS + i, round);
+                        write.remove(&(KEYS + i));
+                    }
+                }
+            })
+        };
+
+        writer.join().expect("writer failed");
+        done.store(true, Release);
+        for reader in readers {
+            reader.join().expect("reader failed");
+        }
+        assert_eq!(read.len(), KEYS as usize);
+        for i in 0 .. KEYS {
+            assert_eq!(read.get_cloned(&i), Some(i * 3));
+        }
+        write.validate();
+    }
+}
@@ -0,0 +1,385 @@
+use super::{insertion::Inserter, Map};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    mem,
+    ptr::NonNull,
+};
+
+/// An observer of the changes of a [`Map`], given to
+/// [`with_hooks`](Map::with_hooks). Each hook is called after the change it
+/// reports succeeded, once the operation no longer pauses the incinerator, so
+/// a hook may freely block, or even wait for removed entries to be reclaimed,
+/// e.g. through [`Removed::try_unwrap`](super::Removed::try_unwrap).
+///
+/// The hooks are passed references to clones of the entries, taken while the
+/// incinerator was still paused: as soon as the pause ends, a concurrent
+/// removal could reclaim an inserted entry, and a removed one might already be
+/// dropped by the operation, as in [`clear`](Map::clear). The clones are
+/// dropped right after the hook returns.
+///
+/// # Reported changes
+/// [`on_insert`](MapHooks::on_insert) reports every entry created or replaced
+/// by the shared methods of the [`Map`], including its batched methods,
+/// [`Entry`](super::Entry) operations and
+/// [`reinsert`](Map::reinsert), and by [`insert_mut`](Map::insert_mut). A
+/// replaced entry is not reported as removed; `replaced` tells it was there.
+/// [`on_remove`](MapHooks::on_remove) reports every entry removed by the shared
+/// methods, including [`retain`](Map::retain), [`drain`](Map::drain) and
+/// [`pop_any`](Map::pop_any), and by [`remove_mut`](Map::remove_mut). Values
+/// changed in place, e.g. through [`update_in_place`](Map::update_in_place) or
+/// [`iter_mut`](Map::iter_mut), are not reported, and neither are the entries
+/// of a [`Map`] consumed or dropped.
+///
+/// # Ordering
+/// Hooks are called by the thread which made the change, before the
+/// operation returns, and in the order the changes were made. Thus, the hooks
+/// called by each thread follow the program order of its operations. The calls
+/// of different threads, however, are not ordered with respect to each other:
+/// even for the same key, a change which happened first might be reported
+/// after a later change made by another thread.
+///
+/// # Exceptions
+/// If the calling thread itself keeps the incinerator paused, e.g. by holding
+/// a [`ReadGuard`](super::ReadGuard), the hooks are called under that pause.
+/// The same goes for [`Entry`](super::Entry) operations returning a
+/// [`ReadGuard`](super::ReadGuard), since the guard keeps the pause of the
+/// insertion.
+pub trait MapHooks<K, V>: Send + Sync {
+    /// Called after an entry with the given key and value was inserted.
+    /// `replaced` tells whether it replaced an entry with the same key.
+    fn on_insert(&self, key: &K, val: &V, replaced: bool);
+
+    /// Called after the entry with the given key and value was removed.
+    fn on_remove(&self, key: &K, val: &V);
+}
+
+// The hooks of a map, along with the clone function of its entries, which is
+// captured when the hooks are given, so the map methods do not need to require
+// `K: Clone` and `V: Clone`.
+pub(super) struct Hooks<K, V> {
+    observer: Arc<dyn MapHooks<K, V>>,
+    clone: fn(&(K, V)) -> (K, V),
+}
+
+impl<K, V> Hooks<K, V> {
+    pub(super) fn new(observer: Arc<dyn MapHooks<K, V>>) -> Self
+    where
+        K: Clone,
+        V: Clone,
+    {
+        Self { observer, clone: <(K, V)>::clone }
+    }
+
+    pub(super) fn cloned(&self, pair: &(K, V)) -> (K, V) {
+        (self.clone)(pair)
+    }
+
+    pub(super) fn on_insert(&self, pair: &(K, V), replaced: bool) {
+        self.observer.on_insert(&pair.0, &pair.1, replaced)
+    }
+
+    pub(super) fn on_remove(&self, pair: &(K, V)) {
+        self.observer.on_remove(&pair.0, &pair.1)
+    }
+}
+
+enum Event<K, V> {
+    Inserted((K, V), bool),
+    Removed((K, V)),
+}
+
+// The changes made by an operation, cloned while the incinerator is paused
+// and reported to the hooks once this is dropped. It must be created before
+// the pause, so that it is dropped after the pause ends. Without hooks,
+// nothing is cloned.
+pub struct Events<'map, K, V> {
+    hooks: Option<&'map Hooks<K, V>>,
+    log: RefCell<Vec<Event<K, V>>>,
+}
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O> {
+    // The changes of an operation on this map, to be reported to its hooks.
+    pub(super) fn events(&self) -> Events<'_, K, V> {
+        Events { hooks: self.hooks.as_ref(), log: RefCell::new(Vec::new()) }
+    }
+}
+
+impl<'map, K, V> Events<'map, K, V> {
+    // Wraps the inserter so that the pair it inserts is cloned into the given
+    // cell, if there are hooks.
+    pub(super) fn observe<'events, I>(
+        &'events self,
+        inserter: I,
+        cloned: &'events Cell<Option<(K, V)>>,
+    ) -> Observed<'events, I, K, V> {
+        let hooks = self.hooks;
+        Observed { inserter, hooks, cloned }
+    }
+
+    pub(super) fn inserted(&self, pair: (K, V), replaced: bool) {
+        self.log.borrow_mut().push(Event::Inserted(pair, replaced));
+    }
+
+    pub(super) fn removed(&self, pair: &(K, V)) {
+        if let Some(hooks) = self.hooks {
+            self.log.borrow_mut().push(Event::Removed(hooks.cloned(pair)));
+        }
+    }
+}
+
+impl<'map, K, V> Drop for Events<'map, K, V> {
+    fn drop(&mut self) {
+        let hooks = match self.hooks {
+            Some(hooks) => hooks,
+            None => return,
+        };
+        for event in mem::take(self.log.get_mut()) {
+            match event {
+                Event::Inserted(pair, replaced) => {
+                    hooks.on_insert(&pair, replaced)
+                },
+                Event::Removed(pair) => hooks.on_remove(&pair),
+            }
+        }
+    }
+}
+
+// An inserter which clones the pair inserted by the wrapped one, once it is
+// published, for the hooks of the map.
+pub(super) struct Observed<'events, I, K, V> {
+    inserter: I,
+    hooks: Option<&'events Hooks<K, V>>,
+    cloned: &'events Cell<Option<(K, V)>>,
+}
+
+impl<'events, I, K, V> Observed<'events, I, K, V> {
+    pub(super) fn into_inner(self) -> I {
+        self.inserter
+    }
+}
+
+impl<'events, I, K, V> Inserter<K, V> for Observed<'events, I, K, V>
+where
+    I: Inserter<K, V>,
+{
+    fn input(&mut self, found: Option<&(K, V)>) {
+        self.inserter.input(found)
+    }
+
+    fn found_version(&mut self, version: usize) {
+        self.inserter.found_version(version)
+    }
+
+    fn pointer(&self) -> Option<NonNull<(K, V)>> {
+        self.inserter.pointer()
+    }
+
+    fn key(&self) -> &K {
+        self.inserter.key()
+    }
+
+    fn shared(&self) -> bool {
+        self.inserter.shared()
+    }
+
+    fn stored(&mut self, pair: NonNull<(K, V)>) {
+        self.inserter.stored(pair)
+    }
+
+    fn take_pointer(self) {
+        if let (Some(hooks), Some(nnptr)) = (self.hooks, self.pointer()) {
+            // Safe because the pair was just published and the caller keeps
+            // the incinerator paused.
+            self.cloned.set(Some(hooks.cloned(unsafe { nnptr.as_ref() })));
+        }
+        self.inserter.take_pointer()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "std")]
+    use map::SharedIncin;
+    #[cfg(feature = "std")]
+    use std::sync::OnceLock;
+    use std::{
+        sync::Mutex,
+        thread::{self, ThreadId},
+    };
+    use std::prelude::v1::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Change {
+        Insert(usize, usize, bool),
+        Remove(usize, usize),
+    }
+
+    // Logs every change along with the thread reporting it, checking that
+    // the incinerator is not paused meanwhile with the `std` feature.
+    #[derive(Default)]
+    struct Recorder {
+        log: Mutex<Vec<(ThreadId, Change)>>,
+        #[cfg(feature = "std")]
+        incin: OnceLock<SharedIncin<usize, usize>>,
+    }
+
+    impl Recorder {
+        fn push(&self, change: Change) {
+            #[cfg(feature = "std")]
+            {
+                let incin = self.incin.get().expect("incinerator not set");
+                assert!(!incin.inner.is_paused_locally());
+            }
+            let id = thread::current().id();
+            self.log.lock().unwrap().push((id, change));
+        }
+
+        fn take(&self) -> Vec<Change> {
+            let log = &mut *self.log.lock().unwrap();
+            log.drain(..).map(|(_, change)| change).collect()
+        }
+    }
+
+    impl MapHooks<usize, usize> for Recorder {
+        fn on_insert(&self, key: &usize, val: &usize, replaced: bool) {
+            self.push(Change::Insert(*key, *val, replaced));
+        }
+
+        fn on_remove(&self, key: &usize, val: &usize) {
+            self.push(Change::Remove(*key, *val));
+        }
+    }
+
+    fn hooked_map() -> (Arc<Recorder>, Map<usize, usize>) {
+        let recorder = Arc::new(Recorder::default());
+        let map = Map::with_hooks(Default::default(), recorder.clone());
+        #[cfg(feature = "std")]
+        recorder.incin.set(map.incin()).ok().unwrap();
+        (recorder, map)
+    }
+
+    #[test]
+    fn reports_each_change() {
+        use self::Change::*;
+
+        let (recorder, mut map) = hooked_map();
+        map.insert(1, 10);
+        map.insert(1, 11);
+        assert!(map.try_insert(1, 12).is_err());
+        map.try_insert(2, 20).unwrap();
+        map.update(&2, |val| val + 1);
+        map.remove(&3);
+        map.remove(&1);
+        map.compute(2, |_| None);
+        map.get_or_insert_with(3, || 30, |_, _| ());
+        map.entry(3).or_insert(31);
+        assert_eq!(recorder.take(), [
+            Insert(1, 10, false),
+            Insert(1, 11, true),
+            Insert(2, 20, false),
+            Insert(2, 21, true),
+            Remove(1, 11),
+            Remove(2, 21),
+            Insert(3, 30, false),
+        ]);
+
+        map.extend((4 .. 6).map(|i| (i, i * 10)));
+        map.retain(|key, _| *key != 4);
+        map.insert_mut(5, 51);
+        map.remove_mut(&5);
+        map.clear();
+        assert_eq!(recorder.take(), [
+            Insert(4, 40, false),
+            Insert(5, 50, false),
+            Remove(4, 40),
+            Insert(5, 51, true),
+            Remove(5, 51),
+            Remove(3, 30),
+        ]);
+
+        map.insert(6, 60);
+        let removed = map.pop_any().unwrap();
+        map.reinsert(removed);
+        map.drain();
+        assert_eq!(recorder.take(), [
+            Insert(6, 60, false),
+            Remove(6, 60),
+            Insert(6, 60, false),
+            Remove(6, 60),
+        ]);
+    }
+
+    #[test]
+    fn concurrent_log_matches_reference() {
+        const THREADS: usize = 8;
+        const OPS: usize = 4000;
+        const KEYS: usize = 64;
+
+        let (recorder, map) = hooked_map();
+        let map = Arc::new(map);
+        let reference = Arc::new(Mutex::new(Vec::new()));
+        let mut threads = Vec::with_capacity(THREADS);
+
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let reference = reference.clone();
+            threads.push(thread::spawn(move || {
+                let id = thread::current().id();
+                for i in 0 .. OPS {
+                    let key = (t * 7 + i * 13) % KEYS;
+                    let val = t * OPS + i;
+                    let change = match i % 4 {
+                        0 | 1 => {
+                            let replaced = map.insert(key, val).is_some();
+                            Some(Change::Insert(key, val, replaced))
+                        },
+                        2 => map
+                            .try_insert(key, val)
+                            .ok()
+                            .map(|()| Change::Insert(key, val, false)),
+                        _ => map
+                            .remove(&key)
+                            .map(|removed| Change::Remove(key, *removed.val())),
+                    };
+                    if let Some(change) = change {
+                        reference.lock().unwrap().push((id, change));
+                    }
+                }
+                id
+            }));
+        }
+
+        let ids = threads
+            .into_iter()
+            .map(|thread| thread.join().expect("thread failed"))
+            .collect::<Vec<_>>();
+
+        // The hooks of each thread follow its program order, while those of
+        // different threads interleave arbitrarily.
+        let log = recorder.log.lock().unwrap();
+        let reference = reference.lock().unwrap();
+        assert_eq!(log.len(), reference.len());
+        for id in ids {
+            let of_thread = |log: &[(ThreadId, Change)]| {
+                log.iter()
+                    .filter(|(other, _)| *other == id)
+                    .map(|(_, change)| *change)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(of_thread(&log), of_thread(&reference));
+        }
+
+        // Creations and removals balance out to the entries left.
+        let mut balance = 0isize;
+        for (_, change) in log.iter() {
+            match change {
+                Change::Insert(_, _, false) => balance += 1,
+                Change::Remove(..) => balance -= 1,
+                Change::Insert(..) => (),
+            }
+        }
+        assert_eq!(balance, map.len() as isize);
+    }
+}
@@ -0,0 +1,242 @@
+use core::{
+    fmt,
+    hash::{BuildHasher, Hash, Hasher},
+    marker::PhantomData,
+};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// An integer key type whose value can be used as its own hash by
+/// [`IdentityState`]. This trait is sealed: it is implemented for the
+/// primitive integers up to 64 bits and cannot be implemented outside of
+/// this crate.
+pub trait IdentityKey: sealed::Sealed + Hash {}
+
+macro_rules! identity_keys {
+    ($($int:ty),*) => {
+        $(
+            impl sealed::Sealed for $int {}
+            impl IdentityKey for $int {}
+        )*
+    };
+}
+
+identity_keys!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// A hasher builder whose hashers give integer keys back as their own hash,
+/// skipping the cost of hashing them, as in [`IntMap`](super::IntMap). Since
+/// each level of the tree of a [`Map`](super::Map) consumes the lowest bits
+/// of the hash not consumed yet, keys which differ in their lowest bits, such
+/// as sequential IDs, spread over the nodes of the top table just like hashed
+/// keys. Keys sharing many low bits, e.g. multiples of a big power of two,
+/// are still stored correctly, but they go down a chain of sub-tables, one
+/// for each shared byte with the default `BITS`, so lookups of them are
+/// slower. An adversary who controls the keys can make them collide too; use
+/// a randomly seeded hasher builder if that is a concern.
+///
+/// The key type is a parameter only to restrict it to [`IdentityKey`].
+pub struct IdentityState<K = u64> {
+    _key: PhantomData<fn(&K)>,
+}
+
+impl<K> IdentityState<K>
+where
+    K: IdentityKey,
+{
+    /// Creates the hasher builder.
+    pub fn new() -> Self {
+        Self { _key: PhantomData }
+    }
+}
+
+impl<K> BuildHasher for IdentityState<K>
+where
+    K: IdentityKey,
+{
+    type Hasher = IdentityHasher;
+
+    fn build_hasher(&self) -> IdentityHasher {
+        IdentityHasher { hash: 0 }
+    }
+}
+
+impl<K> Default for IdentityState<K>
+where
+    K: IdentityKey,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> Clone for IdentityState<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K> Copy for IdentityState<K> {}
+
+impl<K> PartialEq for IdentityState<K> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl<K> Eq for IdentityState<K> {}
+
+impl<K> fmt::Debug for IdentityState<K> {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.write_str("IdentityState")
+    }
+}
+
+/// The hasher built by [`IdentityState`]. The hash is the last integer
+/// written, zero-extended to 64 bits, or sign-extended for signed integers.
+#[derive(Debug, Clone)]
+pub struct IdentityHasher {
+    hash: u64,
+}
+
+impl Hasher for IdentityHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    // Integer keys never write plain bytes. Folding them keeps the hasher
+    // usable anyway.
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash = self.hash.rotate_left(8) ^ u64::from(byte);
+        }
+    }
+
+    fn write_u8(&mut self, int: u8) {
+        self.hash = u64::from(int);
+    }
+
+    fn write_u16(&mut self, int: u16) {
+        self.hash = u64::from(int);
+    }
+
+    fn write_u32(&mut self, int: u32) {
+        self.hash = u64::from(int);
+    }
+
+    fn write_u64(&mut self, int: u64) {
+        self.hash = int;
+    }
+
+    fn write_usize(&mut self, int: usize) {
+        self.hash = int as u64;
+    }
+
+    fn write_i8(&mut self, int: i8) {
+        self.hash = int as u64;
+    }
+
+    fn write_i16(&mut self, int: i16) {
+        self.hash = int as u64;
+    }
+
+    fn write_i32(&mut self, int: i32) {
+        self.hash = int as u64;
+    }
+
+    fn write_i64(&mut self, int: i64) {
+        self.hash = int as u64;
+    }
+
+    fn write_isize(&mut self, int: isize) {
+        self.hash = int as u64;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use map::{IntMap, Map};
+    use std::prelude::v1::*;
+
+    #[test]
+    fn hashes_are_keys() {
+        let state = IdentityState::<u64>::new();
+        assert_eq!(state.hash_one(0x1234_5678_u64), 0x1234_5678);
+        assert_eq!(IdentityState::<u8>::new().hash_one(200u8), 200);
+        assert_eq!(IdentityState::<i32>::new().hash_one(-1i32), u64::MAX);
+    }
+
+    #[test]
+    fn sequential_keys_spread() {
+        // Sequential keys fill every table: 2 levels for 16 bits.
+        let map = IntMap::default();
+        map.extend((0 .. 1 << 16).map(|i| (i, i)));
+        let stats = map.stats();
+        assert_eq!(stats.max_bucket_len(), 1);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.tables, 1 + 256);
+
+        let map = IntMap::default();
+        for i in 0 .. 100_000 {
+            map.insert(i, i);
+        }
+        let stats = map.stats();
+        assert_eq!(stats.max_bucket_len(), 1);
+        assert_eq!(stats.max_depth, 3);
+        for i in 0 .. 100_000 {
+            assert_eq!(map.get(&i).map(|guard| *guard.val()), Some(i));
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn clustered_keys() {
+        // Every key shares the lowest 32 bits, so each one goes down a chain
+        // of 4 sub-tables before the keys are told apart.
+        let map = IntMap::default();
+        for i in 0 .. 10_000u64 {
+            map.insert(i << 32 | 0xabcd, i);
+        }
+        let stats = map.stats();
+        assert_eq!(stats.max_bucket_len(), 1);
+        assert_eq!(stats.max_depth, 6);
+        map.validate();
+
+        for i in 0 .. 10_000u64 {
+            let key = i << 32 | 0xabcd;
+            assert_eq!(map.get(&key).map(|guard| *guard.val()), Some(i));
+            assert!(map.get(&(key ^ 1)).is_none());
+        }
+        for i in (0 .. 10_000u64).step_by(2) {
+            let removed = map.remove(&(i << 32 | 0xabcd));
+            assert_eq!(removed.map(|removed| *removed.val()), Some(i));
+        }
+        assert_eq!(map.len(), 5_000);
+        map.shrink();
+        map.validate();
+        for i in 0 .. 10_000u64 {
+            assert_eq!(map.contains_key(&(i << 32 | 0xabcd)), i % 2 == 1);
+        }
+    }
+
+    #[test]
+    fn signed_and_small_keys() {
+        let map = Map::<i32, i32, IdentityState<i32>>::default();
+        for i in -1000 .. 1000 {
+            map.insert(i * 256, i);
+        }
+        for i in -1000 .. 1000 {
+            assert_eq!(map.get(&(i * 256)).map(|guard| *guard.val()), Some(i));
+        }
+        assert_eq!(map.len(), 2000);
+        map.validate();
+
+        let map = Map::<u8, u8, IdentityState<u8>>::default();
+        for i in 0 ..= 255 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.stats().max_depth, 1);
+    }
+}
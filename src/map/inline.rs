@@ -0,0 +1,236 @@
+use super::Map;
+use core::{hash::BuildHasher, mem};
+
+// The biggest pair kept inline, in bytes. Each copy of an entry copies its
+// pair along, so bigger pairs are better off behind a pointer.
+const MAX_INLINE: usize = 4 * mem::size_of::<usize>();
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+{
+    /// Creates the [`Map`] using the given hasher builder, with tables of
+    /// `1 << BITS` nodes, keeping each key and value inline, in the node of
+    /// its entry, instead of in an allocation of its own. Each entry then
+    /// takes a single allocation, and lookups take one hop less, which pays
+    /// off for small keys and values, e.g. in a `Map<u64, u64>`.
+    ///
+    /// Entries are copied whenever they are removed, or an entry next to them
+    /// is, so their keys and values must be [`Copy`]. Pairs bigger than four
+    /// machine words are still kept out of line, since copying them would
+    /// cost more than the allocation saves. See
+    /// [`has_inline_pairs`](Map::has_inline_pairs).
+    ///
+    /// A [`Removed`](super::Removed) entry of such a [`Map`] holds a copy of
+    /// the pair in an allocation of its own, which
+    /// [`reinsert`](Map::reinsert) copies back into the [`Map`]. Clones of the
+    /// [`Map`] keep their pairs inline too.
+    pub fn with_inline_pairs(builder: H) -> Self
+    where
+        K: Copy,
+        V: Copy,
+    {
+        let mut this = Self::with_fanout(builder);
+        // Only pairs which can be copied bit by bit are kept inline.
+        this.slabs.inline = mem::size_of::<(K, V)>() <= MAX_INLINE;
+        this
+    }
+
+    /// Returns whether this [`Map`] keeps its pairs inline, i.e. it was
+    /// created with [`with_inline_pairs`](Map::with_inline_pairs) and its
+    /// pairs are small enough.
+    pub fn has_inline_pairs(&self) -> bool {
+        self.slabs.inline
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::map::{support::ConstState, ComputeResult, DefaultHashBuilder};
+    use alloc::sync::Arc;
+    use std::prelude::v1::*;
+    use std::thread;
+
+    fn inline_map() -> Map<u64, u64> {
+        let map = Map::with_inline_pairs(DefaultHashBuilder::default());
+        assert!(map.has_inline_pairs());
+        map
+    }
+
+    #[test]
+    fn only_small_pairs_are_inline() {
+        assert!(!Map::<u64, u64>::new().has_inline_pairs());
+        let map = Map::<u64, [u64; 8]>::with_inline_pairs(
+            DefaultHashBuilder::default(),
+        );
+        assert!(!map.has_inline_pairs());
+        map.insert(1, [1; 8]);
+        assert_eq!(*map.get(&1).unwrap().val(), [1; 8]);
+    }
+
+    #[test]
+    fn inline_insert_get_remove() {
+        let mut map = inline_map();
+        for i in 0 .. 1000 {
+            assert!(map.insert(i, i * 2).is_none());
+        }
+        for i in 0 .. 1000 {
+            assert_eq!(*map.get(&i).unwrap().val(), i * 2);
+        }
+        for i in (0 .. 1000).step_by(2) {
+            assert_eq!(*map.insert(i, i * 3).unwrap().val(), i * 2);
+        }
+        for i in (0 .. 1000).step_by(3) {
+            let removed = map.remove(&i).unwrap();
+            let val = if i % 2 == 0 { i * 3 } else { i * 2 };
+            assert_eq!(*removed, (i, val));
+            // The removed pair is a copy, so it goes back just as well.
+            assert!(map.reinsert(removed).created());
+        }
+        assert_eq!(map.len(), 1000);
+        map.validate();
+
+        let usage = map.memory_usage();
+        assert_eq!(usage.pair_bytes, 0);
+        assert!(usage.total_bytes() > 0);
+
+        for i in 0 .. 500 {
+            let val = if i % 2 == 0 { i * 3 } else { i * 2 };
+            assert_eq!(map.remove_mut(&i), Some((i, val)));
+        }
+        *map.get_mut(&999).unwrap() = 0;
+        assert_eq!(map.insert_mut(998, 1), Some((998, 998 * 3)));
+        let mut pairs = map.clone().into_iter().collect::<Vec<_>>();
+        pairs.sort();
+        assert_eq!(pairs.len(), 500);
+        assert_eq!(pairs[498], (998, 1));
+        assert_eq!(pairs[499], (999, 0));
+        map.validate();
+    }
+
+    // The address of the pair stored with the given key.
+    fn addr_of(map: &Map<u64, u64, ConstState>, key: u64) -> usize {
+        map.get_versioned(&key, |key, _| key as *const u64 as usize)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn inline_pairs_keep_their_identity() {
+        let map = Map::with_inline_pairs(ConstState);
+        assert!(map.has_inline_pairs());
+        for i in 0 .. 10 {
+            map.insert(i, i);
+        }
+
+        // Unlinking the entry after 3 copies the entry of 3, and its pair.
+        // Writers unlink the removed entries on their way, even with
+        // snapshots enabled, unlike lookups.
+        let (version, _) = map.get_versioned(&3, |_, _| ()).unwrap();
+        let before = addr_of(&map, 3);
+        map.remove(&4);
+        assert!(map.remove(&10).is_none());
+        assert_ne!(addr_of(&map, 3), before);
+        assert!(map.update_if_version(3, version, 30).is_ok());
+        assert!(map.update_if_version(3, version, 31).is_err());
+
+        // The same goes for pairs found by conditional removals.
+        map.retain(|key, _| {
+            if *key == 5 {
+                map.remove(&6);
+                assert!(map.remove(&10).is_none());
+            }
+            *key != 5
+        });
+        assert!(map.get(&5).is_none());
+        map.map_values(|key, val| {
+            if *key == 7 {
+                map.remove(&8);
+                assert!(map.remove(&10).is_none());
+            }
+            val + 1
+        });
+        assert_eq!(*map.get(&7).unwrap().val(), 8);
+        let res = map.compute(2, |stored| {
+            if stored.is_some() {
+                map.remove(&3);
+                assert!(map.remove(&10).is_none());
+            }
+            None
+        });
+        assert!(matches!(res, ComputeResult::Removed(_)));
+
+        let mut keys = map.keys_cloned();
+        keys.sort();
+        assert_eq!(keys, [0, 1, 7, 9]);
+        map.validate();
+    }
+
+    #[test]
+    fn inline_entry_api() {
+        let map = inline_map();
+        assert_eq!(*map.entry(1).or_insert_with(|| 10).val(), 10);
+        assert_eq!(*map.entry(1).or_insert_with(|| 20).val(), 10);
+        let guard = map.entry(1).and_modify(|val| val + 1).or_default();
+        assert_eq!(*guard.val(), 11);
+        drop(guard);
+        let (old, read) = map.insert_and_read(1, 12, |_, val| *val);
+        assert_eq!(*old.unwrap().val(), 11);
+        assert_eq!(read, 12);
+    }
+
+    #[test]
+    fn inline_concurrent_churn() {
+        const THREADS: u64 = 8;
+        const KEYS: u64 = 512;
+        const ROUNDS: u64 = 20;
+
+        let map = Arc::new(inline_map());
+        let threads = (0 .. THREADS)
+            .map(|id| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for round in 0 .. ROUNDS {
+                        for key in 0 .. KEYS {
+                            let val = round * THREADS + id;
+                            match (key + id + round) % 4 {
+                                0 | 1 => {
+                                    map.insert(key, val);
+                                },
+                                2 => {
+                                    if let Some(removed) = map.remove(&key) {
+                                        assert_eq!(removed.key(), &key);
+                                    }
+                                },
+                                _ if key == 0 => {
+                                    map.retain(|_, val| val % 3 != 0);
+                                },
+                                _ => {
+                                    map.compute(key, |stored| {
+                                        stored.map(|(_, val)| val + 1)
+                                    });
+                                },
+                            }
+                            if let Some(guard) = map.get(&key) {
+                                assert_eq!(*guard.key(), key);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        let mut map = Arc::try_unwrap(map).unwrap_or_else(|_| unreachable!());
+        map.validate();
+        let len = map.iter().count();
+        assert_eq!(len, map.len());
+        assert_eq!(map.drain().len(), len);
+        map.validate();
+        assert!(map.is_empty());
+        assert!(map.get_mut(&0).is_none());
+    }
+}
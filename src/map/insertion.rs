@@ -1,6 +1,12 @@
-use super::Removed;
-use owned_alloc::{OwnedAlloc, UninitAlloc};
-use std::{mem::forget, ptr::NonNull};
+use super::{bucket::Slabs, Removed, Version};
+use alloc::vec::Vec;
+use core::{
+    alloc::Layout,
+    cell::Cell,
+    mem::forget,
+    ptr::{addr_of_mut, NonNull},
+};
+use owned_alloc::{AllocFailed, OwnedAlloc, UninitAlloc};
 
 /// A [`insert_with`](super::Map::insert_with) operation result.
 #[derive(Debug, PartialEq, Eq)]
@@ -60,6 +66,89 @@ impl<K, V, E> Insertion<K, V, E> {
     }
 }
 
+/// A [`upsert`](super::Map::upsert) operation result.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Upserted<K, V> {
+    /// No entry was present, and one was created.
+    Created,
+    /// An entry was present and modified. This is the old pair.
+    Modified(Removed<K, V>),
+}
+
+/// A [`compute`](super::Map::compute) operation result, telling which
+/// transition was applied to the entry.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ComputeResult<K, V> {
+    /// No entry was present, and one was created.
+    Inserted,
+    /// An entry was present and replaced. This is the old pair.
+    Replaced(Removed<K, V>),
+    /// An entry was present and removed. This is the removed pair.
+    Removed(Removed<K, V>),
+    /// No entry was present, and none was created.
+    Absent,
+}
+
+/// A [`remove_if`](super::Map::remove_if) operation result.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Removal<K, V> {
+    /// The entry was removed and this is the removed pair.
+    Done(Removed<K, V>),
+    /// The entry was found, but the condition rejected it.
+    Rejected,
+    /// No entry was found.
+    Absent,
+}
+
+/// The error of a [`swap_values`](super::Map::swap_values) operation, telling
+/// which of the keys had no entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapErr {
+    /// The first key had no entry.
+    FirstMissing,
+    /// The second key had no entry.
+    SecondMissing,
+    /// Neither key had an entry.
+    BothMissing,
+}
+
+/// The error of a [`rename_key`](super::Map::rename_key) operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameErr {
+    /// The old key had no entry, so nothing was inserted.
+    OldMissing,
+}
+
+/// The outcome of a [`merge`](super::Map::merge) operation.
+#[derive(Debug)]
+pub struct Merged<K, V> {
+    /// The entries of the destination map displaced by the moved ones.
+    pub displaced: Vec<Removed<K, V>>,
+    /// The entries drained from the other map which could not be moved, since
+    /// sensitive reads were still active on it. They can be moved later
+    /// through [`reinsert`](super::Map::reinsert).
+    pub unmoved: Vec<Removed<K, V>>,
+}
+
+/// The error of a [`try_insert_alloc`](super::Map::try_insert_alloc)
+/// operation: the allocator failed, so nothing was inserted. The key and value
+/// are given back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError<K, V> {
+    /// The key which was not inserted.
+    pub key: K,
+    /// The value which was not inserted.
+    pub val: V,
+    /// The layout of the allocation which failed.
+    pub layout: Layout,
+}
+
+impl<K, V> AllocError<K, V> {
+    pub(super) fn new((key, val): (K, V), failed: AllocFailed) -> Self {
+        Self { key, val, layout: failed.layout }
+    }
+}
+
 /// The preview of an _interactive_ insertion. It is used by the
 /// [`insert_with`](super::Map::insert_with) method and it is the return value
 /// of the closure passed to the method.
@@ -85,6 +174,10 @@ pub trait Inserter<K, V>: Sized {
     // Feed the inserter with this found pair, if any.
     fn input(&mut self, found: Option<&(K, V)>);
 
+    // Feed the inserter with the version of the entry of the found pair,
+    // right before the pair is given to `input`. Most inserters ignore it.
+    fn found_version(&mut self, _version: usize) {}
+
     // The pointer to memory allocated via `OwnedAlloc`, given the conditions
     // fed by `input`. Return `None` to reject the conditions.
     fn pointer(&self) -> Option<NonNull<(K, V)>>;
@@ -92,6 +185,17 @@ pub trait Inserter<K, V>: Sized {
     // Simply access the key. Must not fail.
     fn key(&self) -> &K;
 
+    // Whether snapshots may still read the pair, which a keeper must then
+    // keep once it is removed again, see `Entry`.
+    fn shared(&self) -> bool {
+        false
+    }
+
+    // Told where the pair is stored once it is published, right before
+    // `take_pointer`. A pair kept inline is copied out of the allocation of
+    // `pointer` into the map.
+    fn stored(&mut self, _pair: NonNull<(K, V)>) {}
+
     // Take ownership of the pointer's allocation.
     fn take_pointer(self) {
         forget(self);
@@ -116,12 +220,13 @@ where
         Self {
             interactive,
             // I know it sounds weird, but we need to initialize just the key.
-            // We handle it in drop through the field `is_val_init`.
+            // We handle it in drop through the field `is_val_init`. The key is
+            // written through a raw pointer, so the pair is never referenced
+            // while the value is uninitialized.
             nnptr: unsafe {
-                let alloc = UninitAlloc::new().init_in_place(|(key_mem, _)| {
-                    (key_mem as *mut K).write(key)
-                });
-                alloc.into_raw()
+                let nnptr = UninitAlloc::<(K, V)>::new().into_raw();
+                addr_of_mut!((*nnptr.as_ptr()).0).write(key);
+                nnptr
             },
             is_val_init: false,
         }
@@ -135,20 +240,34 @@ where
         }
     }
 
+    // The key in the allocation, which is always initialized.
+    fn key_ptr(&self) -> *mut K {
+        // Safe because the pointer is valid, and no reference is made.
+        unsafe { addr_of_mut!((*self.nnptr.as_ptr()).0) }
+    }
+
+    // The value in the allocation, initialized only if `is_val_init` is set.
+    fn val_ptr(&self) -> *mut V {
+        // Safe because the pointer is valid, and no reference is made.
+        unsafe { addr_of_mut!((*self.nnptr.as_ptr()).1) }
+    }
+
     pub fn into_pair(self) -> (K, Option<V>) {
         // Doing this is safe by itself. However, callers should be careful if
-        // they used the pointer.
-        let ((key, val), _) =
-            unsafe { OwnedAlloc::from_raw(self.nnptr) }.move_inner();
-        // Note we check for the case in which val is uninitialized.
-        let val = if self.is_val_init {
-            Some(val)
-        } else {
-            forget(val);
-            None
+        // they used the pointer. Note we check for the case in which val is
+        // uninitialized.
+        let pair = unsafe {
+            let key = self.key_ptr().read();
+            let val = if self.is_val_init {
+                Some(self.val_ptr().read())
+            } else {
+                None
+            };
+            UninitAlloc::from_raw(self.nnptr);
+            (key, val)
         };
         forget(self);
-        (key, val)
+        pair
     }
 }
 
@@ -163,10 +282,7 @@ where
             unsafe { OwnedAlloc::from_raw(self.nnptr) };
         } else {
             unsafe {
-                {
-                    let (key, _) = self.nnptr.as_mut();
-                    (key as *mut K).drop_in_place();
-                }
+                self.key_ptr().drop_in_place();
                 UninitAlloc::from_raw(self.nnptr);
             }
         }
@@ -179,10 +295,16 @@ where
 {
     fn input(&mut self, found: Option<&(K, V)>) {
         // This is safe. This allocation is owned by us.
-        let (key, val) = unsafe { self.nnptr.as_mut() };
+        let key = unsafe { &*self.key_ptr() };
+        let val = self.val_ptr();
 
         let preview = {
-            let val = if self.is_val_init { Some(&mut *val) } else { None };
+            let val = if self.is_val_init {
+                // Safe because the value is initialized.
+                Some(unsafe { &mut *val })
+            } else {
+                None
+            };
             (self.interactive)(key, val, found)
         };
 
@@ -191,17 +313,18 @@ where
                 self.is_val_init = false;
                 // Safe because we check for the initialization of the value and
                 // we update it too.
-                unsafe { (val as *mut V).drop_in_place() };
+                unsafe { val.drop_in_place() };
             },
 
             Preview::New(new_val) => {
                 if self.is_val_init {
-                    *val = new_val;
+                    // Safe because the value is initialized.
+                    unsafe { *val = new_val };
                 } else {
                     self.is_val_init = true;
                     // Safe because we check for the initialization of the value
                     // and we update it too.
-                    unsafe { (val as *mut V).write(new_val) };
+                    unsafe { val.write(new_val) };
                 }
             },
 
@@ -218,9 +341,153 @@ where
     }
 
     fn key(&self) -> &K {
-        // This is safe. This allocation is owned by us
-        let (key, _) = unsafe { self.nnptr.as_ref() };
-        key
+        // This is safe. This allocation is owned by us, and the key is always
+        // initialized.
+        unsafe { &*self.key_ptr() }
+    }
+}
+
+// An inserter which only allocates the pair once the closure produces a value.
+pub struct InsertLazy<F, K, V>
+where
+    F: FnMut(Option<(&K, &V)>) -> Option<V>,
+{
+    make: F,
+    // Exactly one of `key` and `pair` is `Some`.
+    key: Option<K>,
+    pair: Option<OwnedAlloc<(K, V)>>,
+    // The stored pair last given to the closure, if it produced no value.
+    rejected: Option<NonNull<(K, V)>>,
+}
+
+impl<F, K, V> InsertLazy<F, K, V>
+where
+    F: FnMut(Option<(&K, &V)>) -> Option<V>,
+{
+    pub fn new(make: F, key: K) -> Self {
+        Self { make, key: Some(key), pair: None, rejected: None }
+    }
+
+    // The stored pair last given to the closure, if the closure produced no
+    // value for it.
+    pub fn rejected(&self) -> Option<NonNull<(K, V)>> {
+        self.rejected
+    }
+
+    // Gives the key back, dropping the created value, if any.
+    pub fn into_key(mut self) -> K {
+        match self.pair.take() {
+            Some(pair) => (pair.move_inner().0).0,
+            None => self.key.take().expect("lazy inserter without key"),
+        }
+    }
+}
+
+impl<F, K, V> Inserter<K, V> for InsertLazy<F, K, V>
+where
+    F: FnMut(Option<(&K, &V)>) -> Option<V>,
+{
+    fn input(&mut self, found: Option<&(K, V)>) {
+        let made = (self.make)(found.map(|(key, val)| (key, val)));
+        self.rejected = match (&made, found) {
+            (None, Some(found)) => Some(NonNull::from(found)),
+            _ => None,
+        };
+        match (made, self.pair.take()) {
+            (Some(val), Some(mut pair)) => {
+                pair.1 = val;
+                self.pair = Some(pair);
+            },
+
+            (Some(val), None) => {
+                let key = self.key.take().expect("lazy inserter without key");
+                self.pair = Some(OwnedAlloc::new((key, val)));
+            },
+
+            (None, Some(pair)) => {
+                let ((key, _), _) = pair.move_inner();
+                self.key = Some(key);
+            },
+
+            (None, None) => (),
+        }
+    }
+
+    fn pointer(&self) -> Option<NonNull<(K, V)>> {
+        self.pair.as_ref().map(|pair| pair.raw())
+    }
+
+    fn key(&self) -> &K {
+        match (&self.key, &self.pair) {
+            (Some(key), _) => key,
+            (None, Some(pair)) => &pair.0,
+            (None, None) => unreachable!(),
+        }
+    }
+
+    fn take_pointer(mut self) {
+        if let Some(pair) = self.pair.take() {
+            pair.into_raw();
+        }
+    }
+}
+
+// An inserter which inserts an already created pair and gives it back if the
+// conditions are rejected.
+pub struct InsertPair<F, K, V>
+where
+    F: FnMut(Option<&(K, V)>) -> bool,
+{
+    interactive: F,
+    pair: OwnedAlloc<(K, V)>,
+    is_valid: bool,
+}
+
+impl<F, K, V> InsertPair<F, K, V>
+where
+    F: FnMut(Option<&(K, V)>) -> bool,
+{
+    pub fn new(interactive: F, pair: (K, V)) -> Self {
+        Self { interactive, pair: OwnedAlloc::new(pair), is_valid: false }
+    }
+
+    // Gives the pair back if it cannot be allocated.
+    pub fn try_new(
+        interactive: F,
+        pair: (K, V),
+    ) -> Result<Self, ((K, V), AllocFailed)> {
+        let pair = OwnedAlloc::try_new(pair)?;
+        Ok(Self { interactive, pair, is_valid: false })
+    }
+
+    pub fn into_pair(self) -> (K, V) {
+        let (pair, _) = self.pair.move_inner();
+        pair
+    }
+}
+
+impl<F, K, V> Inserter<K, V> for InsertPair<F, K, V>
+where
+    F: FnMut(Option<&(K, V)>) -> bool,
+{
+    fn input(&mut self, found: Option<&(K, V)>) {
+        self.is_valid = (self.interactive)(found);
+    }
+
+    fn pointer(&self) -> Option<NonNull<(K, V)>> {
+        if self.is_valid {
+            Some(self.pair.raw())
+        } else {
+            None
+        }
+    }
+
+    fn key(&self) -> &K {
+        &self.pair.0
+    }
+
+    fn take_pointer(self) {
+        self.pair.into_raw();
     }
 }
 
@@ -268,7 +535,131 @@ where
         key
     }
 
+    fn shared(&self) -> bool {
+        Removed::is_held(&self.removed)
+    }
+
     fn take_pointer(self) {
         forget(Removed::into_alloc(self.removed));
     }
 }
+
+// An inserter which only replaces the entry of the given version, and gives
+// its pair back otherwise. The slabs are the ones of the map, which tag the
+// found pair.
+pub struct InsertVersioned<'slabs, K, V> {
+    expected: Version,
+    found: Option<usize>,
+    pair: OwnedAlloc<(K, V)>,
+    is_valid: bool,
+    slabs: &'slabs Slabs<K, V>,
+}
+
+impl<'slabs, K, V> InsertVersioned<'slabs, K, V> {
+    pub fn new(
+        expected: Version,
+        pair: (K, V),
+        slabs: &'slabs Slabs<K, V>,
+    ) -> Self {
+        Self {
+            expected,
+            found: None,
+            pair: OwnedAlloc::new(pair),
+            is_valid: false,
+            slabs,
+        }
+    }
+
+    pub fn into_pair(self) -> (K, V) {
+        let (pair, _) = self.pair.move_inner();
+        pair
+    }
+}
+
+impl<'slabs, K, V> Inserter<K, V> for InsertVersioned<'slabs, K, V> {
+    fn input(&mut self, found: Option<&(K, V)>) {
+        let version = self.found.take();
+        self.is_valid = match (found, version) {
+            (Some(pair), Some(version)) => {
+                let tag = self.slabs.pair_tag(pair);
+                Version::new(tag, version) == self.expected
+            },
+            _ => false,
+        };
+    }
+
+    fn found_version(&mut self, version: usize) {
+        self.found = Some(version);
+    }
+
+    fn pointer(&self) -> Option<NonNull<(K, V)>> {
+        if self.is_valid {
+            Some(self.pair.raw())
+        } else {
+            None
+        }
+    }
+
+    fn key(&self) -> &K {
+        &self.pair.0
+    }
+
+    fn take_pointer(self) {
+        self.pair.into_raw();
+    }
+}
+
+// An inserter which tells where the pair of the wrapped one is stored, once it
+// is published, since a pair kept inline is not stored in the allocation of
+// the inserter.
+pub struct Located<'cell, I, K, V> {
+    inserter: I,
+    stored: &'cell Cell<Option<NonNull<(K, V)>>>,
+}
+
+impl<'cell, I, K, V> Located<'cell, I, K, V> {
+    pub fn new(
+        inserter: I,
+        stored: &'cell Cell<Option<NonNull<(K, V)>>>,
+    ) -> Self {
+        Self { inserter, stored }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inserter
+    }
+}
+
+impl<'cell, I, K, V> Inserter<K, V> for Located<'cell, I, K, V>
+where
+    I: Inserter<K, V>,
+{
+    fn input(&mut self, found: Option<&(K, V)>) {
+        self.inserter.input(found)
+    }
+
+    fn found_version(&mut self, version: usize) {
+        self.inserter.found_version(version)
+    }
+
+    fn pointer(&self) -> Option<NonNull<(K, V)>> {
+        self.inserter.pointer()
+    }
+
+    fn key(&self) -> &K {
+        self.inserter.key()
+    }
+
+    fn shared(&self) -> bool {
+        self.inserter.shared()
+    }
+
+    fn stored(&mut self, pair: NonNull<(K, V)>) {
+        self.stored.set(Some(pair));
+        self.inserter.stored(pair)
+    }
+
+    fn take_pointer(self) {
+        self.inserter.take_pointer()
+    }
+}
@@ -1,11 +1,12 @@
 use super::{
-    bucket::{self, Bucket, Garbage},
+    bucket::{self, Bucket, Garbage, Slabs},
     guard::ReadGuard,
-    table::Table,
+    table::{self, Table},
 };
+use alloc::{boxed::Box, vec::Vec};
+use core::{fmt, mem::replace, ptr::NonNull, sync::atomic::Ordering::*};
 use incin::Pause;
 use owned_alloc::OwnedAlloc;
-use std::{fmt, mem::replace, ptr::NonNull, sync::atomic::Ordering::*};
 
 /// An iterator over key-vaue entries of a [`Map`](super::Map). The `Item` of
 /// this iterator is a [`ReadGuard`]. This iterator may be inconsistent, but
@@ -22,7 +23,7 @@ where
     pause: Pause<'map, Garbage<K, V>>,
     tables: Vec<&'map Table<K, V>>,
     curr_table: Option<(&'map Table<K, V>, usize)>,
-    cache: Vec<ReadGuard<'map, K, V>>,
+    cache: Vec<&'map (K, V)>,
 }
 
 impl<'map, K, V> Iter<'map, K, V> {
@@ -45,8 +46,8 @@ impl<'map, K, V> Iterator for Iter<'map, K, V> {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             // If we have something in the cache return it.
-            if let Some(guard) = self.cache.pop() {
-                break Some(guard);
+            if let Some(pair) = self.cache.pop() {
+                break Some(ReadGuard::new(pair, self.pause.clone()));
             }
 
             // If the iterator was empty, let's try to get a new one from
@@ -54,12 +55,12 @@ impl<'map, K, V> Iterator for Iter<'map, K, V> {
             let (table, index) = self.curr_table?;
             self.curr_table = match table.load_index(index, Acquire) {
                 // If the pointer is null, simply go to the next element.
-                Some(ptr) if ptr.is_null() => Some((table, index + 1)),
+                Some(ptr) if table::is_vacant(ptr) => Some((table, index + 1)),
 
                 // If the pointer is a bucket, collect all entries into the
                 // cache.
                 Some(ptr) if ptr as usize & 1 == 0 => {
-                    let ptr = ptr as *mut Bucket<K, V>;
+                    let ptr = table::bucket_ptr::<K, V>(ptr);
                     let mut cache = replace(&mut self.cache, Vec::new());
 
                     // This is safe because:
@@ -70,7 +71,7 @@ impl<'map, K, V> Iterator for Iter<'map, K, V> {
                     //
                     // 3. We only store preoperly allocated nodes in the table
                     // and mark buckets with 0.
-                    unsafe { (*ptr).collect(&self.pause, &mut cache) };
+                    unsafe { (*ptr).collect(&mut cache) };
 
                     self.cache = cache;
                     Some((table, index + 1))
@@ -78,7 +79,7 @@ impl<'map, K, V> Iterator for Iter<'map, K, V> {
 
                 // If the pointer is a table, put it on the table list.
                 Some(ptr) => {
-                    let ptr = (ptr as usize & !1) as *mut Table<K, V>;
+                    let ptr = table::table_ptr::<K, V>(ptr);
                     // This is safe because:
                     //
                     // 1. The incinerator is paused.
@@ -100,10 +101,11 @@ impl<'map, K, V> Iterator for Iter<'map, K, V> {
     }
 }
 
+// The iterator yields guards, which are shared references to the entries.
 unsafe impl<'map, K, V> Send for Iter<'map, K, V>
 where
-    K: Send,
-    V: Send,
+    K: Sync,
+    V: Sync,
 {
 }
 
@@ -119,14 +121,20 @@ pub struct IntoIter<K, V> {
     tables: Vec<OwnedAlloc<Table<K, V>>>,
     curr_table: Option<(OwnedAlloc<Table<K, V>>, usize)>,
     entries: bucket::IntoIter<K, V>,
+    // Declared last, since the other fields give nodes back to it.
+    _slabs: Box<Slabs<K, V>>,
 }
 
 impl<K, V> IntoIter<K, V> {
-    pub(super) fn new(top: OwnedAlloc<Table<K, V>>) -> Self {
+    pub(super) fn new(
+        top: OwnedAlloc<Table<K, V>>,
+        slabs: Box<Slabs<K, V>>,
+    ) -> Self {
         Self {
             tables: Vec::new(),
             curr_table: Some((top, 0)),
             entries: bucket::IntoIter::empty(),
+            _slabs: slabs,
         }
     }
 }
@@ -160,10 +168,8 @@ impl<K, V> Iterator for IntoIter<K, V> {
                     // and mark buckets with 0.
                     //
                     // 3. We have ownership over the `Map`.
-                    let alloc = unsafe {
-                        OwnedAlloc::from_raw(NonNull::new_unchecked(ptr))
-                    };
-                    let (bucket, _) = alloc.move_inner();
+                    let bucket =
+                        unsafe { Bucket::take(NonNull::new_unchecked(ptr)) };
                     self.entries = bucket.into_iter();
                     Some((table, index + 1))
                 },
@@ -261,7 +267,7 @@ impl<'map, K, V> Iterator for IterMut<'map, K, V> {
             // If the iterator was empty, let's try to get a new one from
             // another bucket.
             let (table, index) = self.curr_table.take()?;
-            self.curr_table = match table.load_index(index, Relaxed) {
+            self.curr_table = match table.load_index_mut(index) {
                 // If the pointer is null, simply go to the next element.
                 Some(ptr) if ptr.is_null() => Some((table, index + 1)),
 
@@ -305,9 +311,11 @@ impl<'map, K, V> Iterator for IterMut<'map, K, V> {
     }
 }
 
+// The iterator yields shared references to the keys and unique references to
+// the values, just like `&mut HashMap<K, V>` does.
 unsafe impl<'map, K, V> Send for IterMut<'map, K, V>
 where
-    K: Send,
+    K: Sync,
     V: Send,
 {
 }
@@ -328,3 +336,23 @@ impl<'map, K, V> fmt::Debug for IterMut<'map, K, V> {
         )
     }
 }
+
+/// A position in a [`Map`](super::Map) to resume a scan from, returned by
+/// [`Map::scan`](super::Map::scan). A scan starts with
+/// [`ScanCursor::new`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanCursor {
+    // The indices of the nodes leading to the next node to be handled, from
+    // the top table, ending with its own index. Empty before the scan starts.
+    pub(super) path: Vec<usize>,
+    // The bits of the top table the path was taken in, which tell whether a
+    // compact map was upgraded since.
+    pub(super) top_bits: usize,
+}
+
+impl ScanCursor {
+    /// A cursor at the start of a [`Map`](super::Map).
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
@@ -1,39 +1,188 @@
 mod table;
+mod trace;
+mod atomic;
+mod backoff;
+mod bounded;
 mod bucket;
+mod cow;
+mod diff;
+mod entry;
 mod insertion;
 mod guard;
+mod handle;
+mod hooks;
 mod iter;
+mod stats;
+mod fixed;
+mod frozen;
+mod identity;
+mod inline;
+mod order;
+mod pin;
+mod salt;
+#[cfg(target_has_atomic = "64")]
+mod multi;
+mod slab;
+#[cfg(test)]
+mod support;
+mod version;
+mod visit;
+mod weak;
+#[cfg(target_has_atomic = "64")]
+mod ttl;
+
+#[cfg(feature = "serde")]
+mod serde;
+
+#[cfg(feature = "rayon")]
+mod rayon;
+
+#[cfg(feature = "snapshot")]
+mod snapshot;
 
 pub use self::{
-    guard::{ReadGuard, Removed},
-    insertion::{Insertion, Preview},
-    iter::{IntoIter, Iter, IterMut},
+    atomic::AtomicValue,
+    bounded::{BoundedMap, CapacityExceeded, TryInsertErr},
+    diff::DiffEntry,
+    entry::Entry,
+    guard::{ReadGuard, Removed, ValueGuard},
+    handle::{ReadHandle, WriteHandle},
+    hooks::MapHooks,
+    insertion::{
+        AllocError,
+        ComputeResult,
+        Insertion,
+        Merged,
+        Preview,
+        Removal,
+        RenameErr,
+        SwapErr,
+        Upserted,
+    },
+    fixed::{FixedHasher, FixedState},
+    frozen::{FrozenIter, FrozenMap},
+    identity::{IdentityHasher, IdentityKey, IdentityState},
+    iter::{IntoIter, Iter, IterMut, ScanCursor},
+    order::{BucketOrder, Ordered, Unordered},
+    pin::Pinned,
+    stats::{MapStats, MemoryUsage},
+    version::{Version, VersionMismatch},
+    visit::VisitRange,
+    weak::WeakValueMap,
 };
+#[cfg(target_has_atomic = "64")]
+pub use self::{
+    multi::{MultiKey, MultiMap, Values},
+    ttl::{Expiring, TtlMap},
+};
+#[cfg(feature = "std")]
 pub use std::collections::hash_map::RandomState;
 
+/// The default hasher builder of [`Map`] and [`Set`](::set::Set):
+/// [`RandomState`] with the `std` feature, [`FixedState`] otherwise.
+#[cfg(feature = "std")]
+pub type DefaultHashBuilder = RandomState;
+
+/// The default hasher builder of [`Map`] and [`Set`](::set::Set):
+/// `RandomState` with the `std` feature, [`FixedState`] otherwise.
+#[cfg(not(feature = "std"))]
+pub type DefaultHashBuilder = FixedState;
+
+#[cfg(feature = "rayon")]
+pub use self::rayon::ParIter;
+
+#[cfg(feature = "snapshot")]
+pub use self::snapshot::MapSnapshot;
+
+/// A [`Map`] hashing its keys with [aHash](::ahash), through
+/// [`ahash::RandomState`]. Create it with
+/// [`default`](Default::default) or [`with_hasher`](Map::with_hasher).
+#[cfg(feature = "ahash")]
+pub type AHashMap<K, V> = Map<K, V, ::ahash::RandomState>;
+
+/// A [`Map`] hashing its keys with [FxHash](::fxhash), through
+/// [`FxBuildHasher`](::fxhash::FxBuildHasher). Create it with
+/// [`default`](Default::default) or [`with_hasher`](Map::with_hasher).
+#[cfg(feature = "fxhash")]
+pub type FxMap<K, V> = Map<K, V, ::fxhash::FxBuildHasher>;
+
+/// A [`Map`] with [`u64`] keys used as their own hashes, through
+/// [`IdentityState`], for keys such as well-distributed IDs. Create it with
+/// [`default`](Default::default) or [`with_hasher`](Map::with_hasher).
+pub type IntMap<V> = Map<u64, V, IdentityState>;
+
 use self::{
-    bucket::{Bucket, Garbage},
-    insertion::{InsertNew, Reinsert},
-    table::Table,
+    bucket::{Bucket, Garbage, Slabs},
+    hooks::{Events, Hooks},
+    insertion::{
+        InsertLazy,
+        InsertNew,
+        InsertPair,
+        Inserter,
+        Located,
+        Reinsert,
+    },
+    salt::Salt,
+    table::{InsertErr, Table},
+    trace::Probe,
 };
-use owned_alloc::OwnedAlloc;
-use ptr::check_null_align;
-use std::{
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
     borrow::Borrow,
+    cell::Cell,
+    cmp,
     fmt,
     hash::{BuildHasher, Hash, Hasher},
     iter::FromIterator,
+    marker::PhantomData,
     mem,
+    ptr::NonNull,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering::*},
 };
+use incin::Pause;
+use owned_alloc::{AllocFailed, OwnedAlloc};
+use ptr::check_null_align;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+// How many entries are inserted under a single pause by batched insertions.
+const BATCH_LEN: usize = 64;
+
+// How many entries are printed by the `Debug` implementation.
+const DEBUG_LEN: usize = 128;
+
+// The biggest allowed `BITS` of a `Map`, i.e. tables of `65536` nodes.
+const MAX_BITS: usize = 16;
+
+// The `BITS` of the tables of a compact `Map` before it is upgraded.
+const COMPACT_BITS: usize = 4;
+
+// How many entries a compact `Map` holds before it is upgraded.
+const COMPACT_LEN: usize = 64;
+
+// The hash of the keys as stored in the buckets. Sub-tables are created as
+// long as two different hashes still have bits left to tell them apart.
+#[cfg(not(feature = "hash128"))]
+type HashCode = u64;
+
+#[cfg(feature = "hash128")]
+type HashCode = u128;
+
+// Written to the hasher before the key when computing the upper half of a
+// 128-bit hash.
+#[cfg(feature = "hash128")]
+const HASH128_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
 
 /// A lock-free map. Implemented using multi-level hash-tables (in a tree
 /// fashion) with ordered buckets.
 ///
 /// # Design
 /// In order to implement this map, we shall fix a constant named `BITS`, which
-/// should be smaller than the number of bits in the hash (and not 0). We chose
-/// `8` for it. Now, we define a table structure: an array of nodes with length
-/// `1 << BITS` (`256` in this case).
+/// should be smaller than the number of bits in the hash (and not 0). It is the
+/// last type parameter of the map, `8` by default. Now, we define a table
+/// structure: an array of nodes with length `1 << BITS` (`256` by default).
+/// Smaller tables waste less memory in small maps, while bigger ones make the
+/// tree shallower in big maps. `BITS` must be between `1` and `16`.
 ///
 /// For inserting, we take the first `BITS` bits of the hash. Now, we verify
 /// the node. If it is empty, insert a new bucket with our entry (a leaf of the
@@ -45,11 +194,25 @@ use std::{
 /// create a sub-table, insert the old leaf into the new sub-table, and insert
 /// our pair after.
 ///
+/// Hashes are 64 bits wide, so the tree is at most `64 / BITS` levels deep,
+/// rounded up, and keys whose hashes collide share a bucket no matter how deep
+/// the tree gets. With the `hash128` feature, keys are hashed a second time,
+/// with a seed, into the upper half of a 128-bit hash, which keeps the tree
+/// splitting for up to `128 / BITS` levels. It costs a second
+/// hashing of the key per operation, and it is useful when an adversary could
+/// feed keys whose 64-bit hashes collide.
+///
 /// Entries in a bucket are a single linked list ordered by key. The ordering
 /// of the list is because of possible race conditions if e.g. new nodes were
 /// always inserted at end. And if a bucket is detected to be empty, the
 /// table will be requested to delete the bucket.
 ///
+/// Keys which are not [`Ord`] can still be used by giving [`Unordered`] as the
+/// last type parameter, e.g. through [`new_unordered`](Map::new_unordered).
+/// Entries are then always appended at the end of the bucket, which is safe
+/// as long as the last entry is replaced by every append; the details are in
+/// the docs of [`Unordered`]. The default, [`Ordered`], orders the buckets.
+///
 /// For searching, in a similar way, the hash is shifted and sub-tables are
 /// entered until either a node is empty or a leaf is found. If the hash of the
 /// leaf's bucket is equal to our hash, we search for the entry into the bucket.
@@ -60,10 +223,34 @@ use std::{
 /// references to the entries, neither allow the user to move out removed
 /// values, as they must be deinitialized correctly. Instead, we return guarded
 /// references to the entries and wrappers over removed entries.
-pub struct Map<K, V, H = RandomState> {
-    top: OwnedAlloc<Table<K, V>>,
+///
+/// If the [`Hash`] or [`Ord`] implementation of a key, or a closure given to
+/// an operation, panics, the operation is abandoned: the incinerator is no
+/// longer paused by it, nothing allocated on the way is leaked, and the
+/// [`Map`] is left valid for any later operation. Changes made before the
+/// panic are kept, e.g. the entries [`retain`](Map::retain) removed already.
+pub struct Map<
+    K,
+    V,
+    H = DefaultHashBuilder,
+    const BITS: usize = 8,
+    O = Ordered,
+> {
+    // Only replaced once, when a compact map is upgraded.
+    top: AtomicPtr<Table<K, V>>,
     incin: SharedIncin<K, V>,
+    // Where buckets and their nodes come from. Boxed, since buckets point to
+    // it, and declared after `top`, whose buckets are given back to it.
+    slabs: Box<Slabs<K, V>>,
     builder: H,
+    len: AtomicUsize,
+    pop_cursor: AtomicUsize,
+    // Identifies how this map hashes keys. Maps share a stamp only if their
+    // hasher builders are clones of each other, so removed entries can be
+    // reinserted without hashing their keys again.
+    stamp: usize,
+    hooks: Option<Hooks<K, V>>,
+    _order: PhantomData<O>,
 }
 
 impl<K, V> Map<K, V> {
@@ -74,44 +261,525 @@ impl<K, V> Map<K, V> {
         Self::default()
     }
 
+    /// Creates a new [`Map`] with the default hasher builder, whose tables
+    /// are built ahead for the given number of entries. See
+    /// [`reserve`](Map::reserve).
+    pub fn with_capacity(capacity: usize) -> Self {
+        let this = Self::new();
+        this.reserve(capacity);
+        this
+    }
+
     /// Creates the [`Map`] using the given shared incinerator.
     pub fn with_incin(incin: SharedIncin<K, V>) -> Self {
-        Self::with_hasher_and_incin(RandomState::default(), incin)
+        Self::with_hasher_and_incin(DefaultHashBuilder::default(), incin)
+    }
+
+    /// Creates a compact [`Map`] with the default hasher builder. See
+    /// [`with_fanout_compact`](Map::with_fanout_compact).
+    pub fn new_compact() -> Self {
+        Self::with_fanout_compact(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V> Map<K, V, DefaultHashBuilder, 8, Unordered> {
+    /// Creates a new [`Map`] with the default hasher builder, whose buckets
+    /// are not ordered, so keys only need [`Hash`] and [`Eq`]. See
+    /// [`Unordered`].
+    pub fn new_unordered() -> Self {
+        Self::default()
     }
 }
 
-impl<K, V, H> Map<K, V, H> {
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O> {
+    /// The number of entries in this [`Map`]. If the [`Map`] is shared, the
+    /// count is only eventually consistent: concurrent insertions and removals
+    /// might not be reflected yet.
+    pub fn len(&self) -> usize {
+        let len = self.len.load(Relaxed);
+        // A removal might be counted before the insertion of the same entry,
+        // making the counter wrap below zero for a short time.
+        if len > isize::MAX as usize {
+            0
+        } else {
+            len
+        }
+    }
+
+    /// Returns whether this [`Map`] has no entries. The same considerations of
+    /// [`len`](Map::len) apply.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Creates an iterator over guarded references to the key-value entries.
     pub fn iter(&self) -> Iter<K, V> {
         self.into_iter()
     }
 
     /// Creates an iterator over the key-value entries, with a mutable reference
-    /// to the value.
+    /// to the value. Since access is exclusive, the incinerator is not paused,
+    /// and the tables are read without synchronization.
     pub fn iter_mut(&mut self) -> IterMut<K, V> {
         self.into_iter()
     }
 
+    /// Calls the given closure on every entry of the [`Map`], with a mutable
+    /// reference to the value, just like iterating through
+    /// [`iter_mut`](Map::iter_mut). Useful to normalize values before sharing
+    /// the [`Map`].
+    pub fn for_each_mut<F>(&mut self, mut visitor: F)
+    where
+        F: FnMut(&K, &mut V),
+    {
+        for (key, val) in self {
+            visitor(key, val);
+        }
+    }
+
+    /// Consumes this [`Map`] into a [`HashMap`] using the same hasher builder.
+    /// Entries are moved out of their allocations, so nothing is cloned. Only
+    /// available with the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn into_hash_map(self) -> HashMap<K, V, H>
+    where
+        K: Hash + Eq,
+        H: BuildHasher,
+    {
+        let len = self.len();
+        let (iter, builder) = self.into_parts();
+        let mut map = HashMap::with_capacity_and_hasher(len, builder);
+        map.extend(iter);
+        map
+    }
+
     /// Tries to optimize space by removing unnecessary tables *without removing
     /// any entry*. This method might also clear delayed resource destruction.
     /// This method cannot be performed in a shared context.
     pub fn optimize_space(&mut self) {
         self.incin.clear();
-        self.top.optimize_space();
+        self.slabs.recycle_mut();
+        self.top_mut().optimize_space();
     }
 
-    /// Removes all entries. This method might also clear delayed resource
-    /// destruction. This method cannot be performed in a shared context.
-    pub fn clear(&mut self) {
-        self.incin.clear();
-        let mut tables = Vec::new();
-        self.top.clear(&mut tables);
+    /// Retires every sub-table left empty by removals, returning how many were
+    /// retired. Unlike [`optimize_space`](Map::optimize_space), this method
+    /// can be performed in a shared context: retired tables are destroyed
+    /// through the incinerator. Insertions racing with the retirement of the
+    /// sub-table they need are retried from the top of the [`Map`] until the
+    /// sub-table is either retired or kept. A compact [`Map`] is not shrunk
+    /// before it is upgraded.
+    pub fn shrink(&self) -> usize {
+        self.shrink_top(false)
+    }
 
-        while let Some(mut table) = tables.pop() {
-            // This is safe because we won't be using these tables anymore. We
-            // won't load its nodes' contents.
-            unsafe { table.free_nodes(&mut tables) }
+    /// Builds ahead the sub-tables needed for `additional` entries more than
+    /// the current length, so that insertions rarely need to create tables.
+    /// Levels of tables are built until each node of the deepest one is
+    /// expected to hold fewer than two entries, sparing insertions the work
+    /// of branching nodes one collision at a time. Nodes already holding
+    /// entries are left to be branched by insertions. Sub-tables are
+    /// installed just like insertions install them, so this can be called
+    /// concurrently with any operation. A compact [`Map`] is upgraded first,
+    /// unless the entries fit in it before its upgrade.
+    ///
+    /// Sub-tables left empty are retired by [`shrink`](Map::shrink),
+    /// [`compact`](Map::compact) and [`optimize_space`](Map::optimize_space),
+    /// so this should not be called before them. The built depth is shown by
+    /// [`stats`](Map::stats).
+    pub fn reserve(&self, additional: usize) {
+        let capacity = self.len().saturating_add(additional);
+        let pause = self.pause();
+        if self.is_compact(self.top(&pause)) && capacity < COMPACT_LEN {
+            return;
+        }
+        let top = self.upgraded_top(&pause);
+        // The upgraded top table is never replaced, and the walk pauses the
+        // incinerator in chunks.
+        drop(pause);
+        top.reserve(&self.incin.inner, &self.slabs, capacity);
+    }
+
+    /// Acts just like [`shrink`](Map::shrink), but also retires sub-tables
+    /// left with a single bucket of entries, moving the bucket up to the
+    /// place of the sub-table, so lookups for those entries go through fewer
+    /// tables. Returns how many sub-tables were retired.
+    pub fn compact(&self) -> usize {
+        self.shrink_top(true)
+    }
+
+    /// Computes structural statistics of this [`Map`], such as the depth of
+    /// the tables and the lengths of the buckets, which tell how well the keys
+    /// are spread by the hasher. The incinerator is paused only while small
+    /// chunks of the [`Map`] are read, so the statistics are only approximate
+    /// if the [`Map`] is changed concurrently.
+    pub fn stats(&self) -> MapStats {
+        self.walk_top(|top| top.stats(&self.incin.inner))
+    }
+
+    /// Writes the structure of this [`Map`] to the given output, for
+    /// debugging. The first line tells the size of the top table. Then, every
+    /// node holding something gets a line, depth-first and in order of index,
+    /// starting with the indices leading to the node, separated by dots. A
+    /// node holding a sub-table is followed by the nodes of the sub-table. A
+    /// node holding a bucket also shows the hash of the bucket, how many
+    /// entries it has, and the entries themselves, in order. Just like in
+    /// [`for_each`](Map::for_each), the incinerator is paused only while
+    /// small chunks of the [`Map`] are read, so the dump may mix different
+    /// states of the [`Map`] if it is changed concurrently.
+    pub fn dump_structure(&self, out: &mut dyn fmt::Write) -> fmt::Result
+    where
+        K: fmt::Debug,
+        V: fmt::Debug,
+    {
+        self.walk_top(|top| top.dump(&self.incin.inner, out))
+    }
+
+    /// Estimates the memory used by this [`Map`], counting its tables,
+    /// buckets, list cells and pairs, from the sizes of their types. The
+    /// [`Map`] is traversed just like in [`stats`](Map::stats), so the
+    /// estimate is only approximate if the [`Map`] is changed concurrently.
+    /// Buckets and list cells are taken from chunks which the [`Map`] keeps
+    /// until it is dropped, recycling them once removed; the spare ones are
+    /// not counted.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let stats = self.stats();
+        MemoryUsage {
+            tables: stats.tables,
+            table_bytes: stats.table_bytes,
+            buckets: stats.leaves,
+            bucket_bytes: stats.leaves * Bucket::byte_size(&self.slabs),
+            entries: stats.entries,
+            // The first entry of each bucket is kept in it.
+            list_bytes: stats.entries.saturating_sub(stats.leaves)
+                * Bucket::entry_byte_size(&self.slabs),
+            // Pairs kept inline are counted along with their entries.
+            pair_bytes: if self.slabs.inline {
+                0
+            } else {
+                stats.entries * mem::size_of::<(K, V)>()
+            },
+            pending_garbage: self.incin.inner.pending(),
+        }
+    }
+
+    /// Checks the internal invariants of this [`Map`], panicking if one is
+    /// broken: only the first entry of each bucket may be a sentinel entry,
+    /// the keys of the entries are distinct, and strictly ordered unless the
+    /// [`Map`] is [`Unordered`], and the hash of each bucket matches both its
+    /// keys and the path of nodes leading to it. The incinerator is paused
+    /// for the whole check, so nothing is freed meanwhile, but the check is
+    /// only reliable if the [`Map`] is not changed concurrently. Only
+    /// available in tests of this crate and with the `debug-validate` feature.
+    #[cfg(any(test, feature = "debug-validate"))]
+    pub fn validate(&self)
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        H: BuildHasher,
+    {
+        let pause = self.pause();
+        let top = self.top(&pause);
+        let protected = self.is_flood_protected();
+        // Safe because the incinerator is paused until the end.
+        unsafe {
+            top.validate::<O, _>(protected, |key, hash, salted| {
+                assert!(self.hash_of(key) == hash, "key in the wrong bucket");
+                if salted.is_some() {
+                    assert!(
+                        self.salted_of(key) == salted,
+                        "key in the wrong salted bucket",
+                    );
+                }
+            })
+        }
+    }
+
+    /// Removes all entries. Detached entries are destroyed through the
+    /// incinerator, so this method can be performed in a shared context.
+    /// Entries inserted concurrently might either survive or be removed.
+    /// Sub-tables are kept; use [`shrink`](Map::shrink) to release them.
+    pub fn clear(&self) {
+        let events = self.events();
+        let mut count = 0;
+        self.walk_top(|top| {
+            top.drain(&self.incin.inner, &self.slabs, |pair| {
+                events.removed(&pair);
+                count += 1;
+            })
+        });
+        self.len.fetch_sub(count, Relaxed);
+    }
+
+    /// Removes all entries and returns them. The same considerations of
+    /// [`clear`](Map::clear) apply. If many threads drain the [`Map`]
+    /// concurrently, each entry ends up in exactly one of the returned
+    /// vectors.
+    pub fn drain(&self) -> Vec<Removed<K, V>> {
+        let events = self.events();
+        let mut removed = Vec::new();
+        self.walk_top(|top| {
+            top.drain(&self.incin.inner, &self.slabs, |mut pair| {
+                events.removed(&pair);
+                Removed::stamp(&mut pair, self.stamp);
+                removed.push(pair);
+            })
+        });
+        self.len.fetch_sub(removed.len(), Relaxed);
+        removed
+    }
+
+    fn into_parts(mut self) -> (IntoIter<K, V>, H) {
+        // Unfortunately, this unsafe is needed since there is no other way of
+        // moving the fields out and forgetting the Map.
+        let raw = NonNull::from(self.top_mut());
+        unsafe {
+            let builder = (&self.builder as *const H).read();
+            let slabs = (&self.slabs as *const Box<Slabs<K, V>>).read();
+            (&mut self.incin as *mut SharedIncin<K, V>).drop_in_place();
+            (&mut self.hooks as *mut Option<Hooks<K, V>>).drop_in_place();
+            mem::forget(self);
+            (IntoIter::new(OwnedAlloc::from_raw(raw), slabs), builder)
+        }
+    }
+
+    // Pauses the incinerator, recycling the nodes given back to the slabs
+    // first, if nothing is paused. Pausing the incinerator directly is just
+    // as safe, but never recycles.
+    fn pause<'map>(&'map self) -> Pause<'map, Garbage<K, V>> {
+        let incin = &self.incin.inner;
+        self.slabs.recycle(|| incin.is_unpaused());
+        incin.pause()
+    }
+
+    // The current top table. It must only be used while the given pause is
+    // alive, since a compact top table is retired when upgraded.
+    fn top(&self, _pause: &Pause<Garbage<K, V>>) -> &Table<K, V> {
+        // Safe because the top table is only destroyed by the incinerator or
+        // in the destructor.
+        unsafe { &*self.top.load(Acquire) }
+    }
+
+    // Tests whether the given top table still needs to be upgraded.
+    fn is_compact(&self, top: &Table<K, V>) -> bool {
+        top.bits() < BITS
+    }
+
+    // Passes the top table to a traversal which pauses the incinerator in
+    // chunks. A compact top table could be upgraded and retired between two
+    // chunks, so it is traversed under a single pause; it is small anyway. The
+    // upgraded top table is never replaced.
+    fn walk_top<F, T>(&self, walker: F) -> T
+    where
+        F: FnOnce(&Table<K, V>) -> T,
+    {
+        let pause = self.pause();
+        let top = self.top(&pause);
+        let _pause = if self.is_compact(top) { Some(pause) } else { None };
+        walker(top)
+    }
+
+    // The top table once upgraded, upgrading the map if it is still compact.
+    // The upgraded top table is never replaced.
+    fn upgraded_top(&self, pause: &Pause<Garbage<K, V>>) -> &Table<K, V> {
+        let mut top = self.top(pause);
+        while self.is_compact(top) {
+            // Safe because the caller paused properly.
+            if let Err(err) = unsafe { self.upgrade(top, pause) } {
+                err.handle();
+            }
+            top = self.top(pause);
+        }
+        top
+    }
+
+    fn shrink_top(&self, promote: bool) -> usize {
+        let pause = self.pause();
+        let top = self.top(&pause);
+        // The nodes of a compact tree may be frozen by an upgrade for good,
+        // which `shrink` does not expect.
+        if self.is_compact(top) {
+            return 0;
+        }
+        // Safe because we paused properly.
+        unsafe { top.shrink(promote, &pause, &self.slabs) }
+    }
+
+    // Inserts through the current top table. Whenever a frozen node is found,
+    // the insertion starts again from the top, after helping the upgrade of a
+    // compact top table if that is why the node is frozen. A compact top table
+    // is also upgraded once it holds enough entries. The inserted pair is
+    // recorded in the given events. Calls the global allocation error handler
+    // if allocation fails. Unsafe because the pause must come from the
+    // incinerator of this map.
+    pub(super) unsafe fn insert_top<I>(
+        &self,
+        inserter: I,
+        hash: HashCode,
+        pause: &Pause<Garbage<K, V>>,
+        events: &Events<K, V>,
+    ) -> Insertion<K, V, I>
+    where
+        I: Inserter<K, V>,
+        O: BucketOrder<K>,
+    {
+        match self.try_insert_top(inserter, hash, pause, events) {
+            Ok(insertion) => insertion,
+            Err((_, err)) => err.handle(),
+        }
+    }
+
+    // Just like `insert_top`, but if allocation fails, the inserter is given
+    // back and nothing is inserted. Unsafe for the same reasons as
+    // `insert_top`.
+    pub(super) unsafe fn try_insert_top<I>(
+        &self,
+        inserter: I,
+        hash: HashCode,
+        pause: &Pause<Garbage<K, V>>,
+        events: &Events<K, V>,
+    ) -> Result<Insertion<K, V, I>, (I, AllocFailed)>
+    where
+        I: Inserter<K, V>,
+        O: BucketOrder<K>,
+    {
+        let cloned = Cell::new(None);
+        let mut inserter = events.observe(inserter, &cloned);
+        let mut probe = Probe::new();
+
+        let insertion = loop {
+            let top = self.top(pause);
+            let res = top.insert::<O, _>(
+                inserter,
+                hash,
+                pause,
+                &self.incin.inner,
+                &self.slabs,
+                &mut probe,
+            );
+            match res {
+                Ok(insertion) => {
+                    if self.is_compact(top)
+                        && insertion.created()
+                        && self.len() >= COMPACT_LEN
+                    {
+                        // The entry is in already, so, if the upgrade fails,
+                        // it is left to later insertions.
+                        let _ = self.upgrade(top, pause);
+                    }
+                    break insertion;
+                },
+
+                Err(InsertErr::Frozen(returned)) => {
+                    if self.is_compact(top) {
+                        if let Err(err) = self.upgrade(top, pause) {
+                            return Err((returned.into_inner(), err));
+                        }
+                    }
+                    inserter = returned;
+                    probe.retry("frozen");
+                    probe.restart();
+                },
+
+                Err(InsertErr::NoMemory(returned, err)) => {
+                    return Err((returned.into_inner(), err));
+                },
+            }
+        };
+
+        if let Some(pair) = cloned.take() {
+            events.inserted(pair, !insertion.created());
+        }
+        Ok(match insertion {
+            Insertion::Created => Insertion::Created,
+            Insertion::Updated(mut old) => {
+                Removed::stamp(&mut old, self.stamp);
+                Insertion::Updated(old)
+            },
+            Insertion::Failed(observed) => {
+                Insertion::Failed(observed.into_inner())
+            },
+        })
+    }
+
+    // The top table, with exclusive access to the map, after finishing the
+    // upgrade of a frozen compact tree, if any.
+    fn top_mut(&mut self) -> &mut Table<K, V> {
+        self.finish_upgrade_mut();
+        // Safe because we have exclusive access to the tables.
+        unsafe { &mut **self.top.get_mut() }
+    }
+
+    // Upgrades the compact tree if it was left frozen by `upgrade`, which
+    // happens only if allocation failed, since nothing expects frozen nodes
+    // with exclusive access to the map.
+    fn finish_upgrade_mut(&mut self) {
+        // Safe because we have exclusive access to the tables.
+        let top = unsafe { &**self.top.get_mut() };
+        if self.is_compact(top) && top.is_frozen_all() {
+            self.upgrade_mut();
+        }
+    }
+
+    // Replaces the compact top table by a top table with `1 << BITS` nodes,
+    // just like `upgrade`, but with exclusive access to the map, so nothing
+    // needs to be frozen and the compact tables are deallocated right away.
+    fn upgrade_mut(&mut self) {
+        let compact = *self.top.get_mut();
+        // Safe because we have exclusive access to the tables, and the
+        // buckets now belong to the new tree.
+        unsafe {
+            let new_top = (*compact).rebuild(BITS).into_raw();
+            *self.top.get_mut() = new_top.as_ptr();
+            Table::detach_tables(NonNull::new_unchecked(compact), drop);
+        }
+    }
+
+    // Replaces the given compact top table by a top table with `1 << BITS`
+    // nodes, unless it was already replaced. The compact tree is frozen, its
+    // buckets are moved to a new tree, and the new top table is published
+    // with a single swap. Lookups and removals keep working on the frozen tree
+    // meanwhile, since the buckets are shared, while insertions help the
+    // upgrade. Any number of threads may do this at once, but only one of them
+    // publishes its new tree. If allocation fails, the compact tree is left
+    // frozen, for a later upgrade to finish. Unsafe because the pause must
+    // come from the incinerator of this map.
+    unsafe fn upgrade(
+        &self,
+        compact: &Table<K, V>,
+        pause: &Pause<Garbage<K, V>>,
+    ) -> Result<(), AllocFailed> {
+        let compact = compact as *const Table<K, V> as *mut Table<K, V>;
+        if self.top.load(Acquire) != compact {
+            return Ok(());
+        }
+
+        (*compact).freeze_all();
+        let new_top = (*compact).try_rebuild(BITS)?.into_raw();
+
+        // `Release` publishes the new tree. Nothing found on failure is read,
+        // but `Acquire` costs nothing next to rebuilding a tree.
+        let res = self.top.compare_exchange(
+            compact,
+            new_top.as_ptr(),
+            AcqRel,
+            Acquire,
+        );
+
+        match res {
+            // The frozen tables need to be destroyed by the incinerator as
+            // they are shared. Their buckets now belong to the new tree.
+            Ok(_) => {
+                let compact = NonNull::new_unchecked(compact);
+                Table::detach_tables(compact, |table| {
+                    pause.add_to_incin(Garbage::Table(table))
+                })
+            },
+
+            // Someone else published their tree first.
+            Err(_) => Table::detach_tables(new_top, drop),
         }
+        Ok(())
     }
 }
 
@@ -121,13 +789,104 @@ where
 {
     /// Creates the [`Map`] using the given hasher builder.
     pub fn with_hasher(builder: H) -> Self {
-        Self::with_hasher_and_incin(builder, SharedIncin::new())
+        Self::with_fanout(builder)
+    }
+
+    /// Creates the [`Map`] using the given hasher builder, whose tables are
+    /// built ahead for the given number of entries. See
+    /// [`reserve`](Map::reserve).
+    pub fn with_capacity_and_hasher(capacity: usize, builder: H) -> Self {
+        let this = Self::with_hasher(builder);
+        this.reserve(capacity);
+        this
     }
 
     /// Creates the [`Map`] using the given hasher builder and shared
     /// incinerator.
     pub fn with_hasher_and_incin(builder: H, incin: SharedIncin<K, V>) -> Self {
-        Self { top: Table::new_alloc(), incin, builder }
+        Self::with_fanout_and_incin(builder, incin)
+    }
+
+    /// Creates the [`Map`] using the given hasher builder and inserts the
+    /// entries of the given iterable. Repeated keys behave like repeated
+    /// [`insert`](Map::insert)s: the last value wins.
+    pub fn from_iter_with_hasher<I>(iterable: I, builder: H) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Hash + Ord,
+    {
+        let this = Self::with_hasher(builder);
+        this.extend(iterable);
+        this
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+{
+    /// Creates the [`Map`] using the given hasher builder, with tables of
+    /// `1 << BITS` nodes. Unlike [`with_hasher`](Map::with_hasher), this works
+    /// for any `BITS`, which is usually given by the type, e.g.
+    /// `Map::<K, V, RandomState, 4>::with_fanout(RandomState::new())`.
+    ///
+    /// # Panics
+    /// Panics if `BITS` is not between `1` and `16`.
+    pub fn with_fanout(builder: H) -> Self {
+        Self::with_fanout_and_incin(builder, SharedIncin::new())
+    }
+
+    /// Creates the [`Map`] using the given hasher builder and shared
+    /// incinerator, with tables of `1 << BITS` nodes. The same considerations
+    /// of [`with_fanout`](Map::with_fanout) apply.
+    pub fn with_fanout_and_incin(builder: H, incin: SharedIncin<K, V>) -> Self {
+        Self::with_top_bits(builder, incin, BITS)
+    }
+
+    /// Creates a compact [`Map`] using the given hasher builder. A compact
+    /// [`Map`] starts with tables of `16` nodes, and it is upgraded to tables
+    /// of `1 << BITS` nodes once it holds `64` entries, saving memory for
+    /// small maps. The upgrade does not block lookups or removals, and
+    /// insertions racing with it help finishing it. Until upgraded, the
+    /// incinerator is paused for the whole traversal in methods such as
+    /// [`for_each`](Map::for_each). If `BITS` is not bigger than `4`, this is
+    /// the same as [`with_fanout`](Map::with_fanout).
+    pub fn with_fanout_compact(builder: H) -> Self {
+        let bits = if BITS > COMPACT_BITS { COMPACT_BITS } else { BITS };
+        Self::with_top_bits(builder, SharedIncin::new(), bits)
+    }
+
+    /// Creates the [`Map`] using the given hasher builder, with tables of
+    /// `1 << BITS` nodes, whose insertions and removals are reported to the
+    /// given hooks. The entries are cloned for the hooks, as described in
+    /// [`MapHooks`].
+    pub fn with_hooks(builder: H, hooks: Arc<dyn MapHooks<K, V>>) -> Self
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut this = Self::with_fanout(builder);
+        this.hooks = Some(Hooks::new(hooks));
+        this
+    }
+
+    fn with_top_bits(
+        builder: H,
+        incin: SharedIncin<K, V>,
+        bits: usize,
+    ) -> Self {
+        assert!(BITS >= 1 && BITS <= MAX_BITS, "BITS must be between 1 and 16");
+        Self {
+            top: AtomicPtr::new(Table::new_alloc(bits).into_raw().as_ptr()),
+            incin,
+            slabs: Box::new(Slabs::new()),
+            builder,
+            len: AtomicUsize::new(0),
+            pop_cursor: AtomicUsize::new(0),
+            stamp: new_stamp(),
+            hooks: None,
+            _order: PhantomData,
+        }
     }
 
     /// The shared incinerator used by this [`Map`].
@@ -149,646 +908,7298 @@ where
     /// found, [`None`] is returned.
     pub fn get<'map, Q>(&'map self, key: &Q) -> Option<ReadGuard<'map, K, V>>
     where
-        Q: ?Sized + Hash + Ord,
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
         K: Borrow<Q>,
     {
         let hash = self.hash_of(key);
-        let pause = self.incin.inner.pause();
+        let salted = || self.salted_of(key);
+        let pause = self.pause();
         // Safe because we paused properly.
-        unsafe { self.top.get(key, hash, pause) }
+        unsafe { self.top(&pause).get::<O, Q, _>(key, hash, salted, pause) }
     }
 
-    /// Inserts unconditionally the given key and value. If there was a
-    /// previously stored value, it is returned.
-    pub fn insert(&self, key: K, val: V) -> Option<Removed<K, V>>
+    /// Searches for the entry identified by the given key, just like
+    /// [`get`](Map::get), but the key is not hashed: the given hash is used
+    /// instead of the hasher builder, e.g. for keys which are digests already
+    /// or whose hash was computed once for many operations.
+    ///
+    /// A key must always be given along with the same hash, in every
+    /// operation ending in `_hashed`. Otherwise, entries may be missed or
+    /// stored twice, and which ones is unspecified, but it is still
+    /// memory-safe. The methods hashing keys with the builder, such as
+    /// [`get`](Map::get), only find these entries if the given hashes are
+    /// the ones of the builder, which never happens with the `hash128`
+    /// feature.
+    pub fn get_hashed<'map, Q>(
+        &'map self,
+        hash: u64,
+        key: &Q,
+    ) -> Option<ReadGuard<'map, K, V>>
     where
-        K: Hash + Ord,
+        Q: ?Sized + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
     {
-        let pause = self.incin.inner.pause();
-        let hash = self.hash_of(&key);
+        let hash = given_hash(hash);
+        let pause = self.pause();
         // Safe because we paused properly.
-        let insertion = unsafe {
-            self.top.insert(
-                InsertNew::with_pair(|_, _, _| Preview::Keep, (key, val)),
-                hash,
-                &pause,
-                &self.incin.inner,
-            )
-        };
-
-        match insertion {
-            Insertion::Created => None,
-            Insertion::Updated(old) => Some(old),
-            Insertion::Failed(_) => unreachable!(),
-        }
+        unsafe { self.top(&pause).get::<O, Q, _>(key, hash, || None, pause) }
     }
 
-    /// Inserts _interactively_ the given key. A closure is passed to generate
-    /// the value part of the entry and validate it with the found value. Even
-    /// though the closure may have already accepted some condition, it might
-    /// get recalled many times due to concurrent modifications of the [`Map`].
+    /// Searches for the entry identified by the given key, just like
+    /// [`get`](Map::get), but the returned guard dereferences to the value
+    /// alone, which suits code returning early with `?`. The entry stays valid
+    /// while the guard is alive, even if it is removed meanwhile.
     ///
-    /// The first argument passed to the closure is the key passed in first
-    /// place. The second argument is an optional mutable reference to a
-    /// previously generated value. Obviously, if no value was ever generated,
-    /// it is [`None`]. The third argument is a reference to the found stored
-    /// entry. Obviously, if no stored entry was found, it is `None`. The return
-    /// value of the closure is a specification of "what to do with the
-    /// insertion now".
-    pub fn insert_with<F>(
-        &self,
-        key: K,
-        interactive: F,
-    ) -> Insertion<K, V, (K, Option<V>)>
+    /// **Holding the guard blocks memory reclamation for every thread using
+    /// this [`Map`]**, just like holding any [`ReadGuard`]. Drop it as soon as
+    /// possible; in debug builds, a warning is printed if it lives for more
+    /// than a second. See [`ValueGuard`].
+    #[must_use]
+    pub fn get_guarded<'map, Q>(
+        &'map self,
+        key: &Q,
+    ) -> Option<ValueGuard<'map, K, V>>
     where
-        K: Hash + Ord,
-        F: FnMut(&K, Option<&mut V>, Option<&(K, V)>) -> Preview<V>,
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
     {
-        let hash = self.hash_of(&key);
-        let pause = self.incin.inner.pause();
-        // Safe because we paused properly.
-        let insertion = unsafe {
-            self.top.insert(
-                InsertNew::with_key(interactive, key),
-                hash,
-                &pause,
-                &self.incin.inner,
-            )
-        };
-
-        match insertion {
-            Insertion::Created => Insertion::Created,
-            Insertion::Updated(old) => Insertion::Updated(old),
-            Insertion::Failed(inserter) => {
-                Insertion::Failed(inserter.into_pair())
-            },
-        }
+        self.get(key).map(ValueGuard::new)
     }
 
-    /// Reinserts a previously removed entry. The entry must have been either:
-    ///
-    /// 1. Removed from any [`Map`] using the same [`SharedIncin`] as this
-    /// [`Map`]. 2. Removed from an already dead [`Map`] with dead
-    /// [`SharedIncin`]. 3. Removed from a [`Map`] whose `SharedIncin` has
-    /// no sensitive reads active.
-    ///
-    /// If the removed entry does not fit any category, the insertion will fail.
-    /// Otherwise, insertion cannot fail.
-    pub fn reinsert(
-        &self,
-        mut removed: Removed<K, V>,
-    ) -> Insertion<K, V, Removed<K, V>>
+    /// Searches for the entry identified by the given key and clones its
+    /// value. The clone happens while the incinerator is paused; if it panics,
+    /// the pause is still released. See [`Map::get`] for the requirements on
+    /// the borrowed key type.
+    pub fn get_cloned<Q>(&self, key: &Q) -> Option<V>
     where
-        K: Hash + Ord,
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        V: Clone,
     {
-        if !Removed::is_usable_by(&mut removed, &self.incin.inner) {
-            return Insertion::Failed(removed);
-        }
+        self.get(key).map(|guard| guard.val().clone())
+    }
 
-        let hash = self.hash_of(removed.key());
+    /// Searches for the entry identified by the given key and clones both its
+    /// key and value. The clone happens while the incinerator is paused; if it
+    /// panics, the pause is still released. See [`Map::get`] for the
+    /// requirements on the borrowed key type.
+    pub fn get_pair_cloned<Q>(&self, key: &Q) -> Option<(K, V)>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q> + Clone,
+        V: Clone,
+    {
+        self.get(key).map(|guard| (guard.key().clone(), guard.val().clone()))
+    }
 
-        let pause = self.incin.inner.pause();
+    /// Searches for the entry identified by the given key and clones its
+    /// stored key, which may differ from the searched one when they are only
+    /// equal as borrowed keys, e.g. when case is ignored. The clone happens
+    /// while the incinerator is paused, just like in
+    /// [`get_pair_cloned`](Map::get_pair_cloned).
+    pub fn get_key_cloned<Q>(&self, key: &Q) -> Option<K>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q> + Clone,
+    {
+        self.get(key).map(|guard| guard.key().clone())
+    }
+
+    /// Searches for the entry identified by the given key, just like
+    /// [`get`](Map::get), but exploits exclusive access to the [`Map`]: the
+    /// incinerator is not paused, and a plain mutable reference to the value
+    /// is returned.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_of(key);
+        let salt = self.slabs.salt;
+        let salted = || salt.map(|salt| salt.hash(key));
+        let top = self.top_mut();
+        top.get_mut::<O, Q, _>(key, hash, salted).map(|(_, val)| val)
+    }
+
+    /// Searches for the entries identified by each of the given keys and calls
+    /// the given closure on the found ones, returning what the closure returns
+    /// in the same order as the keys, or [`None`] for keys not found. Lookups
+    /// are performed in batches, each one under a single incinerator pause,
+    /// and keys are taken from the iterator before the pause starts. This
+    /// method will only work correctly if [`Hash`] and [`Ord`] are implemented
+    /// in the same way for the borrowed type and the stored type.
+    pub fn get_many<'key, Q, I, F, T>(
+        &self,
+        keys: I,
+        mut reader: F,
+    ) -> Vec<Option<T>>
+    where
+        Q: ?Sized + Hash + Eq + 'key,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        I: IntoIterator<Item = &'key Q>,
+        F: FnMut(&K, &V) -> T,
+    {
+        let mut iter = keys.into_iter();
+        let mut batch = Vec::with_capacity(BATCH_LEN);
+        let mut found = Vec::with_capacity(iter.size_hint().0);
+
+        loop {
+            batch.extend(iter.by_ref().take(BATCH_LEN));
+            if batch.is_empty() {
+                break;
+            }
+
+            let pause = self.pause();
+            for key in batch.drain(..) {
+                let hash = self.hash_of(key);
+                let salted = || self.salted_of(key);
+                // Safe because we paused properly and the pair is only used
+                // while paused.
+                let pair = unsafe {
+                    let top = self.top(&pause);
+                    top.get_paused::<O, Q, _>(key, hash, salted, &pause)
+                };
+                found.push(pair.map(|(key, val)| reader(key, val)));
+            }
+        }
+
+        found
+    }
+
+    /// Tests if the entry identified by the given key is present in the
+    /// [`Map`]. The method accepts a type resulted from borrowing the stored
+    /// key. This method will only work correctly if [`Hash`] and [`Ord`] are
+    /// implemented in the same way for the borrowed type and the stored type.
+    /// Note that, if the [`Map`] is shared, the answer is only a snapshot: the
+    /// entry might be inserted or removed right after this method returns.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Inserts unconditionally the given key and value. If there was a
+    /// previously stored value, it is returned.
+    pub fn insert(&self, key: K, val: V) -> Option<Removed<K, V>>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let events = self.events();
+        let pause = self.pause();
+        self.insert_paused(key, val, &pause, &events)
+    }
+
+    /// Inserts unconditionally the given key and value, just like
+    /// [`insert`](Map::insert), then calls `reader` on the inserted entry and
+    /// returns its return value along with the previously stored entry, if
+    /// any. The entry is read under the same incinerator pause as the
+    /// insertion, so no second lookup is needed. `reader` always sees the
+    /// entry inserted by this call, even if another thread concurrently
+    /// replaced or removed it in the meantime.
+    pub fn insert_and_read<F, T>(
+        &self,
+        key: K,
+        val: V,
+        reader: F,
+    ) -> (Option<Removed<K, V>>, T)
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        F: FnOnce(&K, &V) -> T,
+    {
+        let hash = self.hash_of(&key);
+        let inserter =
+            InsertNew::with_pair(|_, _, _| Preview::Keep, (key, val));
+        let stored = Cell::new(None);
+        let inserter = Located::new(inserter, &stored);
+        let events = self.events();
+        let pause = self.pause();
+        // Safe because we paused properly.
+        let insertion =
+            unsafe { self.insert_top(inserter, hash, &pause, &events) };
+
+        let old = match insertion {
+            Insertion::Created => {
+                self.len.fetch_add(1, Relaxed);
+                None
+            },
+            Insertion::Updated(old) => Some(old),
+            // The closure accepts anything.
+            Insertion::Failed(_) => unreachable!(),
+        };
+
+        // Safe because the pair was inserted by us while paused, so, even if
+        // it was removed since then, it is not deallocated before the pause
+        // ends. An inserted pair is always stored.
+        let (key, val) = unsafe { stored.get().unwrap().as_ref() };
+        (old, reader(key, val))
+    }
+
+    /// Inserts unconditionally the given key and value, just like
+    /// [`insert`](Map::insert), but the key is stored under the given hash
+    /// instead of being hashed by the hasher builder. See
+    /// [`get_hashed`](Map::get_hashed) for the contract on the given hashes.
+    pub fn insert_hashed(
+        &self,
+        hash: u64,
+        key: K,
+        val: V,
+    ) -> Option<Removed<K, V>>
+    where
+        K: Eq,
+        O: BucketOrder<K>,
+    {
+        let events = self.events();
+        let pause = self.pause();
+        self.insert_with_hash(key, val, given_hash(hash), &pause, &events)
+    }
+
+    /// Inserts unconditionally the given key and value, just like
+    /// [`insert`](Map::insert), but if the allocator fails, the key and value
+    /// are given back in an [`AllocError`], instead of the global allocation
+    /// error handler being called. The [`Map`] is left unchanged then, and
+    /// nothing allocated on the way is leaked.
+    ///
+    /// Every allocation of the [`Map`] itself is covered: the pair, the nodes
+    /// of its bucket, and new tables, including the ones of the upgrade of a
+    /// compact [`Map`]. The bookkeeping of the incinerator is not: a thread
+    /// allocates its own the first time it accesses the [`Map`], and garbage
+    /// which cannot be dropped right away is kept in lists. Neither are the
+    /// copies of pairs passed to [`MapHooks`].
+    pub fn try_insert_alloc(
+        &self,
+        key: K,
+        val: V,
+    ) -> Result<Option<Removed<K, V>>, AllocError<K, V>>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let hash = self.hash_of(&key);
+        let inserter = InsertPair::try_new(|_| true, (key, val))
+            .map_err(|(pair, err)| AllocError::new(pair, err))?;
+        let events = self.events();
+        let pause = self.pause();
+        // Safe because we paused properly.
+        let res =
+            unsafe { self.try_insert_top(inserter, hash, &pause, &events) };
+
+        match res {
+            Ok(Insertion::Created) => {
+                self.len.fetch_add(1, Relaxed);
+                Ok(None)
+            },
+            Ok(Insertion::Updated(old)) => Ok(Some(old)),
+            // The closure accepts anything.
+            Ok(Insertion::Failed(_)) => unreachable!(),
+            Err((inserter, err)) => {
+                Err(AllocError::new(inserter.into_pair(), err))
+            },
+        }
+    }
+
+    /// Inserts unconditionally the given key and value, just like
+    /// [`insert`](Map::insert), but exploits exclusive access to the [`Map`]:
+    /// the incinerator is not paused, a stored entry is replaced in place, and
+    /// its previous key and value are returned by value.
+    pub fn insert_mut(&mut self, key: K, val: V) -> Option<(K, V)>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let hash = self.hash_of(&key);
+        let pair = (key, val);
+        let cloned = self.hooks.as_ref().map(|hooks| hooks.cloned(&pair));
+        self.finish_upgrade_mut();
+        // Safe because we have exclusive access to the tables.
+        let top = unsafe { &mut **self.top.get_mut() };
+        let compact = self.is_compact(top);
+        self.slabs.recycle_mut();
+        let old = top.insert_mut::<O>(pair, hash, &mut self.slabs);
+
+        if old.is_none() {
+            let len = self.len.get_mut();
+            *len = len.wrapping_add(1);
+            if compact && self.len() >= COMPACT_LEN {
+                self.upgrade_mut();
+            }
+        }
+        if let (Some(hooks), Some(pair)) = (&self.hooks, cloned) {
+            hooks.on_insert(&pair, old.is_some());
+        }
+        old
+    }
+
+    /// Inserts the given key and value only if no entry with the key is
+    /// present. The test and the insertion are atomic, and the stored entry,
+    /// if any, is left untouched. On failure, the key and value are given back.
+    pub fn try_insert(&self, key: K, val: V) -> Result<(), (K, V)>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let hash = self.hash_of(&key);
+        let events = self.events();
+        let pause = self.pause();
         // Safe because we paused properly.
         let insertion = unsafe {
-            self.top.insert(
-                Reinsert::new(|_, _| true, removed),
+            self.insert_top(
+                InsertPair::new(|found| found.is_none(), (key, val)),
                 hash,
                 &pause,
-                &self.incin.inner,
+                &events,
             )
         };
 
         match insertion {
-            Insertion::Created => Insertion::Created,
-            Insertion::Updated(old) => Insertion::Updated(old),
-            Insertion::Failed(_) => unreachable!(),
+            Insertion::Created => {
+                self.len.fetch_add(1, Relaxed);
+                Ok(())
+            },
+            // The closure never accepts an existing entry.
+            Insertion::Updated(_) => unreachable!(),
+            Insertion::Failed(inserter) => Err(inserter.into_pair()),
         }
     }
 
-    /// Reinserts _interactively_ a previously removed entry. A closure will be
-    /// passed to validate if the conditions are correct for the reinsertion.
-    /// The first argument passed to the closure is a reference to the removed
-    /// entry, the second argument is a reference to the stored found entry.
-    /// Obviously, if no stored entry was found, the argument is [`None`]. The
-    /// returned value is a boolean indicating if the reinsertion should go on.
-    /// Even though the closure may have already accepted some condition, it
-    /// might get recalled many times due to concurrent modifications of the
-    /// [`Map`].
-    ///
-    /// The entry must have been either:
-    ///
-    /// 1. Removed from any [`Map`] using the same [`SharedIncin`] as this
-    /// [`Map`]. 2. Removed from an already dead [`Map`] with dead
-    /// `SharedIncin`. 3. Removed from a [`Map`] whose `SharedIncin` has no
-    /// sensitive reads active.
+    /// Inserts _interactively_ the given key. A closure is passed to generate
+    /// the value part of the entry and validate it with the found value. Even
+    /// though the closure may have already accepted some condition, it might
+    /// get recalled many times due to concurrent modifications of the [`Map`].
     ///
-    /// If the removed entry does not fit any category, the insertion will fail.
-    /// Otherwise, insertion cannot fail.
-    pub fn reinsert_with<F>(
+    /// The first argument passed to the closure is the key passed in first
+    /// place. The second argument is an optional mutable reference to a
+    /// previously generated value. Obviously, if no value was ever generated,
+    /// it is [`None`]. The third argument is a reference to the found stored
+    /// entry. Obviously, if no stored entry was found, it is `None`. The return
+    /// value of the closure is a specification of "what to do with the
+    /// insertion now".
+    pub fn insert_with<F>(
         &self,
-        mut removed: Removed<K, V>,
+        key: K,
         interactive: F,
-    ) -> Insertion<K, V, Removed<K, V>>
+    ) -> Insertion<K, V, (K, Option<V>)>
     where
-        K: Hash + Ord,
-        F: FnMut(&(K, V), Option<&(K, V)>) -> bool,
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        F: FnMut(&K, Option<&mut V>, Option<&(K, V)>) -> Preview<V>,
     {
-        if !Removed::is_usable_by(&mut removed, &self.incin.inner) {
-            return Insertion::Failed(removed);
-        }
-
-        let hash = self.hash_of(removed.key());
-
-        let pause = self.incin.inner.pause();
+        let hash = self.hash_of(&key);
+        let events = self.events();
+        let pause = self.pause();
         // Safe because we paused properly.
         let insertion = unsafe {
-            self.top.insert(
-                Reinsert::new(interactive, removed),
+            self.insert_top(
+                InsertNew::with_key(interactive, key),
                 hash,
                 &pause,
-                &self.incin.inner,
+                &events,
             )
         };
 
         match insertion {
-            Insertion::Created => Insertion::Created,
+            Insertion::Created => {
+                self.len.fetch_add(1, Relaxed);
+                Insertion::Created
+            },
             Insertion::Updated(old) => Insertion::Updated(old),
             Insertion::Failed(inserter) => {
-                Insertion::Failed(inserter.into_removed())
+                Insertion::Failed(inserter.into_pair())
             },
         }
     }
 
-    /// Removes unconditionally the entry identified by the given key. If no
-    /// entry was found, [`None`] is returned. This method will only work
-    /// correctly if [`Hash`] and [`Ord`] are implemented in the same way for
-    /// the borrowed type and the stored type. If the entry was not found,
-    /// `None` is returned.
-    pub fn remove<Q>(&self, key: &Q) -> Option<Removed<K, V>>
+    /// Inserts the given key with a lazily created value. The closure is passed
+    /// the currently stored entry, if any, and decides whether to produce a
+    /// value for the new entry; returning [`None`] aborts the insertion. The
+    /// decision and the swap are atomic with respect to the stored entry, so
+    /// the closure might get recalled many times due to concurrent
+    /// modifications of the [`Map`]. No entry is allocated unless the closure
+    /// produces a value. If an entry was replaced, it is returned.
+    pub fn insert_lazy<F>(&self, key: K, make: F) -> Option<Removed<K, V>>
     where
-        Q: ?Sized + Hash + Ord,
-        K: Borrow<Q>,
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        F: FnMut(Option<(&K, &V)>) -> Option<V>,
     {
-        self.remove_with(key, |_| true)
+        let hash = self.hash_of(&key);
+        let events = self.events();
+        let pause = self.pause();
+        // Safe because we paused properly.
+        let insertion = unsafe {
+            self.insert_top(InsertLazy::new(make, key), hash, &pause, &events)
+        };
+
+        match insertion {
+            Insertion::Created => {
+                self.len.fetch_add(1, Relaxed);
+                None
+            },
+            Insertion::Updated(old) => Some(old),
+            Insertion::Failed(_) => None,
+        }
     }
 
-    /// Removes _interactively_ the entry identified by the given key. A closure
-    /// is passed to validate the removal. The only argument passed to the
-    /// closure is a reference to the found entry. The closure returns if the
-    /// removal should go on. If no entry was found, `None` is returned. This
-    /// method will only work correctly if [`Hash`] and [`Ord`] are implemented
-    /// in the same way for the borrowed type and the stored type. If the
-    /// entry was not found, [`None`] is returned.
-    pub fn remove_with<Q, F>(
-        &self,
-        key: &Q,
-        interactive: F,
-    ) -> Option<Removed<K, V>>
+    /// Updates atomically the value of the entry identified by the given key.
+    /// The closure computes the new value from the current one, and the new
+    /// entry, with a clone of the stored key, replaces the current entry only
+    /// if it was not concurrently replaced; otherwise, the closure is called
+    /// again with the newer value. If no entry was found, [`None`] is returned
+    /// and the closure is not called. Otherwise, the replaced entry is
+    /// returned. This method will only work correctly if [`Hash`] and [`Ord`]
+    /// are implemented in the same way for the borrowed type and the stored
+    /// type.
+    pub fn update<Q, F>(&self, key: &Q, mut update: F) -> Option<Removed<K, V>>
     where
-        Q: ?Sized + Hash + Ord,
-        K: Borrow<Q>,
-        F: FnMut(&(K, V)) -> bool,
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q> + Hash + Eq + Clone,
+        O: BucketOrder<K>,
+        F: FnMut(&V) -> V,
     {
         let hash = self.hash_of(key);
-        let pause = self.incin.inner.pause();
+        let events = self.events();
+        let pause = self.pause();
         // Safe because we paused properly.
-        unsafe {
-            self.top.remove(key, interactive, hash, &pause, &self.incin.inner)
+        let salted = || self.salted_of(key);
+        let stored = unsafe {
+            self.top(&pause).get::<O, Q, _>(key, hash, salted, pause.clone())
+        }?;
+        let inserter = InsertLazy::new(
+            |found: Option<(&K, &V)>| found.map(|(_, val)| update(val)),
+            stored.key().clone(),
+        );
+
+        // Safe because we paused properly.
+        let insertion = unsafe {
+            self.insert_top(inserter, hash, &pause, &events)
+        };
+
+        match insertion {
+            // The closure never accepts an absent entry.
+            Insertion::Created => unreachable!(),
+            Insertion::Updated(old) => Some(old),
+            Insertion::Failed(_) => None,
         }
     }
 
-    /// Acts just like [`Extend::extend`] but does not require mutability.
-    pub fn extend<I>(&self, iterable: I)
+    /// Changes in place the value of the entry identified by the given key,
+    /// which must be an [`AtomicValue`], to the one computed by the closure
+    /// from the current one, returning the previous value. Unlike
+    /// [`update`](Map::update), no entry is allocated and the bucket is left
+    /// untouched, so this suits hot counters. The closure might get recalled
+    /// if the value is concurrently changed. If no entry was found, [`None`]
+    /// is returned and the closure is not called. If the entry is
+    /// concurrently replaced or removed, the change might be applied to the
+    /// old entry and lost with it. This method will only work correctly if
+    /// [`Hash`] and [`Ord`] are implemented in the same way for the borrowed
+    /// type and the stored type.
+    pub fn update_in_place<Q, F>(&self, key: &Q, update: F) -> Option<V::Value>
     where
-        I: IntoIterator<Item = (K, V)>,
-        K: Hash + Ord,
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        V: AtomicValue,
+        F: FnMut(V::Value) -> V::Value,
     {
-        for (key, val) in iterable {
-            self.insert(key, val);
-        }
+        let hash = self.hash_of(key);
+        let pause = self.pause();
+        // Safe because we paused properly and the pair is only used while
+        // paused.
+        let salted = || self.salted_of(key);
+        let (_, val) = unsafe {
+            self.top(&pause).get_paused::<O, Q, _>(key, hash, salted, &pause)
+        }?;
+        Some(val.fetch_update(update))
     }
 
-    fn hash_of<Q>(&self, key: &Q) -> u64
+    /// Changes in place the value of the entry identified by the given key,
+    /// just like [`update_in_place`](Map::update_in_place), returning the
+    /// previous value, or, if no entry is present, inserts one holding
+    /// `init`, returning [`None`]. An entry is only allocated when inserted.
+    /// If another thread inserts the key first, its entry is changed instead.
+    pub fn upsert_in_place<F>(
+        &self,
+        key: K,
+        init: V::Value,
+        mut update: F,
+    ) -> Option<V::Value>
     where
-        Q: ?Sized + Hash,
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        V: AtomicValue,
+        F: FnMut(V::Value) -> V::Value,
     {
-        let mut hasher = self.builder.build_hasher();
-        key.hash(&mut hasher);
-        hasher.finish()
+        let mut key = key;
+        loop {
+            if let Some(prev) = self.update_in_place(&key, &mut update) {
+                break Some(prev);
+            }
+            match self.try_insert(key, V::new(init)) {
+                Ok(()) => break None,
+                Err((returned, _)) => key = returned,
+            }
+        }
     }
-}
 
-impl<K, V, H> Default for Map<K, V, H>
-where
-    H: BuildHasher + Default,
-{
-    fn default() -> Self {
-        Self::with_hasher(H::default())
-    }
-}
+    /// Decides with a single closure whether the entry identified by the given
+    /// key is inserted, replaced or removed. The closure is passed the stored
+    /// entry, if any, and returns the value of the new entry, or [`None`] for
+    /// no entry, removing the stored one. The decision and the change are
+    /// atomic with respect to the stored entry: if it is concurrently changed,
+    /// the closure is called again with the newer entry, so it might get
+    /// recalled many times. The result tells which change was applied.
+    pub fn compute<F>(&self, key: K, mut compute: F) -> ComputeResult<K, V>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        F: FnMut(Option<(&K, &V)>) -> Option<V>,
+    {
+        let hash = self.hash_of(&key);
+        let mut key = key;
+        // The pause is kept during retries, so a stored pair rejected by the
+        // closure cannot be freed, and it can be told apart from its
+        // replacements.
+        let events = self.events();
+        let pause = self.pause();
 
-impl<K, V, H> fmt::Debug for Map<K, V, H>
-where
-    H: fmt::Debug,
-{
-    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            fmtr,
-            "Map {} top_table: {:?}, incin: {:?}, build_hasher: {:?}  {}",
-            '{', self.top, self.incin.inner, self.builder, '}'
-        )
-    }
-}
+        loop {
+            let inserter = InsertLazy::new(&mut compute, key);
 
-impl<K, V, H> Drop for Map<K, V, H> {
-    fn drop(&mut self) {
-        let mut tables = Vec::new();
+            // Safe because we paused properly.
+            let insertion =
+                unsafe { self.insert_top(inserter, hash, &pause, &events) };
 
-        // Safe because we won't use these nodes anymore. We are in the
-        // destructor.
-        unsafe { self.top.free_nodes(&mut tables) }
+            let inserter = match insertion {
+                Insertion::Created => {
+                    self.len.fetch_add(1, Relaxed);
+                    break ComputeResult::Inserted;
+                },
+                Insertion::Updated(old) => break ComputeResult::Replaced(old),
+                Insertion::Failed(inserter) => inserter,
+            };
 
-        while let Some(mut table) = tables.pop() {
-            // Safe because we won't use these nodes anymore. We are in the
-            // destructor.
-            unsafe { table.free_nodes(&mut tables) }
+            let rejected = inserter.rejected();
+            key = inserter.into_key();
+            let rejected = match rejected {
+                Some(rejected) => rejected,
+                None => break ComputeResult::Absent,
+            };
+
+            // Safe because we paused properly, and the rejected pair was found
+            // under the same pause.
+            let removed = unsafe {
+                self.top(&pause).remove::<O, _, _, _>(
+                    &key,
+                    |stored| self.slabs.same_pair(stored, rejected.as_ref()),
+                    hash,
+                    || self.salted_of(&key),
+                    &pause,
+                    &self.slabs,
+                )
+                .map(|pair| {
+                    let incin = &self.incin.inner;
+                    self.slabs.wrap_removed(pair, incin, hash, &pause)
+                })
+            };
+
+            if let Some(mut removed) = removed {
+                self.len.fetch_sub(1, Relaxed);
+                events.removed(&removed);
+                Removed::stamp(&mut removed, self.stamp);
+                break ComputeResult::Removed(removed);
+            }
         }
     }
-}
-
-impl<'map, K, V, H> IntoIterator for &'map Map<K, V, H> {
-    type Item = ReadGuard<'map, K, V>;
 
-    type IntoIter = Iter<'map, K, V>;
+    /// Inserts the value created by `create` if no entry with the given key is
+    /// present, otherwise replaces the stored entry with one whose value is
+    /// computed by `modify` from the stored value. Both cases are handled by a
+    /// single interactive insertion, so there is no window where the key is
+    /// present but neither closure's effect is applied. `create` is called at
+    /// most once, but `modify` might get recalled many times due to concurrent
+    /// modifications of the [`Map`].
+    pub fn upsert<F, G>(
+        &self,
+        key: K,
+        create: F,
+        mut modify: G,
+    ) -> Upserted<K, V>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        F: FnOnce() -> V,
+        G: FnMut(&V) -> V,
+    {
+        let mut create = Some(create);
+        // The created value, whenever it is not the generated one.
+        let mut spare = None;
+        let mut is_created = false;
 
-    fn into_iter(self) -> Self::IntoIter {
-        Iter::new(self.incin.inner.pause(), &self.top)
-    }
-}
+        let mut make_created = move |spare: &mut Option<V>| match spare.take() {
+            Some(val) => val,
+            None => (create.take().expect("create called twice"))(),
+        };
 
-impl<'map, K, V, H> IntoIterator for &'map mut Map<K, V, H> {
-    type Item = (&'map K, &'map mut V);
+        let insertion =
+            self.insert_with(key, |_, val, stored| match (stored, val) {
+                (None, Some(_)) if is_created => Preview::Keep,
 
-    type IntoIter = IterMut<'map, K, V>;
+                (None, Some(val)) => {
+                    *val = make_created(&mut spare);
+                    is_created = true;
+                    Preview::Keep
+                },
 
-    fn into_iter(self) -> Self::IntoIter {
-        IterMut::new(&mut self.top)
-    }
-}
+                (None, None) => {
+                    is_created = true;
+                    Preview::New(make_created(&mut spare))
+                },
 
-impl<K, V, H> IntoIterator for Map<K, V, H> {
-    type Item = (K, V);
+                (Some((_, stored)), Some(val)) => {
+                    let prev = mem::replace(val, modify(stored));
+                    if is_created {
+                        spare = Some(prev);
+                    }
+                    is_created = false;
+                    Preview::Keep
+                },
 
-    type IntoIter = IntoIter<K, V>;
+                (Some((_, stored)), None) => {
+                    is_created = false;
+                    Preview::New(modify(stored))
+                },
+            });
 
-    fn into_iter(mut self) -> Self::IntoIter {
-        let raw = self.top.raw();
-        // Unfortunately, this unsafe is needed since there is no other way of
-        // dropping the field and forgetting the Map.
-        unsafe {
-            (&mut self.builder as *mut H).drop_in_place();
-            (&mut self.incin as *mut SharedIncin<K, V>).drop_in_place();
-            mem::forget(self);
-            IntoIter::new(OwnedAlloc::from_raw(raw))
+        match insertion {
+            Insertion::Created => Upserted::Created,
+            Insertion::Updated(old) => Upserted::Modified(old),
+            // The closure never rejects the conditions.
+            Insertion::Failed(_) => unreachable!(),
         }
     }
-}
 
-impl<K, V, H> Extend<(K, V)> for Map<K, V, H>
-where
-    H: BuildHasher,
-    K: Hash + Ord,
-{
-    fn extend<I>(&mut self, iterable: I)
+    /// Replaces the value of the entry identified by the given key only if the
+    /// stored value satisfies the given condition. The condition test and the
+    /// replacement are atomic with respect to the stored entry; the condition
+    /// might get recalled if the entry is concurrently modified. The new entry
+    /// uses a clone of the stored key. On success, the replaced entry is
+    /// returned. If no entry was found or the condition failed, the new value
+    /// is given back. This method will only work correctly if [`Hash`] and
+    /// [`Ord`] are implemented in the same way for the borrowed type and the
+    /// stored type.
+    pub fn replace_if<Q, F>(
+        &self,
+        key: &Q,
+        new_val: V,
+        mut cond: F,
+    ) -> Result<Removed<K, V>, V>
     where
-        I: IntoIterator<Item = (K, V)>,
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q> + Hash + Eq + Clone,
+        O: BucketOrder<K>,
+        F: FnMut(&V) -> bool,
     {
-        (&*self).extend(iterable)
-    }
-}
+        let hash = self.hash_of(key);
+        let events = self.events();
+        let pause = self.pause();
+        // Safe because we paused properly.
+        let top = self.top(&pause);
+        let salted = || self.salted_of(key);
+        let stored =
+            unsafe { top.get::<O, Q, _>(key, hash, salted, pause.clone()) };
+        let stored = match stored {
+            Some(stored) => stored,
+            None => return Err(new_val),
+        };
+        let inserter = InsertPair::new(
+            |found: Option<&(K, V)>| found.is_some_and(|(_, val)| cond(val)),
+            (stored.key().clone(), new_val),
+        );
 
-impl<K, V, H> FromIterator<(K, V)> for Map<K, V, H>
-where
-    H: BuildHasher + Default,
-    K: Hash + Ord,
-{
-    fn from_iter<I>(iterable: I) -> Self
+        // Safe because we paused properly.
+        let insertion = unsafe {
+            self.insert_top(inserter, hash, &pause, &events)
+        };
+
+        match insertion {
+            // The closure never accepts an absent entry.
+            Insertion::Created => unreachable!(),
+            Insertion::Updated(old) => Ok(old),
+            Insertion::Failed(inserter) => Err(inserter.into_pair().1),
+        }
+    }
+
+    /// Gets the entry identified by the given key, for in-place conditional
+    /// insertion and modification. The key is hashed only once.
+    pub fn entry<'map>(&'map self, key: K) -> Entry<'map, K, V, H, BITS, O>
     where
-        I: IntoIterator<Item = (K, V)>,
+        K: Hash + Eq,
+        O: BucketOrder<K>,
     {
-        let this = Self::default();
-        this.extend(iterable);
-        this
+        let hash = self.hash_of(&key);
+        Entry::new(self, key, hash)
     }
-}
 
-unsafe impl<K, V, H> Send for Map<K, V, H>
-where
-    K: Send,
-    V: Send,
-    H: Send,
-{
-}
+    /// Looks up the entry identified by the given key and, if absent, inserts
+    /// the value created by `make`. Then, `reader` is called on whichever
+    /// entry ended up in the [`Map`], and its return value is returned. `make`
+    /// is called at most once. If another thread concurrently inserts an entry
+    /// with the same key, the value created by `make` might be dropped in favor
+    /// of the other one.
+    pub fn get_or_insert_with<F, R, T>(&self, key: K, make: F, reader: R) -> T
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        F: FnOnce() -> V,
+        R: FnOnce(&K, &V) -> T,
+    {
+        // Unlike through `Entry`, the insertion is reported once the guard is
+        // dropped.
+        let events = self.events();
+        let guard = self.entry(key).or_insert_noted(make, &events);
+        reader(guard.key(), guard.val())
+    }
+
+    /// Looks up the entry identified by the given key and, if absent, inserts
+    /// the default value. Then, `reader` is called on the value of whichever
+    /// entry ended up in the [`Map`], and its return value is returned. Just
+    /// like in [`get_or_insert_with`](Map::get_or_insert_with), the default
+    /// value is only created if the entry is absent, and at most one default
+    /// value survives concurrent calls with the same key.
+    pub fn get_or_default<F, T>(&self, key: K, reader: F) -> T
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        V: Default,
+        F: FnOnce(&V) -> T,
+    {
+        self.get_or_insert_with(key, V::default, |_, val| reader(val))
+    }
+
+    /// Reinserts a previously removed entry. The entry must have been either:
+    ///
+    /// 1. Removed from any [`Map`] using the same [`SharedIncin`] as this
+    /// [`Map`]. 2. Removed from an already dead [`Map`] with dead
+    /// [`SharedIncin`]. 3. Removed from a [`Map`] whose `SharedIncin` has
+    /// no sensitive reads active.
+    ///
+    /// If the removed entry does not fit any category, the insertion will fail.
+    /// Otherwise, insertion cannot fail.
+    ///
+    /// The hash of the key is kept in the removed entry, so the key is not
+    /// hashed again if the entry was removed from this [`Map`], or from a
+    /// [`Map`] sharing its hasher builder, i.e. one it was cloned from or
+    /// [partitioned](Map::partition) with. It is also hashed again after the
+    /// pair is mutated through [`Removed::try_as_mut`].
+    pub fn reinsert(
+        &self,
+        mut removed: Removed<K, V>,
+    ) -> Insertion<K, V, Removed<K, V>>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let snapshots = &self.slabs.snapshots;
+        if !Removed::is_usable_by(&mut removed, &self.incin.inner, snapshots) {
+            return Insertion::Failed(removed);
+        }
+
+        let hash = self.removed_hash(&removed);
+
+        let events = self.events();
+        let pause = self.pause();
+        // Safe because we paused properly.
+        let insertion = unsafe {
+            self.insert_top(
+                Reinsert::new(|_, _| true, removed),
+                hash,
+                &pause,
+                &events,
+            )
+        };
+
+        match insertion {
+            Insertion::Created => {
+                self.len.fetch_add(1, Relaxed);
+                Insertion::Created
+            },
+            Insertion::Updated(old) => Insertion::Updated(old),
+            Insertion::Failed(_) => unreachable!(),
+        }
+    }
+
+    /// Reinserts _interactively_ a previously removed entry. A closure will be
+    /// passed to validate if the conditions are correct for the reinsertion.
+    /// The first argument passed to the closure is a reference to the removed
+    /// entry, the second argument is a reference to the stored found entry.
+    /// Obviously, if no stored entry was found, the argument is [`None`]. The
+    /// returned value is a boolean indicating if the reinsertion should go on.
+    /// Even though the closure may have already accepted some condition, it
+    /// might get recalled many times due to concurrent modifications of the
+    /// [`Map`].
+    ///
+    /// The entry must have been either:
+    ///
+    /// 1. Removed from any [`Map`] using the same [`SharedIncin`] as this
+    /// [`Map`]. 2. Removed from an already dead [`Map`] with dead
+    /// `SharedIncin`. 3. Removed from a [`Map`] whose `SharedIncin` has no
+    /// sensitive reads active.
+    ///
+    /// If the removed entry does not fit any category, the insertion will fail.
+    /// Otherwise, insertion cannot fail.
+    pub fn reinsert_with<F>(
+        &self,
+        mut removed: Removed<K, V>,
+        interactive: F,
+    ) -> Insertion<K, V, Removed<K, V>>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        F: FnMut(&(K, V), Option<&(K, V)>) -> bool,
+    {
+        let snapshots = &self.slabs.snapshots;
+        if !Removed::is_usable_by(&mut removed, &self.incin.inner, snapshots) {
+            return Insertion::Failed(removed);
+        }
+
+        let hash = self.removed_hash(&removed);
+
+        let events = self.events();
+        let pause = self.pause();
+        // Safe because we paused properly.
+        let insertion = unsafe {
+            self.insert_top(
+                Reinsert::new(interactive, removed),
+                hash,
+                &pause,
+                &events,
+            )
+        };
+
+        match insertion {
+            Insertion::Created => {
+                self.len.fetch_add(1, Relaxed);
+                Insertion::Created
+            },
+            Insertion::Updated(old) => Insertion::Updated(old),
+            Insertion::Failed(inserter) => {
+                Insertion::Failed(inserter.into_removed())
+            },
+        }
+    }
+
+    /// Moves every entry of the other [`Map`] into this one, returning the
+    /// entries of this [`Map`] displaced by them. The entries are drained from
+    /// the other [`Map`], just like [`Map::drain`] does, and their allocations
+    /// are reinserted, so nothing is cloned. The keys are hashed again, unless
+    /// the maps share their hasher builder, as described in
+    /// [`reinsert`](Map::reinsert). Entries inserted in the other [`Map`]
+    /// during the merge may be left there.
+    ///
+    /// Other threads may keep using the other [`Map`] meanwhile, and this
+    /// method never waits for them. Unless both maps share a [`SharedIncin`],
+    /// though, an entry can only be reinserted once no sensitive reads are
+    /// active on the other [`Map`], since some of them may still be reading
+    /// the entry. Each entry which cannot be moved is tried once more after
+    /// the others, and then given back as [`unmoved`](Merged::unmoved), out of
+    /// both maps.
+    pub fn merge<H2, const OTHER_BITS: usize, O2>(
+        &self,
+        other: &Map<K, V, H2, OTHER_BITS, O2>,
+    ) -> Merged<K, V>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let mut displaced = Vec::new();
+        let mut unmoved = Vec::new();
+        for removed in other.drain() {
+            match self.reinsert(removed) {
+                Insertion::Created => (),
+                Insertion::Updated(old) => displaced.push(old),
+                Insertion::Failed(removed) => unmoved.push(removed),
+            }
+        }
+
+        // The readers found active have had the time of the first pass to
+        // finish.
+        let mut retried = Vec::new();
+        for removed in unmoved {
+            match self.reinsert(removed) {
+                Insertion::Created => (),
+                Insertion::Updated(old) => displaced.push(old),
+                Insertion::Failed(removed) => retried.push(removed),
+            }
+        }
+
+        Merged { displaced, unmoved: retried }
+    }
+
+    /// Moves every entry of this [`Map`] into one of two new maps: the first
+    /// one gets the entries for which the predicate returns `true`, and the
+    /// second one gets the rest. The entries are drained, just like
+    /// [`Map::drain`] does, and their allocations are reinserted, so nothing is
+    /// cloned. Both new maps use clones of the hasher builder and share the
+    /// incinerator of this [`Map`], so entries still being read by other
+    /// threads can be moved right away.
+    ///
+    /// Each entry present when this method is called ends up in exactly one of
+    /// the new maps, unless it is concurrently removed. Entries inserted
+    /// concurrently may end up in either new map or stay in this [`Map`], but
+    /// never in two of them. If a key is inserted again after its entry was
+    /// drained, both entries may be moved, and the newer one replaces the older
+    /// one if they go to the same map.
+    pub fn partition<F>(&self, mut pred: F) -> (Self, Self)
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        H: Clone,
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut matching = Self::with_fanout_and_incin(
+            self.builder.clone(),
+            self.incin.clone(),
+        );
+        let mut rest = Self::with_fanout_and_incin(
+            self.builder.clone(),
+            self.incin.clone(),
+        );
+        // Clones of the hasher builder hash keys just like it.
+        matching.stamp = self.stamp;
+        rest.stamp = self.stamp;
+
+        for removed in self.drain() {
+            let target = if pred(removed.key(), removed.val()) {
+                &matching
+            } else {
+                &rest
+            };
+            // The maps share our incinerator, so this cannot fail.
+            target.reinsert(removed);
+        }
+
+        (matching, rest)
+    }
+
+    /// Moves every entry of this [`Map`] into a new [`Map`] using the given
+    /// hasher builder, e.g. a freshly seeded one after
+    /// [`stats`](Map::stats) revealed suspiciously long buckets. The current
+    /// hasher builder is given by [`hasher`](Map::hasher). The entries are
+    /// drained, just like [`Map::drain`] does, and their allocations are
+    /// reinserted, so nothing is cloned. The new [`Map`] shares the
+    /// incinerator of this [`Map`], so entries still being read by other
+    /// threads, e.g. through a [`ReadGuard`], can be moved right away, and
+    /// the readers are left undisturbed. The hooks of this [`Map`], if any,
+    /// see the entries removed, and the new [`Map`] has no hooks.
+    ///
+    /// Entries inserted concurrently may either be moved or stay in this
+    /// [`Map`]. Lookups in this [`Map`] may miss entries already moved.
+    pub fn rehash_with<H2>(&self, builder: H2) -> Map<K, V, H2, BITS, O>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        H2: BuildHasher,
+    {
+        let rehashed = Map::with_fanout_and_incin(builder, self.incin.clone());
+        for removed in self.drain() {
+            // The maps share the incinerator, so this cannot fail.
+            rehashed.reinsert(removed);
+        }
+        rehashed
+    }
+
+    /// Consumes this [`Map`] into a [`FrozenMap`], an immutable snapshot whose
+    /// lookups need no atomic operations nor incinerator pauses, and return
+    /// plain references. Useful once a [`Map`] is only read. The entries are
+    /// moved out of their allocations, so nothing is cloned, and the hooks of
+    /// this [`Map`], if any, are dropped without seeing them.
+    /// [`FrozenMap::thaw`] turns the snapshot back into a [`Map`].
+    pub fn freeze(self) -> FrozenMap<K, V, H, BITS, O>
+    where
+        K: Hash,
+    {
+        let mut pairs = Vec::with_capacity(self.len());
+        let (iter, builder) = self.into_parts();
+        pairs.extend(iter);
+        FrozenMap::new(pairs, builder)
+    }
+
+    /// Removes unconditionally the entry identified by the given key. If no
+    /// entry was found, [`None`] is returned. This method will only work
+    /// correctly if [`Hash`] and [`Ord`] are implemented in the same way for
+    /// the borrowed type and the stored type. If the entry was not found,
+    /// `None` is returned.
+    pub fn remove<Q>(&self, key: &Q) -> Option<Removed<K, V>>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        self.remove_with(key, |_| true)
+    }
+
+    /// Removes unconditionally the entry identified by the given key, just
+    /// like [`remove`](Map::remove), but the entry is searched under the given
+    /// hash instead of the key being hashed by the hasher builder. See
+    /// [`get_hashed`](Map::get_hashed) for the contract on the given hashes.
+    pub fn remove_hashed<Q>(&self, hash: u64, key: &Q) -> Option<Removed<K, V>>
+    where
+        Q: ?Sized + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        let events = self.events();
+        let hash = given_hash(hash);
+        let pause = self.pause();
+        self.remove_with_hash(key, hash, || None, |_| true, &pause, &events)
+    }
+
+    /// Removes unconditionally the entry identified by the given key, just like
+    /// [`remove`](Map::remove), but exploits exclusive access to the [`Map`]:
+    /// the incinerator is not paused, the entry is deallocated right away, and
+    /// its key and value are returned by value.
+    pub fn remove_mut<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        let hash = self.hash_of(key);
+        let salt = self.slabs.salt;
+        let salted = || salt.map(|salt| salt.hash(key));
+        let removed = self.top_mut().remove_mut::<O, Q, _>(key, hash, salted);
+
+        if let Some(pair) = &removed {
+            let len = self.len.get_mut();
+            *len = len.wrapping_sub(1);
+            if let Some(hooks) = &self.hooks {
+                hooks.on_remove(pair);
+            }
+        }
+        removed
+    }
+
+    /// Removes _interactively_ the entry identified by the given key. A closure
+    /// is passed to validate the removal. The only argument passed to the
+    /// closure is a reference to the found entry. The closure returns if the
+    /// removal should go on. If no entry was found, `None` is returned. This
+    /// method will only work correctly if [`Hash`] and [`Ord`] are implemented
+    /// in the same way for the borrowed type and the stored type. If the
+    /// entry was not found, [`None`] is returned.
+    pub fn remove_with<Q, F>(
+        &self,
+        key: &Q,
+        interactive: F,
+    ) -> Option<Removed<K, V>>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        F: FnMut(&(K, V)) -> bool,
+    {
+        let events = self.events();
+        self.remove_noted(key, interactive, &events)
+    }
+
+    // Removes interactively the entry identified by the given key, just like
+    // `remove_with`, recording the removed pair in the given events.
+    fn remove_noted<Q, F>(
+        &self,
+        key: &Q,
+        interactive: F,
+        events: &Events<K, V>,
+    ) -> Option<Removed<K, V>>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        F: FnMut(&(K, V)) -> bool,
+    {
+        let hash = self.hash_of(key);
+        let salted = || self.salted_of(key);
+        let pause = self.pause();
+        self.remove_with_hash(key, hash, salted, interactive, &pause, events)
+    }
+
+    // Removes interactively the entry identified by the given key, just like
+    // `remove_noted`, but under the given hash, with the incinerator paused by
+    // the given pause. The closure gives the salted hash of the key, if it can
+    // be hashed.
+    fn remove_with_hash<Q, S, F>(
+        &self,
+        key: &Q,
+        hash: HashCode,
+        salted: S,
+        interactive: F,
+        pause: &Pause<Garbage<K, V>>,
+        events: &Events<K, V>,
+    ) -> Option<Removed<K, V>>
+    where
+        Q: ?Sized + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        S: Fn() -> Option<u64>,
+        F: FnMut(&(K, V)) -> bool,
+    {
+        // Safe because the caller paused properly.
+        let mut removed = unsafe {
+            self.top(pause).remove::<O, _, _, _>(
+                key,
+                interactive,
+                hash,
+                salted,
+                pause,
+                &self.slabs,
+            )
+            .map(|pair| {
+                self.slabs.wrap_removed(pair, &self.incin.inner, hash, pause)
+            })
+        };
+
+        if let Some(removed) = &mut removed {
+            self.len.fetch_sub(1, Relaxed);
+            events.removed(removed);
+            Removed::stamp(removed, self.stamp);
+        }
+        removed
+    }
+
+    /// Removes the entries identified by each of the given keys, returning the
+    /// removed entries in the same order as the keys, or [`None`] for keys
+    /// not found. Removals are performed in batches, each one under a single
+    /// incinerator pause, and keys are taken from the iterator before the
+    /// pause starts. This method will only work correctly if [`Hash`] and
+    /// [`Ord`] are implemented in the same way for the borrowed type and the
+    /// stored type.
+    pub fn remove_many<'key, Q, I>(&self, keys: I) -> Vec<Option<Removed<K, V>>>
+    where
+        Q: ?Sized + Hash + Eq + 'key,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        I: IntoIterator<Item = &'key Q>,
+    {
+        let mut iter = keys.into_iter();
+        let mut batch = Vec::with_capacity(BATCH_LEN);
+        let mut removed = Vec::with_capacity(iter.size_hint().0);
+
+        loop {
+            batch.extend(iter.by_ref().take(BATCH_LEN));
+            if batch.is_empty() {
+                break;
+            }
+
+            let events = self.events();
+            let pause = self.pause();
+            for key in batch.drain(..) {
+                let hash = self.hash_of(key);
+                // Safe because we paused properly.
+                let mut entry = unsafe {
+                    self.top(&pause).remove::<O, _, _, _>(
+                        key,
+                        |_| true,
+                        hash,
+                        || self.salted_of(key),
+                        &pause,
+                        &self.slabs,
+                    )
+                    .map(|pair| {
+                        let incin = &self.incin.inner;
+                        self.slabs.wrap_removed(pair, incin, hash, &pause)
+                    })
+                };
+                if let Some(entry) = &mut entry {
+                    self.len.fetch_sub(1, Relaxed);
+                    events.removed(entry);
+                    Removed::stamp(entry, self.stamp);
+                }
+                removed.push(entry);
+            }
+        }
+
+        removed
+    }
+
+    /// Removes the entry identified by the given key only if the given
+    /// condition holds for it. The condition test and the removal are atomic
+    /// with respect to the stored entry; if the entry is concurrently replaced
+    /// before being removed, the condition is tested again on the new entry.
+    /// The result distinguishes a rejected removal from an absent entry. This
+    /// method will only work correctly if [`Hash`] and [`Ord`] are implemented
+    /// in the same way for the borrowed type and the stored type.
+    pub fn remove_if<Q, F>(&self, key: &Q, mut cond: F) -> Removal<K, V>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut rejected = false;
+        let removed = self.remove_with(key, |(key, val)| {
+            rejected = !cond(key, val);
+            !rejected
+        });
+
+        match removed {
+            Some(removed) => Removal::Done(removed),
+            None if rejected => Removal::Rejected,
+            None => Removal::Absent,
+        }
+    }
+
+    /// Removes unconditionally the entry identified by the given key and calls
+    /// the given closure on the removed key and value, returning whatever the
+    /// closure returns. Unlike [`Map::remove`], the removed entry is handed to
+    /// the incinerator right after the closure returns, so no [`Removed`] needs
+    /// to be kept alive. If no entry was found, [`None`] is returned and the
+    /// closure is not called. This method will only work correctly if [`Hash`]
+    /// and [`Ord`] are implemented in the same way for the borrowed type and
+    /// the stored type.
+    pub fn remove_and_read<Q, F, T>(&self, key: &Q, reader: F) -> Option<T>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        F: FnOnce(&K, &V) -> T,
+    {
+        self.remove(key).map(|removed| reader(removed.key(), removed.val()))
+    }
+
+    /// Moves the entry identified by the old key to the new key, returning the
+    /// entry displaced at the new key, if any. The removed pair is reused, with
+    /// its key replaced, if no sensitive reads are active by then; otherwise
+    /// the value is cloned into a new pair, since readers may still hold the
+    /// removed one.
+    ///
+    /// The move is not atomic: the old entry is removed before the new one is
+    /// inserted, so a concurrent observer may find the value under neither
+    /// key for a while, but never under both. An entry concurrently inserted
+    /// at the new key is either displaced and returned, or replaces the moved
+    /// entry afterwards. If the old key has no entry, nothing is inserted.
+    /// Renaming a key to itself does nothing. This method will only work
+    /// correctly if [`Hash`] and [`Ord`] are implemented in the same way for
+    /// the borrowed type and the stored type.
+    pub fn rename_key<Q>(
+        &self,
+        old: &Q,
+        new: K,
+    ) -> Result<Option<Removed<K, V>>, RenameErr>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q> + Hash + Eq,
+        O: BucketOrder<K>,
+        V: Clone,
+    {
+        if old == new.borrow() {
+            return if self.contains_key(old) {
+                Ok(None)
+            } else {
+                Err(RenameErr::OldMissing)
+            };
+        }
+
+        let mut removed = self.remove(old).ok_or(RenameErr::OldMissing)?;
+
+        let displaced = match Removed::try_as_mut(&mut removed) {
+            Some(pair) => {
+                pair.0 = new;
+                self.reinsert(removed).take_updated().ok()
+            },
+            None => self.insert(new, removed.val().clone()),
+        };
+
+        Ok(displaced)
+    }
+
+    /// Exchanges the values of the entries identified by the given keys. Both
+    /// entries are removed, then each value is inserted under the other key.
+    /// The removed pairs are reused if no sensitive reads are active by then,
+    /// otherwise the keys and values are cloned into new pairs, since readers
+    /// may still hold the removed ones.
+    ///
+    /// The swap is not atomic. A concurrent observer of the first key sees its
+    /// old value, then no entry, then the new value; the same goes for the
+    /// second key, whose entry disappears after the first one does, and
+    /// reappears after the first one is back. So no key ever holds the value of
+    /// the other key before it lost its own, and no value is seen under both
+    /// keys at once. The length of the [`Map`] drops by up to two meanwhile.
+    /// Entries concurrently inserted under either key while it has no entry are
+    /// replaced by the swapped value.
+    ///
+    /// If an entry is missing, the [`Map`] is left as it was and the error
+    /// tells which one. A removed first entry is only put back if its key is
+    /// still vacant, though; otherwise it is dropped, just as if it had been
+    /// overwritten. Swapping a key with itself does nothing. This method will
+    /// only work correctly if [`Hash`] and [`Ord`] are implemented in the same
+    /// way for the borrowed type and the stored type.
+    pub fn swap_values<Q>(&self, first: &Q, second: &Q) -> Result<(), SwapErr>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q> + Hash + Eq + Clone,
+        O: BucketOrder<K>,
+        V: Clone,
+    {
+        if first == second {
+            return if self.contains_key(first) {
+                Ok(())
+            } else {
+                Err(SwapErr::BothMissing)
+            };
+        }
+
+        let mut first_removed = match self.remove(first) {
+            Some(removed) => removed,
+            None if self.contains_key(second) => {
+                return Err(SwapErr::FirstMissing)
+            },
+            None => return Err(SwapErr::BothMissing),
+        };
+
+        let mut second_removed = match self.remove(second) {
+            Some(removed) => removed,
+            None => {
+                self.reinsert_with(first_removed, |_, stored| stored.is_none());
+                return Err(SwapErr::SecondMissing);
+            },
+        };
+
+        let reused = match (
+            Removed::try_as_mut(&mut first_removed),
+            Removed::try_as_mut(&mut second_removed),
+        ) {
+            (Some(first_pair), Some(second_pair)) => {
+                mem::swap(&mut first_pair.1, &mut second_pair.1);
+                true
+            },
+            _ => false,
+        };
+
+        if reused {
+            self.reinsert(first_removed);
+            self.reinsert(second_removed);
+        } else {
+            let first_key = first_removed.key().clone();
+            let second_key = second_removed.key().clone();
+            self.insert(first_key, second_removed.val().clone());
+            self.insert(second_key, first_removed.val().clone());
+        }
+
+        Ok(())
+    }
+
+    /// Removes an arbitrary entry of the [`Map`], if any. The top table is
+    /// scanned from a rotating position, and its sub-tables from a random one,
+    /// so concurrent callers do not fight over the same entries. Each entry is
+    /// returned by at most one caller.
+    pub fn pop_any(&self) -> Option<Removed<K, V>> {
+        let start = random_start();
+        let events = self.events();
+        let pause = self.pause();
+        // Safe because we paused properly.
+        let mut removed = unsafe {
+            self.top(&pause).pop_any(
+                &self.pop_cursor,
+                start,
+                &pause,
+                &self.incin.inner,
+                &self.slabs,
+            )
+        };
+
+        if let Some(removed) = &mut removed {
+            self.len.fetch_sub(1, Relaxed);
+            events.removed(removed);
+            Removed::stamp(removed, self.stamp);
+        }
+        removed
+    }
+
+    /// Calls the given closure on an arbitrary entry of the [`Map`] and returns
+    /// what it returns. The scan starts at a random position, and the closure
+    /// is called while the incinerator is paused. [`None`] is returned if no
+    /// entry was found; if the [`Map`] is not concurrently modified, this
+    /// means it is empty.
+    pub fn get_any<F, T>(&self, reader: F) -> Option<T>
+    where
+        F: FnOnce(&K, &V) -> T,
+    {
+        let start = random_start();
+        let pause = self.pause();
+        // Safe because we paused properly and keep the pause while reading.
+        let pair = unsafe { self.top(&pause).get_any(start) };
+        pair.map(|(key, val)| reader(key, val))
+    }
+
+    /// Calls the given closure on every entry of the [`Map`]. The incinerator
+    /// is paused only while small chunks of the [`Map`] are read, so this
+    /// method does not stall resource destruction for the whole traversal.
+    /// Entries inserted or removed concurrently may or may not be visited, but
+    /// no entry is visited twice.
+    pub fn for_each<F>(&self, mut visitor: F)
+    where
+        F: FnMut(&K, &V),
+    {
+        self.walk_top(|top| {
+            top.visit(&self.incin.inner, |(key, val)| visitor(key, val))
+        })
+    }
+
+    /// Visits up to `batch` entries under a single pause of the incinerator,
+    /// starting at the given cursor, and returns a cursor to resume from, or
+    /// [`None`] once every entry was visited. A scan starts with
+    /// [`ScanCursor::new`]. Unlike [`for_each`](Map::for_each), the traversal
+    /// can be spread over as many calls as needed, e.g. from a background
+    /// thread, and the pause never outlasts a call: each call reads at most
+    /// about `batch` nodes of the tables, and the entries of a bucket are
+    /// visited together, so a bucket holding more than `batch` entries is
+    /// visited whole, in a call of its own. A `batch` of zero is taken as one.
+    ///
+    /// A cursor is a position in the tree of tables, which only depends on
+    /// the hashes of the keys, so an entry present during the whole scan is
+    /// visited exactly once, even if its bucket is moved to a new sub-table, or
+    /// moved up by [`shrink`](Map::shrink) or [`compact`](Map::compact),
+    /// between calls. The only exception is a compact [`Map`] upgraded during
+    /// the scan: the next call starts over, so entries already visited are
+    /// visited again. Entries inserted or removed during the scan, including
+    /// those whose key is renamed or which are reinserted, may or may not be
+    /// visited, possibly twice. A cursor given by another [`Map`] is
+    /// memory-safe to use, but the entries visited are unspecified.
+    pub fn scan<F>(
+        &self,
+        mut cursor: ScanCursor,
+        batch: usize,
+        mut visitor: F,
+    ) -> Option<ScanCursor>
+    where
+        F: FnMut(&K, &V),
+    {
+        let pause = self.pause();
+        let top = self.top(&pause);
+        if cursor.path.is_empty() || cursor.top_bits != top.bits() {
+            cursor.path = vec![0];
+            cursor.top_bits = top.bits();
+        }
+        // Safe because we paused properly and keep the pause while reading.
+        let done = unsafe {
+            top.scan_batch(&mut cursor.path, batch.max(1), |pair| {
+                visitor(&pair.0, &pair.1)
+            })
+        };
+        if done {
+            None
+        } else {
+            Some(cursor)
+        }
+    }
+
+    /// Calls the given closure on every key of the [`Map`], pausing the
+    /// incinerator only while small chunks of the [`Map`] are read, just like
+    /// [`Map::for_each`]. Keys inserted or removed concurrently may or may not
+    /// be visited, but a key present during the whole traversal is visited
+    /// exactly once.
+    pub fn keys<F>(&self, mut visitor: F)
+    where
+        F: FnMut(&K),
+    {
+        self.for_each(|key, _| visitor(key))
+    }
+
+    /// Collects clones of every key of the [`Map`] into a vector, in no
+    /// particular order. See [`Map::keys`] for the guarantees about
+    /// concurrent modifications.
+    pub fn keys_cloned(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let mut keys = Vec::with_capacity(self.len());
+        self.keys(|key| keys.push(key.clone()));
+        keys
+    }
+
+    /// Calls the given closure on every value of the [`Map`]. The closure is
+    /// called while the incinerator is paused, but only small chunks of the
+    /// [`Map`] are read under each pause, just like [`Map::for_each`].
+    /// Entries inserted or removed concurrently may or may not be visited, but
+    /// no entry is visited twice.
+    pub fn values<F>(&self, mut visitor: F)
+    where
+        F: FnMut(&V),
+    {
+        self.for_each(|_, val| visitor(val))
+    }
+
+    /// Collects clones of every value of the [`Map`] into a vector, in no
+    /// particular order. See [`Map::values`] for the guarantees about
+    /// concurrent modifications.
+    pub fn values_cloned(&self) -> Vec<V>
+    where
+        V: Clone,
+    {
+        let mut vals = Vec::with_capacity(self.len());
+        self.values(|val| vals.push(val.clone()));
+        vals
+    }
+
+    /// Collects clones of every entry of the [`Map`] into a vector sorted by
+    /// key, e.g. for reports. The entries are collected just like in
+    /// [`Map::for_each`], pausing the incinerator only while small chunks of
+    /// the [`Map`] are read, and sorted afterwards, so the vector is not a
+    /// snapshot: entries inserted or removed concurrently may or may not be
+    /// in it, but no key is ever in it twice.
+    pub fn to_sorted_vec(&self) -> Vec<(K, V)>
+    where
+        K: Ord + Clone,
+        V: Clone,
+    {
+        self.to_vec_by(|(left, _), (right, _)| left.cmp(right))
+    }
+
+    /// Just like [`to_sorted_vec`](Map::to_sorted_vec), but the entries are
+    /// sorted with the given comparator. Entries comparing as equal are left
+    /// in no particular order.
+    pub fn to_vec_by<F>(&self, mut compare: F) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+        F: FnMut(&(K, V), &(K, V)) -> cmp::Ordering,
+    {
+        let mut pairs = Vec::with_capacity(self.len());
+        self.for_each(|key, val| pairs.push((key.clone(), val.clone())));
+        pairs.sort_unstable_by(|left, right| compare(left, right));
+        pairs
+    }
+
+    /// Folds every entry of the [`Map`] into an accumulator, starting with
+    /// `init`, and returns the final accumulator. The incinerator is paused
+    /// only while small chunks of the [`Map`] are read, just like in
+    /// [`Map::for_each`]. Entries inserted or removed concurrently may or may
+    /// not be folded, but an entry present during the whole fold is folded
+    /// exactly once.
+    pub fn fold<B, F>(&self, init: B, mut fold: F) -> B
+    where
+        F: FnMut(B, &K, &V) -> B,
+    {
+        let mut acc = Some(init);
+        self.for_each(|key, val| {
+            acc = acc.take().map(|inner| fold(inner, key, val));
+        });
+        // The accumulator is always put back.
+        acc.unwrap()
+    }
+
+    /// Counts the entries of the [`Map`] for which the given predicate returns
+    /// `true`. The incinerator is paused only while small chunks of the
+    /// [`Map`] are read, just like in [`Map::for_each`], so the count is not a
+    /// snapshot: entries inserted, changed or removed concurrently may or may
+    /// not be counted, while an entry present and unchanged during the whole
+    /// traversal is counted exactly once.
+    pub fn count_matching<F>(&self, mut predicate: F) -> usize
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.fold(0, |count, key, val| count + predicate(key, val) as usize)
+    }
+
+    /// Removes every entry for which the given predicate returns `false`. The
+    /// predicate is called while the incinerator is paused, and an entry is
+    /// only removed if it was not replaced since the predicate was tested.
+    /// Entries inserted or updated concurrently may be skipped by this method.
+    pub fn retain<F>(&self, mut predicate: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        // The visit keeps the incinerator paused, so the removals are only
+        // reported at the end.
+        let events = self.events();
+        self.walk_top(|top| {
+            top.visit(&self.incin.inner, |pair| {
+                let (key, val) = pair;
+                if !predicate(key, val) {
+                    // Safe because the visit keeps the incinerator paused
+                    // since the pair was found.
+                    let cond = |stored: &(K, V)| unsafe {
+                        self.slabs.same_pair(stored, pair)
+                    };
+                    self.remove_noted(key, cond, &events);
+                }
+            })
+        })
+    }
+
+    /// Replaces the value of every entry with the one computed by the given
+    /// closure from the stored entry. Each entry is replaced by a new one, with
+    /// a clone of the key, and the old one is dropped once no thread reads it,
+    /// so readers see either the old value or the new one, never a mix. The
+    /// closure is called once for each entry found, while the incinerator is
+    /// paused. If the entry was concurrently replaced or removed once the
+    /// closure returns, it is skipped, and the newer entry is left as it is:
+    /// entries inserted or updated concurrently may not be transformed.
+    pub fn map_values<F>(&self, mut transform: F)
+    where
+        F: FnMut(&K, &V) -> V,
+        K: Hash + Eq + Clone,
+        O: BucketOrder<K>,
+    {
+        // The visit keeps the incinerator paused, so the replacements are only
+        // reported at the end.
+        let events = self.events();
+        self.walk_top(|top| {
+            top.visit(&self.incin.inner, |pair| {
+                let (key, val) = pair;
+                // Safe because the visit keeps the incinerator paused since the
+                // pair was found.
+                let inserter = InsertPair::new(
+                    |found: Option<&(K, V)>| unsafe {
+                        found.is_some_and(|stored| {
+                            self.slabs.same_pair(stored, pair)
+                        })
+                    },
+                    (key.clone(), transform(key, val)),
+                );
+                let hash = self.hash_of(key);
+                let pause = self.pause();
+                // Safe because we paused properly. A failed insertion drops
+                // the new entry.
+                unsafe { self.insert_top(inserter, hash, &pause, &events) };
+            })
+        })
+    }
+
+    /// Replaces the value of every entry with the one computed by the given
+    /// closure, just like [`map_values`](Map::map_values), but exploits
+    /// exclusive access to the [`Map`]: values are replaced in place, so
+    /// nothing is allocated and keys are not cloned.
+    pub fn map_values_mut<F>(&mut self, mut transform: F)
+    where
+        F: FnMut(&K, &V) -> V,
+    {
+        self.for_each_mut(|key, val| *val = transform(key, val));
+    }
+
+    /// Acts just like [`Extend::extend`] but does not require mutability.
+    /// Entries are inserted in batches, each one under a single incinerator
+    /// pause. Items are taken from the iterator before the pause starts.
+    pub fn extend<I>(&self, iterable: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        self.insert_batches(iterable, drop);
+    }
+
+    /// Inserts unconditionally every entry of the given iterator, returning the
+    /// previously stored entries which were replaced. Just like
+    /// [`Map::extend`], entries are inserted in batches, each one under a
+    /// single incinerator pause, and items are taken from the iterator before
+    /// the pause starts, so a blocking iterator never stalls the incinerator.
+    pub fn insert_all<I>(&self, iterable: I) -> Vec<Removed<K, V>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let mut replaced = Vec::new();
+        self.insert_batches(iterable, |removed| replaced.push(removed));
+        replaced
+    }
+
+    fn insert_batches<I, F>(&self, iterable: I, mut sink: F)
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        F: FnMut(Removed<K, V>),
+    {
+        let mut iter = iterable.into_iter();
+        let mut batch = Vec::with_capacity(BATCH_LEN);
+
+        loop {
+            batch.extend(iter.by_ref().take(BATCH_LEN));
+            if batch.is_empty() {
+                break;
+            }
+            self.insert_batch(&mut batch, &mut sink);
+        }
+    }
+
+    // Inserts unconditionally every entry of the batch under a single pause,
+    // leaving the batch empty.
+    fn insert_batch<F>(&self, batch: &mut Vec<(K, V)>, mut sink: F)
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+        F: FnMut(Removed<K, V>),
+    {
+        let events = self.events();
+        let pause = self.pause();
+        for (key, val) in batch.drain(..) {
+            let removed = self.insert_paused(key, val, &pause, &events);
+            if let Some(removed) = removed {
+                sink(removed);
+            }
+        }
+    }
+
+    fn insert_paused(
+        &self,
+        key: K,
+        val: V,
+        pause: &Pause<Garbage<K, V>>,
+        events: &Events<K, V>,
+    ) -> Option<Removed<K, V>>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let hash = self.hash_of(&key);
+        self.insert_with_hash(key, val, hash, pause, events)
+    }
+
+    // Inserts unconditionally, just like `insert_paused`, but under the given
+    // hash.
+    fn insert_with_hash(
+        &self,
+        key: K,
+        val: V,
+        hash: HashCode,
+        pause: &Pause<Garbage<K, V>>,
+        events: &Events<K, V>,
+    ) -> Option<Removed<K, V>>
+    where
+        K: Eq,
+        O: BucketOrder<K>,
+    {
+        // Safe because the caller paused properly.
+        let insertion = unsafe {
+            self.insert_top(
+                InsertNew::with_pair(|_, _, _| Preview::Keep, (key, val)),
+                hash,
+                pause,
+                events,
+            )
+        };
+
+        match insertion {
+            Insertion::Created => {
+                self.len.fetch_add(1, Relaxed);
+                None
+            },
+            Insertion::Updated(old) => Some(old),
+            Insertion::Failed(_) => unreachable!(),
+        }
+    }
+
+    #[cfg(not(feature = "hash128"))]
+    fn hash_of<Q>(&self, key: &Q) -> HashCode
+    where
+        Q: ?Sized + Hash,
+    {
+        let mut hasher = self.builder.build_hasher();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // The lower half is the usual hash, so the tree is shaped just like
+    // without the `hash128` feature until two keys collide in it. The upper
+    // half hashes the key again after a seed, telling those keys apart.
+    #[cfg(feature = "hash128")]
+    fn hash_of<Q>(&self, key: &Q) -> HashCode
+    where
+        Q: ?Sized + Hash,
+    {
+        let mut hasher = self.builder.build_hasher();
+        key.hash(&mut hasher);
+        let lower = hasher.finish();
+
+        let mut hasher = self.builder.build_hasher();
+        hasher.write_u64(HASH128_SEED);
+        key.hash(&mut hasher);
+        let upper = hasher.finish();
+
+        HashCode::from(lower) | HashCode::from(upper) << 64
+    }
+
+    // The hash of the key of a removed entry, hashed again only if it was not
+    // cached by a map hashing keys like this one.
+    fn removed_hash(&self, removed: &Removed<K, V>) -> HashCode
+    where
+        K: Hash,
+    {
+        match Removed::hash_for(removed, self.stamp) {
+            Some(hash) => hash,
+            None => self.hash_of(removed.key()),
+        }
+    }
+}
+
+// The hash code of a key whose hash was given by the caller. With `hash128`,
+// the upper half is left zeroed, so keys whose given hashes collide share a
+// bucket.
+#[cfg(not(feature = "hash128"))]
+fn given_hash(hash: u64) -> HashCode {
+    hash
+}
+
+#[cfg(feature = "hash128")]
+fn given_hash(hash: u64) -> HashCode {
+    HashCode::from(hash)
+}
+
+// A random position to start scanning the tables from.
+#[cfg(feature = "std")]
+fn random_start() -> usize {
+    RandomState::new().build_hasher().finish() as usize
+}
+
+// Without `std` there is no source of randomness, so the positions follow a
+// sequence shared by every map instead, which still spreads concurrent callers
+// over the tables. The increment is odd, so every position is reached.
+#[cfg(not(feature = "std"))]
+fn random_start() -> usize {
+    static NEXT_START: AtomicUsize = AtomicUsize::new(0);
+    NEXT_START.fetch_add(0x9e37_79b9, Relaxed)
+}
+
+// A stamp no other map was given, for a map with a new hasher builder. Once
+// they run out, maps get the zero stamp, so they never trust cached hashes.
+fn new_stamp() -> usize {
+    static NEXT_STAMP: AtomicUsize = AtomicUsize::new(1);
+    NEXT_STAMP
+        .fetch_update(Relaxed, Relaxed, |stamp| stamp.checked_add(1))
+        .unwrap_or(0)
+}
+
+impl<K, V, H, O> Default for Map<K, V, H, 8, O>
+where
+    H: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_fanout(H::default())
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Clone for Map<K, V, H, BITS, O>
+where
+    H: BuildHasher + Clone,
+    K: Hash + Eq + Clone,
+    O: BucketOrder<K>,
+    V: Clone,
+{
+    /// Clones every entry into a new [`Map`] with a new incinerator. The
+    /// source is read under short incinerator pauses. If the source is shared,
+    /// every entry present during the whole cloning is in the new [`Map`], but
+    /// entries concurrently inserted or removed may or may not be.
+    fn clone(&self) -> Self {
+        let mut cloned = Self::with_fanout(self.builder.clone());
+        cloned.stamp = self.stamp;
+        cloned.slabs.salt = self.slabs.salt.map(|_| Salt::new());
+        // Only pairs which can be copied bit by bit were kept inline.
+        cloned.slabs.inline = self.slabs.inline;
+        self.for_each(|key, val| {
+            cloned.insert(key.clone(), val.clone());
+        });
+        cloned
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> PartialEq for Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+    K: Hash + Eq,
+    O: BucketOrder<K>,
+    V: PartialEq,
+{
+    /// Compares the lengths and then checks if every entry of `self` is in
+    /// `other` with an equal value. The result is only meaningful if none of
+    /// the maps are being concurrently mutated.
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|guard| {
+                other
+                    .get(guard.key())
+                    .is_some_and(|found| *found.val() == *guard.val())
+            })
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Eq for Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+    K: Hash + Eq,
+    O: BucketOrder<K>,
+    V: Eq,
+{
+}
+
+impl<K, V, H, const BITS: usize, O> fmt::Debug for Map<K, V, H, BITS, O>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    /// Prints at most `128` entries, followed by the count of the omitted
+    /// ones. The incinerator is paused only while small chunks of the [`Map`]
+    /// are read.
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        let mut res = fmtr.write_str("Map {");
+        let mut count = 0usize;
+
+        self.walk_top(|top| {
+            top.visit(&self.incin.inner, |(key, val)| {
+                if count < DEBUG_LEN && res.is_ok() {
+                    let sep = if count == 0 { "" } else { ", " };
+                    res = write!(fmtr, "{}{:?}: {:?}", sep, key, val);
+                }
+                count += 1;
+            })
+        });
+
+        res?;
+        if count > DEBUG_LEN {
+            write!(fmtr, ", ... ({} more)", count - DEBUG_LEN)?;
+        }
+        fmtr.write_str("}")
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Drop for Map<K, V, H, BITS, O> {
+    fn drop(&mut self) {
+        // Safe because the top table is always valid, and we are in the
+        // destructor.
+        let top = unsafe { NonNull::new_unchecked(*self.top.get_mut()) };
+        let mut tables = vec![unsafe { OwnedAlloc::from_raw(top) }];
+
+        while let Some(mut table) = tables.pop() {
+            // Safe because we won't use these nodes anymore. We are in the
+            // destructor.
+            unsafe { table.free_nodes(&mut tables) }
+        }
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> IntoIterator
+    for &'map Map<K, V, H, BITS, O>
+{
+    type Item = ReadGuard<'map, K, V>;
+
+    type IntoIter = Iter<'map, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let pause = self.pause();
+        let top = self.top(&pause);
+        Iter::new(pause, top)
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> IntoIterator
+    for &'map mut Map<K, V, H, BITS, O>
+{
+    type Item = (&'map K, &'map mut V);
+
+    type IntoIter = IterMut<'map, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IterMut::new(self.top_mut())
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> IntoIterator for Map<K, V, H, BITS, O> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_parts().0
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, H> From<HashMap<K, V, H>> for Map<K, V, H>
+where
+    H: BuildHasher + Clone,
+    K: Hash + Ord,
+{
+    fn from(map: HashMap<K, V, H>) -> Self {
+        let builder = map.hasher().clone();
+        Self::from_iter_with_hasher(map, builder)
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Extend<(K, V)>
+    for Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+    K: Hash + Eq,
+    O: BucketOrder<K>,
+{
+    fn extend<I>(&mut self, iterable: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        (&*self).extend(iterable)
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Extend<(K, V)>
+    for &Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+    K: Hash + Eq,
+    O: BucketOrder<K>,
+{
+    fn extend<I>(&mut self, iterable: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        Map::extend(*self, iterable)
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> FromIterator<(K, V)>
+    for Map<K, V, H, BITS, O>
+where
+    H: BuildHasher + Default,
+    K: Hash + Eq,
+    O: BucketOrder<K>,
+{
+    fn from_iter<I>(iterable: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let this = Self::with_fanout(H::default());
+        this.extend(iterable);
+        this
+    }
+}
+
+// Sending a map sends the ownership of its entries, which are dropped by the
+// receiving thread, or by whichever thread clears a shared incinerator. Either
+// way, each entry is only ever owned by one thread at a time, so `K: Send` and
+// `V: Send` are enough, just like for `Vec<(K, V)>`. References to entries
+// cannot be sent along, since guards and iterators borrow the map.
+unsafe impl<K, V, H, const BITS: usize, O> Send for Map<K, V, H, BITS, O>
+where
+    K: Send,
+    V: Send,
+    H: Send,
+{
+}
+
+// Sharing a map lets every thread read the same entries through guards, hence
+// `K: Sync` and `V: Sync`. It also lets a thread insert entries created by
+// another thread, and take out entries, through `Removed`, which were created
+// by another thread, both through `&self`, hence `K: Send` and `V: Send`. The
+// hasher builder is only used through shared references.
+unsafe impl<K, V, H, const BITS: usize, O> Sync for Map<K, V, H, BITS, O>
+where
+    K: Send + Sync,
+    V: Send + Sync,
+    H: Sync,
+{
+}
+
+make_shared_incin! {
+    { "[`Map`]" }
+    pub SharedIncin<K, V> of Garbage<K, V>
+}
+
+impl<K, V> fmt::Debug for SharedIncin<K, V> {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "SharedIncin {} inner: {:?} {}", '{', self.inner, '}')
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use map::support::ConstState;
+    use std::{
+        cmp,
+        collections::{
+            hash_map::{DefaultHasher, RandomState},
+            HashMap,
+            HashSet,
+        },
+        panic,
+        sync::{mpsc, Arc, Barrier},
+        thread,
+    };
+    #[cfg(feature = "std")]
+    use std::time::Duration;
+    use std::prelude::v1::*;
+
+    #[derive(Debug)]
+    struct DropCounter(Arc<AtomicUsize>);
+
+    // Produces hashes whose lower bits are all zeroes, so entries are only
+    // stored in deep sub-tables.
+    #[derive(Debug, Clone, Copy, Default)]
+    struct ShiftState;
+
+    #[derive(Debug, Clone, Copy, Default)]
+    struct ShiftHasher(u64);
+
+    impl BuildHasher for ShiftState {
+        type Hasher = ShiftHasher;
+
+        fn build_hasher(&self) -> ShiftHasher {
+            ShiftHasher(0)
+        }
+    }
+
+    impl Hasher for ShiftHasher {
+        fn finish(&self) -> u64 {
+            self.0 << 40
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            // Most significant bytes first, so small integers keep distinct
+            // hashes on little-endian targets.
+            for &byte in bytes.iter().rev() {
+                self.0 = self.0.rotate_left(8) ^ byte as u64;
+            }
+        }
+    }
+
+    // Counts how many hashers it builds, i.e. how many times keys are hashed.
+    #[derive(Debug, Clone, Default)]
+    struct CountState(Arc<AtomicUsize>);
+
+    impl BuildHasher for CountState {
+        type Hasher = DefaultHasher;
+
+        fn build_hasher(&self) -> DefaultHasher {
+            self.0.fetch_add(1, Relaxed);
+            DefaultHasher::new()
+        }
+    }
+
+    // A key with `Hash` and `Eq`, but not `Ord`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    struct EqKey(u64);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Relaxed);
+        }
+    }
+
+    #[test]
+    fn inserts_and_gets() {
+        let map = Map::new();
+        assert!(map.get("five").is_none());
+        assert!(map.insert("five".to_owned(), 5).is_none());
+        assert_eq!(*map.get("five").unwrap().val(), 5);
+        assert!(map.get("four").is_none());
+        assert!(map.insert("four".to_owned(), 4).is_none());
+        assert_eq!(*map.get("five").unwrap().val(), 5);
+        assert_eq!(*map.get("four").unwrap().val(), 4);
+        let guard = map.get("four").unwrap();
+        assert_eq!(guard.key(), "four");
+        assert_eq!(*guard.val(), 4);
+    }
+
+    #[test]
+    fn contains_key() {
+        let map = Map::new();
+        assert!(!map.contains_key("five"));
+        map.insert("five".to_owned(), 5);
+        assert!(map.contains_key("five"));
+        assert!(!map.contains_key("four"));
+        map.remove("five");
+        assert!(!map.contains_key("five"));
+    }
+
+    #[test]
+    fn contains_key_removed_by_other_thread() {
+        let map = Arc::new(Map::new());
+        for i in 0 .. 512u32 {
+            map.insert(i, i);
+        }
+
+        let remover = {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in (0 .. 512u32).filter(|i| i % 2 == 0) {
+                    map.remove(&i);
+                }
+            })
+        };
+
+        for i in (1 .. 512u32).filter(|i| i % 2 == 1) {
+            assert!(map.contains_key(&i));
+        }
+        remover.join().expect("remover failed");
+
+        for i in 0 .. 512u32 {
+            assert_eq!(map.contains_key(&i), i % 2 == 1);
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn len_single_threaded() {
+        let map = Map::new();
+        assert!(map.is_empty());
+        map.insert("five".to_owned(), 5);
+        map.insert("four".to_owned(), 4);
+        assert_eq!(map.len(), 2);
+        map.insert("five".to_owned(), 50);
+        assert_eq!(map.len(), 2);
+        let removed = map.remove("five").unwrap();
+        assert_eq!(map.len(), 1);
+        assert!(map.remove("five").is_none());
+        assert_eq!(map.len(), 1);
+        map.reinsert(removed).created();
+        assert_eq!(map.len(), 2);
+        let removed = map.remove("four").unwrap();
+        map.insert("four".to_owned(), 40);
+        map.reinsert(removed).take_updated().unwrap();
+        assert_eq!(map.len(), 2);
+        map.remove("four");
+        map.remove("five");
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn len_multithreaded() {
+        fn ops(map: &Map<u64, u64>, thread: u64) {
+            for i in 0 .. 500 {
+                let key = thread * 1000 + i % 300;
+                match i % 5 {
+                    0 | 1 => {
+                        map.insert(key, i);
+                    },
+                    2 => {
+                        map.remove(&key);
+                    },
+                    3 => {
+                        if let Some(removed) = map.remove(&(key + 1)) {
+                            map.reinsert(removed);
+                        }
+                    },
+                    _ => {
+                        map.insert(key, i + 1);
+                    },
+                }
+            }
+        }
+
+        let map = Arc::new(Map::new());
+        let mut threads = Vec::new();
+        for thread in 0 .. 8 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || ops(&map, thread)));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        let mut expected = HashMap::new();
+        for thread in 0 .. 8 {
+            for i in 0 .. 500 {
+                let key = thread * 1000 + i % 300;
+                match i % 5 {
+                    0 | 1 => {
+                        expected.insert(key, i);
+                    },
+                    2 => {
+                        expected.remove(&key);
+                    },
+                    // Removing and reinserting does not change the contents.
+                    3 => (),
+                    _ => {
+                        expected.insert(key, i + 1);
+                    },
+                }
+            }
+        }
+
+        assert_eq!(map.len(), expected.len());
+        map.validate();
+    }
+
+    #[test]
+    fn clear() {
+        let map = Map::new();
+        for i in 0 .. 2000u32 {
+            map.insert(i, i);
+        }
+        map.clear();
+        assert!(map.is_empty());
+        assert!(map.get(&5).is_none());
+        assert!(map.iter().next().is_none());
+        map.insert(5, 5);
+        assert_eq!(*map.get(&5).unwrap().val(), 5);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn clear_multithreaded() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let map = Arc::new(Map::new());
+        let mut threads = Vec::new();
+
+        for i in 0 .. 8u64 {
+            let map = map.clone();
+            let created = created.clone();
+            let dropped = dropped.clone();
+            threads.push(thread::spawn(move || {
+                for j in 0 .. 1000 {
+                    created.fetch_add(1, Relaxed);
+                    map.insert(i * 1000 + j, DropCounter(dropped.clone()));
+                }
+            }));
+        }
+
+        for _ in 0 .. 20 {
+            map.clear();
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        map.clear();
+        assert!(map.is_empty());
+        assert!(map.iter().next().is_none());
+        map.validate();
+        drop(map);
+        assert_eq!(created.load(Relaxed), dropped.load(Relaxed));
+    }
+
+    #[test]
+    fn retain() {
+        let map = Map::new();
+        for i in 0 .. 1000u32 {
+            map.insert(i, i * 2);
+        }
+        map.retain(|k, v| k % 3 != 0 && *v < 1000);
+        assert_eq!(map.len(), 333);
+        for i in 0 .. 1000u32 {
+            assert_eq!(map.contains_key(&i), i % 3 != 0 && i < 500);
+        }
+    }
+
+    #[test]
+    fn retain_multithreaded() {
+        let map = Arc::new(Map::new());
+        for i in 0 .. 1000u64 {
+            map.insert(i, i);
+        }
+
+        let inserter = {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in 1000 .. 2000u64 {
+                    map.insert(i, i);
+                }
+            })
+        };
+
+        map.retain(|_, v| v % 2 == 0);
+        inserter.join().expect("inserter failed");
+
+        for i in 0 .. 1000u64 {
+            assert_eq!(map.contains_key(&i), i % 2 == 0);
+        }
+        for i in (1000 .. 2000u64).filter(|i| i % 2 == 0) {
+            assert!(map.contains_key(&i));
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn map_values() {
+        let mut map = Map::new();
+        for i in 0 .. 1000u32 {
+            map.insert(i, i);
+        }
+        map.map_values(|key, val| key + val * 2);
+        assert_eq!(map.len(), 1000);
+        for i in 0 .. 1000u32 {
+            assert_eq!(map.get(&i).map(|guard| *guard.val()), Some(i * 3));
+        }
+        map.validate();
+
+        map.map_values_mut(|_, val| val + 1);
+        for i in 0 .. 1000u32 {
+            assert_eq!(map.get(&i).map(|guard| *guard.val()), Some(i * 3 + 1));
+        }
+    }
+
+    #[test]
+    fn map_values_with_readers() {
+        const KEYS: u64 = 2000;
+        const READERS: usize = 4;
+
+        // Every word of a value is the same, so a partial one would show.
+        let map = Arc::new(Map::new());
+        for i in 0 .. KEYS {
+            map.insert(i, [i; 8]);
+        }
+        let barrier = Arc::new(Barrier::new(READERS + 1));
+        let mut readers = Vec::new();
+
+        for _ in 0 .. READERS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            readers.push(thread::spawn(move || {
+                barrier.wait();
+                for _ in 0 .. 5 {
+                    for i in 0 .. KEYS {
+                        let val = *map.get(&i).unwrap().val();
+                        assert!(val == [i; 8] || val == [i + KEYS; 8]);
+                    }
+                }
+            }));
+        }
+
+        barrier.wait();
+        map.map_values(|key, _| [key + KEYS; 8]);
+        for reader in readers {
+            reader.join().expect("reader failed");
+        }
+
+        for i in 0 .. KEYS {
+            let val = map.get(&i).map(|guard| *guard.val());
+            assert_eq!(val, Some([i + KEYS; 8]));
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn drain() {
+        let map = Map::new();
+        for i in 0 .. 1000u32 {
+            map.insert(i, i * 2);
+        }
+        let mut drained = map.drain();
+        assert!(map.is_empty());
+        assert!(map.iter().next().is_none());
+        drained.sort_by_key(|removed| *removed.key());
+        assert_eq!(drained.len(), 1000);
+        for (i, removed) in (0 .. 1000u32).zip(&drained) {
+            assert_eq!(*removed, (i, i * 2));
+        }
+        assert!(map.drain().is_empty());
+    }
+
+    #[test]
+    fn drain_multithreaded() {
+        let map = Arc::new(Map::new());
+        for i in 0 .. 2000u64 {
+            map.insert(i, i);
+        }
+
+        let inserter = {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in 2000 .. 4000u64 {
+                    map.insert(i, i);
+                }
+            })
+        };
+        let mut drainers = Vec::new();
+        for _ in 0 .. 2 {
+            let map = map.clone();
+            drainers.push(thread::spawn(move || {
+                let mut keys = Vec::new();
+                for _ in 0 .. 5 {
+                    keys.extend(map.drain().iter().map(|pair| *pair.key()));
+                }
+                keys
+            }));
+        }
+
+        inserter.join().expect("inserter failed");
+        let mut seen = HashMap::new();
+        for drainer in drainers {
+            for key in drainer.join().expect("drainer failed") {
+                assert!(seen.insert(key, ()).is_none());
+            }
+        }
+        map.for_each(|&key, _| assert!(seen.insert(key, ()).is_none()));
+        assert_eq!(seen.len(), 4000);
+        map.validate();
+    }
+
+    #[test]
+    fn extend_shared_ref() {
+        let map = Map::new();
+        Extend::extend(&mut &map, (0 .. 1000u32).map(|i| (i, i * 2)));
+        assert_eq!(map.len(), 1000);
+
+        let other = Map::new();
+        Extend::extend(&mut &other, (1000 .. 1100u32).map(|i| (i, i * 2)));
+        Extend::extend(
+            &mut &map,
+            other
+                .drain()
+                .into_iter()
+                .map(|removed| Removed::try_into(removed).unwrap()),
+        );
+        assert!(other.is_empty());
+
+        assert_eq!(map.len(), 1100);
+        for i in 0 .. 1100u32 {
+            assert_eq!(*map.get(&i).unwrap().val(), i * 2);
+        }
+    }
+
+    #[test]
+    fn extend_multithreaded() {
+        let map = Arc::new(Map::new());
+        let mut threads = Vec::new();
+        for i in 0 .. 2u64 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                let iter = (0 .. 1000).map(|j| (i * 1000 + j, j));
+                Extend::extend(&mut &*map, iter);
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        assert_eq!(map.len(), 2000);
+        for i in 0 .. 2000u64 {
+            assert_eq!(*map.get(&i).unwrap().val(), i % 1000);
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn collect_last_wins() {
+        let map: Map<_, _> =
+            vec![(1, 'a'), (2, 'b'), (1, 'c'), (3, 'd'), (2, 'e')]
+                .into_iter()
+                .collect();
+        assert_eq!(map.len(), 3);
+        assert_eq!(*map.get(&1).unwrap().val(), 'c');
+        assert_eq!(*map.get(&2).unwrap().val(), 'e');
+        assert_eq!(*map.get(&3).unwrap().val(), 'd');
+    }
+
+    #[test]
+    fn from_iter_with_hasher_deep() {
+        let map = Map::from_iter_with_hasher(
+            (0 .. 2000u64).map(|i| (i, i * 5)).chain(Some((7, 0))),
+            ShiftState,
+        );
+        assert_eq!(map.len(), 2000);
+        for i in 0 .. 2000u64 {
+            let expected = if i == 7 { 0 } else { i * 5 };
+            assert_eq!(*map.get(&i).unwrap().val(), expected);
+        }
+    }
+
+    #[test]
+    fn clone() {
+        let map = Map::new();
+        for i in 0 .. 1000u32 {
+            map.insert(i, i.to_string());
+        }
+        let cloned = map.clone();
+        map.clear();
+        assert_eq!(cloned.len(), 1000);
+        for i in 0 .. 1000u32 {
+            assert_eq!(*cloned.get(&i).unwrap().val(), i.to_string());
+        }
+    }
+
+    #[test]
+    fn clone_multithreaded() {
+        let map = Arc::new(Map::new());
+        for i in 0 .. 2000u64 {
+            map.insert(i, i * 7);
+        }
+
+        let mutator = {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in 1000 .. 3000u64 {
+                    map.insert(i, i * 7);
+                    map.remove(&(i - 500));
+                }
+            })
+        };
+
+        for _ in 0 .. 10 {
+            let cloned = map.clone();
+            for i in 0 .. 500u64 {
+                assert_eq!(*cloned.get(&i).unwrap().val(), i * 7);
+            }
+            cloned.for_each(|&k, &v| {
+                assert!(k < 3000);
+                assert_eq!(v, k * 7);
+            });
+        }
+
+        mutator.join().expect("mutator failed");
+        map.validate();
+    }
+
+    #[test]
+    fn partial_eq() {
+        let left = (0 .. 500u32).map(|i| (i, i * 3)).collect::<Map<_, _>>();
+        let right =
+            (0 .. 500u32).rev().map(|i| (i, i * 3)).collect::<Map<_, _>>();
+        assert!(left == right);
+
+        right.insert(7, 0);
+        assert!(left != right);
+
+        right.insert(7, 21);
+        right.insert(500, 1500);
+        assert!(left != right);
+        assert!(right != left);
+
+        right.remove(&500);
+        assert!(left == right);
+        assert!(Map::<u32, u32>::new() == Map::new());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_json_round_trip() {
+        let map =
+            (0 .. 300u32).map(|i| (i, i.to_string())).collect::<Map<_, _>>();
+        let json = serde_json::to_string(&map).unwrap();
+        let loaded: Map<u32, String> = serde_json::from_str(&json).unwrap();
+        assert!(loaded == map);
+        assert_eq!(
+            serde_json::to_string(&Map::<u32, u32>::new()).unwrap(),
+            "{}"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn bincode_round_trip_deep() {
+        let map = Map::from_iter_with_hasher(
+            (0 .. 2000u64).map(|i| (i, i * 3)),
+            ShiftState,
+        );
+        let bytes = bincode::serialize(&map).unwrap();
+        let loaded: Map<u64, u64, ShiftState> =
+            bincode::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.len(), 2000);
+        assert!(loaded == map);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_iter_sum() {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+        let map = (0 .. 100_000u64).map(|i| (i, i * 2)).collect::<Map<_, _>>();
+        let sum: u64 = (&map).into_par_iter().map(|(_, val)| val).sum();
+        assert_eq!(sum, 99_999 * 100_000);
+
+        let count = AtomicUsize::new(0);
+        map.par_visit(|&key, &val| {
+            assert_eq!(val, key * 2);
+            count.fetch_add(1, Relaxed);
+        });
+        assert_eq!(count.into_inner(), 100_000);
+
+        let compact = Map::new_compact();
+        compact.extend((0 .. 10u64).map(|i| (i, i)));
+        let mut pairs: Vec<_> = (&compact).into_par_iter().collect();
+        pairs.sort();
+        assert_eq!(pairs, (0 .. 10).map(|i| (i, i)).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_extend() {
+        use rayon::iter::{
+            IntoParallelIterator,
+            ParallelExtend,
+            ParallelIterator,
+        };
+
+        let mut map = Map::new();
+        (&map).par_extend((0 .. 100_000u64).into_par_iter().map(|i| (i, i)));
+        assert_eq!(map.len(), 100_000);
+        for i in 0 .. 100_000 {
+            assert_eq!(*map.get(&i).unwrap().val(), i);
+        }
+
+        // Duplicated keys replace each other, so one of the values is kept.
+        map.par_extend(
+            (0 .. 200_000u64).into_par_iter().map(|i| (i % 1000, i)),
+        );
+        assert_eq!(map.len(), 100_000);
+        for i in 0 .. 1000 {
+            assert_eq!(*map.get(&i).unwrap().val() % 1000, i);
+        }
+
+        let compact = Map::<u64, u64>::new_compact();
+        (&compact).par_extend((0 .. 10u64).into_par_iter().map(|i| (i, i)));
+        assert_eq!(compact.len(), 10);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_split_skewed() {
+        // Every key shares the lowest 40 bits of the hash, so the top table
+        // has a single node in use.
+        let map = Map::with_hasher(ShiftState);
+        map.extend((0 .. 4096u64).map(|i| (i, i)));
+
+        let mut parts = vec![rayon::Part::new(&map)];
+        let mut done = Vec::new();
+        while let Some(part) = parts.pop() {
+            match part.split() {
+                (part, Some(other)) => parts.extend(vec![part, other]),
+                (part, None) => done.push(part),
+            }
+        }
+
+        let mut keys = Vec::new();
+        let mut nonempty = 0;
+        for part in &done {
+            let before = keys.len();
+            part.visit(|&key, _| keys.push(key));
+            if keys.len() > before {
+                nonempty += 1;
+            }
+        }
+        keys.sort();
+        assert_eq!(keys, (0 .. 4096).collect::<Vec<_>>());
+        // The keys are spread through 16 tables of the sixth level, so there
+        // are enough parts to share between threads.
+        assert!(nonempty >= 16 * 16, "{} parts", nonempty);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_visit_concurrent() {
+        const STABLE: u64 = 2000;
+
+        let map = Arc::new(Map::with_hasher(ShiftState));
+        map.extend((0 .. STABLE).map(|key| (key << 8, key)));
+        let stop = Arc::new(AtomicUsize::new(0));
+        let mut threads = Vec::new();
+
+        for t in 0 .. 2 {
+            let map = map.clone();
+            let stop = stop.clone();
+            threads.push(thread::spawn(move || {
+                while stop.load(Relaxed) == 0 {
+                    for key in 0 .. STABLE {
+                        map.insert((key << 8) + t + 1, key);
+                    }
+                    for key in 0 .. STABLE {
+                        map.remove(&((key << 8) + t + 1));
+                    }
+                    map.compact();
+                }
+            }));
+        }
+
+        for _ in 0 .. 20 {
+            let counts = (0 .. STABLE).map(|_| AtomicUsize::new(0));
+            let counts = counts.collect::<Vec<_>>();
+            map.par_visit(|&key, _| {
+                if key & 0xff == 0 {
+                    counts[(key >> 8) as usize].fetch_add(1, Relaxed);
+                }
+            });
+            for count in counts {
+                assert_eq!(count.into_inner(), 1);
+            }
+        }
+
+        stop.store(1, Relaxed);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn split_visits_skewed() {
+        // Every key shares the lowest 40 bits of the hash, so a split of the
+        // top table alone would put every entry in the same part.
+        let map = Map::with_hasher(ShiftState);
+        map.extend((0 .. 4096u64).map(|i| (i, i)));
+
+        let parts = map.split_visits(8);
+        assert_eq!(parts.len(), 8);
+        let mut keys = Vec::new();
+        for part in &parts {
+            let before = keys.len();
+            map.visit_range(part, |&key, _| keys.push(key));
+            let len = keys.len() - before;
+            assert!(len >= 4096 / 16, "{} entries in a part", len);
+        }
+        keys.sort();
+        assert_eq!(keys, (0 .. 4096).collect::<Vec<_>>());
+
+        let empty = Map::<u64, u64>::new();
+        let parts = empty.split_visits(4);
+        assert_eq!(parts.len(), 1);
+        empty.extend((0 .. 1000u64).map(|i| (i, i)));
+        let mut count = 0;
+        empty.visit_range(&parts[0], |_, _| count += 1);
+        assert_eq!(count, 1000);
+
+        // A single bucket cannot be shared.
+        let map = Map::new();
+        map.insert(1, 1);
+        let parts = map.split_visits(4);
+        let mut count = 0;
+        for part in &parts {
+            map.visit_range(part, |_, _| count += 1);
+        }
+        assert_eq!(count, 1);
+
+        let compact = Map::new_compact();
+        compact.extend((0 .. 10u64).map(|i| (i, i)));
+        let parts = compact.split_visits(4);
+        assert_eq!(parts.len(), 1);
+        compact.extend((10 .. 100u64).map(|i| (i, i)));
+        let mut count = 0;
+        compact.visit_range(&parts[0], |_, _| count += 1);
+        assert_eq!(count, 100);
+    }
+
+    #[test]
+    fn split_visits_concurrent() {
+        const STABLE: u64 = 2000;
+        const WORKERS: usize = 8;
+
+        let map = Arc::new(Map::with_hasher(ShiftState));
+        map.extend((0 .. STABLE).map(|key| (key << 8, key)));
+        let stop = Arc::new(AtomicUsize::new(0));
+        let mut writers = Vec::new();
+
+        for t in 0 .. 2 {
+            let map = map.clone();
+            let stop = stop.clone();
+            writers.push(thread::spawn(move || {
+                while stop.load(Relaxed) == 0 {
+                    for key in 0 .. STABLE {
+                        map.insert((key << 8) + t + 1, key);
+                    }
+                    for key in 0 .. STABLE {
+                        map.remove(&((key << 8) + t + 1));
+                    }
+                    map.compact();
+                }
+            }));
+        }
+
+        for _ in 0 .. 10 {
+            let counts = (0 .. STABLE).map(|_| AtomicUsize::new(0));
+            let counts = Arc::new(counts.collect::<Vec<_>>());
+            let parts = map.split_visits(WORKERS);
+            assert_eq!(parts.len(), WORKERS);
+            let workers = parts
+                .into_iter()
+                .map(|part| {
+                    let map = map.clone();
+                    let counts = counts.clone();
+                    thread::spawn(move || {
+                        map.visit_range(&part, |&key, _| {
+                            if key & 0xff == 0 {
+                                counts[(key >> 8) as usize]
+                                    .fetch_add(1, Relaxed);
+                            }
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+            for worker in workers {
+                worker.join().expect("worker failed");
+            }
+            assert!(counts.iter().all(|count| count.load(Relaxed) == 1));
+        }
+
+        stop.store(1, Relaxed);
+        for writer in writers {
+            writer.join().expect("writer failed");
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn debug_prints_entries() {
+        let map = Map::new();
+        assert_eq!(format!("{:?}", map), "Map {}");
+        map.insert("five", 5);
+        map.insert("four", 4);
+        let printed = format!("{:?}", map);
+        assert!(printed.contains("\"five\": 5"));
+        assert!(printed.contains("\"four\": 4"));
+
+        let map = (0 .. 1000u32).map(|i| (i, i)).collect::<Map<_, _>>();
+        let printed = format!("{:?}", map);
+        assert_eq!(printed.matches(": ").count(), 128);
+        assert!(printed.ends_with(", ... (872 more)}"));
+    }
+
+    #[derive(Debug)]
+    struct CloneBomb(Arc<AtomicUsize>);
+
+    impl Clone for CloneBomb {
+        fn clone(&self) -> Self {
+            panic!("clone bomb")
+        }
+    }
+
+    impl Drop for CloneBomb {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Relaxed);
+        }
+    }
+
+    #[test]
+    fn get_guarded() {
+        fn total_len(map: &Map<String, Vec<u8>>) -> Option<usize> {
+            let five = map.get_guarded("five")?;
+            let four = map.get_guarded("four")?;
+            Some(five.len() + four.len())
+        }
+
+        let map = Map::new();
+        map.insert("five".to_owned(), vec![5; 5]);
+        assert_eq!(total_len(&map), None);
+        map.insert("four".to_owned(), vec![4; 4]);
+        assert_eq!(total_len(&map), Some(9));
+
+        let guard = map.get_guarded("five").unwrap();
+        assert_eq!(guard.key(), "five");
+        assert_eq!(*guard, vec![5; 5]);
+    }
+
+    #[test]
+    fn get_guarded_survives_removal() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let map = Arc::new(Map::new());
+        map.insert(1, DropCounter(dropped.clone()));
+
+        let guard = map.get_guarded(&1).unwrap();
+        let remover = {
+            let map = map.clone();
+            thread::spawn(move || {
+                drop(map.remove(&1).unwrap());
+                map.insert(1, DropCounter(Arc::new(AtomicUsize::new(0))));
+                drop(map.remove(&1).unwrap());
+            })
+        };
+        remover.join().unwrap();
+
+        assert!(map.get(&1).is_none());
+        assert_eq!(*guard.key(), 1);
+        assert!(Arc::ptr_eq(&guard.0, &dropped));
+        assert_eq!(dropped.load(Relaxed), 0);
+
+        map.validate();
+        drop(guard);
+        drop(map);
+        assert_eq!(dropped.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn get_cloned() {
+        let map = Map::new();
+        map.insert("five".to_owned(), vec![5]);
+        assert_eq!(map.get_cloned("five"), Some(vec![5]));
+        assert_eq!(map.get_cloned("four"), None);
+        assert_eq!(
+            map.get_pair_cloned("five"),
+            Some(("five".to_owned(), vec![5]))
+        );
+        assert_eq!(map.get_pair_cloned("four"), None);
+    }
+
+    // A name compared regardless of case, which keeps its original case.
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct Folded(String);
+
+    #[derive(Debug, Clone)]
+    struct Name {
+        folded: Folded,
+        original: String,
+    }
+
+    impl Name {
+        fn new(original: &str) -> Self {
+            Self {
+                folded: Folded(original.to_lowercase()),
+                original: original.to_owned(),
+            }
+        }
+    }
+
+    impl Borrow<Folded> for Name {
+        fn borrow(&self) -> &Folded {
+            &self.folded
+        }
+    }
+
+    impl PartialEq for Name {
+        fn eq(&self, other: &Self) -> bool {
+            self.folded == other.folded
+        }
+    }
+
+    impl Eq for Name {}
+
+    impl PartialOrd for Name {
+        fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Name {
+        fn cmp(&self, other: &Self) -> cmp::Ordering {
+            self.folded.cmp(&other.folded)
+        }
+    }
+
+    impl Hash for Name {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.folded.hash(state)
+        }
+    }
+
+    #[test]
+    fn get_stored_key_cloned() {
+        let map = Map::new();
+        map.insert(Name::new("Hello World"), 1);
+        map.insert(Name::new("rust"), 2);
+
+        let search = Folded("HELLO world".to_lowercase());
+        let name = map.get_key_cloned(&search).unwrap();
+        assert_eq!(name.original, "Hello World");
+        let (name, val) = map.get_pair_cloned(&search).unwrap();
+        assert_eq!((name.original.as_str(), val), ("Hello World", 1));
+
+        let search = Folded("RUST".to_lowercase());
+        assert_eq!(map.get_key_cloned(&search).unwrap().original, "rust");
+        assert!(map.get_key_cloned(&Folded("go".to_owned())).is_none());
+        assert!(map.get_pair_cloned(&Folded("go".to_owned())).is_none());
+    }
+
+    #[test]
+    fn hashed_operations() {
+        let map = Map::new();
+        // Digests sharing a few hashes, so buckets hold many keys.
+        for i in 0 .. 1000u64 {
+            assert!(map.insert_hashed(i % 16, i, i * 2).is_none());
+        }
+        assert_eq!(map.len(), 1000);
+        for i in 0 .. 1000u64 {
+            let guard = map.get_hashed(i % 16, &i).expect("stored");
+            assert_eq!(*guard.val(), i * 2);
+            assert!(map.get_hashed(i % 16 + 16, &i).is_none());
+        }
+
+        let old = map.insert_hashed(3, 3, 33).expect("replaced");
+        assert_eq!(*old.val(), 6);
+        assert!(map.remove_hashed(4, &3).is_none());
+        let removed = map.remove_hashed(3, &3).expect("removed");
+        assert_eq!(removed.val(), &33);
+        assert!(map.get_hashed(3, &3).is_none());
+        // The given hash is kept by the removed entry.
+        map.reinsert(removed);
+        assert_eq!(map.get_hashed(3, &3).map(|guard| *guard.val()), Some(33));
+        assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn hashed_with_wrong_hashes() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let map = Arc::new(Map::new());
+        map.insert(7u64, DropCounter(dropped.clone()));
+        // The same key under another hash is stored twice.
+        map.insert_hashed(1, 7, DropCounter(dropped.clone()));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.iter().filter(|guard| *guard.key() == 7).count(), 2);
+        assert!(map.get(&7).is_some());
+        assert!(map.get_hashed(1, &7).is_some());
+        assert!(map.remove_hashed(2, &7).is_none());
+        assert_eq!(dropped.load(Relaxed), 0);
+
+        let threads = (0 .. 4u64)
+            .map(|t| {
+                let map = map.clone();
+                let dropped = dropped.clone();
+                thread::spawn(move || {
+                    let mut created = 0;
+                    for i in 0 .. 2000u64 {
+                        let key = i % 100;
+                        // Keys are given hashes at random, as a buggy caller
+                        // would.
+                        let hash = ((i * 31 + t) % 5) << 60 | key;
+                        if (i + t) % 3 == 0 {
+                            map.remove_hashed(hash, &key);
+                        } else {
+                            let val = DropCounter(dropped.clone());
+                            map.insert_hashed(hash, key, val);
+                            created += 1;
+                        }
+                        map.get_hashed(hash ^ 1, &key);
+                    }
+                    created
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut created = 2;
+        for thread in threads {
+            created += thread.join().expect("thread failed");
+        }
+
+        assert_eq!(map.iter().count(), map.len());
+        drop(map);
+        // Every value was dropped exactly once.
+        assert_eq!(dropped.load(Relaxed), created);
+    }
+
+    #[test]
+    fn get_cloned_panic_releases_pause() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let map = Map::new();
+        map.insert(1, CloneBomb(dropped.clone()));
+
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            map.get_cloned(&1);
+        }));
+        assert!(res.is_err());
+
+        // With no pause left, the removed value is dropped right away.
+        drop(map.remove(&1));
+        assert_eq!(dropped.load(Relaxed), 1);
+    }
+
+    // A key whose `Hash` or `Ord` panics while its rig is set. Every key holds
+    // the rig, so keys leaked by an operation keep it alive.
+    #[derive(Debug, Clone)]
+    struct Rigged {
+        id: u64,
+        rig: Arc<AtomicUsize>,
+    }
+
+    const RIG_HASH: usize = 1;
+    const RIG_ORD: usize = 2;
+
+    impl Hash for Rigged {
+        fn hash<H>(&self, state: &mut H)
+        where
+            H: Hasher,
+        {
+            assert!(self.rig.load(Relaxed) != RIG_HASH, "rigged hash");
+            self.id.hash(state)
+        }
+    }
+
+    impl PartialEq for Rigged {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == cmp::Ordering::Equal
+        }
+    }
+
+    impl Eq for Rigged {}
+
+    impl PartialOrd for Rigged {
+        fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Rigged {
+        fn cmp(&self, other: &Self) -> cmp::Ordering {
+            assert!(self.rig.load(Relaxed) != RIG_ORD, "rigged ord");
+            self.id.cmp(&other.id)
+        }
+    }
+
+    // Runs an operation which must panic, checking that no pause outlives it.
+    fn must_panic<K, V, H, const BITS: usize, O, F>(
+        map: &Map<K, V, H, BITS, O>,
+        op: F,
+    ) where
+        F: FnOnce(),
+    {
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(op));
+        assert!(res.is_err());
+        assert!(map.incin.inner.is_unpaused());
+    }
+
+    #[test]
+    fn panicking_hash_and_ord() {
+        let rig = Arc::new(AtomicUsize::new(0));
+        let key = |id| Rigged { id, rig: rig.clone() };
+        // A single bucket, so every operation compares keys.
+        let map = Map::with_hasher(ConstState);
+        for i in 0 .. 100 {
+            map.insert(key(i * 2), i);
+        }
+
+        for &mode in &[RIG_HASH, RIG_ORD] {
+            rig.store(mode, Relaxed);
+            must_panic(&map, || drop(map.insert(key(51), 0)));
+            must_panic(&map, || drop(map.insert(key(50), 0)));
+            must_panic(&map, || drop(map.get(&key(50))));
+            must_panic(&map, || drop(map.remove(&key(50))));
+            must_panic(&map, || {
+                map.insert_with(key(51), |_, _, _| Preview::New(0));
+            });
+            must_panic(&map, || drop(map.update(&key(50), |val| val + 1)));
+            rig.store(0, Relaxed);
+            map.validate();
+        }
+
+        assert_eq!(map.len(), 100);
+        for i in 0 .. 100 {
+            assert_eq!(map.get(&key(i * 2)).map(|guard| *guard.val()), Some(i));
+        }
+        assert!(map.get(&key(51)).is_none());
+        assert!(map.insert(key(51), 51).is_none());
+        let removed = map.remove(&key(50)).expect("stored");
+        assert_eq!(*removed.val(), 25);
+        drop(removed);
+        map.validate();
+
+        // The keys given to the panicking operations were all dropped.
+        drop(map);
+        assert_eq!(Arc::strong_count(&rig), 1);
+    }
+
+    #[test]
+    fn panicking_closures() {
+        let token = Arc::new(());
+        let map = Map::new();
+        for i in 0 .. 1000u64 {
+            map.insert(i, token.clone());
+        }
+
+        must_panic(&map, || {
+            map.insert_with(1000, |_, _, _| panic!("insert_with"));
+        });
+        must_panic(&map, || drop(map.update(&1, |_| panic!("update"))));
+        must_panic(&map, || {
+            map.get_or_insert_with(1000, || panic!("make"), |_, _| ());
+        });
+        must_panic(&map, || {
+            map.get_or_insert_with(1002, || token.clone(), |_, _| {
+                panic!("reader")
+            });
+        });
+        must_panic(&map, || {
+            map.get_many(&[2, 3, 4], |key, _| assert!(*key != 3, "reader"));
+        });
+        must_panic(&map, || {
+            map.for_each(|key, _| assert!(*key != 500, "visitor"));
+        });
+        must_panic(&map, || {
+            map.fold(0, |acc, key, _| {
+                assert!(*key != 500, "fold");
+                acc + 1
+            });
+        });
+        must_panic(&map, || {
+            map.remove_and_read(&2, |_, _| panic!("reader"));
+        });
+        // Entries removed before the panic stay removed.
+        let mut visited = 0;
+        must_panic(&map, || {
+            map.retain(|key, _| {
+                visited += 1;
+                assert!(visited <= 100, "predicate");
+                key % 2 == 0
+            })
+        });
+        map.validate();
+
+        let stored = map.iter().map(|guard| *guard.key()).collect::<Vec<_>>();
+        assert_eq!(stored.len(), map.len());
+        assert!(map.len() < 1000);
+        // The value of 1002 was inserted before its reader panicked, and 2
+        // was removed before its reader did.
+        assert!(stored.contains(&1002));
+        assert!(!stored.contains(&1000) && !stored.contains(&2));
+        for key in (0 .. 1000).filter(|key| key % 2 == 0 && *key != 2) {
+            assert!(stored.contains(&key));
+        }
+        assert!(map.insert(1000, token.clone()).is_none());
+        assert!(map.remove(&1000).is_some());
+
+        drop(map);
+        assert_eq!(Arc::strong_count(&token), 1);
+    }
+
+    #[test]
+    fn separate_incins_reclaim_independently() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let paused = Map::new();
+        paused.insert(0, DropCounter(dropped.clone()));
+        let separate = Map::new();
+        let shared = Map::with_incin(paused.incin());
+        for i in 0 .. 10 {
+            separate.insert(i, DropCounter(dropped.clone()));
+            shared.insert(i, DropCounter(dropped.clone()));
+        }
+
+        // The guard pauses only the incinerator of its map, so the garbage of
+        // a map with its own incinerator is still reclaimed right away.
+        let guard = paused.get(&0).unwrap();
+        for i in 0 .. 10 {
+            drop(separate.remove(&i));
+        }
+        assert_eq!(dropped.load(Relaxed), 10);
+
+        // A map sharing the incinerator has to wait for the guard.
+        for i in 0 .. 10 {
+            drop(shared.remove(&i));
+        }
+        assert_eq!(dropped.load(Relaxed), 10);
+        drop(guard);
+        assert_eq!(dropped.load(Relaxed), 20);
+    }
+
+    #[test]
+    fn remove_and_read() {
+        let map = Map::new();
+        map.insert("five".to_owned(), 5);
+        assert_eq!(map.remove_and_read("four", |_, &val| val), None);
+        assert_eq!(
+            map.remove_and_read("five", |key, &val| (key.clone(), val)),
+            Some(("five".to_owned(), 5))
+        );
+        assert!(map.get("five").is_none());
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn remove_and_read_drops_all() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let map = Map::new();
+        for i in 0 .. 5000u32 {
+            map.insert(i, DropCounter(dropped.clone()));
+        }
+
+        let mut sum = 0;
+        for i in 0 .. 5000u32 {
+            sum += map.remove_and_read(&i, |&key, _| key as u64).unwrap();
+        }
+
+        assert_eq!(sum, 4999 * 5000 / 2);
+        assert_eq!(dropped.load(Relaxed), 5000);
+    }
+
+    #[test]
+    fn entry() {
+        let map = Map::new();
+        assert_eq!(*map.entry("five").or_insert(5).val(), 5);
+        assert_eq!(*map.entry("five").or_insert(6).val(), 5);
+        assert_eq!(
+            *map.entry("five").or_insert_with(|| panic!("called")).val(),
+            5
+        );
+        assert_eq!(
+            *map.entry("five").and_modify(|v| v * 2).or_default(),
+            ("five", 10)
+        );
+        assert_eq!(
+            *map.entry("four").and_modify(|v| v * 2).or_default(),
+            ("four", 0)
+        );
+        assert_eq!(map.entry("three").key(), &"three");
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn entry_or_insert_with_multithreaded() {
+        let map = Arc::new(Map::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(16));
+        let mut threads = Vec::new();
+
+        for i in 0 .. 16usize {
+            let map = map.clone();
+            let calls = calls.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                let guard = map.entry(7u8).or_insert_with(|| {
+                    calls.fetch_add(1, Relaxed);
+                    i
+                });
+                *guard.val()
+            }));
+        }
+
+        let seen = threads
+            .into_iter()
+            .map(|thread| thread.join().expect("thread failed"))
+            .collect::<Vec<_>>();
+
+        let winner = *map.get(&7).unwrap().val();
+        assert!(seen.iter().all(|&val| val == winner));
+        assert!(calls.load(Relaxed) >= 1);
+        assert_eq!(map.len(), 1);
+        map.validate();
+    }
+
+    #[test]
+    fn entry_and_modify_multithreaded() {
+        let map = Arc::new(Map::new());
+        map.insert(0u8, 0usize);
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 16 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for _ in 0 .. 500 {
+                    map.entry(0).and_modify(|v| v + 1);
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        assert_eq!(*map.get(&0).unwrap().val(), 8000);
+        map.validate();
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        let map = Map::new();
+        let val = map.get_or_insert_with("five", || 5, |_, &val| val);
+        assert_eq!(val, 5);
+        let pair = map.get_or_insert_with(
+            "five",
+            || panic!("called"),
+            |&key, &val| (key, val),
+        );
+        assert_eq!(pair, ("five", 5));
+    }
+
+    #[test]
+    fn get_or_insert_with_race_drops_losers() {
+        let map = Arc::new(Map::new());
+        let created = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(16));
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 16 {
+            let map = map.clone();
+            let created = created.clone();
+            let dropped = dropped.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                map.get_or_insert_with(
+                    3u8,
+                    || {
+                        created.fetch_add(1, Relaxed);
+                        DropCounter(dropped)
+                    },
+                    |_, _| (),
+                )
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        let created = created.load(Relaxed);
+        assert!(created >= 1);
+        assert_eq!(dropped.load(Relaxed), created - 1);
+        map.validate();
+        drop(Arc::try_unwrap(map).unwrap());
+        assert_eq!(dropped.load(Relaxed), created);
+    }
+
+    #[test]
+    fn get_or_default() {
+        let map = Map::new();
+        assert_eq!(map.get_or_default("five", |val: &Vec<u8>| val.len()), 0);
+        map.insert("four", vec![4; 4]);
+        assert_eq!(map.get_or_default("four", |val| val.len()), 4);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn get_or_default_race_keeps_one() {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug)]
+        struct Counted;
+
+        impl Default for Counted {
+            fn default() -> Self {
+                CREATED.fetch_add(1, Relaxed);
+                Counted
+            }
+        }
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Relaxed);
+            }
+        }
+
+        let map = Arc::new(Map::new());
+        let barrier = Arc::new(Barrier::new(16));
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 16 {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                map.get_or_default(3u8, |_: &Counted| ())
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        let created = CREATED.load(Relaxed);
+        assert!(created >= 1);
+        assert_eq!(DROPPED.load(Relaxed), created - 1);
+        map.get_or_default(3u8, |_| ());
+        assert_eq!(CREATED.load(Relaxed), created);
+        drop(Arc::try_unwrap(map).unwrap());
+        assert_eq!(DROPPED.load(Relaxed), created);
+    }
+
+    #[test]
+    fn insert_and_read() {
+        let map = Map::new();
+        let (old, read) = map.insert_and_read("five", 5, |key, val| {
+            assert_eq!(*key, "five");
+            *val * 2
+        });
+        assert!(old.is_none());
+        assert_eq!(read, 10);
+        assert_eq!(map.len(), 1);
+
+        let (old, read) = map.insert_and_read("five", 6, |_, val| *val);
+        assert_eq!(old.map(|old| *old.val()), Some(5));
+        assert_eq!(read, 6);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get("five").map(|guard| *guard.val()), Some(6));
+    }
+
+    #[test]
+    fn insert_and_read_race() {
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 2000;
+
+        let map = Arc::new(Map::new());
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+        let mut threads = Vec::new();
+
+        for thread in 0 .. THREADS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for i in 0 .. ROUNDS {
+                    let val = vec![thread; i % 16 + 1];
+                    let expected = val.clone();
+                    let (_, read) =
+                        map.insert_and_read(0u8, val, |_, val| val.clone());
+                    assert_eq!(read, expected);
+                }
+            }));
+        }
+
+        let remover = {
+            let map = map.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0 .. ROUNDS {
+                    map.remove(&0);
+                }
+            })
+        };
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        remover.join().expect("thread failed");
+        assert!(map.len() <= 1);
+        map.validate();
+    }
+
+    #[test]
+    fn insert_lazy() {
+        let map = Map::new();
+        assert!(map.insert_lazy("five", |_| None).is_none());
+        assert!(map.get("five").is_none());
+        assert_eq!(map.len(), 0);
+
+        assert!(map
+            .insert_lazy("five", |found| {
+                assert!(found.is_none());
+                Some(5)
+            })
+            .is_none());
+        assert_eq!(map.len(), 1);
+
+        let old = map
+            .insert_lazy("five", |found| found.map(|(_, &val)| val * 2))
+            .unwrap();
+        assert_eq!(*old, ("five", 5));
+        assert_eq!(*map.get("five").unwrap().val(), 10);
+
+        assert!(map.insert_lazy("five", |_| None).is_none());
+        assert_eq!(*map.get("five").unwrap().val(), 10);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn insert_lazy_multithreaded() {
+        let map = Arc::new(Map::new());
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 16 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. 500u64 {
+                    map.insert_lazy(i % 10, |found| {
+                        Some(found.map_or(1, |(_, &val)| val + 1))
+                    });
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        let mut total = 0;
+        map.for_each(|_, &val| total += val);
+        assert_eq!(total, 8000);
+        assert_eq!(map.len(), 10);
+        map.validate();
+    }
+
+    #[test]
+    fn update_in_place() {
+        let map = Map::new();
+        assert!(map.update("five", |_| panic!("called")).is_none());
+        assert!(map.get("five").is_none());
+
+        map.insert("five".to_owned(), 5);
+        let old = map.update("five", |&val| val + 1).unwrap();
+        assert_eq!(*old.val(), 5);
+        assert_eq!(*map.get("five").unwrap().val(), 6);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn update_multithreaded() {
+        let map = Arc::new(Map::new());
+        map.insert(0u8, 0u64);
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 32 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for _ in 0 .. 10000 {
+                    map.update(&0, |&val| val + 1).unwrap();
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        assert_eq!(*map.get(&0).unwrap().val(), 320000);
+        map.validate();
+    }
+
+    #[test]
+    fn update_in_place_atomic() {
+        let map = Map::new();
+        assert!(map.update_in_place("five", |val| val + 1).is_none());
+        map.insert("five", AtomicUsize::new(5));
+        let before = &*map.get("five").unwrap() as *const (&str, AtomicUsize);
+        assert_eq!(map.update_in_place("five", |val| val * 2), Some(5));
+        assert_eq!(map.update_in_place("five", |val| val + 1), Some(10));
+        // The same entry was changed, rather than replaced.
+        let after = map.get("five").unwrap();
+        assert!(core::ptr::eq(before, &*after));
+        assert_eq!(after.val().load(Relaxed), 11);
+    }
+
+    #[test]
+    fn upsert_in_place_multithreaded() {
+        const THREADS: usize = 16;
+        const INCREMENTS: usize = 10000;
+
+        let map = Arc::new(Map::<u8, AtomicUsize>::new());
+        let created = Arc::new(AtomicUsize::new(0));
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let created = created.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. INCREMENTS {
+                    let key = ((i + t) % 4) as u8;
+                    if map.upsert_in_place(key, 1, |val| val + 1).is_none() {
+                        created.fetch_add(1, Relaxed);
+                    }
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        assert_eq!(created.load(Relaxed), 4);
+        let total = (0 .. 4)
+            .map(|key| map.get(&key).unwrap().val().load(Relaxed))
+            .sum::<usize>();
+        assert_eq!(total, THREADS * INCREMENTS);
+        map.validate();
+    }
+
+    #[test]
+    fn compute() {
+        let map = Map::new();
+        assert_eq!(map.compute("five", |_| None), ComputeResult::Absent);
+        assert_eq!(map.len(), 0);
+
+        let res = map.compute("five", |stored| {
+            assert!(stored.is_none());
+            Some(5)
+        });
+        assert_eq!(res, ComputeResult::Inserted);
+
+        match map.compute("five", |stored| stored.map(|(_, &val)| val * 2)) {
+            ComputeResult::Replaced(old) => assert_eq!(*old, ("five", 5)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(map.get_cloned("five"), Some(10));
+
+        match map.compute("five", |_| None) {
+            ComputeResult::Removed(old) => assert_eq!(*old, ("five", 10)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(map.get("five").is_none());
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn compute_against_model() {
+        use std::sync::Mutex;
+
+        const KEYS: u8 = 4;
+
+        // Each operation adds to a counter, and a zero counter has no entry,
+        // so every operation inserts, updates or removes an entry depending
+        // on the current one. Additions commute, so the final state does not
+        // depend on the order of the operations.
+        fn apply(stored: Option<i64>, delta: i64) -> Option<i64> {
+            match stored.unwrap_or(0) + delta {
+                0 => None,
+                sum => Some(sum),
+            }
+        }
+
+        let map = Arc::new(Map::new());
+        let model = Arc::new(Mutex::new(HashMap::new()));
+        let mut threads = Vec::new();
+
+        for t in 0 .. 8i64 {
+            let map = map.clone();
+            let model = model.clone();
+            threads.push(thread::spawn(move || {
+                let mut created = 0i64;
+                for i in 0 .. 20000 {
+                    let key = (i % KEYS as i64) as u8;
+                    let round = i / KEYS as i64;
+                    let delta = if (round / (t + 1)) % 2 == 0 { 1 } else { -1 };
+                    let res = map.compute(key, |stored| {
+                        apply(stored.map(|(_, &val)| val), delta)
+                    });
+                    match res {
+                        ComputeResult::Inserted => created += 1,
+                        ComputeResult::Replaced(old) => {
+                            assert_ne!(old.val() + delta, 0)
+                        },
+                        ComputeResult::Removed(old) => {
+                            assert_eq!(old.val() + delta, 0);
+                            created -= 1;
+                        },
+                        ComputeResult::Absent => panic!("no-op addition"),
+                    }
+
+                    let mut model = model.lock().unwrap();
+                    let stored = model.get(&key).cloned();
+                    match apply(stored, delta) {
+                        Some(sum) => model.insert(key, sum),
+                        None => model.remove(&key),
+                    };
+                }
+                created
+            }));
+        }
+
+        let mut created = 0;
+        for thread in threads {
+            created += thread.join().expect("thread failed");
+        }
+
+        let model = model.lock().unwrap();
+        for key in 0 .. KEYS {
+            assert_eq!(map.get_cloned(&key), model.get(&key).cloned());
+        }
+        assert_eq!(map.len(), model.len());
+        assert_eq!(created, model.len() as i64);
+    }
+
+    #[test]
+    fn upsert() {
+        let map = Map::new();
+        let res = map.upsert("five", || 5, |_| panic!("called"));
+        assert_eq!(res, Upserted::Created);
+        assert_eq!(*map.get("five").unwrap().val(), 5);
+
+        match map.upsert("five", || panic!("called"), |&val| val * 2) {
+            Upserted::Modified(old) => assert_eq!(*old, ("five", 5)),
+            Upserted::Created => panic!("created"),
+        }
+        assert_eq!(*map.get("five").unwrap().val(), 10);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn upsert_multithreaded() {
+        let map = Arc::new(Map::new());
+        let created = Arc::new(AtomicUsize::new(0));
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 16 {
+            let map = map.clone();
+            let created = created.clone();
+            threads.push(thread::spawn(move || {
+                for _ in 0 .. 1000 {
+                    let res = map.upsert(0u8, || 1u64, |&val| val + 1);
+                    if res == Upserted::Created {
+                        created.fetch_add(1, Relaxed);
+                    }
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        assert_eq!(created.load(Relaxed), 1);
+        assert_eq!(*map.get(&0).unwrap().val(), 16000);
+        map.validate();
+    }
+
+    #[test]
+    fn replace_if() {
+        let map = Map::new();
+        assert_eq!(map.replace_if("five", 5, |_| true), Err(5));
+        assert!(map.get("five").is_none());
+
+        map.insert("five".to_owned(), 5);
+        assert_eq!(map.replace_if("five", 6, |&val| val == 4), Err(6));
+        assert_eq!(*map.get("five").unwrap().val(), 5);
+
+        let old = map.replace_if("five", 6, |&val| val == 5).unwrap();
+        assert_eq!(*old.val(), 5);
+        assert_eq!(*map.get("five").unwrap().val(), 6);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn replace_if_race() {
+        let map = Arc::new(Map::new());
+        map.insert(0u8, 0u64);
+
+        for round in 0 .. 200u64 {
+            let observed = *map.get(&0).unwrap().val();
+            let barrier = Arc::new(Barrier::new(2));
+            let threads = (1 .. 3u64)
+                .map(|i| {
+                    let map = map.clone();
+                    let barrier = barrier.clone();
+                    thread::spawn(move || {
+                        barrier.wait();
+                        map.replace_if(&0, round * 2 + i, |&val| {
+                            val == observed
+                        })
+                        .is_ok()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let successes = threads
+                .into_iter()
+                .map(|thread| thread.join().expect("thread failed"))
+                .filter(|&ok| ok)
+                .count();
+            assert_eq!(successes, 1);
+            map.validate();
+        }
+    }
+
+    #[test]
+    fn remove_if() {
+        let map = Map::new();
+        assert_eq!(map.remove_if("five", |_, _| true), Removal::Absent);
+
+        map.insert("five".to_owned(), 5);
+        assert_eq!(
+            map.remove_if("five", |_, &val| val == 4),
+            Removal::Rejected
+        );
+        assert_eq!(map.len(), 1);
+
+        match map.remove_if("five", |key, &val| key == "five" && val == 5) {
+            Removal::Done(removed) => assert_eq!(*removed.val(), 5),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(map.get("five").is_none());
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn remove_if_multithreaded() {
+        let map = Arc::new(Map::new());
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 4 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. 2000u64 {
+                    map.upsert(i % 8, || 0u64, |&gen| gen + 1);
+                }
+            }));
+        }
+
+        for _ in 0 .. 4 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. 2000u64 {
+                    let observed = match map.get(&(i % 8)) {
+                        Some(guard) => *guard.val(),
+                        None => continue,
+                    };
+                    let res =
+                        map.remove_if(&(i % 8), |_, &gen| gen == observed);
+                    if let Removal::Done(removed) = res {
+                        assert_eq!(*removed.val(), observed);
+                    }
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn rename_key() {
+        let map = Map::new();
+        map.insert("old".to_owned(), vec![1]);
+
+        assert_eq!(map.rename_key("old", "new".to_owned()), Ok(None));
+        assert!(map.get("old").is_none());
+        assert_eq!(map.get_cloned("new"), Some(vec![1]));
+
+        assert_eq!(map.rename_key("new", "new".to_owned()), Ok(None));
+        assert_eq!(
+            map.rename_key("old", "new".to_owned()),
+            Err(RenameErr::OldMissing)
+        );
+        assert_eq!(map.get_cloned("new"), Some(vec![1]));
+
+        // A reader keeps the removed pair from being reused.
+        map.insert("other".to_owned(), vec![2]);
+        let guard = map.get("new").unwrap();
+        let displaced = map.rename_key("new", "other".to_owned()).unwrap();
+        assert_eq!(*guard.val(), vec![1]);
+        drop(guard);
+        assert_eq!(displaced.unwrap().val(), &vec![2]);
+        assert_eq!(map.get_cloned("other"), Some(vec![1]));
+        assert_eq!(map.len(), 1);
+    }
+
+    // Races insertions, lookups and removals of a few keys, whose values are
+    // heap-allocated and tied to the key, so readers notice entries read
+    // before they were fully published or after they were freed.
+    fn insert_get_remove_race<H>(builder: H)
+    where
+        H: BuildHasher + Send + Sync + 'static,
+    {
+        const KEYS: u64 = 64;
+        const ROUNDS: u64 = 20_000;
+
+        fn value(key: u64) -> Vec<u64> {
+            vec![key; 1 + key as usize % 16]
+        }
+
+        let map = Arc::new(Map::with_hasher(builder));
+        let done = Arc::new(AtomicUsize::new(0));
+        let mut readers = Vec::new();
+        for _ in 0 .. 2 {
+            let map = map.clone();
+            let done = done.clone();
+            readers.push(thread::spawn(move || {
+                let mut key = 0;
+                while done.load(Relaxed) == 0 {
+                    if let Some(guard) = map.get(&key) {
+                        assert_eq!(*guard.val(), value(key));
+                    }
+                    key = (key + 1) % KEYS;
+                }
+            }));
+        }
+
+        let writers = (0 .. 4)
+            .map(|id| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0 .. ROUNDS {
+                        let key = (i * 7 + id) % KEYS;
+                        if (i + id) % 3 == 0 {
+                            if let Some(removed) = map.remove(&key) {
+                                assert_eq!(*removed.val(), value(key));
+                            }
+                        } else if let Some(old) = map.insert(key, value(key)) {
+                            assert_eq!(*old.val(), value(key));
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in writers {
+            thread.join().unwrap();
+        }
+        done.store(1, Relaxed);
+        for thread in readers {
+            thread.join().unwrap();
+        }
+
+        let mut len = 0;
+        for key in 0 .. KEYS {
+            if let Some(guard) = map.get(&key) {
+                assert_eq!(*guard.val(), value(key));
+                len += 1;
+            }
+        }
+        assert_eq!(map.len(), len);
+        map.validate();
+    }
+
+    #[test]
+    fn insert_get_remove_race_shallow() {
+        insert_get_remove_race(RandomState::new());
+    }
+
+    #[test]
+    fn insert_get_remove_race_deep() {
+        insert_get_remove_race(ShiftState);
+    }
+
+    #[test]
+    fn rename_key_race_lookup() {
+        const RENAMES: u64 = 5000;
+
+        let map = Arc::new(Map::new());
+        map.insert(0, vec![7u8; 8]);
+        let done = Arc::new(AtomicUsize::new(0));
+        let mut readers = Vec::new();
+
+        for _ in 0 .. 4 {
+            let map = map.clone();
+            let done = done.clone();
+            readers.push(thread::spawn(move || {
+                let mut i = 0;
+                while done.load(Relaxed) == 0 {
+                    for key in &[i, i + 1] {
+                        if let Some(guard) = map.get(key) {
+                            assert_eq!(*guard.val(), vec![7u8; 8]);
+                        }
+                    }
+                    i = (i + 1) % RENAMES;
+                }
+            }));
+        }
+
+        for i in 0 .. RENAMES {
+            assert_eq!(map.rename_key(&i, i + 1), Ok(None));
+        }
+        done.store(1, Relaxed);
+        for thread in readers {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(map.get_cloned(&RENAMES), Some(vec![7u8; 8]));
+        assert_eq!(map.len(), 1);
+        map.validate();
+    }
+
+    #[test]
+    fn rename_key_race_insert() {
+        const DEST: u64 = 0;
+        const ROUNDS: u64 = 2000;
+
+        let map = Arc::new(Map::new());
+
+        let renamer = {
+            let map = map.clone();
+            thread::spawn(move || {
+                let mut displaced = Vec::new();
+                for i in 1 .. ROUNDS + 1 {
+                    map.insert(i, i);
+                    let res = map.rename_key(&i, DEST).unwrap();
+                    displaced.extend(res.map(|removed| *removed.val()));
+                }
+                displaced
+            })
+        };
+
+        let inserter = {
+            let map = map.clone();
+            thread::spawn(move || {
+                let mut displaced = Vec::new();
+                for i in 1 .. ROUNDS + 1 {
+                    let res = map.insert(DEST, ROUNDS + i);
+                    displaced.extend(res.map(|removed| *removed.val()));
+                }
+                displaced
+            })
+        };
+
+        let mut vals = renamer.join().unwrap();
+        vals.extend(inserter.join().unwrap());
+        vals.push(map.get_cloned(&DEST).unwrap());
+        vals.sort();
+        assert_eq!(vals, (1 .. 2 * ROUNDS + 1).collect::<Vec<_>>());
+        assert_eq!(map.len(), 1);
+        map.validate();
+    }
+
+    #[test]
+    fn swap_values() {
+        let map = Map::new();
+        map.insert("five".to_owned(), 5);
+        map.insert("four".to_owned(), 4);
+
+        assert_eq!(map.swap_values("five", "four"), Ok(()));
+        assert_eq!(map.get_cloned("five"), Some(4));
+        assert_eq!(map.get_cloned("four"), Some(5));
+
+        assert_eq!(map.swap_values("five", "five"), Ok(()));
+        assert_eq!(map.get_cloned("five"), Some(4));
+
+        // A reader keeps the removed pairs from being reused.
+        let guard = map.get("four").unwrap();
+        assert_eq!(map.swap_values("four", "five"), Ok(()));
+        assert_eq!(*guard.val(), 5);
+        drop(guard);
+        assert_eq!(map.get_cloned("five"), Some(5));
+        assert_eq!(map.get_cloned("four"), Some(4));
+
+        assert_eq!(map.swap_values("six", "five"), Err(SwapErr::FirstMissing));
+        assert_eq!(map.swap_values("five", "six"), Err(SwapErr::SecondMissing));
+        assert_eq!(map.swap_values("six", "six"), Err(SwapErr::BothMissing));
+        assert_eq!(map.swap_values("six", "ten"), Err(SwapErr::BothMissing));
+        assert_eq!(map.get_cloned("five"), Some(5));
+        assert_eq!(map.get_cloned("four"), Some(4));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn swap_values_multithreaded() {
+        const KEYS: u64 = 16;
+
+        let map = Arc::new(Map::new());
+        for i in 0 .. KEYS {
+            map.insert(i, vec![i]);
+        }
+        let done = Arc::new(AtomicUsize::new(0));
+        let mut swappers = Vec::new();
+        let mut readers = Vec::new();
+
+        for t in 0 .. 4 {
+            let map = map.clone();
+            swappers.push(thread::spawn(move || {
+                for i in 0 .. 2000 {
+                    let first = (t * 3 + i) % KEYS;
+                    let second = (first + 1 + i % 5) % KEYS;
+                    let _ = map.swap_values(&first, &second);
+                }
+            }));
+        }
+
+        for _ in 0 .. 4 {
+            let map = map.clone();
+            let done = done.clone();
+            readers.push(thread::spawn(move || {
+                while done.load(Relaxed) == 0 {
+                    for i in 0 .. KEYS {
+                        if let Some(val) = map.get_cloned(&i) {
+                            assert_eq!(val.len(), 1);
+                            assert!(val[0] < KEYS);
+                        }
+                    }
+                }
+            }));
+        }
+
+        for thread in swappers {
+            thread.join().unwrap();
+        }
+        done.store(1, Relaxed);
+        for thread in readers {
+            thread.join().unwrap();
+        }
+
+        let mut vals = map.values_cloned();
+        vals.sort();
+        assert_eq!(vals, (0 .. KEYS).map(|i| vec![i]).collect::<Vec<_>>());
+        assert_eq!(map.len(), KEYS as usize);
+        map.validate();
+    }
+
+    #[test]
+    fn try_insert() {
+        let map = Map::new();
+        assert_eq!(map.try_insert("five", 5), Ok(()));
+        assert_eq!(map.try_insert("five", 6), Err(("five", 6)));
+        assert_eq!(*map.get("five").unwrap().val(), 5);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn try_insert_race() {
+        let map = Arc::new(Map::new());
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(16));
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 16 {
+            let map = map.clone();
+            let dropped = dropped.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                map.try_insert(0u8, DropCounter(dropped)).is_ok()
+            }));
+        }
+
+        let successes = threads
+            .into_iter()
+            .map(|thread| thread.join().expect("thread failed"))
+            .filter(|&ok| ok)
+            .count();
+        assert_eq!(successes, 1);
+        assert_eq!(dropped.load(Relaxed), 15);
+        map.validate();
+        drop(Arc::try_unwrap(map).unwrap());
+        assert_eq!(dropped.load(Relaxed), 16);
+    }
+
+    #[test]
+    fn removed_try_unwrap() {
+        let map = Map::new();
+        map.insert("five".to_owned(), vec![5]);
+        let guard = map.get("five").unwrap();
+        let removed = map.remove("five").unwrap();
+        let removed = Removed::try_unwrap(removed).unwrap_err();
+        drop(guard);
+        assert_eq!(
+            Removed::try_unwrap(removed).unwrap(),
+            ("five".to_owned(), vec![5])
+        );
+    }
+
+    #[test]
+    fn removed_try_unwrap_multithreaded() {
+        let map = Arc::new(Map::<u64, Vec<u64>>::new());
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 4 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. 4000u64 {
+                    if let Some(guard) = map.get(&(i % 16)) {
+                        assert_eq!(guard.val()[0], *guard.key());
+                    }
+                }
+            }));
+        }
+
+        for _ in 0 .. 4 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. 4000u64 {
+                    map.insert(i % 16, vec![i % 16]);
+                    if let Some(removed) = map.remove(&(i % 16)) {
+                        if let Ok((key, val)) = Removed::try_unwrap(removed) {
+                            assert_eq!(val, vec![key]);
+                        }
+                    }
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        map.validate();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn removed_into_inner() {
+        let map = Arc::new(Map::new());
+        for i in 0 .. 200u64 {
+            map.insert(i, vec![i]);
+        }
+
+        let done = Arc::new(AtomicUsize::new(0));
+        let reader = {
+            let map = map.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut i = 0;
+                while done.load(Relaxed) == 0 {
+                    if let Some(guard) = map.get(&(i % 200)) {
+                        thread::sleep(Duration::from_millis(2));
+                        assert_eq!(guard.val()[0], *guard.key());
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                    i += 1;
+                }
+            })
+        };
+
+        for i in 0 .. 200u64 {
+            let removed = map.remove(&i).unwrap();
+            assert_eq!(Removed::into_inner(removed), (i, vec![i]));
+        }
+
+        done.store(1, Relaxed);
+        reader.join().expect("reader failed");
+        map.validate();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    #[should_panic]
+    fn removed_into_inner_paused_locally() {
+        let map = Map::new();
+        map.insert(1, 2);
+        map.insert(3, 4);
+        let _guard = map.get(&3).unwrap();
+        let removed = map.remove(&1).unwrap();
+        Removed::into_inner(removed);
+    }
+
+    #[test]
+    fn removed_sent_to_another_thread() {
+        const KEYS: u64 = 1000;
+
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let map = Arc::new(Map::new());
+        for i in 0 .. KEYS {
+            map.insert(i, (vec![i], DropCounter(dropped.clone())));
+        }
+
+        let done = Arc::new(AtomicUsize::new(0));
+        let reader = {
+            let map = map.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut i = 0;
+                while done.load(Relaxed) == 0 {
+                    if let Some(guard) = map.get(&(i % KEYS)) {
+                        assert_eq!(guard.val().0, vec![*guard.key()]);
+                    }
+                    i += 1;
+                }
+            })
+        };
+
+        let (sender, receiver) =
+            mpsc::channel::<Removed<u64, (Vec<u64>, DropCounter)>>();
+        let worker = thread::spawn(move || {
+            let mut sum = 0;
+            for removed in receiver {
+                assert_eq!(removed.val().0, vec![*removed.key()]);
+                sum += removed.key();
+            }
+            sum
+        });
+
+        // The first entry is still read by this thread while the worker
+        // reads and drops it.
+        let guard = map.get(&0).unwrap();
+        for i in 0 .. KEYS {
+            sender.send(map.remove(&i).unwrap()).unwrap();
+        }
+        drop(sender);
+        assert_eq!(worker.join().unwrap(), KEYS * (KEYS - 1) / 2);
+        assert_eq!(guard.val().0, vec![0]);
+        drop(guard);
+
+        done.store(1, Relaxed);
+        reader.join().expect("reader failed");
+        assert!(map.is_empty());
+        assert!(dropped.load(Relaxed) <= KEYS as usize);
+        drop(Arc::try_unwrap(map).unwrap());
+        assert_eq!(dropped.load(Relaxed), KEYS as usize);
+    }
+
+    #[test]
+    fn removed_clone() {
+        let map = Map::new();
+        let other = Map::new();
+        map.insert("five".to_owned(), vec![5]);
+        let _guard = map.get("five").unwrap();
+
+        let removed = map.remove("five").unwrap();
+        let cloned = removed.clone();
+        assert_eq!(removed.to_pair(), ("five".to_owned(), vec![5]));
+        assert_eq!(<(String, Vec<u32>)>::from(&removed), removed.to_pair());
+        drop(removed);
+
+        // The clone does not depend on the paused incinerator.
+        assert!(other.reinsert(cloned.clone()).created());
+        assert_eq!(Removed::try_unwrap(cloned).unwrap().1, vec![5]);
+        assert_eq!(*other.get("five").unwrap().val(), vec![5]);
+    }
+
+    #[test]
+    fn pop_any() {
+        let map = Map::new();
+        assert!(map.pop_any().is_none());
+        map.insert("five", 5);
+        map.insert("four", 4);
+        let mut popped = vec![
+            Removed::try_unwrap(map.pop_any().unwrap()).unwrap(),
+            Removed::try_unwrap(map.pop_any().unwrap()).unwrap(),
+        ];
+        popped.sort();
+        assert_eq!(popped, vec![("five", 5), ("four", 4)]);
+        assert!(map.pop_any().is_none());
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn pop_any_multithreaded() {
+        let map =
+            Arc::new((0 .. 100000u64).map(|i| (i, ())).collect::<Map<_, _>>());
+        let mut threads = Vec::new();
+
+        for _ in 0 .. 8 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                let mut popped = Vec::new();
+                while let Some(removed) = map.pop_any() {
+                    popped.push(*removed.key());
+                }
+                popped
+            }));
+        }
+
+        let mut popped = Vec::new();
+        for thread in threads {
+            popped.extend(thread.join().expect("thread failed"));
+        }
+        popped.sort();
+        assert_eq!(popped, (0 .. 100000).collect::<Vec<_>>());
+        assert_eq!(map.len(), 0);
+        map.validate();
+    }
+
+    #[test]
+    fn get_any() {
+        let map = Map::new();
+        assert_eq!(map.get_any(|&key, &val| (key, val)), None);
+        map.insert(5, 50);
+        assert_eq!(map.get_any(|&key, &val| (key, val)), Some((5, 50)));
+        map.remove(&5);
+        assert_eq!(map.get_any(|&key, &val| (key, val)), None);
+    }
+
+    #[test]
+    fn get_any_multithreaded() {
+        let map = Arc::new(Map::new());
+        map.insert(0u64, 0u64);
+
+        let mutator = {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in 1 .. 5000u64 {
+                    map.insert(i, i * 3);
+                    map.remove(&(i - 1));
+                }
+            })
+        };
+
+        for _ in 0 .. 5000 {
+            if let Some((key, val)) = map.get_any(|&key, &val| (key, val)) {
+                assert_eq!(val, key * 3);
+            }
+        }
+
+        mutator.join().expect("mutator failed");
+        assert_eq!(map.get_any(|&key, &val| (key, val)), Some((4999, 14997)));
+        map.validate();
+    }
+
+    #[test]
+    fn insert_all() {
+        let map = Map::new();
+        map.insert(3u64, 0u64);
+        map.insert(150, 0);
+        let mut replaced = map
+            .insert_all((0 .. 200u64).map(|i| (i, i * 2)))
+            .into_iter()
+            .map(|removed| *removed.key())
+            .collect::<Vec<_>>();
+        replaced.sort();
+        assert_eq!(replaced, vec![3, 150]);
+        assert_eq!(map.len(), 200);
+        for i in 0 .. 200u64 {
+            assert_eq!(*map.get(&i).unwrap().val(), i * 2);
+        }
+    }
+
+    #[test]
+    fn get_many() {
+        let map = (0 .. 300u32).map(|i| (i * 2, i)).collect::<Map<_, _>>();
+        let keys = (0 .. 600u32).rev().collect::<Vec<_>>();
+        let found = map.get_many(&keys, |&key, &val| (key, val));
+        assert_eq!(found.len(), 600);
+        for (&key, found) in keys.iter().zip(found) {
+            if key % 2 == 0 {
+                assert_eq!(found, Some((key, key / 2)));
+            } else {
+                assert_eq!(found, None);
+            }
+        }
+        assert!(map.get_many(&[], |_, &val| val).is_empty());
+    }
+
+    #[test]
+    fn remove_many_concurrent() {
+        const KEYS: u64 = 2000;
+        const THREADS: u64 = 4;
+
+        let map =
+            Arc::new((0 .. KEYS).map(|i| (i * 2, i)).collect::<Map<_, _>>());
+        let barrier = Arc::new(Barrier::new(THREADS as usize + 1));
+        let mut threads = Vec::new();
+
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for i in 0 .. KEYS {
+                    map.insert(KEYS * 2 * (t + 1) + i, i);
+                }
+            }));
+        }
+
+        barrier.wait();
+        let keys = (0 .. KEYS * 2).collect::<Vec<_>>();
+        let removed = map.remove_many(&keys);
+        assert_eq!(removed.len(), keys.len());
+        for (&key, removed) in keys.iter().zip(removed) {
+            if key % 2 == 0 {
+                let removed = removed.unwrap();
+                assert_eq!(*removed.key(), key);
+                assert_eq!(*removed.val(), key / 2);
+            } else {
+                assert!(removed.is_none());
+            }
+        }
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(map.len(), (KEYS * THREADS) as usize);
+        for &key in &keys {
+            assert!(map.get(&key).is_none());
+        }
+        assert!(map.remove_many(&keys[.. 10]).iter().all(Option::is_none));
+        map.validate();
+    }
+
+    #[test]
+    fn keys_match_reference() {
+        let map = Map::new();
+        let mut reference = HashSet::new();
+        for i in 0 .. 3000u64 {
+            let key = i.wrapping_mul(0x9E3779B97F4A7C15) % 1000;
+            if i % 3 == 0 {
+                map.remove(&key);
+                reference.remove(&key);
+            } else {
+                map.insert(key, i);
+                reference.insert(key);
+            }
+        }
+
+        let keys = map.keys_cloned();
+        assert_eq!(keys.len(), reference.len());
+        assert_eq!(keys.into_iter().collect::<HashSet<_>>(), reference);
+
+        let mut visited = HashSet::new();
+        map.keys(|&key| assert!(visited.insert(key)));
+        assert_eq!(visited, reference);
+    }
+
+    #[test]
+    fn keys_concurrent_no_duplicates() {
+        const STABLE: u64 = 1000;
+        const THREADS: u64 = 4;
+
+        let map =
+            Arc::new((0 .. STABLE).map(|i| (i, i)).collect::<Map<_, _>>());
+        let barrier = Arc::new(Barrier::new(THREADS as usize + 1));
+        let mut threads = Vec::new();
+
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for i in 0 .. 2000 {
+                    let key = STABLE * (t + 1) + i % 500;
+                    if i % 2 == 0 {
+                        map.insert(key, i);
+                    } else {
+                        map.remove(&key);
+                    }
+                    map.insert(i % STABLE, i);
+                }
+            }));
+        }
+
+        barrier.wait();
+        for _ in 0 .. 10 {
+            let mut counts = HashMap::new();
+            map.keys(|&key| *counts.entry(key).or_insert(0) += 1);
+            for key in 0 .. STABLE {
+                assert_eq!(counts.get(&key), Some(&1));
+            }
+            assert!(counts.values().all(|&count| count == 1));
+        }
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn values_sum() {
+        let map = (0 .. 500u64).map(|i| (i, i * 3)).collect::<Map<_, _>>();
+        let mut sum = 0;
+        map.values(|&val| sum += val);
+        assert_eq!(sum, (0 .. 500).map(|i| i * 3).sum::<u64>());
+
+        let mut vals = map.values_cloned();
+        vals.sort();
+        assert_eq!(vals, (0 .. 500).map(|i| i * 3).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn values_concurrent_removals() {
+        const KEYS: usize = 2000;
+        const THREADS: usize = 4;
+
+        let map = Arc::new(Map::new());
+        for i in 0 .. KEYS {
+            map.insert(i, vec![i; 4]);
+        }
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+        let mut threads = Vec::new();
+
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for i in (t .. KEYS).step_by(THREADS) {
+                    map.remove(&i);
+                    map.insert(KEYS + i, vec![i; 4]);
+                    map.remove(&(KEYS + i));
+                }
+            }));
+        }
+
+        barrier.wait();
+        while !map.is_empty() {
+            map.values(|val| {
+                assert_eq!(val.len(), 4);
+                assert!(val.iter().all(|&elem| elem == val[0]));
+            });
+            for val in map.values_cloned() {
+                assert_eq!(val.len(), 4);
+            }
+        }
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(map.values_cloned().is_empty());
+        map.validate();
+    }
+
+    #[test]
+    fn create() {
+        let map = Map::new();
+        assert!(map
+            .insert_with(
+                "five".to_owned(),
+                |_, _, stored| if stored.is_none() {
+                    Preview::New(5)
+                } else {
+                    Preview::Discard
+                }
+            )
+            .created());
+        assert_eq!(*map.get("five").unwrap().val(), 5);
+        assert!(map
+            .insert_with(
+                "five".to_owned(),
+                |_, _, stored| if stored.is_none() {
+                    Preview::New(500)
+                } else {
+                    Preview::Discard
+                }
+            )
+            .failed()
+            .is_some());
+    }
+
+    #[test]
+    fn update() {
+        let map = Map::new();
+        assert!(map
+            .insert_with("five".to_owned(), |_, _, stored| {
+                if let Some((_, n)) = stored {
+                    Preview::New(*n + 6)
+                } else {
+                    Preview::Discard
+                }
+            })
+            .failed()
+            .is_some());
+        assert!(map.insert("five".to_owned(), 5).is_none());
+        let guard = map
+            .insert_with("five".to_owned(), |_, _, stored| {
+                if let Some((_, n)) = stored {
+                    Preview::New(*n + 7)
+                } else {
+                    Preview::Discard
+                }
+            })
+            .take_updated()
+            .unwrap();
+        assert_eq!(guard.key(), "five");
+        assert_eq!(*guard.val(), 5);
+        assert_eq!(*map.get("five").unwrap().val(), 12);
+    }
+
+    #[test]
+    fn never_inserts() {
+        let map = Map::new();
+        assert!(map
+            .insert_with("five".to_owned(), |_, _, _| Preview::Discard)
+            .failed()
+            .is_some());
+        assert!(map.insert("five".to_owned(), 5).is_none());
+        assert!(map
+            .insert_with("five".to_owned(), |_, _, _| Preview::Discard)
+            .failed()
+            .is_some());
+    }
+
+    #[test]
+    fn inserts_reinserts() {
+        let map = Map::new();
+        assert!(map.insert("four".to_owned(), 4).is_none());
+        let prev = map.insert("four".to_owned(), 40).unwrap();
+        assert_eq!(prev.key(), "four");
+        assert_eq!(*prev.val(), 4);
+        let prev = map.reinsert(prev).take_updated().unwrap();
+        assert_eq!(prev.key(), "four");
+        assert_eq!(*prev.val(), 40);
+        assert!(*map.get("four").unwrap().val() == 4);
+    }
+
+    #[test]
+    fn never_reinserts() {
+        let map = Map::new();
+        map.insert("five".to_owned(), 5);
+        let prev = map.remove("five").unwrap();
+        let prev = map.reinsert_with(prev, |_, _| false).take_failed().unwrap();
+        assert!(map.insert("five".to_owned(), 5).is_none());
+        map.reinsert_with(prev, |_, _| false).take_failed().unwrap();
+    }
+
+    #[test]
+    fn reinserts_create() {
+        let map = Map::new();
+        map.insert("five".to_owned(), 5);
+        let first = map.remove("five").unwrap();
+        map.insert("five".to_owned(), 5);
+        let second = map.remove("five").unwrap();
+        assert!(map
+            .reinsert_with(first, |_, stored| stored.is_none())
+            .created());
+        assert_eq!(*map.get("five").unwrap().val(), 5);
+        assert!(map
+            .reinsert_with(second, |_, stored| stored.is_none())
+            .failed()
+            .is_some());
+    }
+
+    #[test]
+    fn reinserts_update() {
+        let map = Map::new();
+        map.insert("five".to_owned(), 5);
+        let prev = map.remove("five").unwrap();
+        let prev = map
+            .reinsert_with(prev, |_, stored| stored.is_some())
+            .take_failed()
+            .unwrap();
+        map.insert("five".to_owned(), 5);
+        assert!(map
+            .reinsert_with(prev, |_, stored| stored.is_some())
+            .updated()
+            .is_some());
+    }
+
+    #[test]
+    fn inserts_and_removes() {
+        let map = Map::new();
+        assert!(map.remove("five").is_none());
+        assert!(map.remove("four").is_none());
+        map.insert("five".to_owned(), 5);
+        let removed = map.remove("five").unwrap();
+        assert_eq!(removed.key(), "five");
+        assert_eq!(*removed.val(), 5);
+        assert!(map.insert("four".to_owned(), 4).is_none());
+        map.insert("three".to_owned(), 3);
+        assert!(map.remove("two").is_none());
+        map.insert("two".to_owned(), 2);
+        let removed = map.remove("three").unwrap();
+        assert_eq!(removed.key(), "three");
+        assert_eq!(*removed.val(), 3);
+        let removed = map.remove("two").unwrap();
+        assert_eq!(removed.key(), "two");
+        assert_eq!(*removed.val(), 2);
+        let removed = map.remove("four").unwrap();
+        assert_eq!(removed.key(), "four");
+        assert_eq!(*removed.val(), 4);
+    }
+
+    #[test]
+    fn repeated_inserts() {
+        let map = Map::new();
+        assert!(map.insert("five".to_owned(), 5).is_none());
+        assert!(*map.insert("five".to_owned(), 5).unwrap().val() == 5);
+    }
+
+    #[test]
+    fn reinsert_from_other_map_fails() {
+        let other = Map::new();
+        other.insert(5, 3);
+        other.insert(0, 0);
+        let removed = other.remove(&5).unwrap();
+        let _active_read = other.get(&0).unwrap();
+        let map = Map::new();
+        map.reinsert(removed).failed().unwrap();
+    }
+
+    #[test]
+    fn reinsert_reuses_hash() {
+        let builder = CountState::default();
+        let hashes = builder.0.clone();
+        let map = Map::with_hasher(builder);
+        for i in 0 .. 100 {
+            map.insert(i, i);
+        }
+
+        let before = hashes.load(Relaxed);
+        let removed = (0 .. 50).map(|i| map.remove(&i).unwrap());
+        let removed = removed.collect::<Vec<_>>();
+        let updated = map.insert(50, 500).unwrap();
+        let mut drained = map.drain();
+        let hashed = hashes.load(Relaxed);
+        assert!(hashed > before);
+
+        for removed in removed {
+            assert!(map.reinsert(removed).created());
+        }
+        assert!(map.reinsert(updated).created());
+        drained.retain(|removed| *removed.key() != 50);
+        let (first, second) = map.partition(|&key, _| key < 25);
+        for removed in drained {
+            assert!(first.reinsert(removed).created());
+        }
+        assert_eq!(hashes.load(Relaxed), hashed);
+        for i in 0 .. 100 {
+            let expected = if i == 50 { 50 } else { i };
+            let found = first.get(&i).or_else(|| second.get(&i)).unwrap();
+            assert_eq!(*found.val(), expected);
+        }
+
+        let mut removed = first.remove(&0).unwrap();
+        Removed::try_as_mut(&mut removed).unwrap().0 = 100;
+        assert!(first.reinsert(removed).created());
+        assert!(hashes.load(Relaxed) > hashed);
+        assert!(first.get(&0).is_none());
+        assert_eq!(*first.get(&100).unwrap().val(), 0);
+        first.validate();
+        second.validate();
+    }
+
+    #[test]
+    fn reinsert_between_hashers() {
+        let source = Map::new();
+        let target = Map::new();
+        let other = Map::with_hasher(ShiftState);
+        for i in 0 .. 1000u32 {
+            source.insert(i.to_string(), i);
+            other.insert((i + 1000).to_string(), i + 1000);
+        }
+
+        let merged = target.merge(&source);
+        assert!(merged.displaced.is_empty() && merged.unmoved.is_empty());
+        for removed in other.drain() {
+            assert!(target.reinsert(removed).created());
+        }
+        assert_eq!(target.len(), 2000);
+        for i in 0 .. 2000u32 {
+            assert_eq!(*target.get(&i.to_string()).unwrap().val(), i);
+        }
+        target.validate();
+    }
+
+    #[test]
+    fn iter_valid_items() {
+        let map = Map::new();
+        for i in 0 .. 10u128 {
+            for j in 0 .. 32 {
+                map.insert((i, j), i << j);
+            }
+        }
+
+        let mut result = HashMap::new();
+        for guard in &map {
+            let (k, v) = *guard;
+            let in_place = result.get(&(k, v)).map_or(0, |&x| x);
+            result.insert((k, v), in_place + 1);
+        }
+
+        for i in 0 .. 10 {
+            for j in 0 .. 32 {
+                let pair = ((i, j), i << j);
+                assert_eq!(*result.get(&pair).unwrap(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn optimize_space_preserves_entries() {
+        let mut map = Map::new();
+        for i in 0 .. 200u128 {
+            for j in 0 .. 128 {
+                map.insert((i, j), i << j);
+            }
+        }
+
+        for i in 0 .. 200 {
+            for j in 0 .. 16 {
+                map.remove(&(i, j));
+            }
+        }
+
+        map.optimize_space();
+
+        let mut result = HashMap::new();
+        for guard in &map {
+            let (k, v) = *guard;
+            let in_place = result.get(&(k, v)).map_or(0, |&x| x);
+            result.insert((k, v), in_place + 1);
+        }
+
+        for i in 0 .. 200 {
+            for j in 16 .. 128 {
+                let pair = ((i, j), i << j);
+                assert_eq!(*result.get(&pair).unwrap(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn for_each_mut_deep_and_colliding() {
+        const THREADS: u64 = 4;
+
+        // The hashes of `deep` are equal in their lowest 40 bits, so entries
+        // are spread through deep sub-tables, while every entry of
+        // `colliding` is in a single long bucket.
+        let mut deep = Map::with_hasher(ShiftState);
+        let mut colliding = Map::with_hasher(ConstState);
+        for key in 0 .. 800u64 {
+            deep.insert_mut(key, key);
+            colliding.insert(key, key);
+        }
+        for key in (0 .. 800).step_by(3) {
+            colliding.remove(&key);
+        }
+        assert!(deep.stats().max_depth > 1);
+
+        let mut visited = 0;
+        for (_, val) in &mut deep {
+            *val *= 2;
+            visited += 1;
+        }
+        assert_eq!(visited, 800);
+        colliding.for_each_mut(|&key, val| {
+            assert_eq!(*val, key);
+            *val += 1;
+        });
+
+        let deep = Arc::new(deep);
+        let colliding = Arc::new(colliding);
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let deep = deep.clone();
+            let colliding = colliding.clone();
+            threads.push(thread::spawn(move || {
+                for key in (t .. 800).step_by(THREADS as usize) {
+                    let val = deep.get(&key).unwrap();
+                    assert_eq!(*val.val(), key * 2);
+                    match colliding.get(&key) {
+                        Some(val) => assert_eq!(*val.val(), key + 1),
+                        None => assert_eq!(key % 3, 0),
+                    }
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        deep.validate();
+        colliding.validate();
+    }
+
+    #[test]
+    fn iter_mut_and_into_iter() {
+        let mut map = Map::new();
+        for i in 0 .. 10u128 {
+            for j in 0 .. 32 {
+                map.insert((i, j), i << j);
+            }
+        }
+
+        let mut result = HashMap::new();
+        for (k, v) in &mut map {
+            let in_place = result.get(&(*k, *v)).map_or(0, |&x| x);
+            result.insert((*k, *v), in_place + 1);
+            *v += 1;
+        }
+
+        for i in 0 .. 10 {
+            for j in 0 .. 32 {
+                let pair = ((i, j), i << j);
+                assert_eq!(*result.get(&pair).unwrap(), 1);
+            }
+        }
+
+        result.clear();
+
+        for (k, v) in map {
+            let in_place = result.get(&(k, v)).map_or(0, |&x| x);
+            result.insert((k, v), in_place + 1);
+        }
+
+        for i in 0 .. 10 {
+            for j in 0 .. 32 {
+                let pair = ((i, j), (i << j) + 1);
+                assert_eq!(*result.get(&pair).unwrap(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn into_iter_collect() {
+        let map = Map::with_hasher(ShiftState);
+        for i in 0 .. 1000u64 {
+            map.insert(i, vec![i; 2]);
+        }
+        map.remove(&500);
+
+        let mut pairs = map.into_iter().collect::<Vec<_>>();
+        pairs.sort();
+        assert_eq!(pairs.len(), 999);
+        for (pair, i) in
+            pairs.into_iter().zip((0 .. 1000).filter(|&i| i != 500))
+        {
+            assert_eq!(pair, (i, vec![i; 2]));
+        }
+    }
+
+    #[test]
+    fn into_iter_dropped_halfway() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let map = Map::with_hasher(ShiftState);
+        for i in 0 .. 1000u64 {
+            map.insert(i, DropCounter(dropped.clone()));
+        }
+
+        let mut iter = map.into_iter();
+        let taken = iter.by_ref().take(400).collect::<Vec<_>>();
+        assert_eq!(taken.len(), 400);
+        assert_eq!(dropped.load(Relaxed), 0);
+
+        drop(iter);
+        assert_eq!(dropped.load(Relaxed), 600);
+        drop(taken);
+        assert_eq!(dropped.load(Relaxed), 1000);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn hash_map_round_trip() {
+        #[derive(Debug, Clone, PartialEq)]
+        struct SeedState(u64);
+
+        impl BuildHasher for SeedState {
+            type Hasher = DefaultHasher;
+
+            fn build_hasher(&self) -> DefaultHasher {
+                let mut hasher = DefaultHasher::new();
+                hasher.write_u64(self.0);
+                hasher
+            }
+        }
+
+        let mut source = HashMap::with_hasher(SeedState(42));
+        for i in 0 .. 4000u64 {
+            source.insert(i, i.to_string());
+        }
+
+        let map = Map::from(source.clone());
+        assert_eq!(map.hasher(), &SeedState(42));
+        assert_eq!(map.len(), 4000);
+        for i in 0 .. 4000u64 {
+            assert_eq!(*map.get(&i).unwrap().val(), i.to_string());
+        }
+
+        map.remove(&7);
+        source.remove(&7);
+        let back = map.into_hash_map();
+        assert_eq!(back.hasher(), &SeedState(42));
+        assert_eq!(back, source);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn hash_map_round_trip_deep() {
+        let source =
+            (0 .. 3000u32).map(|i| (i, vec![i])).collect::<HashMap<_, _, _>>();
+        let map = Map::<_, _, ShiftState>::from_iter_with_hasher(
+            source.clone(),
+            ShiftState,
+        );
+        assert_eq!(
+            map.into_hash_map().into_iter().collect::<HashMap<_, _>>(),
+            source
+        );
+    }
+
+    fn assert_top_empty<K, V, H>(map: &Map<K, V, H>) {
+        let top = unsafe { &*map.top.load(Acquire) };
+        for i in 0 .. 1 << 8 {
+            assert!(top.load_index(i, Relaxed).unwrap().is_null());
+        }
+    }
+
+    #[test]
+    fn shrink_after_removals() {
+        const KEYS: u64 = 1_000_000;
+
+        // Hashes are the keys shifted left by 40 bits, so these keys only
+        // use the highest 20 bits of the hash.
+        let keys = (0 .. KEYS).map(|i| i << 4).collect::<Vec<_>>();
+        let map = Map::with_hasher(ShiftState);
+        map.extend(keys.iter().map(|&key| (key, key)));
+        assert_eq!(map.shrink(), 0);
+
+        let removed = map.remove_many(&keys);
+        assert!(removed.iter().all(Option::is_some));
+        drop(removed);
+        assert!(map.is_empty());
+
+        // Five tables for the zeroed bits, 16 tables for the next four bits
+        // of the keys, and 16 * 256 tables for the following eight bits.
+        assert_eq!(map.shrink(), 5 + 16 + 16 * 256);
+        assert_eq!(map.shrink(), 0);
+        assert_top_empty(&map);
+
+        map.insert(7, 7);
+        assert_eq!(*map.get(&7).unwrap().val(), 7);
+    }
+
+    #[test]
+    fn shrink_racing_insert() {
+        const THREADS: u64 = 4;
+        const KEYS: u64 = 500;
+
+        let map = Arc::new(Map::with_hasher(ShiftState));
+        let barrier = Arc::new(Barrier::new(THREADS as usize + 1));
+        let mut threads = Vec::new();
+
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for round in 0 .. 20 {
+                    for i in 0 .. KEYS {
+                        map.insert(i * THREADS + t, round);
+                    }
+                    if round < 19 {
+                        for i in 0 .. KEYS {
+                            map.remove(&(i * THREADS + t));
+                        }
+                    }
+                }
+            }));
+        }
+
+        barrier.wait();
+        // Threads drop their handles when they are done.
+        while Arc::strong_count(&map) > 1 {
+            map.shrink();
+            let mut count = 0;
+            map.for_each(|_, _| count += 1);
+            assert!(count <= (THREADS * KEYS) as usize);
+        }
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(map.len(), (THREADS * KEYS) as usize);
+        for key in 0 .. THREADS * KEYS {
+            assert_eq!(*map.get(&key).unwrap().val(), 19);
+        }
+        assert_eq!(map.shrink(), 0);
+        map.validate();
+    }
+
+    // Counts the tables on the way to the node of the given hash.
+    fn tables_on_path<K, V, H>(map: &Map<K, V, H>, hash: u64) -> usize {
+        let mut table: &Table<K, V> = unsafe { &*map.top.load(Acquire) };
+        let mut shifted = hash;
+        let mut count = 1;
+
+        loop {
+            let loaded = table.load_index(shifted as usize & 0xff, Acquire);
+            let loaded = loaded.unwrap() as usize;
+            if loaded & 1 == 0 || loaded == 1 {
+                break count;
+            }
+            table = unsafe { &*((loaded & !1) as *const Table<K, V>) };
+            shifted >>= 8;
+            count += 1;
+        }
+    }
+
+    #[test]
+    fn compact_promotes_single_bucket() {
+        let map = Map::with_hasher(ShiftState);
+        // All of them share the lowest 40 bits of the hash, and the first two
+        // also share the next 8 bits.
+        for &key in &[0u64, 256, 1] {
+            map.insert(key, key);
+        }
+        assert_eq!(tables_on_path(&map, 0), 7);
+        assert_eq!(tables_on_path(&map, 1 << 40), 6);
+        assert_eq!(map.compact(), 0);
+
+        map.remove(&256);
+        assert_eq!(map.shrink(), 0);
+        assert_eq!(map.compact(), 1);
+        assert_eq!(tables_on_path(&map, 0), 6);
+        assert_eq!(*map.get(&0).unwrap().val(), 0);
+        assert_eq!(*map.get(&1).unwrap().val(), 1);
+
+        map.remove(&1);
+        assert_eq!(map.compact(), 5);
+        assert_eq!(tables_on_path(&map, 0), 1);
+        assert_eq!(*map.get(&0).unwrap().val(), 0);
+        assert_eq!(map.keys_cloned(), vec![0]);
+
+        map.insert(256, 256);
+        assert_eq!(tables_on_path(&map, 0), 7);
+        assert_eq!(*map.get(&256).unwrap().val(), 256);
+    }
+
+    #[test]
+    fn compact_concurrent() {
+        const STABLE: u64 = 200;
+        const THREADS: u64 = 3;
+
+        let map = Arc::new(Map::with_hasher(ShiftState));
+        for key in 0 .. STABLE {
+            map.insert(key, key);
+        }
+        let barrier = Arc::new(Barrier::new(THREADS as usize + 2));
+        let mut threads = Vec::new();
+
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for round in 0 .. 30 {
+                    // Colliding with stable keys in the lowest 48 bits.
+                    for key in 0 .. STABLE {
+                        map.insert(key + 256 * (t + 1), round);
+                    }
+                    for key in 0 .. STABLE {
+                        map.remove(&(key + 256 * (t + 1)));
+                    }
+                }
+            }));
+        }
+
+        let compactor = {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                while Arc::strong_count(&map) > 2 {
+                    map.compact();
+                }
+            })
+        };
+
+        barrier.wait();
+        while Arc::strong_count(&map) > 2 {
+            let mut counts = HashMap::new();
+            map.keys(|&key| *counts.entry(key).or_insert(0) += 1);
+            for key in 0 .. STABLE {
+                assert_eq!(counts.get(&key), Some(&1));
+                assert_eq!(*map.get(&key).unwrap().val(), key);
+            }
+        }
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        compactor.join().unwrap();
+
+        map.compact();
+        assert_eq!(map.len(), STABLE as usize);
+        for key in 0 .. STABLE {
+            assert_eq!(*map.get(&key).unwrap().val(), key);
+            assert_eq!(tables_on_path(&map, key << 40), 6);
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn with_capacity() {
+        let map = Map::<u32, u32>::with_capacity(0);
+        assert_eq!((map.stats().tables, map.stats().max_depth), (1, 1));
+
+        // 100000 entries need a second level of 256 nodes per node, and the
+        // third one would hold far fewer than two entries per node.
+        let map = Map::with_capacity(100_000);
+        let stats = map.stats();
+        assert_eq!((stats.tables, stats.max_depth), (1 + 256, 2));
+        assert_eq!(stats.entries, 0);
+        map.validate();
+
+        for i in 0 .. 100_000u32 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 100_000);
+        for i in 0 .. 100_000u32 {
+            assert_eq!(map.get(&i).map(|guard| *guard.val()), Some(i));
+        }
+        map.validate();
+
+        let map = Map::<u32, u32>::with_capacity(100_000);
+        assert_eq!(map.shrink(), 256);
+        assert_eq!(map.stats().tables, 1);
+    }
+
+    #[test]
+    fn reserve() {
+        let map = Map::new();
+        for i in 0 .. 1000u32 {
+            map.insert(i, i);
+        }
+        map.reserve(10_000_000);
+        let stats = map.stats();
+        assert!(stats.max_depth >= 3);
+        assert!(stats.tables > 256 * 200);
+        assert_eq!(stats.entries, 1000);
+        map.validate();
+        for i in 0 .. 1000u32 {
+            assert_eq!(map.get(&i).map(|guard| *guard.val()), Some(i));
+        }
+
+        let compact = Map::new_compact();
+        compact.insert(1u32, 1u32);
+        compact.reserve(10);
+        assert_eq!(compact.stats().tables, 1);
+        compact.reserve(100_000);
+        assert_eq!(compact.stats().max_depth, 2);
+        assert_eq!(compact.get(&1).map(|guard| *guard.val()), Some(1));
+        compact.validate();
+    }
+
+    #[test]
+    fn reserve_concurrently() {
+        const THREADS: u32 = 4;
+        const KEYS: u32 = 50_000;
+
+        let map = Arc::new(Map::new());
+        let barrier = Arc::new(Barrier::new(THREADS as usize + 1));
+        let mut threads = Vec::new();
+
+        for thread in 0 .. THREADS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for i in 0 .. KEYS / THREADS {
+                    let key = i * THREADS + thread;
+                    map.insert(key, key);
+                    if i % 4 == 0 {
+                        map.reserve(KEYS as usize * 2);
+                    }
+                }
+            }));
+        }
+
+        barrier.wait();
+        map.reserve(KEYS as usize * 40);
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        assert_eq!(map.len(), KEYS as usize);
+        for key in 0 .. KEYS {
+            assert_eq!(map.get(&key).map(|guard| *guard.val()), Some(key));
+        }
+        assert!(map.stats().max_depth >= 3);
+        map.validate();
+    }
+
+    #[test]
+    fn stats_constant_hash() {
+        let map = Map::with_hasher(ConstState);
+        let stats = map.stats();
+        assert_eq!((stats.tables, stats.max_depth, stats.leaves), (1, 1, 0));
+
+        for i in 0 .. 100u32 {
+            map.insert(i, i);
+        }
+        map.insert(7, 0);
+        let stats = map.stats();
+        assert_eq!(stats.tables, 1);
+        assert_eq!(stats.max_depth, 1);
+        assert_eq!(stats.leaves, 1);
+        assert_eq!(stats.entries, 100);
+        assert_eq!(stats.max_bucket_len(), 100);
+        assert_eq!(stats.bucket_lengths.iter().sum::<usize>(), 1);
+        map.validate();
+    }
+
+    #[test]
+    fn stats_good_hasher() {
+        let map = Map::new();
+        for i in 0 .. 200u32 {
+            map.insert(i, i);
+        }
+        let stats = map.stats();
+        assert!(stats.max_depth <= 4);
+        assert_eq!(stats.leaves, 200);
+        assert_eq!(stats.entries, 200);
+        assert_eq!(stats.bucket_lengths, vec![0, 200]);
+
+        let map = Map::with_hasher(ShiftState);
+        for key in 0 .. 4u64 {
+            map.insert(key, key);
+        }
+        let stats = map.stats();
+        assert_eq!(stats.tables, 6);
+        assert_eq!(stats.max_depth, 6);
+        assert_eq!(stats.max_bucket_len(), 1);
+        assert_eq!(stats.entries, 4);
+        map.validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "key in the wrong bucket")]
+    fn validate_changed_hash() {
+        // A key whose hash changes while it is in the map, which is a logic
+        // error.
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+        struct Shifty(Cell<u64>);
+
+        impl Hash for Shifty {
+            fn hash<H>(&self, state: &mut H)
+            where
+                H: Hasher,
+            {
+                self.0.get().hash(state)
+            }
+        }
+
+        let map = Map::new();
+        for i in 0 .. 10 {
+            map.insert(Shifty(Cell::new(i)), i);
+        }
+        map.validate();
+        map.get(&Shifty(Cell::new(3))).unwrap().key().0.set(300);
+        map.validate();
+    }
+
+    #[test]
+    fn memory_usage_linear() {
+        let map = Map::new();
+        let empty = map.memory_usage();
+        assert_eq!((empty.buckets, empty.entries), (0, 0));
+        assert_eq!(empty.total_bytes(), empty.table_bytes);
+
+        let mut last = empty.clone();
+        let mut per_entry = None;
+        for round in 1 ..= 4u64 {
+            for i in (round - 1) * 1000 .. round * 1000 {
+                map.insert(i, i);
+            }
+            let usage = map.memory_usage();
+            assert_eq!(usage.entries, round as usize * 1000);
+            assert_eq!(usage.pair_bytes, usage.entries * 16);
+            assert!(usage.buckets <= usage.entries);
+            assert!(usage.total_bytes() > last.total_bytes());
+            assert!(usage.table_bytes >= last.table_bytes);
+
+            // Everything but the tables grows with each entry.
+            let entry_bytes = usage.total_bytes() - usage.table_bytes;
+            let per_entry = *per_entry.get_or_insert(entry_bytes / 1000);
+            assert!(entry_bytes >= usage.entries * per_entry * 9 / 10);
+            assert!(entry_bytes <= usage.entries * per_entry * 11 / 10);
+            last = usage;
+        }
+
+        for i in 0 .. 4000 {
+            map.remove(&i);
+        }
+        map.shrink();
+        let usage = map.memory_usage();
+        assert_eq!((usage.buckets, usage.entries), (0, 0));
+        assert_eq!(usage.tables, 1);
+        assert_eq!(usage, empty);
+    }
+
+    #[test]
+    fn memory_usage_pending_garbage() {
+        let map = Map::new();
+        for i in 0 .. 100u64 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.memory_usage().pending_garbage, 0);
+
+        let guard = map.get(&0).unwrap();
+        for i in 1 .. 100 {
+            map.remove(&i);
+        }
+        assert!(map.memory_usage().pending_garbage >= 99);
+        drop(guard);
+        assert_eq!(map.memory_usage().pending_garbage, 0);
+    }
+
+    #[test]
+    fn slabs_recycle_unless_paused() {
+        let churn = |map: &Map<u64, u64>| {
+            for _ in 0 .. 10 {
+                for i in 0 .. 100 {
+                    map.insert(i, i);
+                }
+                for i in 0 .. 100 {
+                    map.remove(&i);
+                }
+            }
+        };
+        let capacity = |map: &Map<u64, u64>| {
+            (map.slabs.buckets.capacity(), map.slabs.entries.capacity())
+        };
+
+        let map = Map::new();
+        churn(&map);
+        let before = capacity(&map);
+        churn(&map);
+        assert_eq!(capacity(&map), before);
+
+        // Nothing is recycled while the map is paused, so the slabs grow.
+        let pause = map.pause();
+        churn(&map);
+        let paused = capacity(&map);
+        assert!(paused.0 > before.0 && paused.1 > before.1);
+        drop(pause);
+
+        churn(&map);
+        assert_eq!(capacity(&map), paused);
+    }
+
+    #[test]
+    fn dump_structure() {
+        // Hashes integers to a tenth of their values, so the layout of the
+        // tree is known.
+        #[derive(Debug, Clone, Copy, Default)]
+        struct TenthState;
+
+        #[derive(Debug, Clone, Copy, Default)]
+        struct TenthHasher(u64);
+
+        impl BuildHasher for TenthState {
+            type Hasher = TenthHasher;
+
+            fn build_hasher(&self) -> TenthHasher {
+                TenthHasher(0)
+            }
+        }
+
+        impl Hasher for TenthHasher {
+            fn finish(&self) -> u64 {
+                self.0 / 10
+            }
+
+            fn write(&mut self, bytes: &[u8]) {
+                for &byte in bytes.iter().rev() {
+                    self.0 = self.0 << 8 | byte as u64;
+                }
+            }
+        }
+
+        let map = Map::with_hasher(TenthState);
+        for &(key, val) in &[(11u64, 'b'), (10, 'a'), (20, 'c'), (2580, 'd')] {
+            map.insert(key, val);
+        }
+
+        let mut dump = String::new();
+        map.dump_structure(&mut dump).unwrap();
+        // The hashes are masked, since they are wider with `hash128`.
+        let masked = dump
+            .split(' ')
+            .map(|word| if word.starts_with("0x") { "0x?" } else { word })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let expected = "\
+top: 256 nodes
+1: bucket 0x? len 2: 10 => 'a', 11 => 'b'
+2: table
+2.0: bucket 0x? len 1: 20 => 'c'
+2.1: bucket 0x? len 1: 2580 => 'd'
+";
+        assert_eq!(masked, expected);
+        #[cfg(not(feature = "hash128"))]
+        assert!(dump.contains(" 0x0000000000000102 len 1"));
+
+        let mut again = String::new();
+        map.dump_structure(&mut again).unwrap();
+        assert_eq!(dump, again);
+    }
+
+    fn fanout_basic<const BITS: usize>() {
+        let map = Map::<u32, u32, RandomState, BITS>::with_fanout(
+            RandomState::new(),
+        );
+        for i in 0 .. 2000 {
+            assert!(map.insert(i, i * 2).is_none());
+        }
+        for i in 0 .. 2000 {
+            assert_eq!(*map.get(&i).unwrap().val(), i * 2);
+        }
+        let mut keys: Vec<_> = map.iter().map(|guard| *guard.key()).collect();
+        keys.sort();
+        assert_eq!(keys, (0 .. 2000).collect::<Vec<_>>());
+        assert_eq!(map.clone(), map);
+
+        for i in 0 .. 1000 {
+            assert_eq!(*map.remove(&i).unwrap().val(), i * 2);
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.stats().entries, 1000);
+        map.clear();
+        map.shrink();
+        assert_eq!(map.stats().tables, 1);
+    }
+
+    #[test]
+    fn fanout_4() {
+        fanout_basic::<4>();
+    }
+
+    #[test]
+    fn fanout_6() {
+        fanout_basic::<6>();
+    }
+
+    #[test]
+    fn fanout_8() {
+        fanout_basic::<8>();
+    }
+
+    // Inserts keys whose hashes only differ in the given bits, checking how
+    // deep the tree gets.
+    fn fanout_depth<const BITS: usize>(shift: u32, depth: usize) {
+        let map = Map::<u64, u64, ShiftState, BITS>::with_fanout(ShiftState);
+        // The hasher shifts the key 40 bits to the left.
+        let keys = [0u64, 1 << (shift - 40), 3 << (shift - 40)];
+        for &key in &keys {
+            map.insert(key, key);
+        }
+        assert_eq!(map.stats().max_depth, depth);
+        for &key in &keys {
+            assert_eq!(*map.get(&key).unwrap().val(), key);
+        }
+
+        map.remove(&keys[1]);
+        map.remove(&keys[2]);
+        assert_eq!(map.compact(), depth - 1);
+        assert_eq!(map.stats().max_depth, 1);
+        assert_eq!(*map.entry(keys[0]).or_insert(1).val(), keys[0]);
+        assert_eq!(*map.entry(keys[2]).or_insert(1).val(), 1);
+        assert_eq!(map.stats().max_depth, depth);
+    }
+
+    #[test]
+    fn fanout_depth_middle_bits() {
+        // Bits 40 and 41 are the first ones to tell the hashes apart.
+        fanout_depth::<4>(40, 11);
+        fanout_depth::<6>(40, 7);
+        fanout_depth::<8>(40, 6);
+    }
+
+    #[test]
+    fn fanout_depth_last_bits() {
+        // Bits 62 and 63 are the only ones to tell the hashes apart, so they
+        // are reached in the last table, which might use less than `BITS`
+        // bits.
+        fanout_depth::<4>(62, 16);
+        fanout_depth::<6>(62, 11);
+        fanout_depth::<8>(62, 8);
+    }
+
+    // A key whose hash is exactly `hash`, whatever the tag. With `hash128`,
+    // the upper half of the hash is `hash` as well, since the hasher only
+    // keeps the last word written.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    struct Crafted {
+        hash: u64,
+        tag: u8,
+    }
+
+    impl Hash for Crafted {
+        fn hash<H>(&self, state: &mut H)
+        where
+            H: Hasher,
+        {
+            state.write_u64(self.hash);
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Default)]
+    struct LastWordState;
+
+    #[derive(Debug, Clone, Copy, Default)]
+    struct LastWordHasher(u64);
+
+    impl BuildHasher for LastWordState {
+        type Hasher = LastWordHasher;
+
+        fn build_hasher(&self) -> LastWordHasher {
+            LastWordHasher(0)
+        }
+    }
+
+    impl Hasher for LastWordHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, _bytes: &[u8]) {
+            unreachable!("only words are hashed")
+        }
+
+        fn write_u64(&mut self, word: u64) {
+            self.0 = word;
+        }
+    }
+
+    // Inserts keys whose hashes agree with `0` up to each level of the tree,
+    // plus two keys whose hashes are both `0`.
+    fn max_depth_collisions<const BITS: usize>() {
+        let map =
+            Map::<Crafted, u8, LastWordState, BITS>::with_fanout(LastWordState);
+        let levels = 64usize.div_ceil(BITS);
+        let mut keys = vec![Crafted { hash: 0, tag: 0 }];
+        keys.push(Crafted { hash: 0, tag: 1 });
+        for level in 0 .. levels {
+            keys.push(Crafted { hash: 1 << (level * BITS), tag: 0 });
+        }
+        if 63 % BITS != 0 {
+            keys.push(Crafted { hash: 1 << 63, tag: 0 });
+        }
+        for (i, &key) in keys.iter().enumerate() {
+            assert!(map.insert(key, i as u8).is_none());
+        }
+        map.validate();
+
+        // The deepest table is the one consuming bit `63`, which splits the
+        // hashes `0` and `1 << 63`, and the keys with hash `0` share a bucket
+        // there instead of branching again.
+        let stats = map.stats();
+        assert_eq!(stats.max_depth, levels);
+        assert!(stats.max_depth <= table::max_depth(BITS));
+        assert_eq!(stats.entries, keys.len());
+        assert_eq!(stats.leaves, keys.len() - 1);
+        assert_eq!(stats.max_bucket_len(), 2);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(*map.get(key).unwrap().val(), i as u8);
+        }
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(*map.remove(key).unwrap().val(), i as u8);
+            map.validate();
+        }
+        assert_eq!(map.stats().entries, 0);
+    }
+
+    #[test]
+    fn max_depth_collisions_of_every_fanout() {
+        max_depth_collisions::<1>();
+        max_depth_collisions::<3>();
+        max_depth_collisions::<5>();
+        max_depth_collisions::<8>();
+        max_depth_collisions::<16>();
+    }
+
+    #[test]
+    fn max_depth_of_hash() {
+        let hash_bits = mem::size_of::<HashCode>() * 8;
+        assert_eq!(table::max_depth(8), hash_bits / 8);
+        assert_eq!(table::max_depth(16), hash_bits / 16);
+        // The last table of a tree of 5-bit tables consumes fewer bits.
+        assert_eq!(table::max_depth(5), hash_bits / 5 + 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn fanout_too_big() {
+        Map::<u8, u8, RandomState, 17>::with_fanout(RandomState::new());
+    }
+
+    #[test]
+    fn hash_collisions() {
+        // Xors the written words, each one multiplied by an odd factor that
+        // depends on how many words were written before it.
+        #[derive(Debug, Clone, Copy, Default)]
+        struct XorState;
+
+        #[derive(Debug, Clone, Copy, Default)]
+        struct XorHasher {
+            state: u64,
+            writes: u64,
+        }
+
+        impl BuildHasher for XorState {
+            type Hasher = XorHasher;
+
+            fn build_hasher(&self) -> XorHasher {
+                XorHasher::default()
+            }
+        }
+
+        impl Hasher for XorHasher {
+            fn finish(&self) -> u64 {
+                self.state
+            }
+
+            fn write(&mut self, bytes: &[u8]) {
+                for &byte in bytes {
+                    self.write_u64(byte as u64);
+                }
+            }
+
+            fn write_u64(&mut self, word: u64) {
+                self.state ^= word.wrapping_mul(self.writes * 2 + 1);
+                self.writes += 1;
+            }
+        }
+
+        let map = Map::with_hasher(XorState);
+        // Both 64-bit hashes are `3`, i.e. `3 ^ 0 * 3` and `0 ^ 1 * 3`.
+        let keys = [(3u64, 0u64), (0, 1)];
+        assert_eq!(XorState.hash_one(keys[0]), XorState.hash_one(keys[1]));
+        for (i, &key) in keys.iter().enumerate() {
+            map.insert(key, i);
+        }
+
+        let stats = map.stats();
+        assert_eq!(stats.entries, 2);
+        if cfg!(feature = "hash128") {
+            // The lower 64 bits are shared by the first 8 tables, and the
+            // upper ones split the keys in the 9th table.
+            assert_eq!(stats.leaves, 2);
+            assert_eq!(stats.max_bucket_len(), 1);
+            assert_eq!(stats.max_depth, 9);
+        } else {
+            assert_eq!(stats.leaves, 1);
+            assert_eq!(stats.max_bucket_len(), 2);
+            assert_eq!(stats.max_depth, 1);
+        }
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(*map.get(key).unwrap().val(), i);
+        }
+        assert_eq!(*map.remove(&keys[0]).unwrap().val(), 0);
+        assert!(map.get(&keys[0]).is_none());
+        assert_eq!(*map.get(&keys[1]).unwrap().val(), 1);
+    }
+
+    #[test]
+    fn new_compact_memory() {
+        let compact = Map::<u32, u32>::new_compact();
+        let full = Map::<u32, u32>::new();
+        let compact_bytes = compact.stats().table_bytes;
+        let full_bytes = full.stats().table_bytes;
+        assert!(compact_bytes * 8 < full_bytes);
+
+        for i in 0 .. 8 {
+            compact.insert(i, i);
+        }
+        assert!(compact.is_compact(unsafe { &*compact.top.load(Acquire) }));
+        assert!(compact.stats().table_bytes < full_bytes);
+        assert_eq!(compact.shrink(), 0);
+    }
+
+    #[test]
+    fn new_compact_upgrade() {
+        let map = Map::new_compact();
+        for i in 0 .. COMPACT_LEN as u32 {
+            assert!(map.insert(i, i * 2).is_none());
+        }
+        let stats = map.stats();
+        let full_bytes = Map::<u32, u32>::new().stats().table_bytes;
+        assert!(stats.table_bytes >= full_bytes);
+        assert_eq!(stats.entries, COMPACT_LEN);
+        for i in 0 .. COMPACT_LEN as u32 {
+            assert_eq!(*map.get(&i).unwrap().val(), i * 2);
+        }
+
+        for i in COMPACT_LEN as u32 .. 1000 {
+            map.insert(i, i * 2);
+        }
+        for i in 0 .. 500 {
+            assert_eq!(*map.remove(&i).unwrap().val(), i * 2);
+        }
+        assert_eq!(map.len(), 500);
+        let mut keys: Vec<_> = map.iter().map(|guard| *guard.key()).collect();
+        keys.sort();
+        assert_eq!(keys, (500 .. 1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn new_compact_deep() {
+        // Every key collides in the lowest 40 bits, so the compact tree is
+        // already deep before the upgrade.
+        let map = Map::<u64, u64, ShiftState>::with_fanout_compact(ShiftState);
+        for key in 0 .. 100 {
+            map.insert(key, key);
+        }
+        let stats = map.stats();
+        assert_eq!((stats.tables, stats.max_depth), (6, 6));
+        for key in 0 .. 100 {
+            assert_eq!(*map.get(&key).unwrap().val(), key);
+        }
+    }
+
+    #[test]
+    fn new_compact_concurrent() {
+        const STABLE: u32 = 32;
+        const THREADS: u32 = 4;
+        const PER_THREAD: u32 = 200;
+
+        for _ in 0 .. 20 {
+            let map = Arc::new(Map::new_compact());
+            for key in 0 .. STABLE {
+                map.insert(key, key);
+            }
+            let barrier = Arc::new(Barrier::new(THREADS as usize * 2));
+            let mut threads = Vec::new();
+
+            for t in 0 .. THREADS {
+                let map = map.clone();
+                let barrier = barrier.clone();
+                threads.push(thread::spawn(move || {
+                    barrier.wait();
+                    let base = STABLE + t * PER_THREAD;
+                    for key in base .. base + PER_THREAD {
+                        map.insert(key, key);
+                        if key % 2 == 0 {
+                            assert_eq!(*map.remove(&key).unwrap().val(), key);
+                        }
+                    }
+                }));
+            }
+
+            for _ in 0 .. THREADS {
+                let map = map.clone();
+                let barrier = barrier.clone();
+                threads.push(thread::spawn(move || {
+                    barrier.wait();
+                    for _ in 0 .. 50 {
+                        for key in 0 .. STABLE {
+                            assert_eq!(*map.get(&key).unwrap().val(), key);
+                        }
+                    }
+                }));
+            }
+
+            for thread in threads {
+                thread.join().unwrap();
+            }
+
+            let expected = STABLE + THREADS * PER_THREAD / 2;
+            assert_eq!(map.len(), expected as usize);
+            assert_eq!(map.stats().entries, expected as usize);
+            assert!(!map.is_compact(unsafe { &*map.top.load(Acquire) }));
+            for t in 0 .. THREADS {
+                let base = STABLE + t * PER_THREAD;
+                for key in base .. base + PER_THREAD {
+                    assert_eq!(map.get(&key).is_some(), key % 2 == 1);
+                }
+            }
+            map.validate();
+        }
+    }
+
+    #[cfg(feature = "fxhash")]
+    #[test]
+    fn fxhash_deep_branches() {
+        const THREADS: u64 = 4;
+        const KEYS: u64 = 500;
+
+        // FxHash multiplies integers by an odd constant, so keys whose lower
+        // bits are zeroes have hashes whose lower bits are zeroes too, and
+        // they are only stored in deep sub-tables.
+        let map = Arc::new(FxMap::default());
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in t * KEYS .. (t + 1) * KEYS {
+                    map.insert(i << 24, i);
+                    if i % 3 == 0 {
+                        assert_eq!(*map.remove(&(i << 24)).unwrap().val(), i);
+                    }
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        assert!(map.stats().max_depth >= 4);
+        for i in 0 .. THREADS * KEYS {
+            let found = map.get(&(i << 24)).map(|guard| *guard.val());
+            assert_eq!(found, if i % 3 == 0 { None } else { Some(i) });
+        }
+        map.validate();
+        map.clear();
+        map.shrink();
+        assert_eq!(map.stats().tables, 1);
+    }
+
+    #[cfg(feature = "fxhash")]
+    #[test]
+    fn fxhash_shared_buckets() {
+        const THREADS: u64 = 4;
+        const KEYS: u64 = 100;
+
+        // FxHash mixes each word into the state with a rotation by 5 bits, a
+        // xor and a multiplication, so pairing a key with its rotated hash
+        // makes the state zero.
+        fn colliding(i: u64) -> (u64, u64) {
+            let mut hasher = ::fxhash::FxBuildHasher::default().build_hasher();
+            hasher.write_u64(i);
+            (i, hasher.finish().rotate_left(5))
+        }
+
+        let map = Arc::new(FxMap::default());
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in t * KEYS .. (t + 1) * KEYS {
+                    let key = colliding(i);
+                    map.insert(key, i);
+                    assert_eq!(*map.get(&key).unwrap().val(), i);
+                    if i % 2 == 0 {
+                        assert_eq!(*map.remove(&key).unwrap().val(), i);
+                    }
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        // With `hash128`, the upper halves of the hashes tell the keys apart.
+        let stats = map.stats();
+        assert_eq!(stats.entries, (THREADS * KEYS / 2) as usize);
+        #[cfg(not(feature = "hash128"))]
+        assert_eq!(stats.leaves, 1);
+        for i in 0 .. THREADS * KEYS {
+            let found = map.get(&colliding(i)).map(|guard| *guard.val());
+            assert_eq!(found, if i % 2 == 0 { None } else { Some(i) });
+        }
+        map.validate();
+    }
+
+    #[cfg(feature = "ahash")]
+    #[test]
+    fn ahash_multithreaded() {
+        let map = Arc::new(AHashMap::default());
+        let mut threads = Vec::new();
+        for t in 0 .. 4u64 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in t * 1000 .. (t + 1) * 1000 {
+                    map.insert(i, i);
+                    if i % 2 == 0 {
+                        assert_eq!(*map.remove(&i).unwrap().val(), i);
+                    }
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        assert_eq!(map.len(), 2000);
+        for i in 0 .. 4000 {
+            assert_eq!(map.get(&i).is_some(), i % 2 == 1);
+        }
+        map.validate();
+    }
 
-unsafe impl<K, V, H> Sync for Map<K, V, H>
-where
-    K: Sync,
-    V: Sync,
-    H: Sync,
-{
-}
+    #[test]
+    fn for_each_visits_all() {
+        let map = Map::new();
+        for i in 0 .. 1000u64 {
+            map.insert(i, i * 3);
+        }
 
-make_shared_incin! {
-    { "[`Map`]" }
-    pub SharedIncin<K, V> of Garbage<K, V>
-}
+        let mut visited = HashMap::new();
+        map.for_each(|&k, &v| {
+            assert_eq!(v, k * 3);
+            *visited.entry(k).or_insert(0) += 1;
+        });
 
-impl<K, V> fmt::Debug for SharedIncin<K, V> {
-    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmtr, "SharedIncin {} inner: {:?} {}", '{', self.inner, '}')
+        assert_eq!(visited.len(), 1000);
+        assert!(visited.values().all(|&count| count == 1));
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::{collections::HashMap, sync::Arc, thread};
+    #[test]
+    fn for_each_multithreaded() {
+        let map = Arc::new(Map::new());
+        let mut threads = Vec::new();
+        for i in 0 .. 4u64 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for j in 0 .. 1000 {
+                    let key = i * 1000 + j;
+                    map.insert(key, key * 3);
+                    if j % 3 == 0 {
+                        map.remove(&key);
+                    }
+                }
+            }));
+        }
+
+        let visitor = {
+            let map = map.clone();
+            thread::spawn(move || {
+                for _ in 0 .. 50 {
+                    let mut visited = HashMap::new();
+                    map.for_each(|&k: &u64, &v| {
+                        assert_eq!(v, k * 3);
+                        *visited.entry(k).or_insert(0) += 1;
+                    });
+                    assert!(visited.values().all(|&count| count == 1));
+                }
+            })
+        };
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        visitor.join().expect("visitor failed");
+
+        let mut count = 0;
+        map.for_each(|_, _| count += 1);
+        assert_eq!(count, 4 * 666);
+        map.validate();
+    }
 
     #[test]
-    fn inserts_and_gets() {
+    fn scan_in_batches() {
         let map = Map::new();
-        assert!(map.get("five").is_none());
-        assert!(map.insert("five".to_owned(), 5).is_none());
-        assert_eq!(*map.get("five").unwrap().val(), 5);
-        assert!(map.get("four").is_none());
-        assert!(map.insert("four".to_owned(), 4).is_none());
-        assert_eq!(*map.get("five").unwrap().val(), 5);
-        assert_eq!(*map.get("four").unwrap().val(), 4);
-        let guard = map.get("four").unwrap();
-        assert_eq!(guard.key(), "four");
-        assert_eq!(*guard.val(), 4);
+        for i in 0 .. 1000u64 {
+            map.insert(i, i * 3);
+        }
+
+        let mut visited = HashMap::new();
+        let mut cursor = Some(ScanCursor::new());
+        let mut calls = 0;
+        while let Some(curr) = cursor.take() {
+            let mut count = 0;
+            cursor = map.scan(curr, 7, |&k, &v| {
+                assert_eq!(v, k * 3);
+                *visited.entry(k).or_insert(0) += 1;
+                count += 1;
+            });
+            assert!(count <= 7);
+            calls += 1;
+        }
+        assert_eq!(visited.len(), 1000);
+        assert!(visited.values().all(|&count| count == 1));
+        assert!(calls >= 1000 / 7);
+
+        let map = Map::with_hasher(ConstState);
+        for i in 0 .. 50u64 {
+            map.insert(i, i);
+        }
+        let mut sizes = Vec::new();
+        let mut cursor = Some(ScanCursor::new());
+        while let Some(curr) = cursor.take() {
+            let mut count = 0;
+            cursor = map.scan(curr, 10, |_, _| count += 1);
+            sizes.push(count);
+        }
+        assert!(sizes.contains(&50));
+        assert_eq!(sizes.iter().sum::<usize>(), 50);
+
+        // Vacant nodes count towards the batch too.
+        let empty = Map::<u64, u64>::new();
+        let mut cursor = Some(ScanCursor::new());
+        let mut calls = 0;
+        while let Some(curr) = cursor.take() {
+            cursor = empty.scan(curr, 0, |_, _| panic!("map is empty"));
+            calls += 1;
+        }
+        assert_eq!(calls, 256);
     }
 
     #[test]
-    fn create() {
-        let map = Map::new();
-        assert!(map
-            .insert_with(
-                "five".to_owned(),
-                |_, _, stored| if stored.is_none() {
-                    Preview::New(5)
-                } else {
-                    Preview::Discard
+    fn scan_during_mutation() {
+        const STABLE: u64 = 200;
+        const THREADS: u64 = 3;
+
+        let map = Arc::new(Map::with_hasher(ShiftState));
+        for key in 0 .. STABLE {
+            map.insert(key, key);
+        }
+        let barrier = Arc::new(Barrier::new(THREADS as usize + 2));
+        let mut threads = Vec::new();
+
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for round in 0 .. 100 {
+                    // Colliding with stable keys in the lowest 48 bits, so
+                    // their buckets are moved into sub-tables and back.
+                    for key in 0 .. STABLE {
+                        map.insert(key + 256 * (t + 1), round);
+                    }
+                    for key in 0 .. STABLE {
+                        map.remove(&(key + 256 * (t + 1)));
+                    }
                 }
-            )
-            .created());
-        assert_eq!(*map.get("five").unwrap().val(), 5);
-        assert!(map
-            .insert_with(
-                "five".to_owned(),
-                |_, _, stored| if stored.is_none() {
-                    Preview::New(500)
+            }));
+        }
+
+        let compactor = {
+            let map = map.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                while Arc::strong_count(&map) > 2 {
+                    map.compact();
+                    map.shrink();
+                }
+            })
+        };
+
+        barrier.wait();
+        loop {
+            let mut counts = HashMap::new();
+            let mut cursor = Some(ScanCursor::new());
+            while let Some(curr) = cursor.take() {
+                cursor = map.scan(curr, 5, |&key, &val| {
+                    if key < STABLE {
+                        assert_eq!(val, key);
+                        *counts.entry(key).or_insert(0) += 1;
+                    }
+                });
+            }
+            assert_eq!(counts.len(), STABLE as usize);
+            assert!(counts.values().all(|&count| count == 1));
+            if Arc::strong_count(&map) <= 2 {
+                break;
+            }
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        compactor.join().expect("compactor failed");
+        map.validate();
+    }
+
+    #[test]
+    fn scan_across_branch_and_collapse() {
+        const STABLE: u64 = 200;
+
+        // Whichever node the first call stops at, the sub-tables it went
+        // through are collapsed before the next call.
+        for batch in 1 .. 20 {
+            let map = Map::with_hasher(ShiftState);
+            map.insert(1, 1);
+            map.insert(2, 2);
+            map.insert(1 + 256, 0);
+            let mut counts = HashMap::new();
+            let mut cursor = map.scan(ScanCursor::new(), batch, |&key, _| {
+                *counts.entry(key).or_insert(0) += 1;
+            });
+            map.remove(&(1 + 256));
+            map.compact();
+            while let Some(curr) = cursor.take() {
+                cursor = map.scan(curr, batch, |&key, _| {
+                    *counts.entry(key).or_insert(0) += 1;
+                });
+            }
+            assert_eq!(counts.get(&1), Some(&1));
+            assert_eq!(counts.get(&2), Some(&1));
+        }
+
+        let map = Map::with_hasher(ShiftState);
+        for key in 0 .. STABLE {
+            map.insert(key, key);
+        }
+
+        for &batch in &[3, 10, 40] {
+            let mut counts = HashMap::new();
+            let mut cursor = Some(ScanCursor::new());
+            let mut round = 0;
+            while let Some(curr) = cursor.take() {
+                cursor = map.scan(curr, batch, |&key, _| {
+                    if key < STABLE {
+                        *counts.entry(key).or_insert(0) += 1;
+                    }
+                });
+                // Moves buckets into new sub-tables, then back up.
+                let shift = 256 * (round % 3 + 1);
+                for key in 0 .. STABLE {
+                    map.insert(key + shift, key);
+                }
+                for key in 0 .. STABLE {
+                    map.remove(&(key + shift));
+                }
+                if round % 2 == 0 {
+                    map.compact();
                 } else {
-                    Preview::Discard
+                    map.shrink();
                 }
-            )
-            .failed()
-            .is_some());
+                round += 1;
+            }
+            assert_eq!(counts.len(), STABLE as usize);
+            assert!(counts.values().all(|&count| count == 1));
+        }
+        map.validate();
     }
 
     #[test]
-    fn update() {
+    fn scan_compact_upgrade() {
+        let map = Map::new_compact();
+        for i in 0 .. 40u64 {
+            map.insert(i, i);
+        }
+
+        let mut counts = HashMap::new();
+        let mut cursor = map.scan(ScanCursor::new(), 10, |&key, _| {
+            *counts.entry(key).or_insert(0) += 1;
+        });
+        for i in 40 .. 200u64 {
+            map.insert(i, i);
+        }
+        while let Some(curr) = cursor.take() {
+            cursor = map.scan(curr, 10, |&key, _| {
+                *counts.entry(key).or_insert(0) += 1;
+            });
+        }
+        // The scan started over once the map was upgraded, so every entry
+        // was visited after, and those visited before were visited twice.
+        assert_eq!(counts.len(), 200);
+        assert!(counts.values().all(|&count| count == 1 || count == 2));
+    }
+
+    #[test]
+    fn sorted_export() {
         let map = Map::new();
-        assert!(map
-            .insert_with("five".to_owned(), |_, _, stored| {
-                if let Some((_, n)) = stored {
-                    Preview::New(*n + 6)
-                } else {
-                    Preview::Discard
+        let mut reference = Vec::new();
+        for i in 0 .. 1000u64 {
+            let key = i.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 40;
+            map.insert(key, i);
+            reference.push((key, i));
+        }
+        reference.sort();
+        assert_eq!(map.to_sorted_vec(), reference);
+
+        let by_val = map.to_vec_by(|left, right| right.1.cmp(&left.1));
+        let vals = by_val.iter().map(|pair| pair.1).collect::<Vec<_>>();
+        assert_eq!(vals, (0 .. 1000u64).rev().collect::<Vec<_>>());
+        assert!(Map::<u64, u64>::new().to_sorted_vec().is_empty());
+    }
+
+    #[test]
+    fn sorted_export_concurrent() {
+        const STABLE: u64 = 1000;
+
+        let map = Arc::new(Map::new());
+        for i in 0 .. STABLE {
+            map.insert(i * 2, i);
+        }
+        let mut threads = Vec::new();
+        for t in 0 .. 3u64 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for round in 0 .. 20 {
+                    for i in 0 .. STABLE {
+                        let key = i * 2 + 1;
+                        if (i + t + round) % 3 == 0 {
+                            map.insert(key, round);
+                        } else {
+                            map.remove(&key);
+                        }
+                    }
                 }
-            })
-            .failed()
-            .is_some());
-        assert!(map.insert("five".to_owned(), 5).is_none());
-        let guard = map
-            .insert_with("five".to_owned(), |_, _, stored| {
-                if let Some((_, n)) = stored {
-                    Preview::New(*n + 7)
-                } else {
-                    Preview::Discard
+            }));
+        }
+
+        for _ in 0 .. 20 {
+            let sorted = map.to_sorted_vec();
+            // Strictly increasing keys: sorted, and never duplicated.
+            assert!(sorted.windows(2).all(|pairs| pairs[0].0 < pairs[1].0));
+            let stable = sorted.iter().filter(|pair| pair.0 % 2 == 0);
+            let stable = stable.copied().collect::<Vec<_>>();
+            let expected = (0 .. STABLE).map(|i| (i * 2, i));
+            assert_eq!(stable, expected.collect::<Vec<_>>());
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        map.validate();
+    }
+
+    #[test]
+    fn fold_sums_stable_entries() {
+        const STABLE: u64 = 2000;
+
+        let map = Arc::new(Map::new());
+        for i in 0 .. STABLE {
+            map.insert(i, i + 1);
+        }
+        let expected = (1 .. STABLE + 1).sum::<u64>();
+        let mut threads = Vec::new();
+
+        for t in 0 .. 4u64 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for j in 0 .. 2000 {
+                    let key = STABLE + t * 2000 + j;
+                    map.insert(key, 0);
+                    if j % 2 == 0 {
+                        map.remove(&key);
+                    }
+                }
+            }));
+        }
+
+        for _ in 0 .. 50 {
+            let (sum, count) = map.fold((0, 0), |(sum, count), &key, &val| {
+                assert!(val == 0 || val == key + 1);
+                (sum + val, count + 1)
+            });
+            assert_eq!(sum, expected);
+            assert!(count >= STABLE);
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        assert_eq!(map.fold(0, |count, _, _| count + 1), STABLE + 4 * 1000);
+        map.validate();
+    }
+
+    #[test]
+    fn count_matching() {
+        let map = Map::new();
+        assert_eq!(map.count_matching(|_, _| true), 0);
+        for i in 0 .. 1000u64 {
+            map.insert(i, i % 3);
+        }
+        assert_eq!(map.count_matching(|_, &val| val == 0), 334);
+        assert_eq!(map.count_matching(|&key, &val| key < 10 && val == 1), 3);
+        assert_eq!(map.count_matching(|_, _| true), 1000);
+    }
+
+    #[test]
+    fn count_matching_multithreaded() {
+        let map = Arc::new(Map::new());
+        for i in 0 .. 2000u64 {
+            map.insert(i, i % 2 == 0);
+        }
+        let before = map.count_matching(|_, &flag| flag);
+        assert_eq!(before, 1000);
+
+        // Writers only add matching entries, so the count only grows.
+        let mut threads = Vec::new();
+        for t in 0 .. 4u64 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. 1000 {
+                    map.insert(2000 + t * 1000 + i, true);
                 }
+            }));
+        }
+
+        let counts = (0 .. 20)
+            .map(|_| map.count_matching(|_, &flag| flag))
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        let after = map.count_matching(|_, &flag| flag);
+        assert_eq!(after, 5000);
+        assert!(counts.iter().all(|&count| before <= count && count <= after));
+        map.validate();
+    }
+
+    #[test]
+    fn merge() {
+        let map = Map::new();
+        map.insert(1, "one".to_owned());
+        map.insert(2, "two".to_owned());
+        let other = Map::<_, _, ShiftState, 4>::with_fanout(ShiftState);
+        other.insert(2, "deux".to_owned());
+        other.insert(3, "trois".to_owned());
+
+        let Merged { displaced, unmoved } = map.merge(&other);
+        assert!(unmoved.is_empty());
+        assert_eq!(displaced.len(), 1);
+        assert_eq!(*displaced[0], (2, "two".to_owned()));
+        assert_eq!(other.len(), 0);
+        assert!(other.get(&2).is_none());
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get_cloned(&2), Some("deux".to_owned()));
+        assert_eq!(map.get_cloned(&3), Some("trois".to_owned()));
+    }
+
+    #[test]
+    fn merge_never_waits_for_readers() {
+        let map = Map::new();
+        let other = Arc::new(Map::new());
+        other.insert(1, vec![1]);
+        let barrier = Arc::new(Barrier::new(2));
+
+        let reader = {
+            let other = other.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let guard = other.get(&1).unwrap();
+                barrier.wait();
+                barrier.wait();
+                assert_eq!(*guard.val(), vec![1]);
             })
-            .take_updated()
-            .unwrap();
-        assert_eq!(guard.key(), "five");
-        assert_eq!(*guard.val(), 5);
-        assert_eq!(*map.get("five").unwrap().val(), 12);
+        };
+
+        barrier.wait();
+        // The reader keeps the other map paused until the merge returns.
+        let Merged { displaced, mut unmoved } = map.merge(&other);
+        assert!(displaced.is_empty());
+        assert_eq!(unmoved.len(), 1);
+        assert!(map.is_empty() && other.is_empty());
+        barrier.wait();
+        reader.join().expect("reader failed");
+
+        assert!(map.reinsert(unmoved.pop().unwrap()).created());
+        assert_eq!(map.get_cloned(&1), Some(vec![1]));
+        map.validate();
+    }
+
+    #[test]
+    fn merge_paused_locally() {
+        let map = Map::new();
+        let other = Map::new();
+        other.insert(1, 2);
+        other.insert(3, 4);
+        let guard = other.get(&3).unwrap();
+        let merged = map.merge(&other);
+        assert_eq!(merged.unmoved.len(), 2);
+        drop(guard);
+        for removed in merged.unmoved {
+            assert!(map.reinsert(removed).created());
+        }
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn merge_multithreaded() {
+        let map = Arc::new(Map::new());
+        let other = Arc::new(Map::new());
+        for i in 0 .. 2000u64 {
+            map.insert(i, i);
+            other.insert(i + 1000, i + 1000);
+        }
+        let mut threads = Vec::new();
+
+        for t in 0 .. 2u64 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. 1000 {
+                    let key = 10_000 + t * 1000 + i;
+                    map.insert(key, key);
+                }
+            }));
+
+            let other = other.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. 1000 {
+                    let key = 20_000 + t * 1000 + i;
+                    other.insert(key, key);
+                    assert!(other
+                        .get(&(i + 1000))
+                        .is_none_or(|guard| *guard.val() == i + 1000));
+                }
+            }));
+        }
+
+        let merged = map.merge(&*other);
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        // Moves whatever was inserted after the first merge drained it.
+        let leftover = map.merge(&*other);
+        let mut displaced = merged.displaced.len() + leftover.displaced.len();
+        // Nobody reads the other map anymore.
+        for removed in merged.unmoved.into_iter().chain(leftover.unmoved) {
+            if map.reinsert(removed).updated().is_some() {
+                displaced += 1;
+            }
+        }
+
+        assert_eq!(displaced, 1000);
+        assert_eq!(other.len(), 0);
+
+        let mut seen = HashMap::new();
+        map.for_each(|&key, &val| {
+            assert_eq!(key, val);
+            *seen.entry(key).or_insert(0) += 1;
+        });
+        assert!(seen.values().all(|&count| count == 1));
+        let expected = (0 .. 3000)
+            .chain(10_000 .. 12_000)
+            .chain(20_000 .. 22_000)
+            .collect::<HashSet<u64>>();
+        assert_eq!(seen.keys().cloned().collect::<HashSet<_>>(), expected);
+        assert_eq!(map.len(), expected.len());
+        map.validate();
+        other.validate();
     }
 
     #[test]
-    fn never_inserts() {
+    fn partition() {
         let map = Map::new();
-        assert!(map
-            .insert_with("five".to_owned(), |_, _, _| Preview::Discard)
-            .failed()
-            .is_some());
-        assert!(map.insert("five".to_owned(), 5).is_none());
-        assert!(map
-            .insert_with("five".to_owned(), |_, _, _| Preview::Discard)
-            .failed()
-            .is_some());
+        for i in 0 .. 100u64 {
+            map.insert(i, i * 10);
+        }
+
+        let (even, odd) = map.partition(|&key, _| key % 2 == 0);
+        assert_eq!(map.len(), 0);
+        assert_eq!(even.len(), 50);
+        assert_eq!(odd.len(), 50);
+        for i in 0 .. 100u64 {
+            let target = if i % 2 == 0 { &even } else { &odd };
+            let other = if i % 2 == 0 { &odd } else { &even };
+            assert_eq!(*target.get(&i).unwrap().val(), i * 10);
+            assert!(other.get(&i).is_none());
+        }
+
+        even.insert(1000, 0);
+        assert!(map.get(&1000).is_none());
+        assert!(odd.get(&1000).is_none());
     }
 
     #[test]
-    fn inserts_reinserts() {
-        let map = Map::new();
-        assert!(map.insert("four".to_owned(), 4).is_none());
-        let prev = map.insert("four".to_owned(), 40).unwrap();
-        assert_eq!(prev.key(), "four");
-        assert_eq!(*prev.val(), 4);
-        let prev = map.reinsert(prev).take_updated().unwrap();
-        assert_eq!(prev.key(), "four");
-        assert_eq!(*prev.val(), 40);
-        assert!(*map.get("four").unwrap().val() == 4);
+    fn partition_multithreaded() {
+        let map = Arc::new(Map::new());
+        for i in 0 .. 2000u64 {
+            map.insert(i, i);
+        }
+        let mut threads = Vec::new();
+
+        for t in 0 .. 4u64 {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. 1000 {
+                    let key = 10_000 + t * 1000 + i;
+                    map.insert(key, key);
+                }
+            }));
+        }
+
+        let (small, large) = map.partition(|&key, _| key < 1000);
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        let mut seen = HashMap::new();
+        for part in [&*map, &small, &large] {
+            part.for_each(|&key, &val| {
+                assert_eq!(key, val);
+                *seen.entry(key).or_insert(0) += 1;
+            });
+        }
+        assert!(seen.values().all(|&count| count == 1));
+        let expected =
+            (0 .. 2000).chain(10_000 .. 14_000).collect::<HashSet<u64>>();
+        assert_eq!(seen.keys().cloned().collect::<HashSet<_>>(), expected);
+        assert_eq!(map.len() + small.len() + large.len(), expected.len());
+
+        for i in 0 .. 1000 {
+            assert!(small.get(&i).is_some());
+            assert!(large.get(&(i + 1000)).is_some());
+        }
+        small.for_each(|&key, _| assert!(!(1000 .. 10_000).contains(&key)));
+        large.for_each(|&key, _| assert!(key >= 1000));
+        map.validate();
+        small.validate();
+        large.validate();
     }
 
     #[test]
-    fn never_reinserts() {
-        let map = Map::new();
-        map.insert("five".to_owned(), 5);
-        let prev = map.remove("five").unwrap();
-        let prev = map.reinsert_with(prev, |_, _| false).take_failed().unwrap();
-        assert!(map.insert("five".to_owned(), 5).is_none());
-        map.reinsert_with(prev, |_, _| false).take_failed().unwrap();
+    fn rehash_flooded() {
+        let flooded = Map::with_hasher(ConstState);
+        for i in 0 .. 500u32 {
+            flooded.insert(i, i * 2);
+        }
+        assert_eq!(flooded.stats().max_bucket_len(), 500);
+        let reader = flooded.get(&42).unwrap();
+
+        let map = flooded.rehash_with(RandomState::new());
+        assert!(flooded.is_empty());
+        assert_eq!(*reader.val(), 84);
+        drop(reader);
+
+        let stats = map.stats();
+        assert_eq!(stats.entries, 500);
+        assert!(stats.max_bucket_len() <= 2);
+        assert_eq!(map.len(), 500);
+        for i in 0 .. 500 {
+            assert_eq!(*map.get(&i).unwrap().val(), i * 2);
+        }
+        map.validate();
     }
 
     #[test]
-    fn reinserts_create() {
-        let map = Map::new();
-        map.insert("five".to_owned(), 5);
-        let first = map.remove("five").unwrap();
-        map.insert("five".to_owned(), 5);
-        let second = map.remove("five").unwrap();
-        assert!(map
-            .reinsert_with(first, |_, stored| stored.is_none())
-            .created());
-        assert_eq!(*map.get("five").unwrap().val(), 5);
-        assert!(map
-            .reinsert_with(second, |_, stored| stored.is_none())
-            .failed()
-            .is_some());
+    fn rehash_multithreaded() {
+        const THREADS: usize = 4;
+        const KEYS: usize = 2000;
+
+        let map = Arc::new(Map::with_hasher(ConstState));
+        map.extend((0 .. KEYS).map(|i| (i, i)));
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+
+        let threads = (0 .. THREADS)
+            .map(|t| {
+                let map = map.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for i in (t .. KEYS).step_by(THREADS) {
+                        if let Some(guard) = map.get(&i) {
+                            assert_eq!(*guard.val(), i);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        barrier.wait();
+        let rehashed = map.rehash_with(RandomState::new());
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        assert!(map.is_empty());
+        assert_eq!(rehashed.len(), KEYS);
+        let mut keys = rehashed.keys_cloned();
+        keys.sort();
+        assert_eq!(keys, (0 .. KEYS).collect::<Vec<_>>());
+        rehashed.validate();
     }
 
     #[test]
-    fn reinserts_update() {
-        let map = Map::new();
-        map.insert("five".to_owned(), 5);
-        let prev = map.remove("five").unwrap();
-        let prev = map
-            .reinsert_with(prev, |_, stored| stored.is_some())
-            .take_failed()
-            .unwrap();
-        map.insert("five".to_owned(), 5);
-        assert!(map
-            .reinsert_with(prev, |_, stored| stored.is_some())
-            .updated()
-            .is_some());
+    fn unordered_inserts_and_gets() {
+        let map = Map::new_unordered();
+        for i in 0 .. 100 {
+            assert!(map.insert(EqKey(i), i).is_none());
+        }
+        assert_eq!(*map.insert(EqKey(7), 70).unwrap().val(), 7);
+        assert_eq!(map.try_insert(EqKey(8), 80), Err((EqKey(8), 80)));
+        assert_eq!(*map.update(&EqKey(9), |val| val * 10).unwrap().val(), 9);
+        assert_eq!(*map.remove(&EqKey(10)).unwrap().val(), 10);
+        assert!(map.remove(&EqKey(10)).is_none());
+        assert!(map.get(&EqKey(100)).is_none());
+
+        for i in 0 .. 100 {
+            let expected = match i {
+                7 => Some(70),
+                9 => Some(90),
+                10 => None,
+                _ => Some(i),
+            };
+            assert_eq!(map.get_cloned(&EqKey(i)), expected);
+        }
+        assert_eq!(map.len(), 99);
+        map.validate();
     }
 
     #[test]
-    fn inserts_and_removes() {
-        let map = Map::new();
-        assert!(map.remove("five").is_none());
-        assert!(map.remove("four").is_none());
-        map.insert("five".to_owned(), 5);
-        let removed = map.remove("five").unwrap();
-        assert_eq!(removed.key(), "five");
-        assert_eq!(*removed.val(), 5);
-        assert!(map.insert("four".to_owned(), 4).is_none());
-        map.insert("three".to_owned(), 3);
-        assert!(map.remove("two").is_none());
-        map.insert("two".to_owned(), 2);
-        let removed = map.remove("three").unwrap();
-        assert_eq!(removed.key(), "three");
-        assert_eq!(*removed.val(), 3);
-        let removed = map.remove("two").unwrap();
-        assert_eq!(removed.key(), "two");
-        assert_eq!(*removed.val(), 2);
-        let removed = map.remove("four").unwrap();
-        assert_eq!(removed.key(), "four");
-        assert_eq!(*removed.val(), 4);
+    fn unordered_shared_bucket() {
+        let map = Map::<_, _, _, 8, Unordered>::with_fanout(ConstState);
+        for i in 0 .. 5 {
+            map.insert(EqKey(i), i);
+        }
+        // Removes from the start, the middle and the end of the bucket, then
+        // appends again.
+        for &i in &[0, 2, 4] {
+            assert_eq!(*map.remove(&EqKey(i)).unwrap().val(), i);
+        }
+        map.insert(EqKey(3), 30);
+        map.insert(EqKey(2), 20);
+        map.insert(EqKey(5), 5);
+
+        let stats = map.stats();
+        assert_eq!((stats.leaves, stats.max_bucket_len()), (1, 4));
+        let mut dump = String::new();
+        map.dump_structure(&mut dump).unwrap();
+        // Replaced entries keep their place, new ones go to the end.
+        assert!(dump.ends_with(
+            "len 4: EqKey(1) => 1, EqKey(3) => 30, EqKey(2) => 20, \
+             EqKey(5) => 5\n"
+        ));
+        map.validate();
     }
 
     #[test]
-    fn repeated_inserts() {
-        let map = Map::new();
-        assert!(map.insert("five".to_owned(), 5).is_none());
-        assert!(*map.insert("five".to_owned(), 5).unwrap().val() == 5);
+    fn unordered_against_model() {
+        let map = Map::<_, _, _, 8, Unordered>::with_fanout(ConstState);
+        let mut model = HashMap::new();
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+
+        for step in 0 .. 2000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let key = EqKey(seed % 64);
+            match seed >> 62 {
+                0 => assert_eq!(
+                    map.insert(key, step).map(|old| *old.val()),
+                    model.insert(key, step),
+                ),
+                1 => assert_eq!(
+                    map.remove(&key).map(|old| *old.val()),
+                    model.remove(&key),
+                ),
+                2 => {
+                    let inserted = map.try_insert(key, step).is_ok();
+                    assert_eq!(inserted, !model.contains_key(&key));
+                    model.entry(key).or_insert(step);
+                },
+                _ => assert_eq!(map.get_cloned(&key), model.get(&key).cloned()),
+            }
+        }
+
+        assert_eq!(map.len(), model.len());
+        for (key, val) in &model {
+            assert_eq!(map.get_cloned(key), Some(*val));
+        }
+        map.validate();
     }
 
     #[test]
-    fn reinsert_from_other_map_fails() {
-        let other = Map::new();
-        other.insert(5, 3);
-        other.insert(0, 0);
-        let removed = other.remove(&5).unwrap();
-        let _active_read = other.get(&0).unwrap();
-        let map = Map::new();
-        map.reinsert(removed).failed().unwrap();
+    fn unordered_try_insert_race() {
+        const THREADS: usize = 8;
+        const KEYS: u64 = 64;
+        const ROUNDS: usize = 20;
+
+        // Every thread tries to insert every key into the same bucket, then
+        // to remove every key. Each key must be inserted, and then removed,
+        // by exactly one thread per round.
+        let map =
+            Arc::new(Map::<_, _, _, 8, Unordered>::with_fanout(ConstState));
+        let inserted = Arc::new(AtomicUsize::new(0));
+        let removed = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let inserted = inserted.clone();
+            let removed = removed.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                for _ in 0 .. ROUNDS {
+                    barrier.wait();
+                    // Each thread starts at a different key, so appends race
+                    // with removals of other keys of the bucket.
+                    for i in 0 .. KEYS {
+                        let key = EqKey((i + t as u64 * 8) % KEYS);
+                        if map.try_insert(key, t).is_ok() {
+                            inserted.fetch_add(1, Relaxed);
+                        }
+                    }
+                    barrier.wait();
+                    for i in 0 .. KEYS {
+                        let key = EqKey((i * 7 + t as u64) % KEYS);
+                        if map.remove(&key).is_some() {
+                            removed.fetch_add(1, Relaxed);
+                        }
+                    }
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        assert_eq!(inserted.load(Relaxed), KEYS as usize * ROUNDS);
+        assert_eq!(removed.load(Relaxed), KEYS as usize * ROUNDS);
+        assert!(map.is_empty());
+        map.validate();
     }
 
     #[test]
-    fn iter_valid_items() {
-        let map = Map::new();
-        for i in 0 .. 10u128 {
-            for j in 0 .. 32 {
-                map.insert((i, j), i << j);
-            }
-        }
+    fn unordered_upsert_multithreaded() {
+        const THREADS: u64 = 8;
+        const COUNTERS: u64 = 16;
+        const INCREMENTS: u64 = 500;
 
-        let mut result = HashMap::new();
-        for guard in &map {
-            let (k, v) = *guard;
-            let in_place = result.get(&(k, v)).map_or(0, |&x| x);
-            result.insert((k, v), in_place + 1);
+        // Counters are incremented while other keys of the same bucket are
+        // inserted and removed, so appends and updates race with the clean-up
+        // of removed entries.
+        let map =
+            Arc::new(Map::<_, _, _, 8, Unordered>::with_fanout(ConstState));
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. INCREMENTS {
+                    let counter = EqKey((i + t) % COUNTERS);
+                    map.upsert(counter, || 1, |count| count + 1);
+                    let churn = EqKey(1000 + t * INCREMENTS + i);
+                    map.insert(churn, 0);
+                    if i % 4 != 0 {
+                        assert!(map.remove(&churn).is_some());
+                    }
+                }
+            }));
         }
-
-        for i in 0 .. 10 {
-            for j in 0 .. 32 {
-                let pair = ((i, j), i << j);
-                assert_eq!(*result.get(&pair).unwrap(), 1);
-            }
+        for thread in threads {
+            thread.join().expect("thread failed");
         }
+
+        let total = (0 .. COUNTERS)
+            .map(|i| map.get_cloned(&EqKey(i)).unwrap())
+            .sum::<u64>();
+        assert_eq!(total, THREADS * INCREMENTS);
+        assert_eq!(map.len() as u64, COUNTERS + THREADS * INCREMENTS / 4);
+        map.validate();
     }
 
     #[test]
-    fn optimize_space_preserves_entries() {
+    fn exclusive_access() {
+        let shared = Arc::new(());
         let mut map = Map::new();
-        for i in 0 .. 200u128 {
-            for j in 0 .. 128 {
-                map.insert((i, j), i << j);
-            }
+        for i in 0 .. 100u64 {
+            assert!(map.insert_mut(i, shared.clone()).is_none());
         }
+        assert_eq!(map.len(), 100);
+        assert_eq!(Arc::strong_count(&shared), 101);
 
-        for i in 0 .. 200 {
-            for j in 0 .. 16 {
-                map.remove(&(i, j));
-            }
+        let (key, val) = map.insert_mut(7, Arc::new(())).unwrap();
+        assert_eq!(key, 7);
+        assert!(Arc::ptr_eq(&val, &shared));
+        drop(val);
+        assert_eq!(Arc::strong_count(&shared), 100);
+
+        let val = map.get_mut(&7).unwrap();
+        *val = shared.clone();
+        assert!(map.get_mut(&100).is_none());
+
+        for i in 0 .. 50u64 {
+            let (key, val) = map.remove_mut(&i).unwrap();
+            assert_eq!(key, i);
+            assert!(Arc::ptr_eq(&val, &shared));
         }
+        assert!(map.remove_mut(&0).is_none());
+        assert_eq!(map.len(), 50);
+        assert_eq!(Arc::strong_count(&shared), 51);
+        map.validate();
 
-        map.optimize_space();
+        drop(map);
+        assert_eq!(Arc::strong_count(&shared), 1);
+    }
 
-        let mut result = HashMap::new();
-        for guard in &map {
-            let (k, v) = *guard;
-            let in_place = result.get(&(k, v)).map_or(0, |&x| x);
-            result.insert((k, v), in_place + 1);
+    #[test]
+    fn exclusive_after_shared() {
+        const THREADS: u64 = 4;
+        const KEYS: u64 = 64;
+
+        // Every key shares the same bucket, and removals through a shared
+        // reference leave removed entries in the middle of it.
+        let map = Arc::new(Map::with_hasher(ConstState));
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for key in (t .. KEYS).step_by(THREADS as usize) {
+                    map.insert(key, key);
+                }
+                for key in (t .. KEYS).step_by(THREADS as usize * 2) {
+                    assert!(map.remove(&key).is_some());
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
         }
 
-        for i in 0 .. 200 {
-            for j in 16 .. 128 {
-                let pair = ((i, j), i << j);
-                assert_eq!(*result.get(&pair).unwrap(), 1);
+        let mut map = Arc::try_unwrap(map).unwrap();
+        assert_eq!(map.len() as u64, KEYS / 2);
+        for key in 0 .. KEYS {
+            let removed = key % (THREADS * 2) < THREADS;
+            assert_eq!(map.get_mut(&key).is_none(), removed);
+            if removed {
+                assert!(map.insert_mut(key, key * 10).is_none());
+            } else {
+                *map.get_mut(&key).unwrap() += 1;
+                assert_eq!(map.remove_mut(&key), Some((key, key + 1)));
             }
         }
+        assert_eq!(map.len() as u64, KEYS / 2);
+        map.validate();
+
+        // And back to a shared phase.
+        let map = Arc::new(map);
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            threads.push(thread::spawn(move || {
+                for key in (t .. KEYS).step_by(THREADS as usize) {
+                    if key % (THREADS * 2) < THREADS {
+                        assert_eq!(*map.remove(&key).unwrap().val(), key * 10);
+                    } else {
+                        assert!(map.insert(key, key).is_none());
+                    }
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+
+        let mut map = Arc::try_unwrap(map).unwrap();
+        for key in 0 .. KEYS {
+            let removed = key % (THREADS * 2) < THREADS;
+            assert_eq!(map.remove_mut(&key).is_none(), removed);
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.stats().leaves, 0);
+        map.validate();
     }
 
     #[test]
-    fn iter_mut_and_into_iter() {
-        let mut map = Map::new();
-        for i in 0 .. 10u128 {
-            for j in 0 .. 32 {
-                map.insert((i, j), i << j);
-            }
+    fn exclusive_deep_and_compact() {
+        let mut map =
+            Map::<u64, u64, ShiftState>::with_fanout_compact(ShiftState);
+        for key in 0 .. 200 {
+            assert!(map.insert_mut(key, key).is_none());
+        }
+        assert!(!map.is_compact(unsafe { &*map.top.load(Acquire) }));
+        assert_eq!(map.stats().entries, 200);
+        for key in 0 .. 200 {
+            assert_eq!(*map.get(&key).unwrap().val(), key);
         }
+        map.validate();
 
-        let mut result = HashMap::new();
-        for (k, v) in &mut map {
-            let in_place = result.get(&(*k, *v)).map_or(0, |&x| x);
-            result.insert((*k, *v), in_place + 1);
-            *v += 1;
+        for key in 0 .. 200 {
+            assert_eq!(map.remove_mut(&key), Some((key, key)));
         }
+        assert!(map.is_empty());
+        assert_eq!(map.stats().leaves, 0);
+        map.validate();
+    }
 
-        for i in 0 .. 10 {
-            for j in 0 .. 32 {
-                let pair = ((i, j), i << j);
-                assert_eq!(*result.get(&pair).unwrap(), 1);
-            }
+    #[test]
+    fn exclusive_unordered() {
+        let mut map = Map::<_, _, _, 8, Unordered>::with_fanout(ConstState);
+        for key in [3, 1, 2] {
+            assert!(map.insert_mut(EqKey(key), key).is_none());
         }
+        assert!(map.remove(&EqKey(1)).is_some());
+        assert_eq!(map.insert_mut(EqKey(3), 30), Some((EqKey(3), 3)));
+        assert!(map.insert_mut(EqKey(1), 1).is_none());
+        *map.get_mut(&EqKey(2)).unwrap() = 20;
 
-        result.clear();
+        let mut dump = String::new();
+        map.dump_structure(&mut dump).unwrap();
+        assert!(dump.ends_with(
+            "len 3: EqKey(3) => 30, EqKey(2) => 20, EqKey(1) => 1\n"
+        ));
+        assert_eq!(map.remove_mut(&EqKey(2)), Some((EqKey(2), 20)));
+        assert!(map.remove_mut(&EqKey(2)).is_none());
+        assert_eq!(map.len(), 2);
+        map.validate();
+    }
 
-        for (k, v) in map {
-            let in_place = result.get(&(k, v)).map_or(0, |&x| x);
-            result.insert((k, v), in_place + 1);
-        }
+    #[test]
+    fn first_entry_of_bucket() {
+        let map = Map::<u64, u64, _>::with_hasher(ConstState);
+        map.insert(5, 5);
+        assert_eq!(map.memory_usage().list_bytes, 0);
+        // Before, after and at the first entry, which is kept in the bucket.
+        assert!(map.insert(2, 2).is_none());
+        assert!(map.insert(8, 8).is_none());
+        assert_eq!(*map.insert(2, 20).unwrap().val(), 2);
+        let usage = map.memory_usage();
+        let entry_bytes = Bucket::entry_byte_size(&map.slabs);
+        assert_eq!(usage.list_bytes, (usage.entries - 1) * entry_bytes);
+        map.validate();
 
-        for i in 0 .. 10 {
-            for j in 0 .. 32 {
-                let pair = ((i, j), (i << j) + 1);
-                assert_eq!(*result.get(&pair).unwrap(), 1);
-            }
+        // Removing the first entry leaves a root entry in its place.
+        assert_eq!(*map.remove(&2).unwrap().val(), 20);
+        assert!(map.get(&2).is_none());
+        assert_eq!(map.get_cloned(&5), Some(5));
+        assert!(map.insert(1, 1).is_none());
+        map.validate();
+
+        let mut keys = map.keys_cloned();
+        keys.sort();
+        assert_eq!(keys, [1, 5, 8]);
+        for key in [1, 5, 8] {
+            assert_eq!(*map.remove(&key).unwrap().val(), key);
+            map.validate();
         }
+        assert!(map.is_empty());
+        assert_eq!(map.stats().leaves, 0);
+
+        // A lone first entry removed makes the whole bucket go away.
+        map.insert(3, 3);
+        assert_eq!(*map.remove(&3).unwrap().val(), 3);
+        assert_eq!(map.stats().leaves, 0);
+        map.validate();
+    }
+
+    #[test]
+    fn first_entry_of_bucket_exclusive() {
+        let mut map = Map::with_hasher(ConstState);
+        assert!(map.insert_mut(5, 5).is_none());
+        assert!(map.insert_mut(2, 2).is_none());
+        assert!(map.insert_mut(8, 8).is_none());
+        *map.get_mut(&2).unwrap() = 20;
+        map.validate();
+
+        // The entry following a removed first entry is moved into its place.
+        assert_eq!(map.remove_mut(&2), Some((2, 20)));
+        assert_eq!(map.get_mut(&5), Some(&mut 5));
+        map.validate();
+
+        // Likewise when the first entry was removed through a shared
+        // reference, leaving a root entry.
+        assert!(map.remove(&5).is_some());
+        assert!(map.insert_mut(0, 0).is_none());
+        assert_eq!(map.iter_mut().count(), 2);
+        map.validate();
+
+        assert!(map.remove(&0).is_some());
+        let mut pairs = map.into_iter().collect::<Vec<_>>();
+        pairs.sort();
+        assert_eq!(pairs, [(8, 8)]);
     }
 
     #[test]
@@ -817,5 +8228,6 @@ mod test {
             let val = *map.get(&format!("prefix{}suffix", i)).unwrap().val();
             assert!(val > 0);
         }
+        map.validate();
     }
 }
@@ -0,0 +1,369 @@
+use super::{bucket::Garbage, DefaultHashBuilder, Map, Removed};
+use alloc::vec::{self, Vec};
+use core::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash, Hasher},
+    iter::FusedIterator,
+    ptr,
+    sync::atomic::{AtomicU64, Ordering::*},
+};
+use incin::Pause;
+
+/// A [`Map`] storing any number of values for each key, e.g. an index of
+/// events by user. Each value is stored in an entry of its own, whose key is
+/// a [`MultiKey`]: the given key along with a number taken from a counter on
+/// insertion. Since only the given key is hashed, the values of a key share a
+/// bucket, whose lock-free list keeps them in the order of their numbers, and
+/// they are inserted and removed just like any entry of a [`Map`].
+///
+/// Looking up the values of a key walks its bucket, so keys holding many
+/// values make their own lookups, insertions and removals slower, and those
+/// of the keys colliding with them.
+pub struct MultiMap<K, V, H = DefaultHashBuilder> {
+    inner: Map<MultiKey<K>, V, H>,
+    next_seq: AtomicU64,
+}
+
+impl<K, V> MultiMap<K, V> {
+    /// Creates a [`MultiMap`] with the default hasher builder.
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V> Default for MultiMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, H> MultiMap<K, V, H> {
+    /// The number of values of all keys. The same considerations of
+    /// [`Map::len`] apply.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns whether there are no values. The same considerations of
+    /// [`Map::len`] apply.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Consumes this [`MultiMap`] into the [`Map`] holding its values.
+    pub fn into_inner(self) -> Map<MultiKey<K>, V, H> {
+        self.inner
+    }
+}
+
+impl<K, V, H> MultiMap<K, V, H>
+where
+    H: BuildHasher,
+{
+    /// Creates a [`MultiMap`] using the given hasher builder.
+    pub fn with_hasher(builder: H) -> Self {
+        Self { inner: Map::with_hasher(builder), next_seq: AtomicU64::new(0) }
+    }
+
+    /// Adds the given value to the values of the given key, after those
+    /// already there.
+    pub fn insert(&self, key: K, val: V)
+    where
+        K: Hash + Ord,
+    {
+        let seq = self.next_seq.fetch_add(1, Relaxed);
+        // The number is unique, so nothing is replaced.
+        let replaced = self.inner.insert(MultiKey { key, seq }, val);
+        debug_assert!(replaced.is_none());
+    }
+
+    /// Calls the given closure on an iterator over the values of the given
+    /// key, in the order they were inserted, returning what the closure
+    /// returns. The values are read under a single pause of the incinerator,
+    /// held until the closure returns. Values inserted or removed
+    /// concurrently may or may not be visited. This method will only work
+    /// correctly if [`Hash`] and [`Eq`] are implemented in the same way for
+    /// the borrowed type and the stored type.
+    pub fn get_all<Q, F, T>(&self, key: &Q, reader: F) -> T
+    where
+        Q: ?Sized + Hash + Eq,
+        K: Borrow<Q>,
+        F: FnOnce(Values<'_, K, V>) -> T,
+    {
+        let pause = self.inner.pause();
+        // Safe because the pairs are only read while paused.
+        let pairs = unsafe { self.entries(key, &pause) };
+        reader(Values { inner: pairs.into_iter() })
+    }
+
+    /// Tests whether the given key has any value.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Eq,
+        K: Borrow<Q>,
+    {
+        self.get_all(key, |mut values| values.next().is_some())
+    }
+
+    /// Removes the first value of the given key equal to the given one, and
+    /// returns its entry. If another thread removes it first, the next equal
+    /// value is looked for.
+    pub fn remove_value<Q>(
+        &self,
+        key: &Q,
+        val: &V,
+    ) -> Option<Removed<MultiKey<K>, V>>
+    where
+        Q: ?Sized + Hash + Eq,
+        K: Borrow<Q> + Hash + Ord,
+        V: PartialEq,
+    {
+        loop {
+            let pause = self.inner.pause();
+            // Safe because the pairs are only read while paused.
+            let pairs = unsafe { self.entries(key, &pause) };
+            let pair = pairs.into_iter().find(|pair| pair.1 == *val)?;
+            if let Some(removed) = self.remove_pair(pair) {
+                break Some(removed);
+            }
+        }
+    }
+
+    /// Removes every value of the given key, and returns their entries in the
+    /// order the values were inserted. Values inserted concurrently may be
+    /// left, and values removed concurrently are not returned, so each entry
+    /// is only ever returned by one removal.
+    pub fn remove_all<Q>(&self, key: &Q) -> Vec<Removed<MultiKey<K>, V>>
+    where
+        Q: ?Sized + Hash + Eq,
+        K: Borrow<Q> + Hash + Ord,
+    {
+        let pause = self.inner.pause();
+        // Safe because the pairs are only read while paused.
+        let pairs = unsafe { self.entries(key, &pause) };
+        pairs.into_iter().filter_map(|pair| self.remove_pair(pair)).collect()
+    }
+
+    // Collects the entries of the given key, in the order of their numbers.
+    // Unsafe because they may only be read while the given pause is alive.
+    unsafe fn entries<Q>(
+        &self,
+        key: &Q,
+        pause: &Pause<Garbage<MultiKey<K>, V>>,
+    ) -> Vec<&(MultiKey<K>, V)>
+    where
+        Q: ?Sized + Hash + Eq,
+        K: Borrow<Q>,
+    {
+        let hash = self.inner.hash_of(key);
+        let mut pairs = Vec::new();
+        self.inner.top(pause).collect_hash(hash, &mut pairs);
+        pairs.retain(|pair| pair.0.key.borrow() == key);
+        pairs
+    }
+
+    // Removes the given entry if it is still stored.
+    fn remove_pair(
+        &self,
+        pair: &(MultiKey<K>, V),
+    ) -> Option<Removed<MultiKey<K>, V>>
+    where
+        K: Hash + Ord,
+    {
+        self.inner.remove_with(&pair.0, |stored| ptr::eq(stored, pair))
+    }
+}
+
+impl<K, V, H> fmt::Debug for MultiMap<K, V, H>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("MultiMap").field("inner", &self.inner).finish()
+    }
+}
+
+/// The key of an entry of a [`MultiMap`]: the key a value was inserted with,
+/// along with the number telling the values of the key apart. Only the key
+/// is hashed, while both are compared.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MultiKey<K> {
+    key: K,
+    seq: u64,
+}
+
+impl<K> MultiKey<K> {
+    /// The key the value was inserted with.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The number of the value, which grows with each insertion into the
+    /// [`MultiMap`].
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Consumes this [`MultiKey`] into the key the value was inserted with.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+impl<K> Hash for MultiKey<K>
+where
+    K: Hash,
+{
+    fn hash<S>(&self, state: &mut S)
+    where
+        S: Hasher,
+    {
+        self.key.hash(state)
+    }
+}
+
+/// An iterator over references to the values of a key of a [`MultiMap`],
+/// given by [`MultiMap::get_all`].
+pub struct Values<'map, K, V>
+where
+    K: 'map,
+    V: 'map,
+{
+    inner: vec::IntoIter<&'map (MultiKey<K>, V)>,
+}
+
+impl<'map, K, V> Iterator for Values<'map, K, V> {
+    type Item = &'map V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|pair| &pair.1)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'map, K, V> ExactSizeIterator for Values<'map, K, V> {}
+
+impl<'map, K, V> FusedIterator for Values<'map, K, V> {}
+
+impl<'map, K, V> fmt::Debug for Values<'map, K, V>
+where
+    V: fmt::Debug,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        let values = self.inner.as_slice().iter().map(|pair| &pair.1);
+        fmtr.debug_list().entries(values).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use map::support::ConstState;
+    use std::{collections::HashSet, sync::Arc, thread};
+    use std::prelude::v1::*;
+
+    #[test]
+    fn values_of_keys() {
+        let map = MultiMap::new();
+        for i in 0 .. 10u64 {
+            map.insert("even".to_owned(), i * 2);
+            map.insert("odd".to_owned(), i * 2 + 1);
+        }
+        map.insert("even".to_owned(), 0);
+        assert_eq!(map.len(), 21);
+
+        let even = map.get_all("even", |values| {
+            values.copied().collect::<Vec<_>>()
+        });
+        let mut expected = (0 .. 10).map(|i| i * 2).collect::<Vec<_>>();
+        expected.push(0);
+        assert_eq!(even, expected);
+        assert_eq!(map.get_all("odd", |values| values.len()), 10);
+        assert!(!map.contains_key("none"));
+
+        let removed = map.remove_value("even", &0).unwrap();
+        assert_eq!((removed.key().key().as_str(), *removed.val()), ("even", 0));
+        assert_eq!(map.get_all("even", |values| values.count()), 10);
+        let first = map.get_all("even", |mut values| values.next().copied());
+        assert_eq!(first, Some(2));
+        assert!(map.remove_value("even", &1).is_none());
+
+        let removed = map.remove_all("odd");
+        let odd = removed.iter().map(|removed| *removed.val());
+        let expected = (0 .. 10).map(|i| i * 2 + 1);
+        assert_eq!(odd.collect::<Vec<_>>(), expected.collect::<Vec<_>>());
+        assert!(removed.windows(2).all(|pair| pair[0].key() < pair[1].key()));
+        assert!(!map.contains_key("odd"));
+        assert!(map.remove_all("odd").is_empty());
+        assert_eq!(map.len(), 10);
+        map.into_inner().validate();
+    }
+
+    #[test]
+    fn colliding_keys() {
+        let map = MultiMap::with_hasher(ConstState);
+        for i in 0 .. 50u64 {
+            map.insert(i % 5, i);
+        }
+
+        for key in 0 .. 5 {
+            let values = map.get_all(&key, |values| {
+                values.copied().collect::<Vec<_>>()
+            });
+            let expected = (0 .. 10).map(|i| i * 5 + key).collect::<Vec<_>>();
+            assert_eq!(values, expected);
+        }
+        assert_eq!(map.remove_all(&3).len(), 10);
+        assert!(map.remove_value(&2, &17).is_some());
+        assert_eq!(map.get_all(&2, |values| values.len()), 9);
+        assert_eq!(map.get_all(&4, |values| values.len()), 10);
+        assert_eq!(map.len(), 39);
+        map.into_inner().validate();
+    }
+
+    #[test]
+    fn append_while_draining() {
+        const THREADS: u64 = 4;
+        const VALUES: u64 = 2000;
+
+        let map = Arc::new(MultiMap::new());
+        let threads = (0 .. THREADS)
+            .map(|t| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0 .. VALUES {
+                        map.insert("events", t * VALUES + i);
+                        // Another key, so removals by value race too.
+                        map.insert("other", i);
+                        if i % 2 == 0 {
+                            assert!(map.remove_value("other", &i).is_some());
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut drained = Vec::new();
+        while threads.iter().any(|thread| !thread.is_finished()) {
+            for removed in map.remove_all("events") {
+                assert_eq!(*removed.key().key(), "events");
+                drained.push(*removed.val());
+            }
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        drained.extend(map.remove_all("events").iter().map(|r| *r.val()));
+
+        let unique = drained.iter().collect::<HashSet<_>>();
+        assert_eq!(unique.len(), drained.len());
+        assert_eq!(drained.len() as u64, THREADS * VALUES);
+        assert!(!map.contains_key("events"));
+        assert_eq!(map.len() as u64, THREADS * VALUES / 2);
+    }
+}
@@ -0,0 +1,62 @@
+use core::cmp::Ordering;
+
+/// How the entries of a bucket of a [`Map`](super::Map) are kept, chosen by
+/// the last type parameter of the [`Map`](super::Map). Keys of type `Q` can be
+/// searched in a bucket kept this way. Implemented by [`Ordered`] and
+/// [`Unordered`]; the comparison must be consistent with [`Eq`].
+pub trait BucketOrder<Q>
+where
+    Q: ?Sized,
+{
+    /// Compares the searched key with a stored key, walking the bucket from
+    /// its start. [`Ordering::Greater`] means the search goes on after the
+    /// stored key, [`Ordering::Less`] means the searched key would be placed
+    /// before the stored key, so the search stops, and [`Ordering::Equal`]
+    /// means the keys are equal.
+    fn compare(key: &Q, stored: &Q) -> Ordering;
+}
+
+/// Keeps the entries of each bucket ordered by key, which requires
+/// `K: Ord`. A search stops as soon as it passes the place of the key. This is
+/// the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ordered;
+
+impl<Q> BucketOrder<Q> for Ordered
+where
+    Q: ?Sized + Ord,
+{
+    #[inline]
+    fn compare(key: &Q, stored: &Q) -> Ordering {
+        key.cmp(stored)
+    }
+}
+
+/// Keeps the entries of each bucket in insertion order, so `K: Eq` is enough.
+/// A search for an absent key walks the whole bucket, but buckets only hold
+/// more than one entry when hashes collide, so this is rarely noticeable. New
+/// entries are appended at the end of the bucket.
+///
+/// The [`Map`](super::Map) is still linearizable: an entry is appended by
+/// replacing the last entry of the bucket, which was read after every other
+/// entry was checked against the key, through a compare-and-swap. Entries are
+/// only added at the end, and the last entry only stops being the last one if
+/// something is appended to it, or if it is removed, and both replace it. So,
+/// if the swap succeeds, no entry with the key was added since the search,
+/// and the check of every other entry still holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Unordered;
+
+impl<Q> BucketOrder<Q> for Unordered
+where
+    Q: ?Sized + Eq,
+{
+    #[inline]
+    fn compare(key: &Q, stored: &Q) -> Ordering {
+        if key == stored {
+            Ordering::Equal
+        } else {
+            Ordering::Greater
+        }
+    }
+}
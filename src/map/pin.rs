@@ -0,0 +1,316 @@
+use super::{
+    bucket::Garbage,
+    given_hash,
+    trace::Hold,
+    BucketOrder,
+    DefaultHashBuilder,
+    Map,
+    Ordered,
+    Removed,
+};
+use core::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash},
+};
+use incin::Pause;
+
+/// A guard pausing the incinerator of a [`Map`] once for many operations,
+/// returned by [`Map::pin`]. Every operation of a [`Map`] pauses the
+/// incinerator on its own, which costs two atomic read-modify-write
+/// operations on a counter shared by every thread; the operations of this
+/// guard reuse its pause instead, and the entries it finds are plain
+/// references valid while it is alive.
+///
+/// **No memory detached from the [`Map`], by any thread, is reclaimed while
+/// the guard is alive**: replaced and removed entries, and retired nodes and
+/// tables, pile up until every pause is released. Pin for a batch of
+/// operations, e.g. a tight loop, and drop the guard right after it. With the
+/// `tracing` and `std` features, a warning event is emitted if a guard is
+/// dropped more than a second after it was created.
+#[must_use = "the incinerator is only paused while the guard is alive"]
+pub struct Pinned<
+    'map,
+    K,
+    V,
+    H = DefaultHashBuilder,
+    const BITS: usize = 8,
+    O = Ordered,
+> where
+    K: 'map,
+    V: 'map,
+{
+    map: &'map Map<K, V, H, BITS, O>,
+    pause: Pause<'map, Garbage<K, V>>,
+    hold: Hold,
+}
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O> {
+    /// Pauses the incinerator once for a batch of operations, performed
+    /// through the returned guard, until it is dropped. Memory reclamation is
+    /// stalled for every thread meanwhile; see [`Pinned`].
+    pub fn pin(&self) -> Pinned<'_, K, V, H, BITS, O> {
+        Pinned {
+            map: self,
+            pause: self.pause(),
+            hold: Hold::new(),
+        }
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> Pinned<'map, K, V, H, BITS, O> {
+    /// The pinned [`Map`].
+    pub fn map(&self) -> &'map Map<K, V, H, BITS, O> {
+        self.map
+    }
+
+    /// The number of entries. See [`Map::len`].
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns whether there are no entries. See [`Map::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> Pinned<'map, K, V, H, BITS, O>
+where
+    H: BuildHasher,
+{
+    /// Searches for the entry identified by the given key, just like
+    /// [`Map::get`], but the entry is borrowed from this guard, which keeps
+    /// it valid even if it is removed meanwhile.
+    pub fn get<Q>(&self, key: &Q) -> Option<&(K, V)>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        let hash = self.map.hash_of(key);
+        let salted = || self.map.salted_of(key);
+        let top = self.map.top(&self.pause);
+        // Safe because the guard paused properly, and the pair is borrowed
+        // from it.
+        unsafe { top.get_paused::<O, Q, _>(key, hash, salted, &self.pause) }
+    }
+
+    /// Searches for the entry identified by the given key under the given
+    /// hash, just like [`get`](Pinned::get). See
+    /// [`Map::get_hashed`] for the contract on the given hashes.
+    pub fn get_hashed<Q>(&self, hash: u64, key: &Q) -> Option<&(K, V)>
+    where
+        Q: ?Sized + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        let hash = given_hash(hash);
+        let top = self.map.top(&self.pause);
+        // Safe because the guard paused properly, and the pair is borrowed
+        // from it.
+        unsafe { top.get_paused::<O, Q, _>(key, hash, || None, &self.pause) }
+    }
+
+    /// Tests if the entry identified by the given key is present. See
+    /// [`Map::contains_key`].
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Inserts unconditionally the given key and value. See [`Map::insert`].
+    pub fn insert(&self, key: K, val: V) -> Option<Removed<K, V>>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let events = self.map.events();
+        self.map.insert_paused(key, val, &self.pause, &events)
+    }
+
+    /// Inserts unconditionally the given key and value under the given hash.
+    /// See [`Map::insert_hashed`].
+    pub fn insert_hashed(
+        &self,
+        hash: u64,
+        key: K,
+        val: V,
+    ) -> Option<Removed<K, V>>
+    where
+        K: Eq,
+        O: BucketOrder<K>,
+    {
+        let events = self.map.events();
+        let hash = given_hash(hash);
+        self.map.insert_with_hash(key, val, hash, &self.pause, &events)
+    }
+
+    /// Removes unconditionally the entry identified by the given key. See
+    /// [`Map::remove`].
+    pub fn remove<Q>(&self, key: &Q) -> Option<Removed<K, V>>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        self.remove_with(key, |_| true)
+    }
+
+    /// Removes unconditionally the entry identified by the given key under
+    /// the given hash. See [`Map::remove_hashed`].
+    pub fn remove_hashed<Q>(&self, hash: u64, key: &Q) -> Option<Removed<K, V>>
+    where
+        Q: ?Sized + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        let events = self.map.events();
+        let hash = given_hash(hash);
+        let map = self.map;
+        map.remove_with_hash(key, hash, || None, |_| true, &self.pause, &events)
+    }
+
+    /// Removes _interactively_ the entry identified by the given key. See
+    /// [`Map::remove_with`].
+    pub fn remove_with<Q, F>(
+        &self,
+        key: &Q,
+        interactive: F,
+    ) -> Option<Removed<K, V>>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        F: FnMut(&(K, V)) -> bool,
+    {
+        let events = self.map.events();
+        let map = self.map;
+        let hash = map.hash_of(key);
+        let salted = || map.salted_of(key);
+        let pause = &self.pause;
+        map.remove_with_hash(key, hash, salted, interactive, pause, &events)
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> fmt::Debug
+    for Pinned<'map, K, V, H, BITS, O>
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("Pinned").field("len", &self.map.len()).finish()
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> Drop
+    for Pinned<'map, K, V, H, BITS, O>
+{
+    fn drop(&mut self) {
+        self.hold.release("Pinned");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering::*};
+    use std::{sync::Barrier, thread};
+    use std::prelude::v1::*;
+
+    #[derive(Debug)]
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Relaxed);
+        }
+    }
+
+    #[test]
+    fn pinned_operations() {
+        let map = Map::new();
+        map.insert(1, 10);
+        let pinned = map.pin();
+        assert_eq!(pinned.get(&1), Some(&(1, 10)));
+        assert!(pinned.insert(2, 20).is_none());
+        assert_eq!(*pinned.insert(1, 11).unwrap().val(), 10);
+        assert!(pinned.contains_key(&2));
+        assert_eq!(pinned.len(), 2);
+
+        // A found entry outlives its removal while pinned.
+        let found = pinned.get(&2).unwrap();
+        assert_eq!(*pinned.remove(&2).unwrap().val(), 20);
+        assert_eq!(*found, (2, 20));
+        assert!(pinned.remove_with(&1, |&(_, val)| val == 10).is_none());
+        assert!(!pinned.is_empty());
+
+        assert!(pinned.insert_hashed(7, 3, 30).is_none());
+        assert_eq!(pinned.get_hashed(7, &3), Some(&(3, 30)));
+        assert!(pinned.get(&3).is_none());
+        assert_eq!(*pinned.remove_hashed(7, &3).unwrap().val(), 30);
+        drop(pinned);
+
+        assert_eq!(map.get(&1).map(|guard| *guard.val()), Some(11));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn garbage_reclaimed_after_pin() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let map = Map::new();
+        let pinned = map.pin();
+        for i in 0 .. 100 {
+            pinned.insert(i % 10, Counted(drops.clone()));
+        }
+        for i in 0 .. 5 {
+            pinned.remove(&i);
+        }
+        // Nothing replaced or removed was dropped while pinned.
+        assert_eq!(drops.load(Relaxed), 0);
+        assert!(map.incin.inner.pending() > 0);
+        drop(pinned);
+
+        // Releasing the last pause reclaims all of it.
+        assert_eq!(drops.load(Relaxed), 95);
+        assert_eq!(map.incin.inner.pending(), 0);
+        assert_eq!(map.len(), 5);
+    }
+
+    #[test]
+    fn pinned_threads() {
+        const THREADS: usize = 4;
+        const KEYS: usize = 1000;
+
+        let map = Arc::new(Map::new());
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let threads = (0 .. THREADS)
+            .map(|id| {
+                let map = map.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for round in 0 .. 10 {
+                        let pinned = map.pin();
+                        for i in 0 .. KEYS {
+                            let key = i * THREADS + id;
+                            pinned.insert(key, round);
+                            assert_eq!(pinned.get(&key), Some(&(key, round)));
+                            if i % 2 == 0 {
+                                pinned.remove(&key);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        assert_eq!(map.len(), THREADS * KEYS / 2);
+        map.validate();
+    }
+}
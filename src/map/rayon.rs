@@ -0,0 +1,206 @@
+use super::{visit::Segment, BucketOrder, Map, Ordered, BATCH_LEN};
+use alloc::vec::Vec;
+use core::hash::{BuildHasher, Hash};
+use rayon::iter::{
+    self,
+    plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer},
+    IntoParallelIterator,
+    ParallelExtend,
+    ParallelIterator,
+};
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O>
+where
+    K: Send + Sync,
+    V: Send + Sync,
+    H: Sync,
+{
+    /// Calls the given closure on every entry of the [`Map`], from the threads
+    /// of the current rayon pool. The [`Map`] is split into parts made of
+    /// ranges of nodes of its tables, and a part made of a single node holding
+    /// a sub-table is split into ranges of the sub-table, so the work is
+    /// balanced even if the hashes are skewed. Each part is visited just like
+    /// [`for_each`](Map::for_each) does, pausing the incinerator only while
+    /// small chunks of the [`Map`] are read, with the same guarantees about
+    /// concurrent modifications.
+    pub fn par_visit<F>(&self, visitor: F)
+    where
+        F: Fn(&K, &V) + Sync + Send,
+    {
+        iter::split(Part::new(self), Part::split)
+            .for_each(|part| part.visit(|key, val| visitor(key, val)))
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> IntoParallelIterator
+    for &'map Map<K, V, H, BITS, O>
+where
+    K: Clone + Send + Sync,
+    V: Clone + Send + Sync,
+    H: Sync,
+{
+    type Item = (K, V);
+    type Iter = ParIter<'map, K, V, H, BITS, O>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        ParIter { part: Part::new(self) }
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> ParallelExtend<(K, V)>
+    for Map<K, V, H, BITS, O>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Send + Sync,
+    H: BuildHasher + Sync,
+    O: BucketOrder<K>,
+{
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        (&*self).par_extend(par_iter)
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> ParallelExtend<(K, V)>
+    for &Map<K, V, H, BITS, O>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Send + Sync,
+    H: BuildHasher + Sync,
+    O: BucketOrder<K>,
+{
+    /// Inserts unconditionally every entry of the parallel iterator, from the
+    /// threads of the current rayon pool. Each thread gathers the entries it
+    /// takes into batches, and inserts each batch under a single incinerator
+    /// pause, just like [`Map::extend`]. Entries with the same key replace
+    /// each other just like in [`insert`](Map::insert): the one inserted last
+    /// is kept, which is not necessarily the last one of the iterator.
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        let map = *self;
+        par_iter
+            .into_par_iter()
+            .fold(
+                || Vec::with_capacity(BATCH_LEN),
+                |mut batch, pair| {
+                    batch.push(pair);
+                    if batch.len() >= BATCH_LEN {
+                        map.insert_batch(&mut batch, drop);
+                    }
+                    batch
+                },
+            )
+            .for_each(|mut batch| map.insert_batch(&mut batch, drop));
+    }
+}
+
+/// A parallel iterator over clones of the entries of a [`Map`], created by
+/// its [`IntoParallelIterator`] implementation. The [`Map`] is split just like
+/// in [`par_visit`](Map::par_visit).
+pub struct ParIter<'map, K, V, H, const BITS: usize = 8, O = Ordered>
+where
+    K: 'map,
+    V: 'map,
+    H: 'map,
+{
+    part: Part<'map, K, V, H, BITS, O>,
+}
+
+impl<'map, K, V, H, const BITS: usize, O> ParallelIterator
+    for ParIter<'map, K, V, H, BITS, O>
+where
+    K: Clone + Send + Sync,
+    V: Clone + Send + Sync,
+    H: Sync,
+{
+    type Item = (K, V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        bridge_unindexed(self.part, consumer)
+    }
+}
+
+// A part of the map visited by a single task.
+pub(super) struct Part<'map, K, V, H, const BITS: usize, O>
+where
+    K: 'map,
+    V: 'map,
+    H: 'map,
+{
+    map: &'map Map<K, V, H, BITS, O>,
+    segment: Segment,
+}
+
+impl<'map, K, V, H, const BITS: usize, O> Part<'map, K, V, H, BITS, O> {
+    pub(super) fn new(map: &'map Map<K, V, H, BITS, O>) -> Self {
+        Self { map, segment: Segment::whole() }
+    }
+
+    pub(super) fn split(mut self) -> (Self, Option<Self>) {
+        let pause = self.map.pause();
+        let top = self.map.top(&pause);
+        // The tables of a compact map are retired when it is upgraded, so
+        // it is visited as a single part, under a single pause. It is small
+        // anyway.
+        if self.map.is_compact(top) {
+            return (self, None);
+        }
+
+        // Safe because we paused the incinerator.
+        let range = unsafe { self.segment.descend(top) };
+        if range.len() < 2 {
+            return (self, None);
+        }
+        let other = self.segment.split_at(range.start + range.len() / 2);
+        let map = self.map;
+        (self, Some(Self { map, segment: other }))
+    }
+
+    pub(super) fn visit<F>(&self, visitor: F)
+    where
+        F: FnMut(&K, &V),
+    {
+        self.map.walk_top(|top| {
+            self.segment.visit(top, &self.map.incin.inner, visitor)
+        })
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> UnindexedProducer
+    for Part<'map, K, V, H, BITS, O>
+where
+    K: Clone + Send + Sync,
+    V: Clone + Send + Sync,
+    H: Sync,
+{
+    type Item = (K, V);
+
+    fn split(self) -> (Self, Option<Self>) {
+        Part::split(self)
+    }
+
+    fn fold_with<F>(self, folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        let mut folder = Some(folder);
+        self.visit(|key, val| {
+            if let Some(inner) = folder.take() {
+                folder = Some(if inner.full() {
+                    inner
+                } else {
+                    inner.consume((key.clone(), val.clone()))
+                });
+            }
+        });
+        // The folder is always put back.
+        folder.unwrap()
+    }
+}
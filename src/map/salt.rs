@@ -0,0 +1,273 @@
+use super::{fixed::FixedHasher, Map};
+use core::hash::{BuildHasher, Hash, Hasher};
+
+#[cfg(feature = "std")]
+use super::RandomState;
+#[cfg(not(feature = "std"))]
+use super::FixedState;
+#[cfg(not(feature = "std"))]
+use core::sync::atomic::{AtomicUsize, Ordering::*};
+
+// The salt of a map with flood protection. Keys whose plain hashes are equal
+// are told apart by a SipHash keyed with the salt, which is taken at random
+// for each map, so an adversary who can make plain hashes collide still
+// cannot predict the salted ones. Only keys reaching past the deepest level
+// of the plain hash are ever hashed with the salt.
+pub struct Salt<K> {
+    keys: (u64, u64),
+    // Hashes a stored key with the salt. Captured when the map is created,
+    // since the tree does not require stored keys to be `Hash` otherwise.
+    hash_stored: fn(&K, (u64, u64)) -> u64,
+}
+
+impl<K> Clone for Salt<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K> Copy for Salt<K> {}
+
+impl<K> Salt<K> {
+    pub fn new() -> Self
+    where
+        K: Hash,
+    {
+        Self { keys: random_keys(), hash_stored: salted_hash::<K> }
+    }
+
+    // The salted hash of a searched key.
+    pub fn hash<Q>(&self, key: &Q) -> u64
+    where
+        Q: ?Sized + Hash,
+    {
+        salted_hash(key, self.keys)
+    }
+
+    // The salted hash of a stored key, the same as `hash` for a key borrowed
+    // from it.
+    pub fn hash_stored(&self, key: &K) -> u64 {
+        (self.hash_stored)(key, self.keys)
+    }
+}
+
+fn salted_hash<Q>(key: &Q, (key0, key1): (u64, u64)) -> u64
+where
+    Q: ?Sized + Hash,
+{
+    let mut hasher = FixedHasher::with_keys(key0, key1);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(feature = "std")]
+fn random_keys() -> (u64, u64) {
+    let builder = RandomState::new();
+    (builder.hash_one(0u8), builder.hash_one(1u8))
+}
+
+// Without `std` there is no source of randomness, so the keys follow a
+// sequence shared by every map instead. They still differ from map to map, but
+// an adversary who knows how many maps were created can predict them.
+#[cfg(not(feature = "std"))]
+fn random_keys() -> (u64, u64) {
+    static NEXT_SALT: AtomicUsize = AtomicUsize::new(0);
+    let seq = NEXT_SALT.fetch_add(1, Relaxed);
+    (FixedState.hash_one(seq), FixedState.hash_one(!seq))
+}
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+{
+    /// Creates the [`Map`] using the given hasher builder, with tables of
+    /// `1 << BITS` nodes, protected against keys chosen to make their hashes
+    /// collide, e.g. by an adversary who knows the hasher. Keys whose hashes
+    /// are equal share a bucket, which turns looking them up into a linear
+    /// scan. With flood protection, they are told apart by a second hash
+    /// instead, keyed by a random salt of this [`Map`], so they spread over
+    /// sub-tables below the deepest level reached by the plain hash. Lookups
+    /// of these keys then take as many levels as in any [`Map`] of the same
+    /// size, plus the levels of the plain hash.
+    ///
+    /// The salted hash is only computed for keys whose hashes are equal to the
+    /// hash of some other key, so keys whose hashes never collide cost the
+    /// same as in any [`Map`]. Without the `std` feature, salts are not random,
+    /// only different for each [`Map`]. Clones of the [`Map`] are protected
+    /// too, with a salt of their own.
+    ///
+    /// Operations ending in `_hashed`, e.g. [`get_hashed`](Map::get_hashed),
+    /// cannot hash the keys they search for with the salt, so they scan the
+    /// keys sharing the given hash, if these were spread.
+    pub fn with_flood_protection(builder: H) -> Self
+    where
+        K: Hash,
+    {
+        let mut this = Self::with_fanout(builder);
+        this.slabs.salt = Some(Salt::new());
+        this
+    }
+
+    /// Returns whether this [`Map`] was created with
+    /// [`with_flood_protection`](Map::with_flood_protection).
+    pub fn is_flood_protected(&self) -> bool {
+        self.slabs.salt.is_some()
+    }
+
+    // The salted hash of the given key, with flood protection.
+    pub(super) fn salted_of<Q>(&self, key: &Q) -> Option<u64>
+    where
+        Q: ?Sized + Hash,
+    {
+        self.slabs.salt.as_ref().map(|salt| salt.hash(key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::map::{support::ConstState, table, FixedState};
+    use alloc::sync::Arc;
+    use std::{sync::Barrier, thread};
+    use std::prelude::v1::*;
+
+    type Flooded = Map<u32, u32, ConstState>;
+    type Fixed = Map<u32, u32, FixedState>;
+
+    const KEYS: u32 = 4000;
+
+    #[test]
+    fn colliding_keys_spread() {
+        let plain = Flooded::with_fanout(ConstState);
+        let map = Flooded::with_flood_protection(ConstState);
+        assert!(!plain.is_flood_protected());
+        assert!(map.is_flood_protected());
+        for i in 0 .. KEYS {
+            plain.insert(i, i);
+            map.insert(i, i);
+        }
+        assert_eq!(plain.stats().max_bucket_len(), KEYS as usize);
+
+        let stats = map.stats();
+        assert_eq!(stats.max_bucket_len(), 1);
+        assert_eq!(stats.entries, KEYS as usize);
+        assert!(stats.max_depth <= table::max_depth(8) + 4);
+        map.validate();
+
+        for i in 0 .. KEYS {
+            assert_eq!(map.get(&i).map(|guard| *guard.val()), Some(i));
+            assert_eq!(map.insert(i, i + 1).map(|old| *old.val()), Some(i));
+        }
+        assert!(map.get(&KEYS).is_none());
+        for i in (0 .. KEYS).step_by(2) {
+            assert_eq!(map.remove(&i).map(|old| *old.val()), Some(i + 1));
+        }
+        for i in 0 .. KEYS {
+            assert_eq!(map.contains_key(&i), i % 2 == 1);
+        }
+        assert_eq!(map.len(), KEYS as usize / 2);
+        map.validate();
+
+        map.shrink();
+        map.validate();
+        for i in (1 .. KEYS).step_by(2) {
+            assert_eq!(map.get(&i).map(|guard| *guard.val()), Some(i + 1));
+        }
+    }
+
+    #[test]
+    fn distinct_hashes_unchanged() {
+        let plain = Fixed::with_fanout(FixedState);
+        let map = Fixed::with_flood_protection(FixedState);
+        for i in 0 .. KEYS {
+            plain.insert(i, i);
+            map.insert(i, i);
+        }
+        assert_eq!(map.stats(), plain.stats());
+        map.validate();
+    }
+
+    #[test]
+    fn hashed_operations_scan() {
+        let map = Flooded::with_flood_protection(ConstState);
+        for i in 0 .. 1000 {
+            assert!(map.insert_hashed(7, i, i).is_none());
+        }
+        assert_eq!(map.stats().max_bucket_len(), 1);
+        for i in 0 .. 1000 {
+            let found = map.get_hashed(7, &i).map(|guard| *guard.val());
+            assert_eq!(found, Some(i));
+            assert!(map.get(&i).is_none());
+        }
+        assert!(map.get_hashed(7, &1000).is_none());
+        for i in 0 .. 500 {
+            assert_eq!(map.remove_hashed(7, &i).map(|old| *old.val()), Some(i));
+        }
+        assert!(map.get_hashed(7, &0).is_none());
+        assert_eq!(map.len(), 500);
+    }
+
+    #[test]
+    fn exclusive_operations() {
+        let mut map = Flooded::with_flood_protection(ConstState);
+        for i in 0 .. 1000 {
+            assert!(map.insert_mut(i, i).is_none());
+        }
+        assert_eq!(map.stats().max_bucket_len(), 1);
+        for i in 0 .. 1000 {
+            *map.get_mut(&i).unwrap() += 1;
+        }
+        assert_eq!(map.insert_mut(3, 0), Some((3, 4)));
+        for i in (0 .. 1000).step_by(2) {
+            assert!(map.remove_mut(&i).is_some());
+        }
+        assert!(map.get_mut(&0).is_none());
+        assert_eq!(map.get(&5).map(|guard| *guard.val()), Some(6));
+        assert_eq!(map.len(), 500);
+        map.validate();
+    }
+
+    #[test]
+    fn clone_keeps_protection() {
+        let map = Flooded::with_flood_protection(ConstState);
+        for i in 0 .. 1000 {
+            map.insert(i, i);
+        }
+        let cloned = map.clone();
+        assert!(cloned.is_flood_protected());
+        assert_eq!(cloned.stats().max_bucket_len(), 1);
+        assert_eq!(cloned, map);
+        cloned.validate();
+    }
+
+    #[test]
+    fn colliding_threads() {
+        const THREADS: u32 = 4;
+
+        let map = Arc::new(Flooded::with_flood_protection(ConstState));
+        let barrier = Arc::new(Barrier::new(THREADS as usize));
+        let threads = (0 .. THREADS)
+            .map(|id| {
+                let map = map.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for i in 0 .. KEYS / THREADS {
+                        let key = i * THREADS + id;
+                        map.insert(key, key);
+                        assert!(map.contains_key(&key));
+                        if i % 2 == 0 {
+                            assert!(map.remove(&key).is_some());
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        assert_eq!(map.len(), KEYS as usize / 2);
+        assert_eq!(map.stats().max_bucket_len(), 1);
+        map.validate();
+    }
+}
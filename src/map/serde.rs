@@ -0,0 +1,95 @@
+use super::{BucketOrder, Map};
+use core::{
+    fmt,
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+};
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::{Error, SerializeMap},
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+
+impl<K, V, H, const BITS: usize, O> Serialize for Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+    K: Serialize,
+    V: Serialize,
+{
+    /// Serializes the map as a map of entries, walking the tree under short
+    /// incinerator pauses. The hasher is not serialized. Since some formats
+    /// write the length before the entries, an error is returned if the number
+    /// of visited entries differs from the length at the start (i.e. the map
+    /// was concurrently mutated).
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = self.len();
+        let mut ser_map = serializer.serialize_map(Some(len))?;
+        let mut count = 0;
+        let mut res = Ok(());
+
+        self.for_each(|key, val| {
+            if res.is_ok() {
+                count += 1;
+                res = ser_map.serialize_entry(key, val);
+            }
+        });
+
+        res?;
+        if count != len {
+            return Err(S::Error::custom("map changed during serialization"));
+        }
+        ser_map.end()
+    }
+}
+
+impl<'de, K, V, H, const BITS: usize, O> Deserialize<'de>
+    for Map<K, V, H, BITS, O>
+where
+    H: BuildHasher + Default,
+    K: Deserialize<'de> + Hash + Eq,
+    O: BucketOrder<K>,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(MapVisitor { _marker: PhantomData })
+    }
+}
+
+struct MapVisitor<K, V, H, const BITS: usize, O> {
+    _marker: PhantomData<Map<K, V, H, BITS, O>>,
+}
+
+impl<'de, K, V, H, const BITS: usize, O> Visitor<'de>
+    for MapVisitor<K, V, H, BITS, O>
+where
+    H: BuildHasher + Default,
+    K: Deserialize<'de> + Hash + Eq,
+    O: BucketOrder<K>,
+    V: Deserialize<'de>,
+{
+    type Value = Map<K, V, H, BITS, O>;
+
+    fn expecting(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.write_str("a map")
+    }
+
+    fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let map = Map::with_fanout(H::default());
+        while let Some((key, val)) = access.next_entry()? {
+            map.insert(key, val);
+        }
+        Ok(map)
+    }
+}
@@ -0,0 +1,375 @@
+// Slabs of nodes of a single type, from which the buckets of a map and their
+// list cells are taken, instead of being allocated one by one. Slots come from
+// chunks, each one as big as all the chunks before it, up to `MAX_CHUNK`
+// slots, and are only given back to the allocator when the slab is dropped.
+//
+// A node given back to its slab is retired, not freed: other threads might
+// still read it under a pause, just like garbage handed to the incinerator.
+// `recycle` moves retired slots to the free list only if nothing is paused
+// once they are taken, so a slot cannot be taken from the free list and put
+// back while another thread, which found it there under a pause, is still
+// trying to take it. That is what keeps the free list from the ABA problem,
+// so slots must only be taken under a pause, or with exclusive access.
+
+use alloc::boxed::Box;
+use core::{
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicUsize, Ordering::*},
+};
+use owned_alloc::{try_boxed_slice, AllocFailed, OwnedAlloc};
+use primitive::{AtomicPtr, PtrMut};
+
+// How many slots the first chunk has.
+const MIN_CHUNK: usize = 8;
+
+// The most slots a chunk has.
+const MAX_CHUNK: usize = 4096;
+
+// Set in the link of a retired slot whose node was moved out, so it is not
+// dropped again.
+const MOVED: usize = 1;
+
+pub struct Slab<T> {
+    // The slots ready to be taken.
+    free: AtomicPtr<Slot<T>>,
+    // The slots given back, waiting for `recycle`.
+    retired: AtomicPtr<Slot<T>>,
+    chunks: AtomicPtr<Chunk<T>>,
+    // How many slots the chunks have altogether. Only a hint to size the next
+    // chunk.
+    capacity: AtomicUsize,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Self {
+            free: AtomicPtr::new(null_mut()),
+            retired: AtomicPtr::new(null_mut()),
+            chunks: AtomicPtr::new(null_mut()),
+            capacity: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    // The memory taken by each node of this slab.
+    pub fn slot_size() -> usize {
+        mem::size_of::<Slot<T>>()
+    }
+
+    // How many slots were taken from the allocator so far.
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Relaxed)
+    }
+
+    // Moves the node into a free slot, taking a new chunk if there is none.
+    // Calls the global allocation error handler if allocation fails. Unsafe
+    // because the incinerator of the map must be paused, or the slab
+    // exclusively accessed.
+    pub unsafe fn alloc(&self, val: T) -> NonNull<T> {
+        match self.try_alloc(val) {
+            Ok(node) => node,
+            Err((_, err)) => err.handle(),
+        }
+    }
+
+    // Just like `alloc`, but gives the node back if a new chunk is needed and
+    // cannot be allocated. Unsafe for the same reasons as `alloc`.
+    pub unsafe fn try_alloc(
+        &self,
+        val: T,
+    ) -> Result<NonNull<T>, (T, AllocFailed)> {
+        let slot = match self.pop() {
+            Some(slot) => slot,
+            None => match self.grow() {
+                Ok(slot) => slot,
+                Err(err) => return Err((val, err)),
+            },
+        };
+        let node = slot.cast::<T>();
+        node.as_ptr().write(val);
+        Ok(node)
+    }
+
+    // Gives the node back. It is dropped once it is recycled. Unsafe because
+    // the node must come from this slab, and it must not be read afterwards,
+    // except under pauses started before it was detached.
+    pub unsafe fn retire(&self, node: NonNull<T>) {
+        let slot = node.cast::<Slot<T>>();
+        slot.as_ref().link.store(null_mut(), Relaxed);
+        Self::push(&self.retired, slot, slot);
+    }
+
+    // Moves the node out and gives its slot back. Unsafe for the same reasons
+    // as `retire`.
+    pub unsafe fn take(&self, node: NonNull<T>) -> T {
+        let val = node.as_ptr().read();
+        let slot = node.cast::<Slot<T>>();
+        slot.as_ref().link.store(MOVED as *mut _, Relaxed);
+        Self::push(&self.retired, slot, slot);
+        val
+    }
+
+    // Drops the retired nodes and moves their slots to the free list, if
+    // `unpaused` tells that the incinerator of the map is not paused. It is
+    // asked again once the slots are taken, since whoever paused meanwhile
+    // may be reading them, in which case they are retired again.
+    pub fn recycle<F>(&self, unpaused: F)
+    where
+        F: Fn() -> bool,
+    {
+        // This is tried before every operation of the map, so the cheap test
+        // comes first.
+        if self.retired.load(Relaxed).is_null() || !unpaused() {
+            return;
+        }
+
+        let first = match NonNull::new(self.retired.swap(null_mut(), AcqRel)) {
+            Some(first) => first,
+            None => return,
+        };
+
+        if unpaused() {
+            // Safe because we took the slots and nobody is paused.
+            let last = unsafe { Self::reuse(first) };
+            Self::push(&self.free, first, last);
+        } else {
+            let mut last = first;
+            // Safe because the taken slots are ours.
+            while let Some(next) = unsafe { Self::next(last) } {
+                last = next;
+            }
+            Self::push(&self.retired, first, last);
+        }
+    }
+
+    // Just like `recycle`, but with exclusive access, so nothing can be
+    // paused.
+    pub fn recycle_mut(&mut self) {
+        let first = self.retired.replace_mut(null_mut());
+        if let Some(first) = NonNull::new(first) {
+            // Safe because we have exclusive access.
+            let last = unsafe { Self::reuse(first) };
+            Self::push(&self.free, first, last);
+        }
+    }
+
+    fn pop(&self) -> Option<NonNull<Slot<T>>> {
+        let mut top = self.free.load(Acquire);
+        loop {
+            let slot = NonNull::new(top)?;
+            // If someone took the slot meanwhile, its link may have changed,
+            // but then the exchange fails. Slots are only freed along with
+            // the slab, so it can still be read.
+            let next = unsafe { slot.as_ref() }.link.load(Relaxed);
+            match self.free.compare_exchange(top, next, Acquire, Acquire) {
+                Ok(_) => break Some(slot),
+                Err(new) => top = new,
+            }
+        }
+    }
+
+    // Takes a new chunk from the allocator, keeping its first slot and
+    // pushing the others to the free list. Nothing changes if the chunk
+    // cannot be allocated.
+    fn grow(&self) -> Result<NonNull<Slot<T>>, AllocFailed> {
+        let len = self.capacity.load(Relaxed).clamp(MIN_CHUNK, MAX_CHUNK);
+
+        let slots = Box::into_raw(try_boxed_slice(len, Slot::new)?);
+        let chunk = Chunk { slots, next: null_mut() };
+        let chunk = match OwnedAlloc::try_new(chunk) {
+            Ok(chunk) => chunk.into_raw(),
+            Err((chunk, err)) => {
+                // Safe because the slots were never shared.
+                drop(unsafe { Box::from_raw(chunk.slots) });
+                return Err(err);
+            },
+        };
+        self.capacity.fetch_add(len, Relaxed);
+        let first = slots as *mut Slot<T>;
+
+        // Safe because the chunk is not shared until its slots are pushed,
+        // and it has more than two slots.
+        unsafe {
+            for i in 1 .. len - 1 {
+                (*first.add(i)).link.store(first.add(i + 1), Relaxed);
+            }
+
+            let mut top = self.chunks.load(Relaxed);
+            loop {
+                (*chunk.as_ptr()).next = top;
+                match self.chunks.compare_exchange(
+                    top,
+                    chunk.as_ptr(),
+                    Release,
+                    Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(new) => top = new,
+                }
+            }
+
+            Self::push(
+                &self.free,
+                NonNull::new_unchecked(first.add(1)),
+                NonNull::new_unchecked(first.add(len - 1)),
+            );
+            Ok(NonNull::new_unchecked(first))
+        }
+    }
+
+    // Pushes a chain of slots, given its first and last slots, which must not
+    // be shared. The mark of the last slot is kept.
+    fn push(
+        list: &AtomicPtr<Slot<T>>,
+        first: NonNull<Slot<T>>,
+        last: NonNull<Slot<T>>,
+    ) {
+        // Safe because the chain is still ours.
+        let link = unsafe { &last.as_ref().link };
+        let moved = link.load(Relaxed) as usize & MOVED;
+        let mut top = list.load(Relaxed);
+        loop {
+            link.store((top as usize | moved) as *mut _, Relaxed);
+            match list.compare_exchange(top, first.as_ptr(), Release, Relaxed)
+            {
+                Ok(_) => break,
+                Err(new) => top = new,
+            }
+        }
+    }
+
+    // The slot after the given one in a chain of retired slots. Unsafe
+    // because the chain must not be shared.
+    unsafe fn next(slot: NonNull<Slot<T>>) -> Option<NonNull<Slot<T>>> {
+        let link = slot.as_ref().link.load(Relaxed) as usize;
+        NonNull::new((link & !MOVED) as *mut _)
+    }
+
+    // Drops the nodes of a chain of retired slots which were not moved out,
+    // and clears the marks, returning the last slot. Unsafe because the chain
+    // must not be shared, nor read by anyone else anymore.
+    unsafe fn reuse(first: NonNull<Slot<T>>) -> NonNull<Slot<T>> {
+        let mut slot = first;
+        loop {
+            let link = &slot.as_ref().link;
+            let loaded = link.load(Relaxed) as usize;
+            if loaded & MOVED == 0 {
+                slot.cast::<T>().as_ptr().drop_in_place();
+            }
+            link.store((loaded & !MOVED) as *mut _, Relaxed);
+
+            match NonNull::new((loaded & !MOVED) as *mut _) {
+                Some(next) => slot = next,
+                None => break slot,
+            }
+        }
+    }
+}
+
+impl<T> Drop for Slab<T> {
+    fn drop(&mut self) {
+        // Every node was given back by now, but the retired ones were not
+        // dropped yet.
+        self.recycle_mut();
+
+        let mut top = self.chunks.replace_mut(null_mut());
+        while let Some(nnptr) = NonNull::new(top) {
+            // Safe because we have exclusive access, and the slots hold no
+            // node anymore.
+            unsafe {
+                let chunk = OwnedAlloc::from_raw(nnptr);
+                top = chunk.next;
+                drop(Box::from_raw(chunk.slots));
+            }
+        }
+    }
+}
+
+// The node comes first, so a pointer to a node is a pointer to its slot.
+#[repr(C)]
+struct Slot<T> {
+    val: MaybeUninit<T>,
+    // The next slot, while this slot is either free or retired. Slots taken
+    // meanwhile may still be read here by threads trying to take them.
+    link: AtomicPtr<Slot<T>>,
+}
+
+impl<T> Slot<T> {
+    fn new() -> Self {
+        Self { val: MaybeUninit::uninit(), link: AtomicPtr::new(null_mut()) }
+    }
+}
+
+struct Chunk<T> {
+    slots: *mut [Slot<T>],
+    next: *mut Chunk<T>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::{sync::Arc, vec::Vec};
+
+    #[test]
+    fn recycles_retired_slots() {
+        let slab = Slab::new();
+        let nodes = (0 .. 100)
+            .map(|i| unsafe { slab.alloc(i) })
+            .collect::<Vec<_>>();
+        let capacity = slab.capacity();
+        assert!(capacity >= 100);
+
+        for node in nodes {
+            unsafe { slab.retire(node) };
+        }
+        slab.recycle(|| true);
+        for i in 0 .. 100 {
+            let node = unsafe { slab.alloc(i) };
+            assert_eq!(unsafe { *node.as_ref() }, i);
+        }
+        assert_eq!(slab.capacity(), capacity);
+    }
+
+    #[test]
+    fn recycling_waits_for_unpaused() {
+        let slab = Slab::new();
+        let shared = Arc::new(());
+        let nodes = (0 .. MIN_CHUNK)
+            .map(|_| unsafe { slab.alloc(shared.clone()) })
+            .collect::<Vec<_>>();
+        for node in nodes {
+            unsafe { slab.retire(node) };
+        }
+
+        slab.recycle(|| false);
+        assert_eq!(Arc::strong_count(&shared), MIN_CHUNK + 1);
+        let node = unsafe { slab.alloc(shared.clone()) };
+        assert_eq!(slab.capacity(), MIN_CHUNK * 2);
+
+        slab.recycle(|| true);
+        assert_eq!(Arc::strong_count(&shared), 2);
+        unsafe { slab.retire(node) };
+    }
+
+    #[test]
+    fn taken_nodes_are_not_dropped_again() {
+        let mut slab = Slab::new();
+        let shared = Arc::new(());
+        let taken = unsafe { slab.alloc(shared.clone()) };
+        let retired = unsafe { slab.alloc(shared.clone()) };
+        assert_eq!(Arc::strong_count(&shared), 3);
+
+        let taken = unsafe { slab.take(taken) };
+        unsafe { slab.retire(retired) };
+        slab.recycle_mut();
+        assert_eq!(Arc::strong_count(&shared), 2);
+        drop(slab);
+        assert_eq!(Arc::strong_count(&shared), 2);
+        drop(taken);
+        assert_eq!(Arc::strong_count(&shared), 1);
+    }
+}
@@ -0,0 +1,515 @@
+use super::{
+    cow::Keeper,
+    table::Table,
+    BucketOrder,
+    DefaultHashBuilder,
+    Map,
+    Ordered,
+    DEBUG_LEN,
+};
+use alloc::sync::Arc;
+use core::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash},
+    ptr::NonNull,
+};
+
+/// A read-only view of a [`Map`] as it was at some point, made by
+/// [`Map::snapshot`]. Taking it copies only the top table of the [`Map`]:
+/// everything below it is shared, and the [`Map`] copies each table or bucket
+/// it shares with snapshots right before changing it, leaving the snapshot
+/// untouched. Its lookups neither pause the incinerator nor use atomic
+/// read-modify-write operations, and they return plain references, valid
+/// while the snapshot is alive.
+///
+/// Changes made to a value through interior mutability, e.g. by
+/// [`Map::update_in_place`], are seen by snapshots, since values are shared,
+/// not copied.
+///
+/// A snapshot only keeps what it may read: the tables and buckets it shares,
+/// and the entries removed from the [`Map`] since it was taken. Everything
+/// else detached from the [`Map`] is reclaimed as usual. Once the snapshot is
+/// dropped, whatever only it kept is reclaimed too, and the [`Map`] stops
+/// copying what it shared with it.
+#[must_use = "a snapshot only keeps the entries while it is alive"]
+pub struct MapSnapshot<
+    'map,
+    K,
+    V,
+    H = DefaultHashBuilder,
+    const BITS: usize = 8,
+    O = Ordered,
+> where
+    K: 'map,
+    V: 'map,
+{
+    map: &'map Map<K, V, H, BITS, O>,
+    // The copy of the top table of the map, held by this snapshot alone.
+    top: NonNull<Table<K, V>>,
+    // Keeps the pairs removed from the map while this snapshot may read them.
+    _keeper: Arc<Keeper<K, V>>,
+}
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O> {
+    /// Takes a snapshot of this [`Map`], whose contents stay the same while
+    /// the [`Map`] keeps changing. It costs a copy of the top table of the
+    /// [`Map`], i.e. `1 << BITS` pointers, whatever the number of entries; a
+    /// compact [`Map`] is upgraded first. Afterwards, the first change to each
+    /// table or bucket shared with the snapshot copies it, until the snapshot
+    /// is dropped.
+    ///
+    /// The snapshot sees every change completed before this is called, and
+    /// no change started after it returns. Changes made concurrently may or
+    /// may not be seen. Operations changing the [`Map`] never wait for
+    /// snapshots, but taking a snapshot waits until the operations already
+    /// changing the [`Map`] are done, so this must not be called from
+    /// closures given to operations changing this [`Map`], e.g.
+    /// [`insert_with`](Map::insert_with). Only available with the `snapshot`
+    /// feature.
+    pub fn snapshot(&self) -> MapSnapshot<'_, K, V, H, BITS, O> {
+        let (top, keeper) = self.slabs.snapshots.take(&self.incin.inner, || {
+            let pause = self.pause();
+            let top = self.upgraded_top(&pause);
+            // Safe because we paused properly, and the upgraded top table is
+            // never replaced.
+            let copy = unsafe { top.share_top(&pause) };
+            copy.unwrap_or_else(|err| err.handle())
+        });
+
+        MapSnapshot { map: self, top, _keeper: keeper }
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> MapSnapshot<'map, K, V, H, BITS, O> {
+    /// The [`Map`] this snapshot was taken of.
+    pub fn map(&self) -> &'map Map<K, V, H, BITS, O> {
+        self.map
+    }
+
+    /// Calls the given closure on every entry of this snapshot, in no
+    /// particular order.
+    pub fn for_each<F>(&self, mut visitor: F)
+    where
+        F: FnMut(&K, &V),
+    {
+        // Safe because the snapshot holds its tables and buckets, and its
+        // keeper the pairs removed from them.
+        unsafe { self.top().for_each_held(|(key, val)| visitor(key, val)) }
+    }
+
+    /// Counts the entries of this snapshot, visiting every one of them.
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+        self.for_each(|_, _| len += 1);
+        len
+    }
+
+    /// Returns whether this snapshot has no entries.
+    pub fn is_empty(&self) -> bool {
+        // Safe for the same reasons as in `for_each`.
+        unsafe { self.top().get_any(0).is_none() }
+    }
+
+    fn top(&self) -> &Table<K, V> {
+        // Safe because the snapshot holds its copy until dropped.
+        unsafe { self.top.as_ref() }
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> MapSnapshot<'map, K, V, H, BITS, O>
+where
+    H: BuildHasher,
+{
+    /// Searches for the entry identified by the given key, returning a
+    /// reference to its value as of the snapshot. See [`Map::get`].
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        self.get_key_value(key).map(|(_, val)| val)
+    }
+
+    /// Just like [`get`](MapSnapshot::get), but the stored key is returned
+    /// along with the value.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        let hash = self.map.hash_of(key);
+        let salted = || self.map.salted_of(key);
+        // Safe for the same reasons as in `for_each`, and the pair is
+        // borrowed from the snapshot.
+        let found =
+            unsafe { self.top().get_versioned::<O, Q, _>(key, hash, salted) };
+        found.map(|((key, val), _)| (key, val))
+    }
+
+    /// Tests whether an entry is identified by the given key in this
+    /// snapshot.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+    {
+        self.get_key_value(key).is_some()
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> fmt::Debug
+    for MapSnapshot<'map, K, V, H, BITS, O>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    /// Prints at most `128` entries, followed by the count of the omitted
+    /// ones, just like the `Debug` implementation of [`Map`].
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.write_str("MapSnapshot {")?;
+        let mut res = Ok(());
+        let mut count = 0;
+        self.for_each(|key, val| {
+            if count < DEBUG_LEN && res.is_ok() {
+                let sep = if count == 0 { "" } else { ", " };
+                res = write!(fmtr, "{}{:?}: {:?}", sep, key, val);
+            }
+            count += 1;
+        });
+        res?;
+        if count > DEBUG_LEN {
+            write!(fmtr, ", ... ({} more)", count - DEBUG_LEN)?;
+        }
+        fmtr.write_str("}")
+    }
+}
+
+impl<'map, K, V, H, const BITS: usize, O> Drop
+    for MapSnapshot<'map, K, V, H, BITS, O>
+{
+    fn drop(&mut self) {
+        let pause = self.map.pause();
+        // Safe because we paused properly, and nothing reads the copy once
+        // the snapshot is dropped.
+        unsafe { Table::release(self.top, &pause) }
+    }
+}
+
+// The snapshot only reads what it holds, just like a shared `Map`.
+unsafe impl<'map, K, V, H, const BITS: usize, O> Send
+    for MapSnapshot<'map, K, V, H, BITS, O>
+where
+    Map<K, V, H, BITS, O>: Sync,
+{
+}
+
+unsafe impl<'map, K, V, H, const BITS: usize, O> Sync
+    for MapSnapshot<'map, K, V, H, BITS, O>
+where
+    Map<K, V, H, BITS, O>: Sync,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::sync::Arc;
+    use map::Removed;
+    use core::sync::atomic::{AtomicUsize, Ordering::*};
+    use std::{sync::Barrier, thread};
+    use std::prelude::v1::*;
+
+    #[derive(Debug)]
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Relaxed);
+        }
+    }
+
+    fn sorted<K, V, H, const BITS: usize, O>(
+        snapshot: &MapSnapshot<K, V, H, BITS, O>,
+    ) -> Vec<(K, V)>
+    where
+        K: Ord + Clone,
+        V: Clone,
+    {
+        let mut pairs = Vec::new();
+        snapshot.for_each(|key, val| pairs.push((key.clone(), val.clone())));
+        pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        pairs
+    }
+
+    #[test]
+    fn contents_stay_frozen() {
+        let map = Map::new();
+        for i in 0 .. 1000 {
+            map.insert(i, i);
+        }
+        let snapshot = map.snapshot();
+        let expected = (0 .. 1000).map(|i| (i, i)).collect::<Vec<_>>();
+        assert_eq!(sorted(&snapshot), expected);
+
+        for i in 1000 .. 2000 {
+            map.insert(i, i);
+        }
+        for i in 0 .. 500 {
+            map.insert(i, i + 1);
+        }
+        for i in 500 .. 800 {
+            assert!(map.remove(&i).is_some());
+        }
+        assert!(map.pop_any().is_some());
+        map.shrink();
+        map.validate();
+        assert_eq!(map.len(), 1699);
+        assert_eq!(map.get(&3).map(|guard| *guard.val()), Some(4));
+        assert!(map.get(&600).is_none());
+
+        assert_eq!(snapshot.len(), 1000);
+        assert_eq!(sorted(&snapshot), expected);
+        for i in 0 .. 1000 {
+            assert_eq!(snapshot.get(&i), Some(&i));
+        }
+        assert!(!snapshot.contains_key(&1500));
+
+        map.clear();
+        assert!(map.is_empty());
+        assert!(!snapshot.is_empty());
+        assert_eq!(snapshot.get_key_value(&999), Some((&999, &999)));
+        assert_eq!(sorted(&snapshot), expected);
+        drop(snapshot);
+
+        assert!(map.snapshot().is_empty());
+        map.insert(1, 1);
+        assert_eq!(map.get(&1).map(|guard| *guard.val()), Some(1));
+        map.validate();
+    }
+
+    #[test]
+    fn many_snapshots() {
+        let map = Map::new();
+        let mut snapshots = Vec::new();
+        for round in 0 .. 10 {
+            for i in 0 .. 200 {
+                map.insert(i, round);
+            }
+            map.remove(&round);
+            snapshots.push(map.snapshot());
+        }
+
+        for (round, snapshot) in snapshots.iter().enumerate() {
+            assert_eq!(snapshot.len(), 200 - 1);
+            assert!(!snapshot.contains_key(&round));
+            for i in 10 .. 200 {
+                assert_eq!(snapshot.get(&i), Some(&round));
+            }
+        }
+        assert!(format!("{:?}", snapshots[0]).starts_with("MapSnapshot {"));
+        map.validate();
+    }
+
+    #[test]
+    fn compact_map() {
+        let map = Map::new();
+        map.insert(1, 10);
+        let snapshot = map.snapshot();
+        for i in 0 .. 100 {
+            map.insert(i, i);
+        }
+        assert_eq!(sorted(&snapshot), vec![(1, 10)]);
+        assert_eq!(map.len(), 100);
+        map.validate();
+    }
+
+    #[test]
+    fn memory_reclaimed() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let map = Map::new();
+        for i in 0 .. 300 {
+            map.insert(i, Counted(drops.clone()));
+        }
+        let snapshot = map.snapshot();
+        for i in 0 .. 100 {
+            map.insert(i, Counted(drops.clone()));
+        }
+        for i in 100 .. 200 {
+            map.remove(&i);
+        }
+        map.clear();
+        // The snapshot still reads everything replaced and removed, but the
+        // pairs inserted after it was taken are reclaimed as usual.
+        assert_eq!(drops.load(Relaxed), 100);
+        assert_eq!(snapshot.len(), 300);
+        drop(snapshot);
+
+        assert_eq!(drops.load(Relaxed), 400);
+        assert_eq!(map.incin.inner.pending(), 0);
+        for i in 0 .. 300 {
+            map.insert(i, Counted(drops.clone()));
+        }
+        let snapshot = map.snapshot();
+        map.insert(0, Counted(drops.clone()));
+        drop(snapshot);
+        drop(map);
+        assert_eq!(drops.load(Relaxed), 701);
+    }
+
+    #[test]
+    fn every_kind_of_writer() {
+        const THREADS: usize = 4;
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let created = Arc::new(AtomicUsize::new(0));
+        let map = Arc::new(Map::new());
+        let threads = (0 .. THREADS)
+            .map(|id| {
+                let map = map.clone();
+                let drops = drops.clone();
+                let created = created.clone();
+                thread::spawn(move || {
+                    for i in 0 .. 3000 {
+                        map.insert(i % 500, Counted(drops.clone()));
+                        created.fetch_add(1, Relaxed);
+                        match (i + id) % 100 {
+                            0 => map.clear(),
+                            1 => drop(map.drain()),
+                            2 => drop(map.shrink()),
+                            3 ..= 30 => drop(map.pop_any()),
+                            31 ..= 60 => drop(map.remove(&(i % 300))),
+                            _ => (),
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0 .. 100 {
+            let snapshot = map.snapshot();
+            let len = snapshot.len();
+            assert!(len <= 500);
+            let mut found = 0;
+            snapshot.for_each(|key, _| {
+                assert!(snapshot.contains_key(key));
+                found += 1;
+            });
+            assert_eq!(found, len);
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        map.validate();
+        let map = Arc::try_unwrap(map).expect("map still shared");
+        drop(map);
+        assert_eq!(drops.load(Relaxed), created.load(Relaxed));
+    }
+
+    #[test]
+    fn frozen_under_writers() {
+        const THREADS: usize = 4;
+        const KEYS: usize = 1000;
+
+        let map = Arc::new(Map::new());
+        for i in 0 .. KEYS {
+            map.insert(i, 0);
+        }
+        // Writers never wait for snapshots, even for one kept all along.
+        let first = map.snapshot();
+        let expected = sorted(&first);
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+        let threads = (0 .. THREADS)
+            .map(|id| {
+                let map = map.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for i in 0 .. 3000 {
+                        map.insert((i * THREADS + id) % KEYS, i);
+                        map.insert(KEYS + i * THREADS + id, i);
+                        if i % 3 == 0 {
+                            map.remove(&(KEYS + i / 2 * THREADS + id));
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        barrier.wait();
+        for round in 0 .. 50 {
+            let snapshot = map.snapshot();
+            // Inserted after the snapshot was taken.
+            let marker = usize::MAX - round;
+            map.insert(marker, round);
+            let pairs = sorted(&snapshot);
+            // The keys inserted before the writers started are never removed.
+            for i in 0 .. KEYS {
+                assert!(snapshot.contains_key(&i));
+            }
+            assert!(!snapshot.contains_key(&marker));
+            thread::yield_now();
+            assert_eq!(sorted(&snapshot), pairs);
+            assert!(map.remove(&marker).is_some());
+        }
+
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        assert_eq!(sorted(&first), expected);
+        drop(first);
+        map.validate();
+        let snapshot = map.snapshot();
+        assert_eq!(snapshot.len(), map.len());
+    }
+
+    #[test]
+    fn nothing_copied_once_dropped() {
+        let map = Map::new();
+        for i in 0 .. 5000 {
+            map.insert(i, i);
+        }
+        let nodes = |map: &Map<_, _>| {
+            let pause = map.pause();
+            let top = map.top(&pause);
+            (0 .. 1 << 8)
+                .map(|i| top.load_index(i, Acquire).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let snapshot = map.snapshot();
+        let before = nodes(&map);
+        for i in 0 .. 5000 {
+            map.insert(i, i + 1);
+        }
+        // Every node shared with the snapshot was copied.
+        let copied = nodes(&map);
+        assert!(before.iter().zip(&copied).all(|(old, new)| old != new));
+        assert_eq!(snapshot.get(&7), Some(&7));
+        drop(snapshot);
+
+        for i in 0 .. 5000 {
+            map.insert(i, i + 2);
+        }
+        assert_eq!(nodes(&map), copied);
+        assert_eq!(map.get(&7).map(|guard| *guard.val()), Some(9));
+        map.validate();
+    }
+
+    #[test]
+    fn held_removed_pair() {
+        let map = Map::new();
+        map.insert(1, 10);
+        let snapshot = map.snapshot();
+        let removed = map.remove(&1).unwrap();
+        // The snapshot may still read the pair.
+        let removed = Removed::try_unwrap(removed).unwrap_err();
+        assert_eq!(snapshot.get(&1), Some(&10));
+        drop(snapshot);
+        assert_eq!(Removed::try_unwrap(removed).ok(), Some((1, 10)));
+    }
+}
@@ -0,0 +1,83 @@
+use alloc::vec::Vec;
+
+/// Structural statistics of a [`Map`](super::Map), as returned by
+/// [`Map::stats`](super::Map::stats). If the [`Map`](super::Map) is changed
+/// concurrently, the statistics are only approximate, since different parts of
+/// the [`Map`](super::Map) are read at different times.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MapStats {
+    /// The number of tables, including the top one.
+    pub tables: usize,
+    /// The number of leaves, i.e. buckets stored in the tables.
+    pub leaves: usize,
+    /// The number of tables from the top to the deepest one, inclusive. A
+    /// [`Map`](super::Map) with no sub-tables has depth `1`.
+    pub max_depth: usize,
+    /// The histogram of bucket lengths: the element at index `n` is how many
+    /// buckets had exactly `n` entries. Buckets with no entries are about to
+    /// be removed from their tables.
+    pub bucket_lengths: Vec<usize>,
+    /// The number of entries found in the buckets.
+    pub entries: usize,
+    /// The memory taken by the tables, in bytes. Buckets and entries are not
+    /// counted.
+    pub table_bytes: usize,
+}
+
+impl MapStats {
+    /// The length of the longest bucket, or `0` if there are no buckets.
+    pub fn max_bucket_len(&self) -> usize {
+        self.bucket_lengths.iter().rposition(|&count| count > 0).unwrap_or(0)
+    }
+
+    // Records a leaf of the given length.
+    pub(super) fn add_bucket(&mut self, len: usize) {
+        if self.bucket_lengths.len() <= len {
+            self.bucket_lengths.resize(len + 1, 0);
+        }
+        self.bucket_lengths[len] += 1;
+        self.leaves += 1;
+        self.entries += len;
+    }
+}
+
+/// An estimate of the memory used by a [`Map`](super::Map), as returned by
+/// [`Map::memory_usage`](super::Map::memory_usage). Only the allocations of
+/// the [`Map`](super::Map) itself are counted, not the memory owned by its keys
+/// and values. If the [`Map`](super::Map) is changed concurrently, the counts
+/// are only approximate.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MemoryUsage {
+    /// The number of tables, including the top one.
+    pub tables: usize,
+    /// The memory taken by the tables, in bytes.
+    pub table_bytes: usize,
+    /// The number of buckets stored in the tables.
+    pub buckets: usize,
+    /// The memory taken by the buckets, in bytes.
+    pub bucket_bytes: usize,
+    /// The number of entries found in the buckets.
+    pub entries: usize,
+    /// The memory taken by the list cells linking the entries of the buckets,
+    /// in bytes. The first entry of a bucket is kept in the bucket, so it is
+    /// counted in [`bucket_bytes`](MemoryUsage::bucket_bytes) instead.
+    pub list_bytes: usize,
+    /// The memory taken by the allocations of the key-value pairs, in bytes.
+    /// Zero if the pairs are kept inline, as in a [`Map`](super::Map) created
+    /// with [`with_inline_pairs`](super::Map::with_inline_pairs), since they
+    /// are counted along with their entries.
+    pub pair_bytes: usize,
+    /// The number of detached allocations waiting in the incinerator of the
+    /// [`Map`](super::Map) to be freed. They are not counted in
+    /// [`total_bytes`](MemoryUsage::total_bytes), since their size is not
+    /// known.
+    pub pending_garbage: usize,
+}
+
+impl MemoryUsage {
+    /// The memory taken by the tables, buckets, list cells and pairs, in
+    /// bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.table_bytes + self.bucket_bytes + self.list_bytes + self.pair_bytes
+    }
+}
@@ -0,0 +1,24 @@
+// Fixtures shared by the tests of the map and its wrappers.
+
+use core::hash::{BuildHasher, Hasher};
+
+// Hashes every key to the same value, as keys crafted by an adversary would,
+// so every entry shares a bucket of the map.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstState;
+
+impl BuildHasher for ConstState {
+    type Hasher = ConstState;
+
+    fn build_hasher(&self) -> ConstState {
+        ConstState
+    }
+}
+
+impl Hasher for ConstState {
+    fn finish(&self) -> u64 {
+        0x5555
+    }
+
+    fn write(&mut self, _bytes: &[u8]) {}
+}
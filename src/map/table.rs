@@ -1,93 +1,351 @@
 use super::{
-    bucket::{Bucket, Garbage, GetRes, InsertRes},
+    backoff::Backoff,
+    bucket::{Bucket, Garbage, GetRes, InsertRes, RemovedPair, Slabs},
+    cow::Writing,
+    given_hash,
     guard::{ReadGuard, Removed},
     insertion::{Inserter, Insertion},
+    order::BucketOrder,
+    stats::MapStats,
+    trace::Probe,
+    HashCode,
 };
-use incin::{Incinerator, Pause};
-use owned_alloc::{Cache, OwnedAlloc, UninitAlloc};
-use std::{
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
     borrow::Borrow,
     fmt,
     marker::PhantomData,
+    mem,
+    ops::Range,
     ptr::{null_mut, NonNull},
-    sync::{
-        atomic::{
-            AtomicPtr,
-            Ordering::{self, *},
-        },
-        Arc,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering::{self, *},
     },
 };
-
-const BITS: usize = 8;
+#[cfg(feature = "snapshot")]
+use core::sync::atomic::AtomicBool;
+use incin::{Incinerator, Pause};
+use owned_alloc::{try_boxed_slice, AllocFailed, Cache, OwnedAlloc};
+use primitive::{AtomicPtr, PtrMut};
+
+// How many nodes of a table are visited under a single pause by `visit`.
+const VISIT_CHUNK: usize = 32;
+
+// How many entries each node of the deepest level of the tree must be
+// expected to hold for `reserve` to build another level below it. Such a node
+// would be branched into a sub-table by insertions anyway.
+const RESERVE_LOAD: usize = 2;
+
+// How many bits a hash code has. Each level of the tree consumes `bits` of
+// them, starting from the lowest ones.
+const HASH_BITS: usize = mem::size_of::<HashCode>() * 8;
+
+// How many bits a salted hash has, see `Salt`.
+const SALTED_BITS: usize = u64::BITS as usize;
+
+// Stored in the nodes of a table being retired by `shrink`. A frozen node is
+// empty, but nothing can be inserted in it. Since it has the lower bit set but
+// no address, it can be neither a bucket nor a table.
+const FROZEN: usize = 1;
+
+// Set in the pointer to the single bucket of a table being collapsed by
+// `shrink`, and in every pointer of a compact tree being upgraded by
+// `freeze_all`. The entries of a frozen bucket can still be read and changed,
+// and a frozen table can still be entered, but the node holding them cannot be
+// changed.
+const FROZEN_MARK: usize = 2;
+
+// Set, with snapshots, in every node of a table copied by `Node::own`, on top
+// of what the node holds, so operations which entered the table before cannot
+// change it anymore, and start again from the top. Zero without snapshots.
+#[cfg(feature = "snapshot")]
+const COPIED: usize = 4;
+#[cfg(not(feature = "snapshot"))]
+const COPIED: usize = 0;
 
 // If you remove this alignment, don't remove it. Please, set it to 2.
 #[repr(align(64))]
 pub struct Table<K, V> {
-    nodes: [Node<K, V>; 1 << BITS],
+    // Always `1 << bits` nodes, where `bits` is the same for every table of a
+    // map.
+    nodes: Box<[Node<K, V>]>,
+    // How many tables hold this table, or, for a copy of the top table, how
+    // many snapshots do, see `Snapshots`.
+    #[cfg(feature = "snapshot")]
+    refs: AtomicUsize,
+    // Set once this table was copied, so it is never changed anymore.
+    #[cfg(feature = "snapshot")]
+    copied: AtomicBool,
 }
 
 impl<K, V> Table<K, V> {
-    pub fn new_alloc() -> OwnedAlloc<Self> {
-        // Safe because it calls a correctly a function which correctly
-        // initializes uninitialized memory with, indeed, uninitialized memory.
-        unsafe {
-            UninitAlloc::<Self>::new().init_in_place(|val| val.init_in_place())
+    pub fn new_alloc(bits: usize) -> OwnedAlloc<Self> {
+        Self::try_new_alloc(bits).unwrap_or_else(|err| err.handle())
+    }
+
+    pub fn try_new_alloc(
+        bits: usize,
+    ) -> Result<OwnedAlloc<Self>, AllocFailed> {
+        let nodes = try_boxed_slice(1 << bits, Node::new)?;
+        let table = Self {
+            nodes,
+            #[cfg(feature = "snapshot")]
+            refs: AtomicUsize::new(1),
+            #[cfg(feature = "snapshot")]
+            copied: AtomicBool::new(false),
+        };
+        OwnedAlloc::try_new(table).map_err(|(_, err)| err)
+    }
+
+    // Takes a copy of this table, held by nobody yet, whose nodes hold the
+    // same tables and buckets, counting one more hold on each one of them.
+    // If `mark` is set, this table is copied for good by `Node::own`: every
+    // node is marked with `COPIED` before it is copied, so this table never
+    // changes afterwards. Otherwise, this is the top table of the map, copied
+    // by a snapshot, and each node is copied as it is right after it is held.
+    // Fails if a table or a bucket found is not held anymore, which only
+    // happens if this table was released meanwhile, since the top table never
+    // is. Unsafe because the incinerator needs to be paused.
+    #[cfg(feature = "snapshot")]
+    unsafe fn try_copy(
+        &self,
+        mark: bool,
+        pause: &Pause<Garbage<K, V>>,
+    ) -> Result<OwnedAlloc<Self>, OwnErr> {
+        let copy = Self::try_new_alloc(self.bits()).map_err(OwnErr::NoMemory)?;
+        if mark {
+            self.copied.store(true, Relaxed);
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let held = loop {
+                let loaded = if mark {
+                    node.mark_copied()
+                } else {
+                    node.atomic.load(Acquire)
+                };
+                let held = held(loaded);
+                if share_node::<K, V>(held) {
+                    // The node of a copied table cannot change anymore.
+                    if mark || node.atomic.load(Acquire) == loaded {
+                        break held;
+                    }
+                    release_node(held, pause);
+                } else if mark {
+                    let copy = OwnedAlloc::into_raw(copy);
+                    Self::release_nodes(copy, i, pause);
+                    return Err(OwnErr::Changed(null_mut()));
+                }
+            };
+            // `Relaxed` is enough, since the copy is published with `Release`.
+            copy.nodes[i].atomic.store(held, Relaxed);
         }
+        Ok(copy)
     }
 
-    // Unsafe because passing ininitialized memory may cause leaks.
-    #[inline]
-    pub unsafe fn init_in_place(&mut self) {
-        for node in &mut self.nodes as &mut [_] {
-            (node as *mut Node<K, V>).write(Node::new())
+    // Counts one more table or snapshot holding this table, unless nothing
+    // holds it anymore, in which case it is being released and false is
+    // returned.
+    #[cfg(feature = "snapshot")]
+    fn share(&self) -> bool {
+        let mut refs = self.refs.load(Relaxed);
+        loop {
+            if refs == 0 {
+                break false;
+            }
+            // Nothing is published by a new holder.
+            match self.refs.compare_exchange_weak(
+                refs,
+                refs + 1,
+                Relaxed,
+                Relaxed,
+            ) {
+                Ok(_) => break true,
+                Err(new) => refs = new,
+            }
+        }
+    }
+
+    // Whether this table is held by a single table, and was never copied, so
+    // it can be changed in place.
+    #[cfg(feature = "snapshot")]
+    fn is_owned(&self) -> bool {
+        self.refs.load(Acquire) == 1 && !self.copied.load(Relaxed)
+    }
+
+    // Counts one holder less of the given table, handing it to the
+    // incinerator once nothing holds it, after releasing whatever its nodes
+    // hold. Unsafe because the table must not be used by the caller
+    // afterwards, and the incinerator needs to be paused.
+    #[cfg(feature = "snapshot")]
+    pub unsafe fn release(table: NonNull<Self>, pause: &Pause<Garbage<K, V>>) {
+        // `AcqRel` makes every use of the table by its holders happen before
+        // it is released.
+        if table.as_ref().refs.fetch_sub(1, AcqRel) == 1 {
+            let len = table.as_ref().nodes.len();
+            Self::release_nodes(table, len, pause);
+        }
+    }
+
+    // Without snapshots, a table is only held by its parent, and what its
+    // nodes hold is not destroyed with it.
+    #[cfg(not(feature = "snapshot"))]
+    pub unsafe fn release(table: NonNull<Self>, pause: &Pause<Garbage<K, V>>) {
+        pause.add_to_incin(Garbage::Table(OwnedAlloc::from_raw(table)));
+    }
+
+    // Releases what the given number of nodes of the given table hold, from
+    // the first one, then hands the table to the incinerator. Unsafe for the
+    // same reasons as `release`, and nobody else may hold the table.
+    #[cfg(feature = "snapshot")]
+    unsafe fn release_nodes(
+        table: NonNull<Self>,
+        count: usize,
+        pause: &Pause<Garbage<K, V>>,
+    ) {
+        let table = OwnedAlloc::from_raw(table);
+        for node in &table.nodes[.. count] {
+            release_node(held(node.atomic.load(Acquire)), pause);
         }
+        // The tables and buckets of the nodes are not destroyed with it.
+        pause.add_to_incin(Garbage::Table(table));
     }
 
+    // Takes a copy of this top table for a snapshot, see `Snapshots`. Unsafe
+    // because the incinerator needs to be paused, and this must be the top
+    // table of the map, which is never released.
+    #[cfg(feature = "snapshot")]
+    pub unsafe fn share_top(
+        &self,
+        pause: &Pause<Garbage<K, V>>,
+    ) -> Result<NonNull<Self>, AllocFailed> {
+        match self.try_copy(false, pause) {
+            Ok(copy) => Ok(copy.into_raw()),
+            Err(OwnErr::NoMemory(err)) => Err(err),
+            Err(_) => unreachable!(),
+        }
+    }
+
+    // How many bits of the hash are used to index this table.
+    #[inline]
+    pub fn bits(&self) -> usize {
+        self.nodes.len().trailing_zeros() as usize
+    }
+
+    // Selects the index of a node from the lower bits of a shifted hash.
+    #[inline]
+    fn mask(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    // The memory taken by this table and its nodes.
+    fn byte_size(&self) -> usize {
+        mem::size_of::<Self>() + self.nodes.len() * mem::size_of::<Node<K, V>>()
+    }
+
+    // Searches for the given key, whose plain hash is given. The closure gives
+    // its salted hash, if the search gets past the deepest level of the plain
+    // hash, which only happens with flood protection. If it gives none, the
+    // salted hash of the stored key equal to the searched one is taken
+    // instead, found by scanning the sub-table of the salted levels.
+    //
     // Unsafe because the incinerator needs to be paused and there are no
     // guarantees the passed pause comes from the incinerator used with the map
     // by other threads. Map implementation guarantees that.
-    pub unsafe fn get<'map, Q>(
-        &self,
+    pub unsafe fn get<'map, O, Q, S>(
+        &'map self,
         key: &Q,
-        hash: u64,
+        hash: HashCode,
+        salted: S,
         pause: Pause<'map, Garbage<K, V>>,
     ) -> Option<ReadGuard<'map, K, V>>
     where
-        Q: ?Sized + Ord,
+        O: BucketOrder<Q>,
+        Q: ?Sized,
         K: Borrow<Q>,
+        S: Fn() -> Option<u64>,
     {
-        let mut shifted = hash;
+        let pair = self.get_paused::<O, Q, S>(key, hash, salted, &pause)?;
+        Some(ReadGuard::new(pair, pause))
+    }
+
+    // Just like `get`, but borrows the pause, so the returned pair may only be
+    // used while the pause is alive. Unsafe for the same reasons as `get`.
+    pub unsafe fn get_paused<'map, O, Q, S>(
+        &'map self,
+        key: &Q,
+        hash: HashCode,
+        salted: S,
+        _pause: &Pause<Garbage<K, V>>,
+    ) -> Option<&'map (K, V)>
+    where
+        O: BucketOrder<Q>,
+        Q: ?Sized,
+        K: Borrow<Q>,
+        S: Fn() -> Option<u64>,
+    {
+        let (pair, _) = self.get_versioned::<O, Q, S>(key, hash, salted)?;
+        Some(pair)
+    }
+
+    // Just like `get_paused`, but the version of the entry of the pair is
+    // returned too, and the returned pair may only be used while the table is
+    // kept alive, e.g. by a pause of the incinerator of the map, or by a
+    // snapshot holding it. Unsafe for the same reasons as `get`.
+    pub unsafe fn get_versioned<'map, O, Q, S>(
+        &'map self,
+        key: &Q,
+        hash: HashCode,
+        salted: S,
+    ) -> Option<(&'map (K, V), usize)>
+    where
+        O: BucketOrder<Q>,
+        Q: ?Sized,
+        K: Borrow<Q>,
+        S: Fn() -> Option<u64>,
+    {
+        let mut digits = Digits::new(hash, self.bits());
         let mut table = self;
 
         loop {
             // Compute the index from the shifted hash's lower bits.
-            let index = shifted as usize & (1 << BITS) - 1;
+            let index = digits.index();
             let loaded = table.nodes[index].atomic.load(Acquire);
 
-            // Null means we have nothing.
-            if loaded.is_null() {
+            // Null means we have nothing. So does a frozen node.
+            if is_vacant(loaded) {
                 break None;
             }
 
             // Cleared lower bit means this is a bucket.
             if loaded as usize & 1 == 0 {
-                let bucket = &*(loaded as *mut Bucket<K, V>);
+                let bucket = &*bucket_ptr(loaded);
 
                 // This bucket only matters if it has the same hash we do.
                 if bucket.hash() != hash {
                     break None;
                 }
 
-                break match bucket.get(key, pause) {
+                break match bucket.get::<O, Q>(key) {
                     // Success.
-                    GetRes::Found(pair) => Some(pair),
+                    GetRes::Found(pair, version) => Some((pair, version)),
 
                     // Not here.
                     GetRes::NotFound => None,
 
-                    // Delete the bucket completely.
-                    GetRes::Delete(pause) => {
+                    // Delete the bucket completely, unless its node is frozen.
+                    // With snapshots, lookups never change nodes, which may be
+                    // shared with them, and writers delete the bucket.
+                    GetRes::Delete
+                        if is_frozen(loaded) || cfg!(feature = "snapshot") =>
+                    {
+                        None
+                    },
+                    GetRes::Delete => {
+                        // Storing null publishes nothing, and the bucket was
+                        // already loaded with `Acquire`. It is retired under
+                        // our pause, whose end synchronizes with later pauses.
                         let res = table.nodes[index].atomic.compare_exchange(
                             loaded,
                             null_mut(),
@@ -96,12 +354,10 @@ impl<K, V> Table<K, V> {
                         );
 
                         if res.is_ok() {
-                            let alloc = OwnedAlloc::from_raw(
-                                NonNull::new_unchecked(loaded as *mut _),
-                            );
-                            // Needs to be destroyed by the incinerator as it is
-                            // shared.
-                            pause.add_to_incin(Garbage::Bucket(alloc));
+                            // Needs to be retired as it is shared.
+                            Bucket::retire(NonNull::new_unchecked(
+                                loaded as *mut Bucket<K, V>,
+                            ));
                         }
 
                         None
@@ -111,38 +367,124 @@ impl<K, V> Table<K, V> {
 
             // If none of other cases have been confirmed, the only remaining
             // case is a branching table. Let's try to look at it.
-            table = &*((loaded as usize & !1) as *mut Self);
+            table = &*table_ptr(loaded);
+            let stored = || table.salted_of_stored::<O, Q>(key);
             // Shifting the hash so we test some other bits.
-            shifted >>= BITS;
+            if !digits.descend(|| salted().or_else(stored)) {
+                break None;
+            }
+        }
+    }
+
+    // The salted hash of the stored key equal to the given one, if any, found
+    // by scanning this table and its sub-tables, for a search which cannot
+    // hash its key with the salt. Unsafe because the table must be kept alive,
+    // just like in `get_versioned`.
+    unsafe fn salted_of_stored<O, Q>(&self, key: &Q) -> Option<u64>
+    where
+        O: BucketOrder<Q>,
+        Q: ?Sized,
+        K: Borrow<Q>,
+    {
+        self.scan(None, 0, None, |_, _, _, bucket| {
+            match bucket.get::<O, Q>(key) {
+                // The stored keys of a bucket share their salted hash.
+                GetRes::Found(..) => bucket.salted(),
+                _ => None,
+            }
+        })
+    }
+
+    // Pushes every pair of the bucket storing the given hash, if any, into the
+    // given vector. Unlike `get_paused`, an empty bucket is left for the next
+    // lookup to delete. Only meant for maps without flood protection, whose
+    // keys of the same hash share a bucket. Unsafe for the same reasons as
+    // `get_versioned`.
+    pub unsafe fn collect_hash<'map>(
+        &'map self,
+        hash: HashCode,
+        out: &mut Vec<&'map (K, V)>,
+    ) {
+        let bits = self.bits();
+        let mask = self.mask();
+        let mut shifted = hash;
+        let mut table = self;
+
+        loop {
+            let index = shifted as usize & mask;
+            let loaded = table.nodes[index].atomic.load(Acquire);
+
+            if is_vacant(loaded) {
+                break;
+            }
+
+            if loaded as usize & 1 == 0 {
+                let bucket = &*bucket_ptr::<K, V>(loaded);
+                if bucket.hash() == hash {
+                    bucket.collect(out);
+                }
+                break;
+            }
+
+            table = &*table_ptr(loaded);
+            shifted >>= bits;
         }
     }
 
+    // Gives the inserter back as an error if a frozen node is found, or if
+    // allocation fails, in which case the tree is left unchanged.
+    //
     // Unsafe because the incinerator needs to be paused and there are no
     // guarantees the passed pause comes from the incinerator used with the map
     // by other threads. Map implementation guarantees that.
     #[inline(never)]
-    pub unsafe fn insert<I>(
+    pub unsafe fn insert<O, I>(
         &self,
         mut inserter: I,
-        hash: u64,
+        hash: HashCode,
         pause: &Pause<Garbage<K, V>>,
         incin: &Arc<Incinerator<Garbage<K, V>>>,
-    ) -> Insertion<K, V, I>
+        slabs: &Slabs<K, V>,
+        probe: &mut Probe,
+    ) -> Result<Insertion<K, V, I>, InsertErr<I>>
     where
+        O: BucketOrder<K>,
         I: Inserter<K, V>,
-        K: Ord,
     {
+        let bits = self.bits();
         let mut table = self;
-        let mut shifted = hash;
-        let mut depth = 1;
+        let mut digits = Digits::new(hash, bits);
+        // The salted hash of our key, computed once it is needed.
+        let mut salted = None;
         let mut tbl_cache = Cache::<OwnedAlloc<Self>>::new();
+        let mut backoff = Backoff::new();
+        let writing = slabs.snapshots.write();
 
         // Compute the index from the shifted hash's lower bits.
-        let mut index = shifted as usize & (1 << BITS) - 1;
+        let mut index = digits.index();
         // Load what is in the index before trying to insert.
         let mut loaded = table.nodes[index].atomic.load(Acquire);
 
         loop {
+            // What we change must not be shared with snapshots.
+            loaded = match table.nodes[index].own(loaded, &writing, pause) {
+                Ok(owned) => owned,
+                Err(OwnErr::Changed(new)) => {
+                    loaded = new;
+                    probe.retry("node");
+                    backoff.snooze();
+                    continue;
+                },
+                // The table was copied, so we start again from the top, just
+                // like when it is frozen.
+                Err(OwnErr::Frozen) | Err(OwnErr::Copied) => {
+                    break Err(InsertErr::Frozen(inserter));
+                },
+                Err(OwnErr::NoMemory(err)) => {
+                    break Err(InsertErr::NoMemory(inserter, err));
+                },
+            };
+
             if loaded.is_null() {
                 // Let's test the found conditions.
                 inserter.input(None);
@@ -150,15 +492,24 @@ impl<K, V> Table<K, V> {
                     // The inserter accepted the conditions.
                     Some(nnptr) => nnptr,
                     // The inserter rejected the conditions.
-                    None => break Insertion::Failed(inserter),
+                    None => break Ok(Insertion::Failed(inserter)),
                 };
 
                 // Allocation of a bucket containing a single entry. Our pair.
-                let bucket = Bucket::new(hash, pair);
-                let bucket_nnptr = OwnedAlloc::new(bucket).into_raw();
+                let shared = inserter.shared();
+                let mut bucket_nnptr =
+                    match Bucket::try_alloc(hash, pair, shared, slabs) {
+                        Ok(nnptr) => nnptr,
+                        Err(err) => {
+                            break Err(InsertErr::NoMemory(inserter, err));
+                        },
+                    };
+                let head = bucket_nnptr.as_ref().head();
 
-                // We try to put it in the index.
-                let res = table.nodes[index].atomic.compare_exchange(
+                // We try to put it in the index. `Release` publishes the
+                // bucket, and `Acquire` on failure lets us enter whatever beat
+                // us. A spurious failure only takes us around the loop again.
+                let res = table.nodes[index].atomic.compare_exchange_weak(
                     loaded,
                     bucket_nnptr.as_ptr() as *mut (),
                     AcqRel,
@@ -169,41 +520,71 @@ impl<K, V> Table<K, V> {
                     Ok(_) => {
                         // Let's not forget to prevent the inserter from
                         // deallocating the pointer.
-                        inserter.take_pointer();
-                        break Insertion::Created;
+                        slabs.publish(inserter, head);
+                        break Ok(Insertion::Created);
                     },
 
                     Err(new) => {
-                        // If we failed this try, we have to clean up.
-                        let mut bucket = OwnedAlloc::from_raw(bucket_nnptr);
-                        bucket.take_first();
+                        // If we failed this try, we have to clean up, but
+                        // the pair still belongs to the inserter.
+                        bucket_nnptr.as_mut().take_first();
+                        Bucket::retire(bucket_nnptr);
                         loaded = new;
+                        probe.retry("node");
+                        backoff.snooze();
                     },
                 }
+            } else if is_frozen(loaded) {
+                // This table is being retired or collapsed by `shrink`, which
+                // either succeeds and detaches it or gives up and unfreezes
+                // the node, or it is part of a compact tree being upgraded.
+                // Either way, we must start again from the top.
+                break Err(InsertErr::Frozen(inserter));
             } else if loaded as usize & 1 == 0 {
                 // We keep pointers to Buckets with the lower bit cleared.
                 let bucket = &*(loaded as *mut Bucket<K, V>);
 
                 // If the hash of the bucket is equal to ours, there is no need
                 // for us to branch. Actually, we must not do it. We must insert
-                // in the bucket.
-                if bucket.hash() == hash {
-                    match bucket.insert(inserter, pause, incin) {
-                        InsertRes::Created => break Insertion::Created,
+                // in the bucket. At the maximum depth, every bit of the hashes
+                // was consumed, so this is the only case, and the keys share
+                // the ordered bucket instead of branching again. With flood
+                // protection, the salted hashes must be equal too.
+                let same = bucket.hash() == hash
+                    && Self::shares_bucket::<O, I>(
+                        bucket,
+                        &inserter,
+                        &mut salted,
+                        slabs,
+                    );
+                if same {
+                    match bucket.insert::<O, I>(inserter, pause, incin, probe) {
+                        InsertRes::Created => break Ok(Insertion::Created),
 
                         InsertRes::Updated(old) => {
-                            break Insertion::Updated(old);
+                            break Ok(Insertion::Updated(old));
                         },
 
                         InsertRes::Failed(inserter) => {
-                            break Insertion::Failed(inserter);
+                            break Ok(Insertion::Failed(inserter));
+                        },
+
+                        InsertRes::NoMemory(inserter, err) => {
+                            break Err(InsertErr::NoMemory(inserter, err));
+                        },
+
+                        // The bucket was copied meanwhile.
+                        InsertRes::Frozen(inserter) => {
+                            break Err(InsertErr::Frozen(inserter));
                         },
 
                         // This means we must delete the bucket entirely. And
                         // try again, obviously.
                         InsertRes::Delete(returned) => {
+                            // `Acquire` on failure, since we enter what we
+                            // find then.
                             let ptr = &table.nodes[index].atomic;
-                            let res = ptr.compare_exchange(
+                            let res = ptr.compare_exchange_weak(
                                 loaded,
                                 null_mut(),
                                 AcqRel,
@@ -212,17 +593,19 @@ impl<K, V> Table<K, V> {
 
                             match res {
                                 Ok(_) => {
-                                    let alloc = OwnedAlloc::from_raw(
-                                        NonNull::new_unchecked(
-                                            loaded as *mut _,
-                                        ),
-                                    );
-                                    incin.add(Garbage::Bucket(alloc));
-                                    loaded = null_mut()
+                                    // A snapshot may have held the bucket
+                                    // since we found it owned.
+                                    Bucket::release(NonNull::new_unchecked(
+                                        loaded as *mut Bucket<K, V>,
+                                    ));
+                                    loaded = null_mut();
+                                    probe.collapse();
                                 },
 
                                 Err(new) => {
                                     loaded = new;
+                                    probe.retry("node");
+                                    backoff.snooze();
                                 },
                             }
 
@@ -230,16 +613,32 @@ impl<K, V> Table<K, V> {
                         },
                     }
                 } else {
-                    // In the case hashes aren't equal, we will branch!
-                    let new_table = tbl_cache.take_or(|| Self::new_alloc());
-                    let other_shifted = bucket.hash() >> (depth * BITS);
-                    let other_index = other_shifted as usize & (1 << BITS) - 1;
+                    // In the case hashes aren't equal, we will branch! They
+                    // differ in bits not consumed yet, so this is above the
+                    // maximum depth.
+                    debug_assert!(if slabs.salt.is_some() {
+                        digits.depth < salted_max_depth(bits)
+                    } else {
+                        digits.depth < max_depth(bits)
+                    });
+                    let created =
+                        tbl_cache.try_take_or(|| Self::try_new_alloc(bits));
+                    let new_table = match created {
+                        Ok(new_table) => new_table,
+                        Err(err) => {
+                            break Err(InsertErr::NoMemory(inserter, err));
+                        },
+                    };
+                    let other_index = bucket_index(bucket, digits.depth, bits);
 
                     // Placing the found bucket into the new table first.
                     new_table.nodes[other_index].atomic.store(loaded, Relaxed);
 
+                    // `Release` publishes the new table along with the bucket
+                    // placed in it, and `Acquire` on failure lets us enter
+                    // whatever beat us.
                     let new_table_nnptr = new_table.into_raw();
-                    let res = table.nodes[index].atomic.compare_exchange(
+                    let res = table.nodes[index].atomic.compare_exchange_weak(
                         loaded,
                         // Note we mark the lower bit!
                         (new_table_nnptr.as_ptr() as usize | 1) as *mut (),
@@ -251,12 +650,15 @@ impl<K, V> Table<K, V> {
                         Ok(_) => {
                             // If we succeeded, let's act like we found another
                             // table in this index.
-                            depth += 1;
+                            probe.branch();
+                            probe.descend();
                             table = &*new_table_nnptr.as_ptr();
-                            shifted >>= BITS;
+                            digits.descend(|| {
+                                Self::key_salted(&inserter, &mut salted, slabs)
+                            });
                             // Compute the index from the shifted hash's lower
                             // bits.
-                            index = shifted as usize & (1 << BITS) - 1;
+                            index = digits.index();
                             // Load what is in the index before trying to
                             // insert.
                             loaded = table.nodes[index].atomic.load(Acquire);
@@ -275,6 +677,8 @@ impl<K, V> Table<K, V> {
                                 .store(null_mut(), Relaxed);
                             tbl_cache.store(new_table);
                             loaded = new;
+                            probe.retry("node");
+                            backoff.snooze();
                         },
                     }
                 }
@@ -282,13 +686,15 @@ impl<K, V> Table<K, V> {
                 // If none of other cases have been confirmed, the only
                 // remaining case is a branching table. Let's
                 // try to look at it.
-                depth += 1;
-                table = &*((loaded as usize & !1) as *mut Self);
-                shifted >>= BITS;
+                probe.descend();
+                table = &*table_ptr(loaded);
+                digits.descend(|| {
+                    Self::key_salted(&inserter, &mut salted, slabs)
+                });
 
                 // Compute the index from the shifted hash's lower
                 // bits.
-                index = shifted as usize & (1 << BITS) - 1;
+                index = digits.index();
                 // Load what is in the index before trying to
                 // insert.
                 loaded = table.nodes[index].atomic.load(Acquire);
@@ -296,50 +702,139 @@ impl<K, V> Table<K, V> {
         }
     }
 
+    // Tests whether the key of the inserter belongs in the given bucket, whose
+    // plain hash is the same as the key's. Without flood protection, it always
+    // does. An empty bucket is taken too, so the insertion deletes it. Unsafe
+    // because the incinerator needs to be paused.
+    unsafe fn shares_bucket<O, I>(
+        bucket: &Bucket<K, V>,
+        inserter: &I,
+        salted: &mut Option<u64>,
+        slabs: &Slabs<K, V>,
+    ) -> bool
+    where
+        O: BucketOrder<K>,
+        I: Inserter<K, V>,
+    {
+        let salt = match &slabs.salt {
+            Some(salt) => salt,
+            None => return true,
+        };
+        let (key, _) = match bucket.first() {
+            Some(pair) => pair,
+            None => return true,
+        };
+        // Updates skip the salt.
+        O::compare(inserter.key(), key).is_eq()
+            || *salted.get_or_insert_with(|| salt.hash_stored(inserter.key()))
+                == salt.hash_stored(key)
+    }
+
+    // The salted hash of the key of the inserter, with flood protection,
+    // computed at most once and kept in the given cell.
+    fn key_salted<I>(
+        inserter: &I,
+        salted: &mut Option<u64>,
+        slabs: &Slabs<K, V>,
+    ) -> Option<u64>
+    where
+        I: Inserter<K, V>,
+    {
+        let salt = slabs.salt.as_ref()?;
+        Some(*salted.get_or_insert_with(|| salt.hash_stored(inserter.key())))
+    }
+
+    // The salted hash is given just like in `get`. The removed pair is given
+    // back to be wrapped in a `Removed` by the caller.
+    //
     // Unsafe because the incinerator needs to be paused and there are no
     // guarantees the passed pause comes from the incinerator used with the map
     // by other threads. Map implementation guarantees that.
-    pub unsafe fn remove<Q, F>(
+    pub unsafe fn remove<O, Q, F, S>(
         &self,
         key: &Q,
-        interactive: F,
-        hash: u64,
+        mut interactive: F,
+        hash: HashCode,
+        salted: S,
         pause: &Pause<Garbage<K, V>>,
-        incin: &Arc<Incinerator<Garbage<K, V>>>,
-    ) -> Option<Removed<K, V>>
+        slabs: &Slabs<K, V>,
+    ) -> Option<RemovedPair<K, V>>
     where
-        Q: ?Sized + Ord,
+        O: BucketOrder<Q>,
+        Q: ?Sized,
         K: Borrow<Q>,
         F: FnMut(&(K, V)) -> bool,
+        S: Fn() -> Option<u64>,
     {
+        let mut digits = Digits::new(hash, self.bits());
         let mut table = self;
-        let mut shifted = hash;
+        let mut probe = Probe::new();
+        let mut backoff = Backoff::new();
+        let writing = slabs.snapshots.write();
 
         loop {
             // Compute the index from the shifted hash's lower bits.
-            let index = shifted as usize & (1 << BITS) - 1;
+            let index = digits.index();
             // Let's load to see what is in there.
             let loaded = table.nodes[index].atomic.load(Acquire);
 
-            // Null means we have nothing.
-            if loaded.is_null() {
+            // Null means we have nothing. So does a frozen node.
+            if is_vacant(loaded) {
+                break None;
+            }
+
+            // This bucket only matters if it has the same hash we do.
+            if loaded as usize & 1 == 0
+                && (*bucket_ptr::<K, V>(loaded)).hash() != hash
+            {
                 break None;
             }
 
+            // What we change must not be shared with snapshots. If the node
+            // changed, even to a copy, let's load it again.
+            match table.nodes[index].own(loaded, &writing, pause) {
+                Ok(owned) if owned == loaded => (),
+                Ok(_) | Err(OwnErr::Changed(_)) => {
+                    probe.retry("node");
+                    continue;
+                },
+                // The bucket cannot be copied before `shrink` is done with
+                // its node, which may detach this table, and a copied table
+                // is never changed anymore, so we must start again from the
+                // top.
+                Err(OwnErr::Frozen) | Err(OwnErr::Copied) => {
+                    probe.retry("frozen");
+                    probe.restart();
+                    backoff.snooze();
+                    digits = Digits::new(hash, self.bits());
+                    table = self;
+                    continue;
+                },
+                Err(OwnErr::NoMemory(err)) => err.handle(),
+            }
+
             // Cleared lower bit means this is a bucket.
             if loaded as usize & 1 == 0 {
-                let bucket = &*(loaded as *mut Bucket<K, V>);
-
-                // This bucket only matters if it has the same hash we do.
-                if bucket.hash() != hash {
-                    break None;
+                let bucket = &*bucket_ptr(loaded);
+
+                let res =
+                    bucket.remove::<O, Q, _>(key, &mut interactive, &mut probe);
+
+                // The bucket was copied meanwhile, so the copy must be found
+                // again from the top.
+                if res.restart {
+                    probe.retry("frozen");
+                    probe.restart();
+                    digits = Digits::new(hash, self.bits());
+                    table = self;
+                    continue;
                 }
 
-                let res = bucket.remove(key, interactive, pause, incin);
-
                 // If this field is true it means the whole bucket must be
-                // removed. Regardless of failure or success.
-                if res.delete {
+                // removed. Regardless of failure or success. A frozen node
+                // cannot be changed, though.
+                if res.delete && !is_frozen(loaded) {
+                    // Just like the clean-up in `get`.
                     let res = table.nodes[index].atomic.compare_exchange(
                         loaded,
                         null_mut(),
@@ -348,134 +843,1458 @@ impl<K, V> Table<K, V> {
                     );
 
                     if res.is_ok() {
-                        let alloc = OwnedAlloc::from_raw(
-                            NonNull::new_unchecked(loaded as *mut _),
-                        );
-                        incin.add(Garbage::Bucket(alloc));
+                        Bucket::release(NonNull::new_unchecked(
+                            loaded as *mut Bucket<K, V>,
+                        ));
+                        probe.collapse();
                     }
                 }
-                break res.pair;
+                break res.removed;
             }
 
             // If none of other cases have been confirmed, the only remaining
             // case is a branching table. Let's try to look at it.
-            table = &*((loaded as usize & !1) as *mut Self);
+            probe.descend();
+            table = &*table_ptr(loaded);
+            let stored = || table.salted_of_stored::<O, Q>(key);
             // Shifting the hash so we test some other bits.
-            shifted >>= BITS;
+            if !digits.descend(|| salted().or_else(stored)) {
+                break None;
+            }
         }
     }
 
-    // Unsafe because calling this function and using the table again later will
-    // cause undefined behavior.
-    #[inline]
-    pub unsafe fn free_nodes(
-        &mut self,
-        tbl_stack: &mut Vec<OwnedAlloc<Table<K, V>>>,
-    ) {
-        for node in &self.nodes as &[Node<K, V>] {
-            Node::free_ptr(node.atomic.load(Relaxed), tbl_stack);
+    // Removes the first entry found by scanning this table and its sub-tables.
+    // This table is scanned starting at the node pointed by the cursor, and the
+    // cursor is advanced past nodes found exhausted, so callers skip them from
+    // then on. Sub-tables are scanned starting at the given index, so
+    // concurrent callers with different starts do not fight over the same
+    // nodes. Unsafe because the incinerator needs to be paused, and the pause
+    // must come from the given incinerator.
+    pub unsafe fn pop_any(
+        &self,
+        cursor: &AtomicUsize,
+        start: usize,
+        pause: &Pause<Garbage<K, V>>,
+        incin: &Arc<Incinerator<Garbage<K, V>>>,
+        slabs: &Slabs<K, V>,
+    ) -> Option<Removed<K, V>> {
+        let first = cursor.load(Relaxed);
+        let writing = slabs.snapshots.write();
+
+        for i in 0 .. self.nodes.len() {
+            let pos = first.wrapping_add(i);
+            let index = pos & self.mask();
+            let popped =
+                self.pop_any_at(index, start, pause, incin, &writing);
+            if popped.is_some() {
+                return popped;
+            }
+            // Fails if someone else already advanced it, which is fine. The
+            // cursor is only a hint, so it needs no ordering.
+            let _ = cursor.compare_exchange(
+                pos,
+                pos.wrapping_add(1),
+                Relaxed,
+                Relaxed,
+            );
         }
+
+        None
     }
 
-    #[inline]
-    pub fn clear(&mut self, tbl_stack: &mut Vec<OwnedAlloc<Table<K, V>>>) {
-        for node in &self.nodes as &[Node<K, V>] {
-            // This should be safe because we store only proper pointers.
-            unsafe {
-                Node::free_ptr(
-                    node.atomic.swap(null_mut(), Relaxed),
-                    tbl_stack,
-                );
+    // Removes the first entry found under the node at the given index. Unsafe
+    // for the same reasons as `pop_any`.
+    unsafe fn pop_any_at(
+        &self,
+        index: usize,
+        start: usize,
+        pause: &Pause<Garbage<K, V>>,
+        incin: &Arc<Incinerator<Garbage<K, V>>>,
+        writing: &Writing,
+    ) -> Option<Removed<K, V>> {
+        let owner = Some((writing, pause));
+        loop {
+            let popped = self.scan(
+                Some(index),
+                start,
+                owner,
+                |table, index, loaded, bucket| {
+                    let popped = bucket.pop_first(pause, incin);
+
+                    // Just some clean up if the bucket became empty, unless
+                    // its node is frozen.
+                    if !is_frozen(loaded) && bucket.try_clear_first() {
+                        // Just like the clean-up in `get`.
+                        let res = table.nodes[index].atomic.compare_exchange(
+                            loaded,
+                            null_mut(),
+                            Relaxed,
+                            Relaxed,
+                        );
+
+                        if res.is_ok() {
+                            Bucket::release(NonNull::new_unchecked(
+                                loaded as *mut Bucket<K, V>,
+                            ));
+                        }
+                    }
+
+                    popped.transpose()
+                },
+            );
+
+            match popped {
+                Some(Ok(removed)) => break Some(removed),
+                // The bucket was copied meanwhile, so the node is scanned
+                // again.
+                Some(Err(Restart)) => continue,
+                None => break None,
             }
         }
     }
 
-    pub fn optimize_space(&mut self) -> OptSpaceRes<K, V> {
-        let mut removed = 0usize;
-        let mut last_bucket = None;
+    // Returns the first live pair found by scanning this table and its
+    // sub-tables, each one starting at the given index. Unsafe because the
+    // table must be kept alive while the pair is used, e.g. by a pause of the
+    // incinerator of the map, or by a snapshot holding it.
+    pub unsafe fn get_any(&self, start: usize) -> Option<&(K, V)> {
+        self.scan(None, start, None, |_, _, _, bucket| bucket.first())
+    }
 
-        for node in &self.nodes as &[Node<K, V>] {
-            let loaded = node.atomic.load(Relaxed);
+    // Calls the closure on every live pair of this table and its sub-tables.
+    // Unsafe for the same reasons as `get_any`.
+    #[cfg(feature = "snapshot")]
+    pub unsafe fn for_each_held<'map, F>(&'map self, mut visitor: F)
+    where
+        F: FnMut(&'map (K, V)),
+    {
+        let mut pairs = Vec::new();
+        self.scan(None, 0, None, |_, _, _, bucket| {
+            bucket.collect(&mut pairs);
+            pairs.drain(..).for_each(&mut visitor);
+            None::<()>
+        });
+    }
 
-            if loaded.is_null() {
-                removed += 1;
-            } else if loaded as usize & 1 == 0 {
-                let bucket_ptr = loaded as *mut Bucket<K, V>;
-                // This is safe because:
-                //
-                // 1. We have exclusive reference to the table.
-                //
-                // 2. We proper allocate pointers stored in the table.
-                //
-                // 3. Bucket pointers are not marked and we checked for it.
-                if unsafe { (*bucket_ptr).is_empty() } {
-                    node.atomic.store(null_mut(), Release);
-                    removed += 1;
+    // Scans this table and its sub-tables depth-first, calling the closure on
+    // each bucket until it returns something. If an index is given, only the
+    // node at that index is scanned in this table. Sub-tables are scanned
+    // starting at the given index. If an operation changing the map is given,
+    // every sub-table and bucket is made to belong to the map before it is
+    // scanned, see `Node::own`, and the scan starts again if a table on the
+    // way is copied meanwhile. Unsafe because the incinerator needs to be
+    // paused, unless no operation is given, and the tables are held.
+    unsafe fn scan<'map, F, R>(
+        &'map self,
+        index: Option<usize>,
+        start: usize,
+        owner: Option<(&Writing, &Pause<Garbage<K, V>>)>,
+        mut on_bucket: F,
+    ) -> Option<R>
+    where
+        F: FnMut(&'map Self, usize, *mut (), &'map Bucket<K, V>) -> Option<R>,
+    {
+        let mut tables = Vec::new();
+        let first = index.map(|index| (self, index));
+        let mut next = first;
+        if next.is_none() {
+            tables.push((self, 0));
+        }
 
-                    // This is safe because we have exclusive reference to the
-                    // map. Also, we remove the bucket from the table so no one
-                    // else will find it.
-                    unsafe {
-                        OwnedAlloc::from_raw(NonNull::new_unchecked(
-                            bucket_ptr,
-                        ));
-                    }
-                } else {
-                    // Safe because of the same things in the list above. Also,
-                    // we checked for null already, we can by-pass this check.
-                    let nnptr = unsafe { NonNull::new_unchecked(bucket_ptr) };
-                    last_bucket = Some(nnptr);
+        loop {
+            let (table, index) = match next.take() {
+                Some(node) => node,
+                None => match tables.pop() {
+                    Some((table, i)) if i < table.nodes.len() => {
+                        tables.push((table, i + 1));
+                        (table, start.wrapping_add(i) & table.mask())
+                    },
+                    Some(_) => continue,
+                    None => break None,
+                },
+            };
+
+            let mut loaded = table.nodes[index].atomic.load(Acquire);
+
+            if let Some((writing, pause)) = owner {
+                loaded = match table.nodes[index].own(loaded, writing, pause) {
+                    Ok(owned) => owned,
+                    // Let's handle this node again.
+                    Err(OwnErr::Changed(_)) => {
+                        next = Some((table, index));
+                        continue;
+                    },
+                    // A bucket shared with snapshots, whose node `shrink`
+                    // froze, cannot be changed, so it is skipped.
+                    Err(OwnErr::Frozen) => continue,
+                    // A table on the way was copied, so the copy must be
+                    // scanned instead, from the start.
+                    Err(OwnErr::Copied) => {
+                        tables.clear();
+                        next = first;
+                        if next.is_none() {
+                            tables.push((self, 0));
+                        }
+                        continue;
+                    },
+                    Err(OwnErr::NoMemory(err)) => err.handle(),
+                };
+            }
+
+            if is_vacant(loaded) {
+                continue;
+            }
+
+            // Cleared lower bit means this is a bucket.
+            if loaded as usize & 1 == 0 {
+                let bucket = &*bucket_ptr(loaded);
+                let found = on_bucket(table, index, loaded, bucket);
+                if found.is_some() {
+                    break found;
                 }
             } else {
-                let table_ptr = (loaded as usize & !1) as *mut Table<K, V>;
+                tables.push((&*table_ptr(loaded), 0));
+            }
+        }
+    }
+
+    // Calls the visitor on every pair found in this table and its sub-tables.
+    // The incinerator is paused only while a chunk of nodes is read, and it is
+    // resumed before moving to the next chunk, so reclamation is never stalled
+    // for the whole traversal. The visitor is only called after a bucket was
+    // completely read, so no pair is visited twice because of retries.
+    pub fn visit<F>(&self, incin: &Incinerator<Garbage<K, V>>, visitor: F)
+    where
+        F: FnMut(&(K, V)),
+    {
+        self.visit_part(incin, &[], 0 .. self.nodes.len(), visitor)
+    }
+
+    // Just like `visit`, but only visits the given range of nodes of the
+    // sub-table found through the given indices, along with their sub-tables.
+    // See `walk_part`.
+    pub fn visit_part<F>(
+        &self,
+        incin: &Incinerator<Garbage<K, V>>,
+        prefix: &[usize],
+        range: Range<usize>,
+        mut visitor: F,
+    ) where
+        F: FnMut(&(K, V)),
+    {
+        let mut pairs = Vec::new();
 
+        self.walk_part(incin, prefix, range, |node, _, _| {
+            let loaded = node.atomic.load(Acquire);
+
+            if !is_vacant(loaded) && loaded as usize & 1 == 0 {
+                let bucket = bucket_ptr(loaded);
                 // This is safe because:
                 //
-                // 1. We have exclusive reference to the table.
-                //
-                // 2. We proper allocate pointers stored in the table.
+                // 1. The incinerator is paused.
                 //
-                // 3. Table pointers are marked and we checked for it.
+                // 2. We checked for null already.
                 //
-                // 4. We cleared the marked bit.
-                match unsafe { &mut *table_ptr }.optimize_space() {
-                    OptSpaceRes::NoOpt => (),
-
-                    OptSpaceRes::Remove => {
-                        node.atomic.store(null_mut(), Relaxed);
-                        // This is safe because we have exclusive reference to
-                        // the map. Also, we remove the inner table from the
-                        // outer table so no one else will find it.
-                        unsafe {
-                            let nnptr = NonNull::new_unchecked(table_ptr);
-                            OwnedAlloc::from_raw(nnptr);
-                        }
-                        removed += 1;
-                    },
+                // 3. We only store preoperly allocated nodes in the table and
+                // mark buckets with 0.
+                unsafe { (*bucket).collect(&mut pairs) };
 
-                    OptSpaceRes::TableToBucket(bucket) => {
-                        unsafe {
-                            // This is safe because we have exclusive reference
-                            // to the map. Also, we remove the inner table from
-                            // the outer table so no one else will find it.
-                            let nnptr = NonNull::new_unchecked(table_ptr);
-                            OwnedAlloc::from_raw(nnptr);
-                        }
-                        node.atomic.store(bucket.as_ptr() as *mut _, Relaxed)
-                    },
+                for pair in pairs.drain(..) {
+                    visitor(pair);
                 }
             }
-        }
-
-        match (last_bucket, self.nodes.len() - removed) {
-            (Some(nnptr), 1) => OptSpaceRes::TableToBucket(nnptr),
 
-            (_, 0) => OptSpaceRes::Remove,
+            loaded
+        })
+    }
+
+    // Visits the pairs of this table and its sub-tables depth-first, starting
+    // at the node found through the given path (see `walk`), until about
+    // `budget` nodes and pairs were handled. The path is left at the next node
+    // to be handled, and true is returned once the whole tree was handled.
+    // Buckets are visited whole, so one holding more pairs than the budget
+    // left is only visited if nothing was handled before it. Sub-tables
+    // retired since the path was taken are skipped just like in `walk_part`.
+    // Unsafe because the incinerator needs to be paused.
+    pub unsafe fn scan_batch<F>(
+        &self,
+        path: &mut Vec<usize>,
+        budget: usize,
+        mut visitor: F,
+    ) -> bool
+    where
+        F: FnMut(&(K, V)),
+    {
+        let bits = self.bits();
+        let mut pairs = Vec::new();
+        let mut spent = 0;
+        // The table of each level of the path, since the pause keeps them
+        // alive until we return.
+        let mut tables = vec![self];
+
+        for level in 0 .. path.len() - 1 {
+            let table = tables[level];
+            let loaded = table.nodes[path[level]].atomic.load(Acquire);
+            match as_table(loaded) {
+                Some(ptr) => tables.push(&*ptr),
+
+                // The sub-table is gone, so let's skip its node. If it was
+                // collapsed into its single bucket, the bucket must be handled
+                // now, unless its place in the sub-tables is behind the path.
+                None => {
+                    if !is_vacant(loaded) && loaded as usize & 1 == 0 {
+                        let bucket = &*bucket_ptr::<K, V>(loaded);
+                        let digits = (level + 1 .. path.len())
+                            .map(|depth| bucket_index(bucket, depth, bits));
+                        if digits.ge(path[level + 1 ..].iter().cloned()) {
+                            bucket.collect(&mut pairs);
+                            spent += pairs.len();
+                            for pair in pairs.drain(..) {
+                                visitor(pair);
+                            }
+                        }
+                    }
+                    path.truncate(level + 1);
+                    path[level] += 1;
+                    break;
+                },
+            }
+        }
+
+        loop {
+            let depth = path.len() - 1;
+            let table = tables[depth];
+            let index = path[depth];
+
+            if index == table.nodes.len() {
+                // This table is done, let's go back to its parent.
+                path.pop();
+                tables.pop();
+                match path.last_mut() {
+                    Some(parent) => *parent += 1,
+                    None => break true,
+                }
+                continue;
+            }
+
+            if spent >= budget {
+                break false;
+            }
+
+            let loaded = table.nodes[index].atomic.load(Acquire);
+            if let Some(ptr) = as_table(loaded) {
+                tables.push(&*ptr);
+                path.push(0);
+                spent += 1;
+                continue;
+            }
+
+            if !is_vacant(loaded) && loaded as usize & 1 == 0 {
+                (*bucket_ptr::<K, V>(loaded)).collect(&mut pairs);
+                if spent > 0 && spent + pairs.len() > budget {
+                    pairs.clear();
+                    break false;
+                }
+                spent += pairs.len().max(1);
+                for pair in pairs.drain(..) {
+                    visitor(pair);
+                }
+            } else {
+                spent += 1;
+            }
+            path[depth] += 1;
+        }
+    }
+
+    // Counts the tables, buckets and entries of this table and its sub-tables.
+    // Just like `visit`, the incinerator is paused only while a chunk of nodes
+    // is read.
+    pub fn stats(&self, incin: &Incinerator<Garbage<K, V>>) -> MapStats {
+        let mut stats = MapStats {
+            tables: 1,
+            max_depth: 1,
+            table_bytes: self.byte_size(),
+            ..MapStats::default()
+        };
+        let mut pairs = Vec::new();
+
+        self.walk(incin, |node, path, _| {
+            let loaded = node.atomic.load(Acquire);
+
+            if let Some(ptr) = as_table::<K, V>(loaded) {
+                stats.tables += 1;
+                stats.max_depth = stats.max_depth.max(path.len() + 1);
+                // This is safe because the incinerator is paused.
+                stats.table_bytes += unsafe { (*ptr).byte_size() };
+            } else if !is_vacant(loaded) && loaded as usize & 1 == 0 {
+                // This is safe because the incinerator is paused and we only
+                // store properly allocated buckets with the lower bit
+                // cleared.
+                unsafe {
+                    (*bucket_ptr::<K, V>(loaded)).collect(&mut pairs)
+                };
+                stats.add_bucket(pairs.len());
+                pairs.clear();
+            }
+
+            loaded
+        });
+
+        stats
+    }
+
+    // Writes a line for this table, then a line for every node of this table
+    // and of its sub-tables holding something, in the order of `walk`. Just
+    // like `visit`, the incinerator is paused only while a chunk of nodes is
+    // read.
+    pub fn dump(
+        &self,
+        incin: &Incinerator<Garbage<K, V>>,
+        out: &mut dyn fmt::Write,
+    ) -> fmt::Result
+    where
+        K: fmt::Debug,
+        V: fmt::Debug,
+    {
+        let mut res = writeln!(out, "top: {} nodes", self.nodes.len());
+
+        self.walk(incin, |node, path, _| {
+            let loaded = node.atomic.load(Acquire);
+            if res.is_ok() && !is_vacant(loaded) {
+                // This is safe because the incinerator is paused.
+                res = unsafe { Self::dump_node(out, loaded, path) };
+            }
+            loaded
+        });
+
+        res
+    }
+
+    // Writes the line of `dump` for a node holding the given pointer, which is
+    // not vacant. Unsafe because the incinerator needs to be paused.
+    unsafe fn dump_node(
+        out: &mut dyn fmt::Write,
+        loaded: *mut (),
+        path: &[usize],
+    ) -> fmt::Result
+    where
+        K: fmt::Debug,
+        V: fmt::Debug,
+    {
+        for (depth, index) in path.iter().enumerate() {
+            let sep = if depth == 0 { "" } else { "." };
+            write!(out, "{}{}", sep, index)?;
+        }
+
+        if as_table::<K, V>(loaded).is_some() {
+            write!(out, ": table")?;
+        } else {
+            let bucket = &*bucket_ptr::<K, V>(loaded);
+            let mut pairs = Vec::new();
+            bucket.collect(&mut pairs);
+            let width = 2 + 2 * mem::size_of::<HashCode>();
+            write!(
+                out,
+                ": bucket {:#0width$x} len {}",
+                bucket.hash(),
+                pairs.len(),
+                width = width,
+            )?;
+            for (i, (key, val)) in pairs.into_iter().enumerate() {
+                let sep = if i == 0 { ":" } else { "," };
+                write!(out, "{} {:?} => {:?}", sep, key, val)?;
+            }
+        }
+
+        if is_frozen(loaded) {
+            write!(out, " (frozen)")?;
+        }
+        writeln!(out)
+    }
+
+    // Unsafe because calling this function and using the table again later will
+    // cause undefined behavior.
+    #[inline]
+    pub unsafe fn free_nodes(
+        &mut self,
+        tbl_stack: &mut Vec<OwnedAlloc<Table<K, V>>>,
+    ) {
+        for node in self.nodes.iter() {
+            Node::free_ptr(node.atomic.load(Relaxed), tbl_stack);
+        }
+    }
+
+    // Removes every entry of this table and its sub-tables, passing them to
+    // the given closure, and detaches the buckets left empty. The buckets are
+    // handed to the incinerator, but sub-tables are kept.
+    pub fn drain<F>(
+        &self,
+        incin: &Arc<Incinerator<Garbage<K, V>>>,
+        slabs: &Slabs<K, V>,
+        mut sink: F,
+    ) where
+        F: FnMut(Removed<K, V>),
+    {
+        let range = 0 .. self.nodes.len();
+        let slabs = Some(slabs);
+        self.walk_with(incin, slabs, &[], range, |node, _, pause, writing| {
+            // What we change must not be shared with snapshots. Safe because
+            // we paused the incinerator, and the walk only reaches tables
+            // belonging to the map.
+            let mut loaded = unsafe { node.load_for(writing, pause) };
+
+            while !is_vacant(loaded)
+                && !is_copied(loaded)
+                && loaded as usize & 1 == 0
+            {
+                // This is safe because we only store properly allocated
+                // buckets with the lower bit cleared.
+                let bucket = unsafe { &*bucket_ptr::<K, V>(loaded) };
+
+                // Safe because we paused the incinerator.
+                let popped = loop {
+                    match unsafe { bucket.pop_first(pause, incin) } {
+                        Ok(Some(removed)) => sink(removed),
+                        Ok(None) => break true,
+                        Err(Restart) => break false,
+                    }
+                };
+                if !popped {
+                    // The bucket was copied meanwhile, so let's drain the
+                    // copy. Safe for the same reasons as above.
+                    loaded = unsafe { node.load_for(writing, pause) };
+                    continue;
+                }
+
+                // The node of a frozen bucket cannot be changed, so it is
+                // left empty. Safe because we paused the incinerator.
+                if is_frozen(loaded) || !unsafe { bucket.try_clear_first() } {
+                    break;
+                }
+
+                // Just like the clean-up in `get`.
+                let res = node.atomic.compare_exchange(
+                    loaded,
+                    null_mut(),
+                    Relaxed,
+                    Relaxed,
+                );
+
+                match res {
+                    Ok(_) => {
+                        // Needs to be released as it is shared. Safe because
+                        // we detached it.
+                        unsafe {
+                            Bucket::release(NonNull::new_unchecked(
+                                loaded as *mut Bucket<K, V>,
+                            ))
+                        };
+                        loaded = null_mut();
+                    },
+
+                    // Whatever beat us may be shared, e.g. a bucket promoted
+                    // by `shrink`. Safe for the same reasons as above.
+                    Err(_) => loaded = unsafe { node.load_for(writing, pause) },
+                }
+            }
+
+            loaded
+        })
+    }
+
+    // Builds empty sub-tables, so that the tree has enough levels for the
+    // given number of entries, this table being the first level: a level is
+    // added while each node of the deepest one is expected to hold at least
+    // `RESERVE_LOAD` entries. Sub-tables are installed in null nodes just like
+    // insertions branch, so this can run concurrently with any operation.
+    // Nodes holding buckets are left to be branched by insertions.
+    pub fn reserve(
+        &self,
+        incin: &Incinerator<Garbage<K, V>>,
+        slabs: &Slabs<K, V>,
+        capacity: usize,
+    ) {
+        let bits = self.bits();
+        let mut levels = 1;
+        while levels < max_depth(bits)
+            && capacity.checked_shr((bits * levels) as u32).unwrap_or(0)
+                >= RESERVE_LOAD
+        {
+            levels += 1;
+        }
+        if levels == 1 {
+            return;
+        }
+
+        let range = 0 .. self.nodes.len();
+        let slabs = Some(slabs);
+        self.walk_with(incin, slabs, &[], range, |node, path, pause, writing| {
+            // What we change must not be shared with snapshots. Safe because
+            // we paused the incinerator, and the walk only reaches tables
+            // belonging to the map.
+            let mut loaded = unsafe { node.load_for(writing, pause) };
+
+            while loaded.is_null() {
+                let new_table_nnptr = Self::new_alloc(bits).into_raw();
+                // Note we mark the lower bit!
+                let marked =
+                    (new_table_nnptr.as_ptr() as usize | 1) as *mut ();
+                // `Release` publishes the new table.
+                let res = node.atomic.compare_exchange(
+                    null_mut(),
+                    marked,
+                    Release,
+                    Relaxed,
+                );
+
+                match res {
+                    Ok(_) => loaded = marked,
+
+                    // Whatever beat us may be shared, just like in `drain`.
+                    // Safe because we never published the new table.
+                    Err(_) => unsafe {
+                        drop(OwnedAlloc::from_raw(new_table_nnptr));
+                        loaded = node.load_for(writing, pause);
+                    },
+                }
+            }
+
+            // The sub-table of this node is at the level after the node's.
+            // The deepest sub-tables are not entered, so the walk does not go
+            // through the entries below them, unless this table was copied,
+            // and the walk must find the copy.
+            if path.len() + 1 < levels || is_copied(loaded) {
+                loaded
+            } else {
+                null_mut()
+            }
+        })
+    }
+
+    // Walks this table and its sub-tables depth-first, calling the closure on
+    // every node, along with its path: the indices of the nodes leading to it
+    // from this table, ending with its own index. The closure returns what it
+    // left in the node, and the walk descends into it if it is a table. The
+    // incinerator is paused only while a chunk of nodes is handled. Since
+    // `shrink` may retire sub-tables while the incinerator is not paused, no
+    // reference to a sub-table is kept between pauses: each chunk starts by
+    // finding the current sub-table again through the indices leading to it.
+    // Sub-tables retired in the meantime are skipped, as they are empty.
+    fn walk<F>(&self, incin: &Incinerator<Garbage<K, V>>, on_node: F)
+    where
+        F: FnMut(&Node<K, V>, &[usize], &Pause<Garbage<K, V>>) -> *mut (),
+    {
+        self.walk_part(incin, &[], 0 .. self.nodes.len(), on_node)
+    }
+
+    // Walks just like `walk`, but only through the given range of nodes of the
+    // sub-table found through the given indices (the prefix), and through
+    // their sub-tables. Paths still start from this table. If the sub-table of
+    // the prefix is retired meanwhile, the walk stops, handling the bucket it
+    // was collapsed into if the bucket falls in what is left of the range.
+    fn walk_part<F>(
+        &self,
+        incin: &Incinerator<Garbage<K, V>>,
+        prefix: &[usize],
+        range: Range<usize>,
+        mut on_node: F,
+    ) where
+        F: FnMut(&Node<K, V>, &[usize], &Pause<Garbage<K, V>>) -> *mut (),
+    {
+        self.walk_with(incin, None, prefix, range, |node, path, pause, _| {
+            on_node(node, path, pause)
+        })
+    }
+
+    // Walks just like `walk_part`. If the slabs are given, each chunk is
+    // handled as an operation changing the map, passed to the closure, and
+    // the sub-tables leading to the chunk are made to belong to the map, see
+    // `Node::own`. If the closure returns a node marked with `COPIED`, its
+    // table was copied, and the chunk starts again, finding the copy.
+    fn walk_with<F>(
+        &self,
+        incin: &Incinerator<Garbage<K, V>>,
+        slabs: Option<&Slabs<K, V>>,
+        prefix: &[usize],
+        range: Range<usize>,
+        mut on_node: F,
+    ) where
+        F: FnMut(
+            &Node<K, V>,
+            &[usize],
+            &Pause<Garbage<K, V>>,
+            Option<&Writing>,
+        ) -> *mut (),
+    {
+        let bits = self.bits();
+        let base = prefix.len();
+        // The index of the next node to be handled at each depth.
+        let mut path = prefix.to_vec();
+        path.push(range.start);
+
+        'chunk: while path.len() > base {
+            let pause = incin.pause();
+            let writing = slabs.map(|slabs| slabs.snapshots.write());
+            let writing = writing.as_ref();
+            let mut depth = path.len() - 1;
+            let mut table = self;
+
+            for level in 0 .. depth {
+                let node = &table.nodes[path[level]];
+                // This is safe because the incinerator is paused, and this
+                // table belongs to the map if we change it.
+                let loaded = unsafe { node.load_for(writing, &pause) };
+                if is_copied(loaded) {
+                    continue 'chunk;
+                }
+                match as_table(loaded) {
+                    // This is safe because the incinerator is paused.
+                    Some(ptr) => table = unsafe { &*ptr },
+
+                    // The sub-table is gone, so let's skip its node. If it
+                    // was collapsed into its single bucket, the bucket must
+                    // be handled now, unless it was handled in the sub-table.
+                    None => {
+                        if !is_vacant(loaded) && loaded as usize & 1 == 0 {
+                            // This is safe because the incinerator is paused.
+                            let bucket =
+                                unsafe { &*bucket_ptr::<K, V>(loaded) };
+                            // This is safe because the incinerator is paused.
+                            let index_at = |at: usize| unsafe {
+                                bucket_index(bucket, at, bits)
+                            };
+                            let handle = if level < base {
+                                (level + 1 .. base)
+                                    .all(|at| index_at(at) == path[at])
+                                    && index_at(base) >= path[base]
+                                    && index_at(base) < range.end
+                            } else {
+                                index_at(level + 1) >= path[level + 1]
+                            };
+                            if handle {
+                                on_node(
+                                    &table.nodes[path[level]],
+                                    &path[..= level],
+                                    &pause,
+                                    writing,
+                                );
+                            }
+                        }
+                        if level < base {
+                            break 'chunk;
+                        }
+                        path.truncate(level + 1);
+                        path[level] += 1;
+                        continue 'chunk;
+                    },
+                }
+            }
+
+            for _ in 0 .. VISIT_CHUNK {
+                let index = path[depth];
+                let end =
+                    if depth == base { range.end } else { table.nodes.len() };
+
+                if index == end {
+                    // This table is done, let's go back to its parent.
+                    path.pop();
+                    if path.len() > base {
+                        path[depth - 1] += 1;
+                    }
+                    continue 'chunk;
+                }
+
+                let loaded =
+                    on_node(&table.nodes[index], &path, &pause, writing);
+                if is_copied(loaded) {
+                    continue 'chunk;
+                }
+                match as_table(loaded) {
+                    Some(ptr) => {
+                        // This is safe because the incinerator is paused.
+                        table = unsafe { &*ptr };
+                        path.push(0);
+                        depth += 1;
+                    },
+
+                    None => path[depth] += 1,
+                }
+            }
+        }
+    }
+
+    // Checks the invariants of this table and of its sub-tables, panicking if
+    // one is broken: every sub-table has as many nodes as this one, and every
+    // bucket has a hash matching the indices of the nodes leading to it, along
+    // with the invariants checked by `Bucket::validate`. The key of each entry
+    // is passed to the closure with the hash of its bucket, and its salted
+    // hash if the map has flood protection and the bucket is not empty.
+    // Unsafe because the incinerator needs to be paused during the whole
+    // check.
+    #[cfg(any(test, feature = "debug-validate"))]
+    pub unsafe fn validate<O, F>(&self, protected: bool, mut on_key: F)
+    where
+        O: BucketOrder<K>,
+        F: FnMut(&K, HashCode, Option<u64>),
+    {
+        let bits = self.bits();
+        let deepest =
+            if protected { salted_max_depth(bits) } else { max_depth(bits) };
+        let mut tables = vec![(self, Vec::new())];
+
+        while let Some((table, path)) = tables.pop() {
+            assert_eq!(table.bits(), bits, "sub-table of a different size");
+            assert!(path.len() < deepest, "sub-table too deep");
+
+            for (index, node) in table.nodes.iter().enumerate() {
+                let loaded = node.atomic.load(Acquire);
+                let mut path = path.clone();
+                path.push(index);
+
+                if let Some(ptr) = as_table::<K, V>(loaded) {
+                    tables.push((&*ptr, path));
+                } else if !is_vacant(loaded) {
+                    let bucket = &*bucket_ptr::<K, V>(loaded);
+                    let hash = bucket.hash();
+                    let salted = bucket.salted();
+                    for (depth, &index) in path.iter().enumerate() {
+                        assert_eq!(
+                            bucket_index(bucket, depth, bits),
+                            index,
+                            "bucket hash does not match its place",
+                        );
+                    }
+                    bucket.validate::<O, _>(|key| on_key(key, hash, salted));
+                }
+            }
+        }
+    }
+
+    // Finds the sub-table reached through the given indices, if every node
+    // on the way holds a table. Unsafe because the incinerator needs to be
+    // paused while the sub-table is used.
+    pub unsafe fn sub_table(&self, path: &[usize]) -> Option<&Self> {
+        let mut table = self;
+        for &index in path {
+            table = &*as_table(table.nodes[index].atomic.load(Acquire))?;
+        }
+        Some(table)
+    }
+
+    // Estimates how many entries each node of the given range leads to, in
+    // order to balance the parts of a traversal: a bucket counts as a single
+    // entry, and a sub-table as its buckets plus a full table for each of its
+    // own sub-tables. Unsafe because the incinerator needs to be paused.
+    pub unsafe fn node_weights(&self, range: Range<usize>) -> Vec<usize> {
+        let weight = |loaded: *mut ()| match as_table::<K, V>(loaded) {
+            Some(_) => 1 << self.bits(),
+            None if is_vacant(loaded) => 0,
+            None => 1,
+        };
+        self.nodes[range]
+            .iter()
+            .map(|node| match as_table::<K, V>(node.atomic.load(Acquire)) {
+                Some(table) => (*table)
+                    .nodes
+                    .iter()
+                    .map(|node| weight(node.atomic.load(Acquire)))
+                    .sum(),
+                None => weight(node.atomic.load(Acquire)),
+            })
+            .collect()
+    }
+
+    // Tests whether `freeze_all` froze this table. Only meaningful for the top
+    // table of a compact tree, which is never shrunk.
+    pub fn is_frozen_all(&self) -> bool {
+        is_frozen(self.nodes[0].atomic.load(Acquire))
+    }
+
+    // Freezes every node of this table and of its sub-tables for good, so their
+    // buckets can be moved to a tree with bigger tables by `rebuild`. Unlike
+    // `try_freeze`, nodes holding tables are frozen too, and no node is ever
+    // restored, so any number of threads may freeze the same table. It must
+    // not be shrunk, though. A node is frozen before its table is entered, so
+    // once this returns, no new node can show up.
+    //
+    // Unsafe because the incinerator needs to be paused.
+    pub unsafe fn freeze_all(&self) {
+        for node in self.nodes.iter() {
+            let mut loaded = node.atomic.load(Acquire);
+
+            while !is_frozen(loaded) {
+                let frozen = if loaded.is_null() {
+                    FROZEN as *mut ()
+                } else {
+                    (loaded as usize | FROZEN_MARK) as *mut ()
+                };
+
+                // `Acquire` on failure, since a table found then is frozen
+                // too.
+                match node.atomic.compare_exchange_weak(
+                    loaded,
+                    frozen,
+                    AcqRel,
+                    Acquire,
+                ) {
+                    Ok(_) => loaded = frozen,
+                    Err(new) => loaded = new,
+                }
+            }
+
+            if let Some(ptr) = as_table::<K, V>(loaded) {
+                (*ptr).freeze_all();
+            }
+        }
+    }
+
+    // Builds a new tree, with tables of `1 << bits` nodes, holding the buckets
+    // of this table and of its sub-tables, which must have been frozen by
+    // `freeze_all`. The buckets are shared between both trees, so changes to
+    // their entries through the frozen tree are seen in the new one. The new
+    // tree is private until published by the caller. Unsafe because the
+    // incinerator needs to be paused.
+    pub unsafe fn rebuild(&self, bits: usize) -> OwnedAlloc<Self> {
+        self.try_rebuild(bits).unwrap_or_else(|err| err.handle())
+    }
+
+    // Just like `rebuild`, but if allocation fails, the new tree is
+    // deallocated, leaving the buckets to this tree. Unsafe for the same
+    // reasons as `rebuild`.
+    pub unsafe fn try_rebuild(
+        &self,
+        bits: usize,
+    ) -> Result<OwnedAlloc<Self>, AllocFailed> {
+        let top = Self::try_new_alloc(bits)?;
+        match top.place_all(self) {
+            Ok(()) => Ok(top),
+            Err(err) => {
+                Self::detach_tables(top.into_raw(), drop);
+                Err(err)
+            },
+        }
+    }
+
+    // Places the buckets of the given table and of its sub-tables in this
+    // private table. Nothing needs to be allocated to walk the given tree,
+    // which is at most `max_depth` tables deep. Unsafe for the same reasons
+    // as `place`.
+    unsafe fn place_all(&self, table: &Self) -> Result<(), AllocFailed> {
+        for node in table.nodes.iter() {
+            let loaded = node.atomic.load(Acquire);
+            match as_table::<K, V>(loaded) {
+                Some(ptr) => self.place_all(&*ptr)?,
+                None if !is_vacant(loaded) => self.place(bucket_ptr(loaded))?,
+                None => (),
+            }
+        }
+        Ok(())
+    }
+
+    // Stores the given bucket in this private table or in one of its
+    // sub-tables, creating sub-tables whenever two buckets share a node.
+    // If a sub-table cannot be allocated, the bucket is not stored. Unsafe
+    // because the bucket must be alive, and no other bucket in this tree can
+    // have the same hash, or the same salted hash with flood protection.
+    unsafe fn place(
+        &self,
+        bucket: *mut Bucket<K, V>,
+    ) -> Result<(), AllocFailed> {
+        let bits = self.bits();
+        let mut table = self;
+        let mut depth = 0;
+
+        loop {
+            let index = bucket_index(&*bucket, depth, bits);
+            let node = &table.nodes[index];
+            let loaded = node.atomic.load(Relaxed);
+
+            if loaded.is_null() {
+                node.atomic.store(bucket as *mut (), Relaxed);
+                break Ok(());
+            }
+
+            depth += 1;
+            table = match as_table(loaded) {
+                Some(ptr) => &*ptr,
+
+                None => {
+                    // The buckets have different hashes, so they are apart
+                    // before the hashes are completely consumed.
+                    let other = &*(loaded as *mut Bucket<K, V>);
+                    debug_assert!(depth < salted_max_depth(bits));
+                    let new_table = Self::try_new_alloc(bits)?;
+                    let other_index = bucket_index(other, depth, bits);
+                    new_table.nodes[other_index].atomic.store(loaded, Relaxed);
+                    let ptr = new_table.into_raw().as_ptr();
+                    node.atomic.store((ptr as usize | 1) as *mut (), Relaxed);
+                    &*ptr
+                },
+            };
+        }
+    }
+
+    // Passes the given table and each one of its sub-tables to the closure,
+    // but not their buckets. The nodes of a table are read before it is
+    // passed. Nothing is allocated to walk the tree, which is at most
+    // `max_depth` tables deep, so a tree can be freed after an allocation
+    // failure. Unsafe because the tables must not be reachable by other
+    // threads anymore, except under an incinerator pause if the closure hands
+    // them to the incinerator.
+    pub unsafe fn detach_tables<F>(top: NonNull<Self>, mut sink: F)
+    where
+        F: FnMut(OwnedAlloc<Self>),
+    {
+        Self::detach_tables_with(top, &mut sink);
+    }
+
+    unsafe fn detach_tables_with<F>(top: NonNull<Self>, sink: &mut F)
+    where
+        F: FnMut(OwnedAlloc<Self>),
+    {
+        let table = OwnedAlloc::from_raw(top);
+        for node in table.nodes.iter() {
+            let loaded = node.atomic.load(Acquire);
+            if let Some(ptr) = as_table(loaded) {
+                Self::detach_tables_with(NonNull::new_unchecked(ptr), sink);
+            }
+        }
+        sink(table);
+    }
+
+    // Tries to retire every empty sub-table of this table and of its
+    // sub-tables, deepest first, returning how many were retired. If `promote`
+    // is set, sub-tables holding a single bucket are retired too, and the
+    // bucket takes the place of the sub-table in the parent. Empty buckets
+    // found along the way are detached, since they would keep their tables
+    // from being retired.
+    //
+    // To retire a sub-table, every one of its nodes is changed from null to
+    // `FROZEN`, or, for a single bucket to be promoted, marked with
+    // `FROZEN_MARK`. If some other node is found, the frozen nodes are
+    // restored and the sub-table is kept. Once every node is frozen, nothing
+    // can be inserted in the sub-table anymore, except in the frozen bucket,
+    // which stays the same. So, the node of the sub-table in the parent can be
+    // changed to null or to the promoted bucket. Insertions which find a frozen
+    // node start again from the top, and lookups and removals see frozen nodes
+    // as they were, without changing them. Only the thread which froze a node
+    // restores it, so concurrent shrinks of the same sub-table make at most one
+    // of them succeed.
+    //
+    // Unsafe because the incinerator needs to be paused and there are no
+    // guarantees the passed pause comes from the incinerator used with the map
+    // by other threads. Map implementation guarantees that.
+    pub unsafe fn shrink(
+        &self,
+        promote: bool,
+        pause: &Pause<Garbage<K, V>>,
+        slabs: &Slabs<K, V>,
+    ) -> usize {
+        let writing = slabs.snapshots.write();
+        self.shrink_owned(promote, pause, &writing)
+    }
+
+    // Shrinks just like `shrink`, as the given operation changing the map.
+    // Sub-tables shared with snapshots are copied before they are shrunk, but
+    // buckets are not: a shared bucket is left as it is. Unsafe for the same
+    // reasons as `shrink`, and this table must belong to the map.
+    unsafe fn shrink_owned(
+        &self,
+        promote: bool,
+        pause: &Pause<Garbage<K, V>>,
+        writing: &Writing,
+    ) -> usize {
+        let mut retired = 0;
+
+        'nodes: for node in self.nodes.iter() {
+            let mut loaded = node.atomic.load(Acquire);
+
+            while as_table::<K, V>(loaded).is_some() {
+                match node.own(loaded, writing, pause) {
+                    Ok(owned) if owned == loaded => break,
+                    Ok(new) | Err(OwnErr::Changed(new)) => loaded = new,
+                    // Left as it is, just like any frozen node.
+                    Err(OwnErr::Frozen) => continue 'nodes,
+                    // This table was copied meanwhile, so the copy is left
+                    // to later shrinks.
+                    Err(OwnErr::Copied) => break 'nodes,
+                    Err(OwnErr::NoMemory(err)) => err.handle(),
+                }
+            }
+            if is_copied(loaded) {
+                break;
+            }
+
+            if let Some(ptr) = as_table(loaded) {
+                let table = &*ptr;
+                retired += table.shrink_owned(promote, pause, writing);
+
+                if let Some(single) = table.try_freeze(promote) {
+                    // The promoted bucket, if any, is held by this node too
+                    // once the table is replaced.
+                    let promoted = NonNull::new(single as *mut Bucket<K, V>);
+                    if let Some(bucket) = promoted {
+                        bucket.as_ref().share();
+                    }
+
+                    // `Release` publishes the promoted bucket, if any, which
+                    // we only loaded with `Acquire` ourselves. A failure is
+                    // final, since the table is unfrozen then.
+                    let res = node
+                        .atomic
+                        .compare_exchange(loaded, single, AcqRel, Relaxed);
+
+                    match res {
+                        Ok(_) => {
+                            // Needs to be released through the incinerator as
+                            // it is shared.
+                            Table::release(NonNull::new_unchecked(ptr), pause);
+                            retired += 1;
+                        },
+
+                        Err(_) => {
+                            if let Some(bucket) = promoted {
+                                Bucket::release(bucket);
+                            }
+                            table.unfreeze(table.nodes.len());
+                        },
+                    }
+                }
+            } else if !is_vacant(loaded) && !is_frozen(loaded) {
+                let bucket = &*(loaded as *mut Bucket<K, V>);
+
+                // A bucket shared with snapshots must not change.
+                if bucket.is_owned() && bucket.try_clear_first() {
+                    // Just like the clean-up in `get`.
+                    let res = node.atomic.compare_exchange(
+                        loaded,
+                        null_mut(),
+                        Relaxed,
+                        Relaxed,
+                    );
+
+                    if res.is_ok() {
+                        Bucket::release(NonNull::new_unchecked(
+                            loaded as *mut Bucket<K, V>,
+                        ));
+                    }
+                }
+            }
+        }
+
+        retired
+    }
+
+    // Freezes every node of this table if all of them are null, except, if
+    // `promote` is set, for a single bucket. Returns what should replace this
+    // table in its parent: either null or the single bucket. Otherwise,
+    // restores the nodes frozen so far and returns `None`.
+    fn try_freeze(&self, promote: bool) -> Option<*mut ()> {
+        let mut single: *mut () = null_mut();
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            // `Acquire` on failure, since a bucket found then may be promoted.
+            // A failure is final, so this is not a weak exchange.
+            let res = node.atomic.compare_exchange(
+                null_mut(),
+                FROZEN as *mut (),
+                AcqRel,
+                Acquire,
+            );
+
+            let frozen = match res {
+                Ok(_) => true,
+
+                Err(loaded)
+                    if promote
+                        && single.is_null()
+                        && !is_vacant(loaded)
+                        && !is_frozen(loaded)
+                        && !is_copied(loaded)
+                        && loaded as usize & 1 == 0 =>
+                {
+                    // Nothing found on failure is read, and a failure is
+                    // final.
+                    let marked = (loaded as usize | FROZEN_MARK) as *mut ();
+                    let res = node
+                        .atomic
+                        .compare_exchange(loaded, marked, AcqRel, Relaxed);
+                    single = loaded;
+                    res.is_ok()
+                },
+
+                Err(_) => false,
+            };
+
+            if !frozen {
+                self.unfreeze(i);
+                return None;
+            }
+        }
+
+        Some(single)
+    }
+
+    // Restores the given number of nodes frozen by this thread, from the
+    // first one. A node marked with `COPIED` meanwhile stays marked.
+    fn unfreeze(&self, count: usize) {
+        for node in &self.nodes[.. count] {
+            let mut loaded = node.atomic.load(Relaxed);
+            loop {
+                let restored =
+                    (loaded as usize & !(FROZEN | FROZEN_MARK)) as *mut ();
+                // `Release` publishes the node again, just like it was
+                // published before.
+                match node.atomic.compare_exchange_weak(
+                    loaded,
+                    restored,
+                    Release,
+                    Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(new) => loaded = new,
+                }
+            }
+        }
+    }
+
+    pub fn optimize_space(&mut self) -> OptSpaceRes<K, V> {
+        let mut removed = 0usize;
+        let mut last_bucket = None;
+
+        for node in self.nodes.iter() {
+            let loaded = node.atomic.load(Relaxed);
+
+            if loaded.is_null() {
+                removed += 1;
+            } else if loaded as usize & 1 == 0 {
+                let bucket_ptr = loaded as *mut Bucket<K, V>;
+                // This is safe because:
+                //
+                // 1. We have exclusive reference to the table.
+                //
+                // 2. We proper allocate pointers stored in the table.
+                //
+                // 3. Bucket pointers are not marked and we checked for it.
+                if unsafe { (*bucket_ptr).is_empty() } {
+                    node.atomic.store(null_mut(), Release);
+                    removed += 1;
+
+                    // This is safe because we have exclusive reference to the
+                    // map. Also, we remove the bucket from the table so no one
+                    // else will find it.
+                    unsafe {
+                        Bucket::retire(NonNull::new_unchecked(bucket_ptr));
+                    }
+                } else {
+                    // Safe because of the same things in the list above. Also,
+                    // we checked for null already, we can by-pass this check.
+                    let nnptr = unsafe { NonNull::new_unchecked(bucket_ptr) };
+                    last_bucket = Some(nnptr);
+                }
+            } else {
+                let table_ptr = (loaded as usize & !1) as *mut Table<K, V>;
+
+                // This is safe because:
+                //
+                // 1. We have exclusive reference to the table.
+                //
+                // 2. We proper allocate pointers stored in the table.
+                //
+                // 3. Table pointers are marked and we checked for it.
+                //
+                // 4. We cleared the marked bit.
+                match unsafe { &mut *table_ptr }.optimize_space() {
+                    OptSpaceRes::NoOpt => (),
+
+                    OptSpaceRes::Remove => {
+                        node.atomic.store(null_mut(), Relaxed);
+                        // This is safe because we have exclusive reference to
+                        // the map. Also, we remove the inner table from the
+                        // outer table so no one else will find it.
+                        unsafe {
+                            let nnptr = NonNull::new_unchecked(table_ptr);
+                            OwnedAlloc::from_raw(nnptr);
+                        }
+                        removed += 1;
+                    },
+
+                    OptSpaceRes::TableToBucket(bucket) => {
+                        unsafe {
+                            // This is safe because we have exclusive reference
+                            // to the map. Also, we remove the inner table from
+                            // the outer table so no one else will find it.
+                            let nnptr = NonNull::new_unchecked(table_ptr);
+                            OwnedAlloc::from_raw(nnptr);
+                        }
+                        node.atomic.store(bucket.as_ptr() as *mut _, Relaxed)
+                    },
+                }
+            }
+        }
+
+        match (last_bucket, self.nodes.len() - removed) {
+            (Some(nnptr), 1) => OptSpaceRes::TableToBucket(nnptr),
+
+            (_, 0) => OptSpaceRes::Remove,
 
             _ => OptSpaceRes::NoOpt,
         }
     }
 
+    // Just like `get_paused`, but with exclusive access to the tree, so no
+    // pause is needed. The closure must give the salted hash of the key with
+    // flood protection.
+    pub fn get_mut<O, Q, S>(
+        &mut self,
+        key: &Q,
+        hash: HashCode,
+        salted: S,
+    ) -> Option<&mut (K, V)>
+    where
+        O: BucketOrder<Q>,
+        Q: ?Sized,
+        K: Borrow<Q>,
+        S: FnOnce() -> Option<u64>,
+    {
+        self.bucket_mut(hash, salted)?.get_mut::<O, Q>(key)
+    }
+
+    // Just like `insert`, but with exclusive access to the tree. A new bucket
+    // is placed just like `rebuild` does, since nobody else can see the tree.
+    pub fn insert_mut<O>(
+        &mut self,
+        pair: (K, V),
+        hash: HashCode,
+        slabs: &mut Slabs<K, V>,
+    ) -> Option<(K, V)>
+    where
+        O: BucketOrder<K>,
+    {
+        let salt = slabs.salt.as_ref();
+        let salted = || salt.map(|salt| salt.hash_stored(&pair.0));
+        if let Some(bucket) = self.bucket_mut(hash, salted) {
+            // With flood protection, the salted hashes must be equal too. Safe
+            // because we have exclusive access to the tree.
+            let stored = unsafe { bucket.salted() };
+            if stored.is_none() || stored == salted() {
+                return bucket.insert_mut::<O>(pair);
+            }
+        }
+
+        let pair = OwnedAlloc::new(pair).into_raw();
+        // Safe because we have exclusive access to the tree and to the slabs,
+        // and we just checked there is no bucket with the same hashes.
+        unsafe {
+            let bucket = Bucket::try_alloc(hash, pair, false, slabs)
+                .unwrap_or_else(|err| err.handle());
+            slabs.release(pair);
+            self.place(bucket.as_ptr()).unwrap_or_else(|err| err.handle());
+        }
+        None
+    }
+
+    // Just like `remove`, but with exclusive access to the tree. A bucket
+    // left empty is detached and deallocated right away. The salted hash is
+    // given just like in `get_mut`.
+    pub fn remove_mut<O, Q, S>(
+        &mut self,
+        key: &Q,
+        hash: HashCode,
+        salted: S,
+    ) -> Option<(K, V)>
+    where
+        O: BucketOrder<Q>,
+        Q: ?Sized,
+        K: Borrow<Q>,
+        S: FnOnce() -> Option<u64>,
+    {
+        let node = self.leaf_mut(hash, salted)?;
+        let bucket = Self::as_bucket_mut(node.read_mut(), hash)?;
+
+        let removed = bucket.remove_mut::<O, Q>(key);
+        if bucket.is_empty_mut() {
+            let loaded = node.replace_mut(null_mut());
+            // Safe because we have exclusive access to the tree, and we just
+            // detached the bucket.
+            unsafe {
+                let bucket = NonNull::new_unchecked(bucket_ptr::<K, V>(loaded));
+                Bucket::retire(bucket);
+            }
+        }
+        removed
+    }
+
+    // Finds the bucket with the given hash, with exclusive access to the
+    // tree. With flood protection, its salted hash may still differ.
+    fn bucket_mut<S>(
+        &mut self,
+        hash: HashCode,
+        salted: S,
+    ) -> Option<&mut Bucket<K, V>>
+    where
+        S: FnOnce() -> Option<u64>,
+    {
+        Self::as_bucket_mut(self.leaf_mut(hash, salted)?.read_mut(), hash)
+    }
+
+    // Finds the node where the search for the given hashes stops, either
+    // vacant or holding a bucket, with exclusive access to the tree. No node
+    // can be frozen, since nothing freezes a node without restoring or
+    // detaching it before returning, except for an upgrade which failed to
+    // allocate, and the map finishes it before anything else. Fails if the
+    // search reaches the salted levels but the closure gives no salted hash.
+    fn leaf_mut<S>(
+        &mut self,
+        hash: HashCode,
+        salted: S,
+    ) -> Option<&mut AtomicPtr<()>>
+    where
+        S: FnOnce() -> Option<u64>,
+    {
+        let mut digits = Digits::new(hash, self.bits());
+        let mut salted = Some(salted);
+        let mut table: *mut Self = self;
+
+        // Safe because we have exclusive access to the tree, and we only store
+        // properly allocated tables.
+        unsafe {
+            loop {
+                let node = &mut (*table).nodes[digits.index()].atomic;
+                match as_table::<K, V>(node.read_mut()) {
+                    Some(ptr) => {
+                        table = ptr;
+                        if !digits.descend(|| salted.take()?()) {
+                            break None;
+                        }
+                    },
+                    None => break Some(node),
+                }
+            }
+        }
+    }
+
+    // Converts the given node to a bucket, if it holds a bucket with the
+    // given hash. The node must have been loaded with exclusive access to the
+    // tree.
+    fn as_bucket_mut<'map>(
+        loaded: *mut (),
+        hash: HashCode,
+    ) -> Option<&'map mut Bucket<K, V>> {
+        if is_vacant(loaded) {
+            return None;
+        }
+        // Safe because we only store properly allocated buckets with the lower
+        // bit cleared.
+        let bucket = unsafe { &mut *bucket_ptr::<K, V>(loaded) };
+        if bucket.hash() == hash {
+            Some(bucket)
+        } else {
+            None
+        }
+    }
+
     pub fn load_index(
         &self,
         index: usize,
@@ -483,6 +2302,175 @@ impl<K, V> Table<K, V> {
     ) -> Option<*mut ()> {
         self.nodes.get(index).map(|node| node.atomic.load(ordering))
     }
+
+    // Just like `load_index`, but reads the node plainly, with exclusive
+    // access to the table.
+    pub fn load_index_mut(&mut self, index: usize) -> Option<*mut ()> {
+        self.nodes.get_mut(index).map(|node| node.atomic.read_mut())
+    }
+}
+
+// Why an insertion gave its inserter back.
+pub enum InsertErr<I> {
+    // A frozen node was found, so the insertion must start again from the
+    // top, once the caller is done with whatever froze the node.
+    Frozen(I),
+    // Allocation failed, and nothing was inserted.
+    NoMemory(I, AllocFailed),
+}
+
+// The deepest level of a tree of tables of `1 << bits` nodes, the top table
+// being at depth `1`. A table at this depth consumes the last bits of the
+// hashes, so no sub-table is ever created below it: distinct hashes are told
+// apart before it, and equal hashes share a bucket.
+pub fn max_depth(bits: usize) -> usize {
+    HASH_BITS.div_ceil(bits)
+}
+
+// The deepest level of a tree of tables of `1 << bits` nodes with flood
+// protection. Below the levels of the plain hash, keys sharing it are told
+// apart by the bits of their salted hash.
+pub fn salted_max_depth(bits: usize) -> usize {
+    max_depth(bits) + SALTED_BITS.div_ceil(bits)
+}
+
+// The index of the node of the given bucket in a table `at` levels below the
+// top one. Past the deepest level of the plain hash, the index comes from the
+// salted hash, and an empty bucket, which has none, is put at index `0`.
+// Unsafe because the incinerator needs to be paused, or the tree accessed
+// exclusively.
+unsafe fn bucket_index<K, V>(
+    bucket: &Bucket<K, V>,
+    at: usize,
+    bits: usize,
+) -> usize {
+    let plain = max_depth(bits);
+    let shifted = if at < plain {
+        bucket.hash() >> (at * bits)
+    } else {
+        given_hash(bucket.salted().unwrap_or(0) >> ((at - plain) * bits))
+    };
+    shifted as usize & ((1 << bits) - 1)
+}
+
+// Picks the nodes of a searched key level by level, from the bits of its plain
+// hash, then, past the deepest level of the plain hash, from those of its
+// salted hash, only computed once a search gets that deep.
+struct Digits {
+    shifted: HashCode,
+    depth: usize,
+    bits: usize,
+}
+
+impl Digits {
+    fn new(hash: HashCode, bits: usize) -> Self {
+        Self { shifted: hash, depth: 1, bits }
+    }
+
+    // The index of the node in the table at the current depth.
+    fn index(&self) -> usize {
+        self.shifted as usize & ((1 << self.bits) - 1)
+    }
+
+    // Moves to the next level, calling the closure for the salted hash when
+    // entering the levels below the plain hash. Fails if it gives none.
+    fn descend<F>(&mut self, salted: F) -> bool
+    where
+        F: FnOnce() -> Option<u64>,
+    {
+        if self.depth == max_depth(self.bits) {
+            match salted() {
+                Some(salted) => self.shifted = given_hash(salted),
+                None => return false,
+            }
+        } else {
+            self.shifted >>= self.bits;
+        }
+        self.depth += 1;
+        true
+    }
+}
+
+// Tests if the given node is either null or frozen, copied or not.
+pub fn is_vacant(ptr: *mut ()) -> bool {
+    let ptr = ptr as usize & !COPIED;
+    ptr == 0 || ptr == FROZEN
+}
+
+// Tests if the given node is frozen, either as an empty node, as a bucket or
+// as a table.
+fn is_frozen(ptr: *mut ()) -> bool {
+    ptr as usize & !COPIED == FROZEN || ptr as usize & FROZEN_MARK != 0
+}
+
+// Tests if the table of the given node was copied.
+#[cfg(feature = "snapshot")]
+fn is_copied(ptr: *mut ()) -> bool {
+    ptr as usize & COPIED != 0
+}
+
+// Without snapshots, nothing is ever copied.
+#[cfg(not(feature = "snapshot"))]
+const fn is_copied(_ptr: *mut ()) -> bool {
+    false
+}
+
+// Converts the given node to a bucket pointer, given that it is a bucket,
+// frozen, copied or not.
+pub fn bucket_ptr<K, V>(ptr: *mut ()) -> *mut Bucket<K, V> {
+    (ptr as usize & !(FROZEN_MARK | COPIED)) as *mut Bucket<K, V>
+}
+
+// Converts the given node to a table pointer, given that it is a table, frozen,
+// copied or not.
+pub fn table_ptr<K, V>(ptr: *mut ()) -> *mut Table<K, V> {
+    (ptr as usize & !(1 | FROZEN_MARK | COPIED)) as *mut Table<K, V>
+}
+
+// Converts the given node to a table pointer, if it is a table.
+fn as_table<K, V>(ptr: *mut ()) -> Option<*mut Table<K, V>> {
+    let stripped = ptr as usize & !COPIED;
+    if stripped & 1 == 1 && stripped != FROZEN {
+        Some(table_ptr(ptr))
+    } else {
+        None
+    }
+}
+
+// What the given node holds, as a copy of it would: null if it is vacant, or
+// its table or bucket, unmarked but for the lower bit of a table.
+#[cfg(feature = "snapshot")]
+fn held(ptr: *mut ()) -> *mut () {
+    if is_vacant(ptr) {
+        null_mut()
+    } else {
+        (ptr as usize & !(FROZEN_MARK | COPIED)) as *mut ()
+    }
+}
+
+// Counts one more table holding what is given, as returned by `held`. Fails
+// if it is not held anymore, see `Table::share`. Unsafe because the
+// incinerator needs to be paused.
+#[cfg(feature = "snapshot")]
+unsafe fn share_node<K, V>(held: *mut ()) -> bool {
+    match as_table::<K, V>(held) {
+        Some(table) => (*table).share(),
+        None if held.is_null() => true,
+        None => (*bucket_ptr::<K, V>(held)).share(),
+    }
+}
+
+// Counts one table less holding what is given, as returned by `held`. Unsafe
+// for the same reasons as `Table::release`.
+#[cfg(feature = "snapshot")]
+unsafe fn release_node<K, V>(held: *mut (), pause: &Pause<Garbage<K, V>>) {
+    match as_table::<K, V>(held) {
+        Some(table) => Table::release(NonNull::new_unchecked(table), pause),
+        None if held.is_null() => (),
+        None => Bucket::release(NonNull::new_unchecked(bucket_ptr::<K, V>(
+            held,
+        ))),
+    }
 }
 
 impl<K, V> fmt::Debug for Table<K, V> {
@@ -490,7 +2478,7 @@ impl<K, V> fmt::Debug for Table<K, V> {
         write!(
             fmtr,
             "Table {} nodes: {:?} {}",
-            '{', &self.nodes as &[Node<K, V>], '}'
+            '{', self.nodes, '}'
         )
     }
 }
@@ -508,20 +2496,23 @@ impl<K, V> Node<K, V> {
         ptr: *mut (),
         tbl_stack: &mut Vec<OwnedAlloc<Table<K, V>>>,
     ) {
-        if ptr.is_null() {
+        // A compact tree is dropped frozen if its upgrade failed to
+        // allocate.
+        if is_vacant(ptr) {
             return;
         }
 
-        if ptr as usize & 1 == 0 {
-            OwnedAlloc::from_raw(NonNull::new_unchecked(
-                ptr as *mut Bucket<K, V>,
-            ));
-        } else {
-            let table_ptr = (ptr as usize & !1) as *mut Table<K, V>;
-
-            debug_assert!(!table_ptr.is_null());
-            tbl_stack
-                .push(OwnedAlloc::from_raw(NonNull::new_unchecked(table_ptr)));
+        match as_table::<K, V>(ptr) {
+            None => {
+                let bucket = bucket_ptr::<K, V>(ptr);
+                Bucket::retire(NonNull::new_unchecked(bucket));
+            },
+            Some(table_ptr) => {
+                debug_assert!(!table_ptr.is_null());
+                tbl_stack.push(OwnedAlloc::from_raw(NonNull::new_unchecked(
+                    table_ptr,
+                )));
+            },
         }
     }
 }
@@ -530,8 +2521,193 @@ impl<K, V> Node<K, V> {
     fn new() -> Self {
         Self { atomic: AtomicPtr::new(null_mut()), _marker: PhantomData }
     }
+
+    // Makes what was loaded from this node belong to the map alone, copying
+    // it if it is shared with snapshots, see `Snapshots`. Returns what the
+    // node holds then, which is what was loaded if it already belonged to the
+    // map. Unsafe because the incinerator needs to be paused.
+    #[cfg(feature = "snapshot")]
+    unsafe fn own(
+        &self,
+        loaded: *mut (),
+        _writing: &Writing,
+        pause: &Pause<Garbage<K, V>>,
+    ) -> Result<*mut (), OwnErr> {
+        if is_copied(loaded) {
+            return Err(OwnErr::Copied);
+        }
+        if is_vacant(loaded) {
+            return Ok(loaded);
+        }
+
+        let copied = match as_table::<K, V>(loaded) {
+            Some(ptr) => {
+                let table = &*ptr;
+                if table.is_owned() {
+                    return Ok(loaded);
+                }
+                if is_frozen(loaded) {
+                    return Err(OwnErr::Frozen);
+                }
+                match table.try_copy(true, pause) {
+                    Ok(copy) => {
+                        (copy.into_raw().as_ptr() as usize | 1) as *mut ()
+                    },
+                    // The table was released, so this node changed.
+                    Err(OwnErr::Changed(_)) => {
+                        return Err(OwnErr::Changed(self.atomic.load(Acquire)));
+                    },
+                    Err(err) => return Err(err),
+                }
+            },
+
+            None => {
+                let bucket = &*bucket_ptr::<K, V>(loaded);
+                if bucket.is_owned() {
+                    return Ok(loaded);
+                }
+                if is_frozen(loaded) {
+                    return Err(OwnErr::Frozen);
+                }
+                match bucket.try_copy() {
+                    Ok(Some(copy)) => copy.as_ptr() as *mut (),
+                    // An empty bucket is just removed.
+                    Ok(None) => null_mut(),
+                    Err(err) => return Err(OwnErr::NoMemory(err)),
+                }
+            },
+        };
+
+        // `Release` publishes the copy, and `Acquire` on failure lets the
+        // caller enter whatever beat us.
+        let res =
+            self.atomic.compare_exchange(loaded, copied, AcqRel, Acquire);
+
+        match (res, as_table::<K, V>(loaded)) {
+            (Ok(_), Some(ptr)) => {
+                Table::release(NonNull::new_unchecked(ptr), pause);
+                Ok(copied)
+            },
+
+            (Ok(_), None) => {
+                Bucket::release(NonNull::new_unchecked(bucket_ptr::<K, V>(
+                    loaded,
+                )));
+                Ok(copied)
+            },
+
+            (Err(new), Some(_)) => {
+                // The copy was never shared, but its nodes hold what the
+                // table does.
+                let copy = NonNull::new_unchecked(table_ptr::<K, V>(copied));
+                Table::release(copy, pause);
+                Err(OwnErr::Changed(new))
+            },
+
+            (Err(new), None) => {
+                if let Some(copy) = NonNull::new(copied as *mut Bucket<K, V>) {
+                    // The pairs still belong to the bucket we copied.
+                    Bucket::retire_shell(copy);
+                }
+                Err(OwnErr::Changed(new))
+            },
+        }
+    }
+
+    // Without snapshots, everything belongs to the map.
+    #[cfg(not(feature = "snapshot"))]
+    #[inline(always)]
+    unsafe fn own(
+        &self,
+        loaded: *mut (),
+        _writing: &Writing,
+        _pause: &Pause<Garbage<K, V>>,
+    ) -> Result<*mut (), OwnErr> {
+        Ok(loaded)
+    }
+
+    // Marks this node with `COPIED`, and returns what it holds, marked.
+    #[cfg(feature = "snapshot")]
+    fn mark_copied(&self) -> *mut () {
+        let mut loaded = self.atomic.load(Acquire);
+        loop {
+            if is_copied(loaded) {
+                break loaded;
+            }
+            let marked = (loaded as usize | COPIED) as *mut ();
+            // `Acquire` since the copy holds what we find.
+            match self.atomic.compare_exchange_weak(
+                loaded,
+                marked,
+                AcqRel,
+                Acquire,
+            ) {
+                Ok(_) => break marked,
+                Err(new) => loaded = new,
+            }
+        }
+    }
+
+    // Loads this node and makes what it holds belong to the map, just like
+    // `own`, waiting while it is frozen. If the table of this node was
+    // copied, what the node holds is returned marked with `COPIED`, and the
+    // copy must be found again from the top. Unsafe for the same reasons as
+    // `own`.
+    unsafe fn load_owned(
+        &self,
+        writing: &Writing,
+        pause: &Pause<Garbage<K, V>>,
+    ) -> *mut () {
+        let mut backoff = Backoff::new();
+        let mut loaded = self.atomic.load(Acquire);
+        loop {
+            match self.own(loaded, writing, pause) {
+                Ok(owned) => break owned,
+                Err(OwnErr::Changed(new)) => loaded = new,
+                Err(OwnErr::Frozen) => {
+                    // Only `shrink` freezes nodes once snapshots are taken,
+                    // and it soon either collapses or restores them.
+                    backoff.snooze();
+                    loaded = self.atomic.load(Acquire);
+                },
+                Err(OwnErr::Copied) => break loaded,
+                Err(OwnErr::NoMemory(err)) => err.handle(),
+            }
+        }
+    }
+
+    // Loads this node, making what it holds belong to the map if an
+    // operation changing the map is given. Unsafe for the same reasons as
+    // `own`.
+    unsafe fn load_for(
+        &self,
+        writing: Option<&Writing>,
+        pause: &Pause<Garbage<K, V>>,
+    ) -> *mut () {
+        match writing {
+            Some(writing) => self.load_owned(writing, pause),
+            None => self.atomic.load(Acquire),
+        }
+    }
 }
 
+// Why `Node::own` failed.
+#[cfg_attr(not(feature = "snapshot"), allow(dead_code))]
+enum OwnErr {
+    // The node changed, and now holds the given pointer.
+    Changed(*mut ()),
+    // The node is frozen, so it cannot be changed to hold a copy.
+    Frozen,
+    // The table of the node was copied, so it cannot be changed anymore.
+    Copied,
+    NoMemory(AllocFailed),
+}
+
+// A bucket was copied while it was changed, so its copy must be found again
+// from the top.
+#[derive(Debug)]
+pub struct Restart;
+
 impl<K, V> fmt::Debug for Node<K, V> {
     fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
         write!(fmtr, "Node {} pointer: {:?} {}", '{', self.atomic, '}')
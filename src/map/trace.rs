@@ -0,0 +1,334 @@
+// Instrumentation of the contention in a map, and of guards stalling its
+// memory reclamation. With the `tracing` feature, the events below are emitted
+// under the `lockfree::map` target. They carry the depth in the tree and the
+// retry count of the operation, or the kind of guard, but never the keys.
+// Without the feature, `Probe` and `Hold` are zero-sized types whose methods
+// do nothing, so the instrumentation is compiled out.
+
+#[cfg(all(feature = "tracing", feature = "std"))]
+use std::time::{Duration, Instant};
+
+// How many retries a single operation makes before a warning is emitted.
+#[cfg(feature = "tracing")]
+pub const RETRY_WARN: usize = 32;
+
+// How long a guard may keep the incinerator paused before a warning is
+// emitted.
+#[cfg(all(feature = "tracing", feature = "std"))]
+pub const LONG_HOLD: Duration = Duration::from_secs(1);
+
+// Follows a single insertion or removal down the tree, counting how many times
+// it retries because a compare-and-swap failed.
+#[derive(Debug, Clone, Copy)]
+pub struct Probe {
+    #[cfg(feature = "tracing")]
+    depth: usize,
+    #[cfg(feature = "tracing")]
+    retries: usize,
+}
+
+#[cfg(feature = "tracing")]
+impl Probe {
+    pub fn new() -> Self {
+        Self { depth: 1, retries: 0 }
+    }
+
+    // The operation restarts from the top table.
+    pub fn restart(&mut self) {
+        self.depth = 1;
+    }
+
+    // The operation enters a sub-table.
+    pub fn descend(&mut self) {
+        self.depth += 1;
+    }
+
+    // A compare-and-swap on the given kind of location failed, so the
+    // operation tries again.
+    pub fn retry(&mut self, site: &'static str) {
+        self.retries += 1;
+        tracing::trace!(
+            target: "lockfree::map",
+            site,
+            depth = self.depth,
+            retries = self.retries,
+            "retry"
+        );
+        if self.retries == RETRY_WARN {
+            tracing::warn!(
+                target: "lockfree::map",
+                depth = self.depth,
+                retries = self.retries,
+                "operation retrying too many times"
+            );
+        }
+    }
+
+    // A sub-table was created below the current depth, since the hash of a
+    // bucket and the hash of the operation were told apart.
+    pub fn branch(&self) {
+        tracing::debug!(
+            target: "lockfree::map",
+            depth = self.depth + 1,
+            retries = self.retries,
+            "table created"
+        );
+    }
+
+    // An empty bucket was removed from its table.
+    pub fn collapse(&self) {
+        tracing::debug!(
+            target: "lockfree::map",
+            depth = self.depth,
+            retries = self.retries,
+            "bucket collapsed"
+        );
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl Probe {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    #[inline(always)]
+    pub fn restart(&mut self) {}
+
+    #[inline(always)]
+    pub fn descend(&mut self) {}
+
+    #[inline(always)]
+    pub fn retry(&mut self, _site: &'static str) {}
+
+    #[inline(always)]
+    pub fn branch(&self) {}
+
+    #[inline(always)]
+    pub fn collapse(&self) {}
+}
+
+// Times a guard keeping the incinerator paused, such as `ValueGuard` and
+// `Pinned`, which stalls memory reclamation for every thread while alive. It
+// also needs `std`, to read the clock, which is only read if warnings are
+// enabled when the guard is created.
+#[derive(Debug)]
+pub struct Hold {
+    #[cfg(all(feature = "tracing", feature = "std"))]
+    since: Option<Instant>,
+}
+
+#[cfg(all(feature = "tracing", feature = "std"))]
+impl Hold {
+    pub fn new() -> Self {
+        let enabled =
+            tracing::enabled!(target: "lockfree::map", tracing::Level::WARN);
+        Self { since: if enabled { Some(Instant::now()) } else { None } }
+    }
+
+    // The given kind of guard is dropped.
+    pub fn release(&self, guard: &'static str) {
+        let elapsed = match self.since {
+            Some(since) => since.elapsed(),
+            None => return,
+        };
+        if elapsed > LONG_HOLD {
+            tracing::warn!(
+                target: "lockfree::map",
+                guard,
+                millis = elapsed.as_millis() as u64,
+                "guard stalled memory reclamation"
+            );
+        }
+    }
+}
+
+#[cfg(not(all(feature = "tracing", feature = "std")))]
+impl Hold {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    #[inline(always)]
+    pub fn release(&self, _guard: &'static str) {}
+}
+
+#[cfg(all(test, feature = "tracing", feature = "std"))]
+mod test {
+    use super::LONG_HOLD;
+    use map::Map;
+    use std::{
+        fmt,
+        sync::{Arc, Mutex},
+        thread,
+    };
+    use std::prelude::v1::*;
+    use tracing::{
+        dispatcher::{self, Dispatch},
+        field::{Field, Visit},
+        span,
+        Event,
+        Metadata,
+        Subscriber,
+    };
+
+    // The message and the fields of an event.
+    #[derive(Debug, Default)]
+    struct Record {
+        message: String,
+        fields: Vec<(&'static str, String)>,
+    }
+
+    impl Record {
+        fn field(&self, name: &str) -> Option<&str> {
+            self.fields
+                .iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, val)| &**val)
+        }
+    }
+
+    impl Visit for Record {
+        fn record_debug(&mut self, field: &Field, val: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                self.message = format!("{:?}", val);
+            } else {
+                self.fields.push((field.name(), format!("{:?}", val)));
+            }
+        }
+    }
+
+    // Collects every event of the map.
+    #[derive(Clone, Default)]
+    struct Collector {
+        records: Arc<Mutex<Vec<Record>>>,
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.target() == "lockfree::map"
+        }
+
+        fn new_span(&self, _: &span::Attributes) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event) {
+            let mut record = Record::default();
+            event.record(&mut record);
+            self.records.lock().unwrap().push(record);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn retries_under_contention() {
+        const THREADS: usize = 8;
+        const UPDATES: usize = 200;
+
+        let collector = Collector::default();
+        let dispatch = Dispatch::new(collector.clone());
+        let map = Arc::new(Map::new());
+        map.insert(0usize, 0usize);
+
+        let threads = (0 .. THREADS)
+            .map(|_| {
+                let map = map.clone();
+                let dispatch = dispatch.clone();
+                thread::spawn(move || {
+                    dispatcher::with_default(&dispatch, || {
+                        for _ in 0 .. UPDATES {
+                            // Yielding between reading the entry and
+                            // replacing it lets the others replace it first.
+                            map.update(&0, |val| {
+                                thread::yield_now();
+                                val + 1
+                            });
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        assert_eq!(*map.get(&0).unwrap().val(), THREADS * UPDATES);
+
+        let records = collector.records.lock().unwrap();
+        let retries = records
+            .iter()
+            .filter(|record| record.message == "retry")
+            .collect::<Vec<_>>();
+        assert!(!retries.is_empty());
+        for record in retries {
+            assert_eq!(record.field("site"), Some("\"bucket\""));
+            assert_eq!(record.field("depth"), Some("1"));
+            let count = record.field("retries").unwrap();
+            assert!(count.parse::<usize>().unwrap() > 0);
+        }
+        assert!(records.iter().all(|record| record.field("key").is_none()));
+    }
+
+    #[test]
+    fn tables_created_and_buckets_collapsed() {
+        let collector = Collector::default();
+        let dispatch = Dispatch::new(collector.clone());
+
+        dispatcher::with_default(&dispatch, || {
+            let map = Map::new();
+            for i in 0 .. 1000u32 {
+                map.insert(i, i);
+            }
+            for i in 0 .. 1000u32 {
+                map.remove(&i);
+            }
+        });
+
+        let records = collector.records.lock().unwrap();
+        let count = |message: &str| {
+            records.iter().filter(|record| record.message == message).count()
+        };
+        assert!(count("table created") > 0);
+        assert_eq!(count("bucket collapsed"), 1000);
+        assert!(records.iter().all(|record| {
+            record.field("depth").unwrap().parse::<usize>().unwrap() > 0
+        }));
+    }
+
+    #[test]
+    fn long_held_guards() {
+        let collector = Collector::default();
+        let dispatch = Dispatch::new(collector.clone());
+        let map = Map::new();
+        map.insert(1u32, 2u32);
+
+        dispatcher::with_default(&dispatch, || {
+            drop(map.get_guarded(&1).unwrap());
+            drop(map.pin());
+
+            let guard = map.get_guarded(&1).unwrap();
+            let pinned = map.pin();
+            thread::sleep(LONG_HOLD + LONG_HOLD / 10);
+            drop(guard);
+            drop(pinned);
+        });
+
+        let message = "guard stalled memory reclamation";
+        let records = collector.records.lock().unwrap();
+        let guards = records
+            .iter()
+            .filter(|record| record.message == message)
+            .map(|record| record.field("guard").unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(guards, ["\"ValueGuard\"", "\"Pinned\""]);
+    }
+}
@@ -0,0 +1,465 @@
+use super::{DefaultHashBuilder, Map, ReadGuard, Removed};
+use core::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash},
+    ptr,
+    sync::atomic::{AtomicU64, Ordering::*},
+};
+#[cfg(feature = "std")]
+use std::{sync::OnceLock, time::Instant};
+
+// The deadline of an entry claimed by a removal because it expired. Since no
+// tick is smaller, such an entry stays expired, and cannot be refreshed.
+const EXPIRED: u64 = 0;
+
+/// A [`Map`] whose entries expire, e.g. a cache of sessions. Each entry gets a
+/// deadline when inserted: the current tick of the clock plus a time to live.
+/// Once the clock reaches the deadline, the entry is treated as absent, and
+/// it is removed either lazily, by the operations which find it, or by a
+/// sweep with [`prune_expired`](TtlMap::prune_expired).
+///
+/// The clock is any `Fn() -> u64` returning monotonic ticks, so tests can
+/// drive it by hand. With the `std` feature, [`TtlMap::new`] counts
+/// milliseconds.
+///
+/// The deadline of an entry can be pushed with
+/// [`refresh`](TtlMap::refresh), which never revives an expired entry: a
+/// removal of an expired entry first claims it, changing its deadline for
+/// good, so a refresh racing with the removal either happens before the
+/// claim, keeping the entry, or fails.
+pub struct TtlMap<K, V, H = DefaultHashBuilder, C = fn() -> u64> {
+    inner: Map<K, Expiring<V>, H>,
+    clock: C,
+}
+
+#[cfg(feature = "std")]
+impl<K, V> TtlMap<K, V> {
+    /// Creates a [`TtlMap`] with the default hasher builder, whose ticks are
+    /// milliseconds of a monotonic clock. Only available with the `std`
+    /// feature.
+    pub fn new() -> Self {
+        Self::with_clock(monotonic_millis)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> Default for TtlMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, C> TtlMap<K, V, DefaultHashBuilder, C>
+where
+    C: Fn() -> u64,
+{
+    /// Creates a [`TtlMap`] with the default hasher builder, reading ticks
+    /// from the given clock.
+    pub fn with_clock(clock: C) -> Self {
+        Self::with_clock_and_hasher(clock, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, H, C> TtlMap<K, V, H, C>
+where
+    C: Fn() -> u64,
+{
+    /// The current tick of the clock.
+    pub fn now(&self) -> u64 {
+        (self.clock)()
+    }
+
+    /// The number of entries, counting expired entries which were not removed
+    /// yet. The same considerations of [`Map::len`] apply.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns whether there are no entries, expired or not. The same
+    /// considerations of [`Map::len`] apply.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Consumes this [`TtlMap`] into the [`Map`] holding its entries, expired
+    /// or not.
+    pub fn into_inner(self) -> Map<K, Expiring<V>, H> {
+        self.inner
+    }
+}
+
+impl<K, V, H, C> TtlMap<K, V, H, C>
+where
+    H: BuildHasher,
+    C: Fn() -> u64,
+{
+    /// Creates a [`TtlMap`] using the given hasher builder, reading ticks from
+    /// the given clock.
+    pub fn with_clock_and_hasher(clock: C, builder: H) -> Self {
+        Self { inner: Map::with_hasher(builder), clock }
+    }
+
+    /// Inserts unconditionally the given key and value, expiring after the
+    /// given number of ticks. If there was a previously stored entry which
+    /// had not expired, it is returned.
+    pub fn insert(
+        &self,
+        key: K,
+        val: V,
+        ttl: u64,
+    ) -> Option<Removed<K, Expiring<V>>>
+    where
+        K: Hash + Ord,
+    {
+        let now = self.now();
+        let deadline = now.saturating_add(ttl);
+        let expiring = Expiring { val, deadline: AtomicU64::new(deadline) };
+        let removed = self.inner.insert(key, expiring)?;
+        if removed.val().is_expired(now) {
+            None
+        } else {
+            Some(removed)
+        }
+    }
+
+    /// Searches for the entry identified by the given key, if it has not
+    /// expired. An expired entry found is removed. See [`Map::get`].
+    pub fn get<'map, Q>(
+        &'map self,
+        key: &Q,
+    ) -> Option<ReadGuard<'map, K, Expiring<V>>>
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        let now = self.now();
+        let guard = self.inner.get(key)?;
+        if !guard.val().is_expired(now) {
+            return Some(guard);
+        }
+        self.remove_expired(key, &guard, now);
+        None
+    }
+
+    /// Tests if an entry identified by the given key is present and has not
+    /// expired. An expired entry found is removed.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Pushes the deadline of the entry identified by the given key, so it
+    /// expires after the given number of ticks from now. Returns whether the
+    /// entry was found and had not expired; an expired entry is never
+    /// revived.
+    pub fn refresh<Q>(&self, key: &Q, ttl: u64) -> bool
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        let now = self.now();
+        let deadline = now.saturating_add(ttl);
+        match self.inner.get(key) {
+            Some(guard) => guard
+                .val()
+                .deadline
+                .fetch_update(AcqRel, Acquire, |stored| {
+                    if stored > now {
+                        Some(deadline)
+                    } else {
+                        None
+                    }
+                })
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    /// Removes unconditionally the entry identified by the given key. It is
+    /// only returned if it had not expired.
+    pub fn remove<Q>(&self, key: &Q) -> Option<Removed<K, Expiring<V>>>
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        let now = self.now();
+        let removed = self.inner.remove(key)?;
+        if removed.val().is_expired(now) {
+            None
+        } else {
+            Some(removed)
+        }
+    }
+
+    /// Removes every entry which expired by the given tick, through the same
+    /// path as [`Map::remove`], returning how many were removed. Just like
+    /// [`Map::retain`], the incinerator is paused only while small chunks of
+    /// the [`Map`] are read, and entries inserted or refreshed concurrently
+    /// may be skipped.
+    pub fn prune_expired(&self, now: u64) -> usize
+    where
+        K: Hash + Ord,
+    {
+        let mut pruned = 0;
+        self.inner.walk_top(|top| {
+            top.visit(&self.inner.incin.inner, |pair| {
+                if pair.1.is_expired(now)
+                    && self.remove_expired(&pair.0, pair, now)
+                {
+                    pruned += 1;
+                }
+            })
+        });
+        pruned
+    }
+
+    // Removes the given entry, found through the given key, if it is still
+    // stored and it expired by the given tick. Returns whether it was removed.
+    fn remove_expired<Q>(
+        &self,
+        key: &Q,
+        pair: &(K, Expiring<V>),
+        now: u64,
+    ) -> bool
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        let removed = self.inner.remove_with(key, |stored| {
+            ptr::eq(stored, pair) && stored.1.claim(now)
+        });
+        removed.is_some()
+    }
+}
+
+impl<K, V, H, C> fmt::Debug for TtlMap<K, V, H, C>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("TtlMap").field("inner", &self.inner).finish()
+    }
+}
+
+/// A value of a [`TtlMap`], along with the tick at which it expires.
+pub struct Expiring<V> {
+    val: V,
+    deadline: AtomicU64,
+}
+
+impl<V> Expiring<V> {
+    /// The value.
+    pub fn val(&self) -> &V {
+        &self.val
+    }
+
+    /// The tick at which this value expires.
+    pub fn deadline(&self) -> u64 {
+        self.deadline.load(Acquire)
+    }
+
+    /// Tests whether this value expired by the given tick.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.deadline() <= now
+    }
+
+    /// Consumes this value, discarding the deadline.
+    pub fn into_val(self) -> V {
+        self.val
+    }
+
+    // Claims this value for a removal if it expired by the given tick, so
+    // that it can never be refreshed.
+    fn claim(&self, now: u64) -> bool {
+        self.deadline
+            .fetch_update(AcqRel, Acquire, |stored| {
+                if stored <= now {
+                    Some(EXPIRED)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+}
+
+impl<V> fmt::Debug for Expiring<V>
+where
+    V: fmt::Debug,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("Expiring")
+            .field("val", &self.val)
+            .field("deadline", &self.deadline())
+            .finish()
+    }
+}
+
+// Milliseconds since the first call.
+#[cfg(feature = "std")]
+fn monotonic_millis() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::{atomic::AtomicBool, Arc},
+        thread,
+    };
+    use std::prelude::v1::*;
+
+    fn manual_clock() -> (Arc<AtomicU64>, impl Fn() -> u64) {
+        let tick = Arc::new(AtomicU64::new(1));
+        let clock = {
+            let tick = tick.clone();
+            move || tick.load(Acquire)
+        };
+        (tick, clock)
+    }
+
+    #[test]
+    fn lazy_expiry_on_get() {
+        let (tick, clock) = manual_clock();
+        let map = TtlMap::with_clock(clock);
+        map.insert("short", 1, 10);
+        map.insert("long", 2, 100);
+        assert_eq!(*map.get("short").unwrap().val().val(), 1);
+        assert_eq!(map.get("short").unwrap().val().deadline(), 11);
+
+        tick.store(11, Release);
+        assert!(map.get("short").is_none());
+        assert_eq!(map.len(), 1);
+        assert!(map.contains_key("long"));
+        assert!(!map.refresh("short", 10));
+
+        assert!(map.refresh("long", 100));
+        tick.store(105, Release);
+        assert!(map.contains_key("long"));
+        assert!(map.insert("long", 3, 0).is_some());
+        assert!(map.get("long").is_none());
+        assert!(map.remove("long").is_none());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn prune_sweep() {
+        let (tick, clock) = manual_clock();
+        let map = TtlMap::with_clock(clock);
+        for i in 0 .. 1000u64 {
+            map.insert(i, i, i % 10 + 1);
+        }
+        assert_eq!(map.prune_expired(map.now()), 0);
+
+        tick.store(6, Release);
+        // Deadlines up to `6` are reached, i.e. TTLs up to `5`.
+        assert_eq!(map.prune_expired(map.now()), 500);
+        assert_eq!(map.len(), 500);
+        for i in 0 .. 1000u64 {
+            assert_eq!(map.get(&i).is_some(), i % 10 >= 5);
+        }
+        assert_eq!(map.prune_expired(100), 500);
+        assert!(map.is_empty());
+        map.into_inner().validate();
+    }
+
+    #[test]
+    fn background_sweep() {
+        const THREADS: u64 = 4;
+        const KEYS: u64 = 500;
+
+        let (tick, clock) = manual_clock();
+        let map = Arc::new(TtlMap::with_clock(clock));
+        let done = Arc::new(AtomicBool::new(false));
+        let sweeper = {
+            let map = map.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut pruned = 0;
+                while !done.load(Acquire) {
+                    pruned += map.prune_expired(map.now());
+                }
+                pruned + map.prune_expired(map.now())
+            })
+        };
+
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let tick = tick.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0 .. KEYS {
+                    let key = t * KEYS + i;
+                    map.insert(key, key, 5);
+                    if let Some(guard) = map.get(&key) {
+                        assert_eq!(*guard.val().val(), key);
+                    }
+                    tick.fetch_add(1, AcqRel);
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        tick.fetch_add(5, AcqRel);
+        done.store(true, Release);
+        let pruned = sweeper.join().expect("thread failed");
+
+        assert!(pruned <= (THREADS * KEYS) as usize);
+        assert!(map.is_empty());
+        assert_eq!(map.prune_expired(map.now()), 0);
+    }
+
+    #[test]
+    fn refresh_races_prune() {
+        const THREADS: u64 = 4;
+        const ROUNDS: u64 = 2000;
+
+        // Every refreshed entry must stay until its new deadline, even though
+        // its old deadline is reached meanwhile.
+        let (tick, clock) = manual_clock();
+        let map = Arc::new(TtlMap::with_clock(clock));
+        let done = Arc::new(AtomicBool::new(false));
+        let mut pruners = Vec::new();
+        for _ in 0 .. 2 {
+            let map = map.clone();
+            let done = done.clone();
+            pruners.push(thread::spawn(move || {
+                while !done.load(Acquire) {
+                    map.prune_expired(map.now());
+                }
+            }));
+        }
+
+        let mut threads = Vec::new();
+        for t in 0 .. THREADS {
+            let map = map.clone();
+            let tick = tick.clone();
+            threads.push(thread::spawn(move || {
+                let key = t;
+                for i in 0 .. ROUNDS {
+                    if map.refresh(&key, 1_000_000) {
+                        assert!(map.get(&key).is_some());
+                    }
+                    // Let it expire again soon.
+                    map.insert(key, i, 1);
+                    tick.fetch_add(1, AcqRel);
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        done.store(true, Release);
+        for pruner in pruners {
+            pruner.join().expect("thread failed");
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use super::{
+    insertion::{InsertVersioned, Insertion},
+    BucketOrder,
+    Map,
+    Removed,
+};
+use core::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+/// A token identifying which pair an entry of a [`Map`] held when it was read
+/// by [`get_versioned`](Map::get_versioned), to be given back to
+/// [`update_if_version`](Map::update_if_version).
+///
+/// Every pair published in a [`Map`], by insertion, update or reinsertion,
+/// gets a new version, even if its allocation reuses the address of a dropped
+/// pair or if it is the same removed pair published again. Changes made in
+/// place, e.g. through [`get_mut`](Map::get_mut) or
+/// [`update_in_place`](Map::update_in_place), keep the version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Version {
+    // The tag of the pair, i.e. its address unless it is kept inline, along
+    // with the version of the entry, so that even a wrapped counter needs the
+    // tag to be reused to match.
+    tag: usize,
+    seq: usize,
+}
+
+impl Version {
+    pub(super) fn new(tag: usize, seq: usize) -> Self {
+        Self { tag, seq }
+    }
+}
+
+/// The error of an [`update_if_version`](Map::update_if_version) operation:
+/// the entry was changed or removed since its version was read, so nothing
+/// was updated. The key and value are given back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionMismatch<K, V> {
+    /// The key which was not updated.
+    pub key: K,
+    /// The value which was not stored.
+    pub val: V,
+}
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O>
+where
+    H: BuildHasher,
+{
+    /// Searches for the entry identified by the given key, calling the given
+    /// closure on it and returning what the closure returns along with the
+    /// [`Version`] of the entry, for an edit-if-unchanged workflow: a new
+    /// value can be computed from the read one with the incinerator no longer
+    /// paused, and written back through
+    /// [`update_if_version`](Map::update_if_version) only if nobody changed
+    /// the entry meanwhile. The closure is called while the incinerator is
+    /// paused. See [`Map::get`] for the requirements on the borrowed key
+    /// type.
+    pub fn get_versioned<Q, F, T>(
+        &self,
+        key: &Q,
+        reader: F,
+    ) -> Option<(Version, T)>
+    where
+        Q: ?Sized + Hash + Eq,
+        O: BucketOrder<Q>,
+        K: Borrow<Q>,
+        F: FnOnce(&K, &V) -> T,
+    {
+        let hash = self.hash_of(key);
+        let salted = || self.salted_of(key);
+        let pause = self.pause();
+        let top = self.top(&pause);
+        // Safe because we paused properly and the pair is only read while
+        // paused.
+        let (pair, seq) = unsafe {
+            top.get_versioned::<O, Q, _>(key, hash, salted)?
+        };
+        let version = Version::new(self.slabs.pair_tag(pair), seq);
+        Some((version, reader(&pair.0, &pair.1)))
+    }
+
+    /// Replaces the value of the entry identified by the given key, only if
+    /// the entry still holds the pair of the given [`Version`], as read by
+    /// [`get_versioned`](Map::get_versioned). The check and the replacement
+    /// are a single atomic step, so, among writers racing with the same
+    /// version, exactly one succeeds. The replaced pair is returned;
+    /// otherwise, the key and value are given back in a [`VersionMismatch`],
+    /// including when the entry was removed.
+    ///
+    /// A [`Version`] read from another [`Map`] is only unlikely to match, not
+    /// guaranteed not to.
+    pub fn update_if_version(
+        &self,
+        key: K,
+        version: Version,
+        val: V,
+    ) -> Result<Removed<K, V>, VersionMismatch<K, V>>
+    where
+        K: Hash + Eq,
+        O: BucketOrder<K>,
+    {
+        let hash = self.hash_of(&key);
+        let inserter = InsertVersioned::new(version, (key, val), &self.slabs);
+        let events = self.events();
+        let pause = self.pause();
+        // Safe because we paused properly.
+        let insertion =
+            unsafe { self.insert_top(inserter, hash, &pause, &events) };
+
+        match insertion {
+            Insertion::Updated(old) => Ok(old),
+            Insertion::Failed(inserter) => {
+                let (key, val) = inserter.into_pair();
+                Err(VersionMismatch { key, val })
+            },
+            // The inserter never accepts a missing entry.
+            Insertion::Created => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::sync::Arc;
+    use std::{sync::Barrier, thread};
+    use std::prelude::v1::*;
+
+    #[test]
+    fn update_if_unchanged() {
+        let map = Map::new();
+        map.insert("a", 1);
+        let (version, val) = map.get_versioned("a", |_, val| *val).unwrap();
+        assert_eq!(val, 1);
+        assert!(map.get_versioned("b", |_, val| *val).is_none());
+
+        let old = map.update_if_version("a", version, 2).unwrap();
+        assert_eq!(*old.val(), 1);
+        // The version is stale now.
+        let err = map.update_if_version("a", version, 3).unwrap_err();
+        assert_eq!(err, VersionMismatch { key: "a", val: 3 });
+        assert_eq!(*map.get("a").unwrap().val(), 2);
+
+        // A version is not shared by keys.
+        map.insert("b", 20);
+        assert!(map.update_if_version("b", version, 21).is_err());
+    }
+
+    #[test]
+    fn republished_pair_changes_version() {
+        let map = Map::new();
+        map.insert(1, 10);
+        let (version, _) = map.get_versioned(&1, |_, _| ()).unwrap();
+
+        // The very same allocation is published again.
+        let removed = map.remove(&1).unwrap();
+        assert!(map.update_if_version(1, version, 11).is_err());
+        let pair = &*removed as *const (i32, i32);
+        map.reinsert(removed);
+        let (current, addr) = map
+            .get_versioned(&1, |key, _| key as *const i32 as usize)
+            .unwrap();
+        assert_eq!(addr, pair as usize);
+        assert_ne!(current, version);
+        assert!(map.update_if_version(1, version, 11).is_err());
+        assert!(map.update_if_version(1, current, 12).is_ok());
+
+        // So is a pair replaced with exclusive access.
+        let mut map = map;
+        let (version, _) = map.get_versioned(&1, |_, _| ()).unwrap();
+        map.insert_mut(1, 13);
+        assert!(map.update_if_version(1, version, 14).is_err());
+        // Unlike a value changed in place.
+        let (version, _) = map.get_versioned(&1, |_, _| ()).unwrap();
+        *map.get_mut(&1).unwrap() += 1;
+        assert_eq!(*map.update_if_version(1, version, 15).unwrap().val(), 14);
+    }
+
+    #[test]
+    fn stale_writers_race() {
+        const WRITERS: usize = 4;
+        const ROUNDS: usize = 200;
+
+        let map = Arc::new(Map::new());
+        map.insert(0u64, 0usize);
+        let barrier = Arc::new(Barrier::new(WRITERS));
+
+        let writers = (0 .. WRITERS)
+            .map(|id| {
+                let map = map.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    let mut won = 0;
+                    for _ in 0 .. ROUNDS {
+                        let (version, round) =
+                            map.get_versioned(&0, |_, val| *val / 10).unwrap();
+                        // Every writer read the same version.
+                        barrier.wait();
+                        let val = (round + 1) * 10 + id;
+                        if map.update_if_version(0, version, val).is_ok() {
+                            won += 1;
+                        }
+                        barrier.wait();
+                    }
+                    won
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let won = writers
+            .into_iter()
+            .map(|writer| writer.join().expect("writer failed"))
+            .sum::<usize>();
+        // Exactly one writer won each round.
+        assert_eq!(won, ROUNDS);
+        assert_eq!(*map.get(&0).unwrap().val() / 10, ROUNDS);
+    }
+
+    #[test]
+    fn optimistic_increments() {
+        const THREADS: usize = 4;
+        const INCREMENTS: usize = 1000;
+
+        let map = Arc::new(Map::new());
+        map.insert("counter", 0usize);
+        let threads = (0 .. THREADS)
+            .map(|_| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for _ in 0 .. INCREMENTS {
+                        loop {
+                            let (version, val) = map
+                                .get_versioned("counter", |_, val| *val)
+                                .unwrap();
+                            let res = map.update_if_version(
+                                "counter",
+                                version,
+                                val + 1,
+                            );
+                            if res.is_ok() {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().expect("thread failed");
+        }
+        // No increment was lost.
+        assert_eq!(*map.get("counter").unwrap().val(), THREADS * INCREMENTS);
+    }
+}
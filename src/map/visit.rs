@@ -0,0 +1,192 @@
+use super::{bucket::Garbage, table::Table, Map};
+use alloc::vec::Vec;
+use core::ops::Range;
+use incin::Incinerator;
+
+/// A part of a [`Map`] to be visited by [`Map::visit_range`], made by
+/// [`Map::split_visits`]: some ranges of nodes of its tables, along with
+/// their sub-tables. The parts made by one call never overlap, and together
+/// they cover the whole [`Map`], whatever is inserted or removed later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VisitRange {
+    segments: Vec<Segment>,
+}
+
+// A range of nodes of the table reached through the given indices. No
+// reference to a table is kept, since sub-tables may be retired by `shrink`
+// while the range is not being split or visited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Segment {
+    prefix: Vec<usize>,
+    // `None` for the whole top table, whose length is only known when the
+    // map is not compact anymore.
+    range: Option<Range<usize>>,
+}
+
+impl Segment {
+    pub(super) fn whole() -> Self {
+        Self { prefix: Vec::new(), range: None }
+    }
+
+    // Moves a range of a single node holding a sub-table to the whole
+    // sub-table, for as long as it applies, so the range can be split, and
+    // returns the range. Unsafe because the incinerator needs to be paused,
+    // and the top table must not be compact.
+    pub(super) unsafe fn descend<K, V>(
+        &mut self,
+        top: &Table<K, V>,
+    ) -> Range<usize> {
+        let mut range = self.range.take().unwrap_or(0 .. 1 << top.bits());
+        while range.len() == 1 {
+            let mut path = self.prefix.clone();
+            path.push(range.start);
+            match top.sub_table(&path) {
+                Some(table) => {
+                    self.prefix = path;
+                    range = 0 .. 1 << table.bits();
+                },
+                None => break,
+            }
+        }
+        self.range = Some(range.clone());
+        range
+    }
+
+    // Splits off the nodes from `middle` on, which must fall in the range
+    // given by `descend`.
+    pub(super) fn split_at(&mut self, middle: usize) -> Self {
+        let range = self.range.as_mut().expect("descend first");
+        let other = middle .. range.end;
+        range.end = middle;
+        Self { prefix: self.prefix.clone(), range: Some(other) }
+    }
+
+    // Visits the pairs of the range just like `Table::visit_part`.
+    pub(super) fn visit<K, V, F>(
+        &self,
+        top: &Table<K, V>,
+        incin: &Incinerator<Garbage<K, V>>,
+        mut visitor: F,
+    ) where
+        F: FnMut(&K, &V),
+    {
+        let range = self.range.clone().unwrap_or(0 .. 1 << top.bits());
+        top.visit_part(incin, &self.prefix, range, |(key, val)| {
+            visitor(key, val)
+        })
+    }
+}
+
+impl<K, V, H, const BITS: usize, O> Map<K, V, H, BITS, O> {
+    /// Splits the [`Map`] into at most `n` parts to be visited by
+    /// [`visit_range`](Map::visit_range), e.g. one for each thread of a pool,
+    /// without a dependency on rayon. The parts are made of ranges of nodes of
+    /// the tables, so they never overlap and cover every entry, even those
+    /// inserted later. They are balanced by the entries found while
+    /// splitting, descending into sub-tables where the hashes are skewed.
+    ///
+    /// A compact [`Map`] is not split at all, since its tables are replaced
+    /// when it is upgraded. Fewer than `n` parts are also returned when the
+    /// [`Map`] holds too few entries to share, but at least one part is
+    /// always returned.
+    pub fn split_visits(&self, n: usize) -> Vec<VisitRange> {
+        let pause = self.pause();
+        let top = self.top(&pause);
+        if self.is_compact(top) {
+            return vec![VisitRange { segments: vec![Segment::whole()] }];
+        }
+
+        // Each segment along with its estimated entries, and whether it can
+        // still be split.
+        let mut segments = vec![(Segment::whole(), usize::MAX, true)];
+
+        loop {
+            let loaded = segments.iter().filter(|&&(_, weight, _)| weight > 0);
+            if loaded.count() >= n {
+                break;
+            }
+            let heaviest = segments
+                .iter()
+                .enumerate()
+                .filter(|&(_, &(_, weight, splittable))| {
+                    splittable && weight > 1
+                })
+                .max_by_key(|&(_, &(_, weight, _))| weight);
+            let index = match heaviest {
+                Some((index, _)) => index,
+                None => break,
+            };
+
+            let segment = &mut segments[index].0;
+            // Safe because we paused properly and the map is not compact.
+            let range = unsafe { segment.descend(top) };
+            if range.len() < 2 {
+                segments[index].2 = false;
+                continue;
+            }
+            // Safe because we paused properly.
+            let weights = unsafe {
+                match top.sub_table(&segment.prefix) {
+                    Some(table) => table.node_weights(range.clone()),
+                    None => vec![0; range.len()],
+                }
+            };
+
+            let total = weights.iter().sum::<usize>();
+            let mut middle = range.start + 1;
+            let mut lower = weights[0];
+            while middle < range.end - 1 && lower * 2 < total {
+                lower += weights[middle - range.start];
+                middle += 1;
+            }
+            let other = segment.split_at(middle);
+            segments[index].1 = lower;
+            segments.push((other, total - lower, true));
+        }
+
+        // Segments found empty are handed to the lightest parts.
+        let (parts, empty): (Vec<_>, Vec<_>) = segments
+            .into_iter()
+            .map(|(segment, weight, _)| (segment, weight))
+            .partition(|&(_, weight)| weight > 0);
+        if parts.is_empty() {
+            let segments = empty.into_iter().map(|(segment, _)| segment);
+            return vec![VisitRange { segments: segments.collect() }];
+        }
+        let mut parts = parts
+            .into_iter()
+            .map(|(segment, weight)| (vec![segment], weight))
+            .collect::<Vec<_>>();
+        for (segment, _) in empty {
+            let lightest = parts
+                .iter_mut()
+                .min_by_key(|(_, weight)| *weight)
+                .expect("at least one part");
+            lightest.0.push(segment);
+        }
+
+        parts
+            .into_iter()
+            .map(|(segments, _)| VisitRange { segments })
+            .collect()
+    }
+
+    /// Calls the given closure on every entry in a part made by
+    /// [`split_visits`](Map::split_visits), pausing the incinerator only while
+    /// small chunks of the [`Map`] are read, with the same guarantees as
+    /// [`for_each`](Map::for_each) about concurrent modifications. Visiting
+    /// each part of a single call to [`split_visits`](Map::split_visits),
+    /// from any threads, visits an entry present all along exactly once. A
+    /// part made by another [`Map`] may visit unspecified entries or panic,
+    /// but it is memory-safe.
+    pub fn visit_range<F>(&self, part: &VisitRange, mut visitor: F)
+    where
+        F: FnMut(&K, &V),
+    {
+        for segment in &part.segments {
+            self.walk_top(|top| {
+                segment.visit(top, &self.incin.inner, &mut visitor)
+            })
+        }
+    }
+}
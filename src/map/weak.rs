@@ -0,0 +1,275 @@
+use super::{DefaultHashBuilder, Map};
+use alloc::sync::{Arc, Weak};
+use core::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash},
+    ptr,
+};
+
+/// A [`Map`] which does not keep its values alive, e.g. an index from IDs to
+/// shared resources. Values are inserted as [`Arc`]s but stored as [`Weak`]
+/// references, and once every [`Arc`] of a value is dropped, its entry is
+/// treated as absent. Such dead entries are removed either lazily, by the
+/// lookups which find them, or by a sweep with
+/// [`prune`](WeakValueMap::prune).
+///
+/// A value found alive is upgraded to an [`Arc`] while the incinerator is
+/// paused, so the upgrade either wins against the drop of the last [`Arc`],
+/// keeping the value alive, or finds the value dead. A dead entry never comes
+/// back to life, so removing it cannot race with a revival; an entry replaced
+/// meanwhile is never removed for being dead.
+pub struct WeakValueMap<K, T, H = DefaultHashBuilder> {
+    inner: Map<K, Weak<T>, H>,
+}
+
+impl<K, T> WeakValueMap<K, T> {
+    /// Creates a [`WeakValueMap`] with the default hasher builder.
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, T> Default for WeakValueMap<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, T, H> WeakValueMap<K, T, H> {
+    /// The number of entries, counting dead entries which were not removed
+    /// yet. The same considerations of [`Map::len`] apply.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns whether there are no entries, dead or alive. The same
+    /// considerations of [`Map::len`] apply.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Consumes this [`WeakValueMap`] into the [`Map`] holding its entries,
+    /// dead or alive.
+    pub fn into_inner(self) -> Map<K, Weak<T>, H> {
+        self.inner
+    }
+}
+
+impl<K, T, H> WeakValueMap<K, T, H>
+where
+    H: BuildHasher,
+{
+    /// Creates a [`WeakValueMap`] using the given hasher builder.
+    pub fn with_hasher(builder: H) -> Self {
+        Self { inner: Map::with_hasher(builder) }
+    }
+
+    /// Inserts unconditionally the given key along with a weak reference to
+    /// the given value. If there was a previously stored entry which was
+    /// still alive, its value is returned.
+    pub fn insert(&self, key: K, val: &Arc<T>) -> Option<Arc<T>>
+    where
+        K: Hash + Ord,
+    {
+        let removed = self.inner.insert(key, Arc::downgrade(val))?;
+        removed.val().upgrade()
+    }
+
+    /// Searches for the entry identified by the given key, returning its
+    /// value if it is still alive. A dead entry found is removed. See
+    /// [`Map::get`].
+    pub fn get<Q>(&self, key: &Q) -> Option<Arc<T>>
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        let guard = self.inner.get(key)?;
+        let found = guard.val().upgrade();
+        if found.is_none() {
+            self.remove_dead(key, &guard);
+        }
+        found
+    }
+
+    /// Tests if an entry identified by the given key is present and alive. A
+    /// dead entry found is removed.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Removes unconditionally the entry identified by the given key. Its
+    /// value is only returned if it was still alive.
+    pub fn remove<Q>(&self, key: &Q) -> Option<Arc<T>>
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        self.inner.remove(key)?.val().upgrade()
+    }
+
+    /// Removes every dead entry, through the same path as [`Map::remove`],
+    /// returning how many were removed. Just like [`Map::retain`], the
+    /// incinerator is paused only while small chunks of the [`Map`] are
+    /// read, and entries inserted concurrently may be skipped.
+    pub fn prune(&self) -> usize
+    where
+        K: Hash + Ord,
+    {
+        let mut pruned = 0;
+        self.inner.walk_top(|top| {
+            top.visit(&self.inner.incin.inner, |pair| {
+                if pair.1.strong_count() == 0
+                    && self.remove_dead(&pair.0, pair)
+                {
+                    pruned += 1;
+                }
+            })
+        });
+        pruned
+    }
+
+    // Removes the given entry, found through the given key, if it is still
+    // stored and dead. Returns whether it was removed.
+    fn remove_dead<Q>(&self, key: &Q, pair: &(K, Weak<T>)) -> bool
+    where
+        Q: ?Sized + Hash + Ord,
+        K: Borrow<Q>,
+    {
+        let removed = self.inner.remove_with(key, |stored| {
+            ptr::eq(stored, pair) && stored.1.strong_count() == 0
+        });
+        removed.is_some()
+    }
+}
+
+impl<K, T, H> fmt::Debug for WeakValueMap<K, T, H>
+where
+    K: fmt::Debug,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("WeakValueMap").field("inner", &self.inner).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering::*},
+            Barrier,
+        },
+        thread,
+    };
+    use std::prelude::v1::*;
+
+    #[test]
+    fn dead_entries_are_absent() {
+        let map = WeakValueMap::new();
+        let one = Arc::new(1);
+        let two = Arc::new(2);
+        assert!(map.insert("one", &one).is_none());
+        assert!(map.insert("two", &two).is_none());
+        assert_eq!(map.get("one").as_deref(), Some(&1));
+        assert_eq!(Arc::strong_count(&one), 1);
+
+        drop(one);
+        assert_eq!(map.len(), 2);
+        assert!(map.get("one").is_none());
+        assert_eq!(map.len(), 1);
+        assert!(map.contains_key("two"));
+
+        let other = Arc::new(22);
+        assert_eq!(map.insert("two", &other).as_deref(), Some(&2));
+        drop(other);
+        assert!(map.insert("two", &two).is_none());
+        assert_eq!(map.remove("two").as_deref(), Some(&2));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn prune_dead() {
+        let map = WeakValueMap::new();
+        let values = (0 .. 1000u64).map(Arc::new).collect::<Vec<_>>();
+        for val in &values {
+            map.insert(**val, val);
+        }
+        assert_eq!(map.prune(), 0);
+
+        let kept = values
+            .into_iter()
+            .filter(|val| **val % 4 == 0)
+            .collect::<Vec<_>>();
+        assert_eq!(map.prune(), 750);
+        assert_eq!(map.len(), 250);
+        for i in 0 .. 1000u64 {
+            assert_eq!(map.get(&i).is_some(), i % 4 == 0);
+        }
+        drop(kept);
+        assert_eq!(map.prune(), 250);
+        assert!(map.is_empty());
+        map.into_inner().validate();
+    }
+
+    #[test]
+    fn upgrade_racing_last_drop() {
+        const KEYS: u64 = 200;
+        const ROUNDS: usize = 50;
+
+        let map = Arc::new(WeakValueMap::new());
+        let barrier = Arc::new(Barrier::new(3));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers = (0 .. 2)
+            .map(|_| {
+                let map = map.clone();
+                let barrier = barrier.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    while !done.load(Acquire) {
+                        for key in 0 .. KEYS {
+                            // An upgraded value must be intact, even if the
+                            // owner dropped its `Arc` meanwhile.
+                            if let Some(val) = map.get(&key) {
+                                assert_eq!(*val, vec![key; 4]);
+                            }
+                        }
+                        map.prune();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        barrier.wait();
+        for _ in 0 .. ROUNDS {
+            let values = (0 .. KEYS)
+                .map(|key| Arc::new(vec![key; 4]))
+                .collect::<Vec<_>>();
+            for (key, val) in values.iter().enumerate() {
+                map.insert(key as u64, val);
+            }
+            for (key, val) in values.into_iter().enumerate() {
+                drop(val);
+                // Once the owner's `Arc` is gone, an upgrade may only
+                // succeed through a reader still holding the value.
+                let _ = map.get(&(key as u64));
+            }
+        }
+        done.store(true, Release);
+        for reader in readers {
+            reader.join().expect("reader failed");
+        }
+
+        for key in 0 .. KEYS {
+            assert!(map.get(&key).is_none());
+        }
+        assert_eq!(map.prune(), 0);
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,432 @@
+// Vendored from the `owned-alloc` crate, version 0.2.0, found at
+// <https://gitlab.com/bzim/owned-alloc>. That crate uses `std` everywhere, so
+// it cannot be built without the `std` feature. Only what this crate uses is
+// here; `init_in_place` hands out a raw pointer instead of a reference to
+// uninitialized memory, and every allocation has a fallible version, which
+// gives back an `AllocFailed` instead of calling the global allocation error
+// handler. Zero-sized values are not allocated; a dangling pointer is used
+// instead, just like `Box` does.
+//
+// The original code is under the following license:
+//
+// MIT License
+//
+// Copyright (c) 2018 Bruno Corrêa Zimmermann
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to
+// deal in the Software without restriction, including without limitation the
+// rights to use, copy, modify, merge, publish, distribute, sublicense, and/or
+// sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS
+// IN THE SOFTWARE.
+
+use alloc::{
+    alloc::{alloc, dealloc, handle_alloc_error, Layout},
+    boxed::Box,
+};
+use core::{
+    fmt,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
+
+// An allocation which the allocator failed to make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocFailed {
+    pub layout: Layout,
+}
+
+impl AllocFailed {
+    // Calls the global allocation error handler, for callers which cannot
+    // give the failure back.
+    pub fn handle(self) -> ! {
+        handle_alloc_error(self.layout)
+    }
+}
+
+// An allocation of a `T` whose memory is considered initialized. Both the
+// value and the allocation are freed on drop, just like a `Box`, but the
+// allocation can be turned into a raw pointer and back.
+pub struct OwnedAlloc<T> {
+    nnptr: NonNull<T>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> OwnedAlloc<T> {
+    pub fn new(val: T) -> Self {
+        UninitAlloc::new().init(val)
+    }
+
+    // Gives the value back if allocation fails.
+    pub fn try_new(val: T) -> Result<Self, (T, AllocFailed)> {
+        match UninitAlloc::try_new() {
+            Ok(alloc) => Ok(alloc.init(val)),
+            Err(err) => Err((val, err)),
+        }
+    }
+
+    // Moves the value out, giving back the allocation as uninitialized.
+    pub fn move_inner(self) -> (T, UninitAlloc<T>) {
+        // Safe because the memory is initialized, and it is considered
+        // uninitialized from now on.
+        let val = unsafe { self.nnptr.as_ptr().read() };
+        let alloc = unsafe { UninitAlloc::from_raw(self.nnptr) };
+        mem::forget(self);
+        (val, alloc)
+    }
+
+    // Forgets about dropping the value, giving back the allocation as
+    // uninitialized. The memory is still initialized, though.
+    pub fn forget_inner(self) -> UninitAlloc<T> {
+        // Safe because the allocation is not freed by `self` anymore.
+        unsafe { UninitAlloc::from_raw(self.into_raw()) }
+    }
+
+    // Unsafe because the pointer must come from `into_raw` or `raw` of an
+    // allocation that is not used anymore.
+    pub unsafe fn from_raw(nnptr: NonNull<T>) -> Self {
+        Self { nnptr, _marker: PhantomData }
+    }
+
+    pub fn raw(&self) -> NonNull<T> {
+        self.nnptr
+    }
+
+    // Forgets about freeing both the value and the allocation.
+    pub fn into_raw(self) -> NonNull<T> {
+        let nnptr = self.nnptr;
+        mem::forget(self);
+        nnptr
+    }
+}
+
+impl<T> Drop for OwnedAlloc<T> {
+    fn drop(&mut self) {
+        // Safe because we own both the value and the allocation.
+        unsafe {
+            self.nnptr.as_ptr().drop_in_place();
+            UninitAlloc::from_raw(self.nnptr);
+        }
+    }
+}
+
+impl<T> Deref for OwnedAlloc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe because the memory is initialized and we own it.
+        unsafe { self.nnptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for OwnedAlloc<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safe because the memory is initialized and we own it.
+        unsafe { self.nnptr.as_mut() }
+    }
+}
+
+impl<T> fmt::Debug for OwnedAlloc<T> {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "{:?}", self.nnptr)
+    }
+}
+
+unsafe impl<T> Send for OwnedAlloc<T> where T: Send {}
+unsafe impl<T> Sync for OwnedAlloc<T> where T: Sync {}
+
+// An allocation with room for a `T`, whose memory is considered
+// uninitialized. Only the allocation is freed on drop.
+pub struct UninitAlloc<T> {
+    nnptr: NonNull<T>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> UninitAlloc<T> {
+    // Calls the global allocation error handler if allocation fails.
+    pub fn new() -> Self {
+        Self::try_new().unwrap_or_else(|err| err.handle())
+    }
+
+    pub fn try_new() -> Result<Self, AllocFailed> {
+        let layout = Layout::new::<T>();
+
+        let nnptr = if layout.size() == 0 {
+            NonNull::dangling()
+        } else {
+            // Safe because the layout is not zero-sized.
+            let ptr = unsafe { alloc(layout) };
+            match NonNull::new(ptr as *mut T) {
+                Some(nnptr) => nnptr,
+                None => return Err(AllocFailed { layout }),
+            }
+        };
+
+        Ok(Self { nnptr, _marker: PhantomData })
+    }
+
+    pub fn init(self, val: T) -> OwnedAlloc<T> {
+        let nnptr = self.into_raw();
+        // Safe because we own the memory, which is initialized right away.
+        unsafe {
+            nnptr.as_ptr().write(val);
+            OwnedAlloc::from_raw(nnptr)
+        }
+    }
+
+    // Unsafe because the closure must initialize the memory it is given. It is
+    // given a raw pointer, since no reference may point to uninitialized
+    // memory.
+    pub unsafe fn init_in_place<F>(self, init: F) -> OwnedAlloc<T>
+    where
+        F: FnOnce(*mut T),
+    {
+        let nnptr = self.into_raw();
+        init(nnptr.as_ptr());
+        OwnedAlloc::from_raw(nnptr)
+    }
+
+    // Unsafe because the pointer must come from `into_raw` of an allocation
+    // that is not used anymore.
+    pub unsafe fn from_raw(nnptr: NonNull<T>) -> Self {
+        Self { nnptr, _marker: PhantomData }
+    }
+
+    // Forgets about freeing the allocation.
+    pub fn into_raw(self) -> NonNull<T> {
+        let nnptr = self.nnptr;
+        mem::forget(self);
+        nnptr
+    }
+}
+
+impl<T> Drop for UninitAlloc<T> {
+    fn drop(&mut self) {
+        let layout = Layout::new::<T>();
+        if layout.size() != 0 {
+            // Safe because the memory was allocated with this very layout.
+            unsafe { dealloc(self.nnptr.as_ptr() as *mut u8, layout) }
+        }
+    }
+}
+
+impl<T> fmt::Debug for UninitAlloc<T> {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "{:?}", self.nnptr)
+    }
+}
+
+unsafe impl<T> Send for UninitAlloc<T> where T: Send {}
+unsafe impl<T> Sync for UninitAlloc<T> where T: Sync {}
+
+// Allocates a boxed slice of `len` values made by `init`, which is not called
+// at all if allocation fails. The memory leaks if `init` panics.
+pub fn try_boxed_slice<T, F>(
+    len: usize,
+    mut init: F,
+) -> Result<Box<[T]>, AllocFailed>
+where
+    F: FnMut() -> T,
+{
+    let layout = match Layout::array::<T>(len) {
+        Ok(layout) => layout,
+        // Too big to be allocated at all.
+        Err(_) => return Err(AllocFailed { layout: Layout::new::<T>() }),
+    };
+
+    let first = if layout.size() == 0 {
+        NonNull::<T>::dangling().as_ptr()
+    } else {
+        // Safe because the layout is not zero-sized.
+        let ptr = unsafe { alloc(layout) } as *mut T;
+        if ptr.is_null() {
+            return Err(AllocFailed { layout });
+        }
+        ptr
+    };
+
+    // Safe because the memory has room for `len` values, all of them written
+    // before the slice is boxed, with the layout `Box` would use for it.
+    unsafe {
+        for i in 0 .. len {
+            first.add(i).write(init());
+        }
+        Ok(Box::from_raw(ptr::slice_from_raw_parts_mut(first, len)))
+    }
+}
+
+// Saves a discarded allocation, so it can be reused in a tight loop.
+#[derive(Debug)]
+pub struct Cache<A> {
+    stored: Option<A>,
+}
+
+impl<A> Cache<A> {
+    pub fn new() -> Self {
+        Self { stored: None }
+    }
+
+    pub fn store(&mut self, val: A) {
+        self.stored = Some(val);
+    }
+
+    pub fn take(&mut self) -> Option<A> {
+        self.stored.take()
+    }
+
+    pub fn take_or<F>(&mut self, create: F) -> A
+    where
+        F: FnOnce() -> A,
+    {
+        self.take().unwrap_or_else(create)
+    }
+
+    // Just like `take_or`, but the creation may fail.
+    pub fn try_take_or<F, E>(&mut self, create: F) -> Result<A, E>
+    where
+        F: FnOnce() -> Result<A, E>,
+    {
+        match self.take() {
+            Some(val) => Ok(val),
+            None => create(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{try_boxed_slice, Cache, OwnedAlloc, UninitAlloc};
+    use std::prelude::v1::*;
+
+    #[test]
+    fn inner_eq() {
+        let mut alloc = OwnedAlloc::new(20);
+
+        assert_eq!(*alloc, 20);
+
+        *alloc = 30;
+
+        assert_eq!(*alloc, 30);
+    }
+
+    #[test]
+    fn move_inner_eq() {
+        let alloc = OwnedAlloc::new(20);
+
+        assert_eq!(alloc.move_inner().0, 20);
+    }
+
+    #[test]
+    fn into_from_raw() {
+        let alloc = OwnedAlloc::new(String::from("owned"));
+        let raw_borrowed = alloc.raw();
+        let raw = alloc.into_raw();
+
+        assert_eq!(raw, raw_borrowed);
+
+        let alloc = unsafe { OwnedAlloc::from_raw(raw) };
+        assert_eq!(alloc.raw(), raw_borrowed);
+        assert_eq!(*alloc, "owned");
+    }
+
+    #[test]
+    fn uninit_into_from_raw() {
+        let alloc = UninitAlloc::<usize>::new();
+        let raw = alloc.into_raw();
+        let alloc = unsafe { UninitAlloc::from_raw(raw) };
+
+        assert_eq!(*alloc.init(5), 5);
+    }
+
+    #[test]
+    fn init_in_place() {
+        let alloc = UninitAlloc::<[u64; 4]>::new();
+        let inited = unsafe {
+            alloc.init_in_place(|ptr| {
+                for i in 0 .. 4 {
+                    (ptr as *mut u64).add(i).write(i as u64 * 3);
+                }
+            })
+        };
+
+        assert_eq!(*inited, [0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn try_new_zero_sized() {
+        let alloc = OwnedAlloc::try_new(()).unwrap();
+        let (val, uninit) = alloc.move_inner();
+
+        assert_eq!(val, ());
+        assert_eq!(*uninit.init(()), ());
+        assert!(UninitAlloc::<()>::try_new().is_ok());
+    }
+
+    #[test]
+    fn forget_inner_reuses_memory() {
+        let alloc = OwnedAlloc::new(7u64);
+        let raw = alloc.raw();
+        let uninit = alloc.forget_inner();
+
+        let alloc = uninit.init(8);
+        assert_eq!(alloc.raw(), raw);
+        let (inner, uninit) = alloc.move_inner();
+        assert_eq!(inner, 8);
+        assert_eq!(uninit.into_raw(), raw);
+
+        drop(unsafe { UninitAlloc::from_raw(raw) });
+    }
+
+    #[test]
+    fn boxed_slice() {
+        let mut count = 0;
+        let slice = try_boxed_slice(5, || {
+            count += 1;
+            count
+        })
+        .unwrap();
+
+        assert_eq!(&*slice, &[1, 2, 3, 4, 5]);
+        assert_eq!(&*try_boxed_slice(3, || ()).unwrap(), &[(), (), ()]);
+        assert!(try_boxed_slice::<u64, _>(0, || 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn boxed_slice_too_big() {
+        let mut called = false;
+        let res = try_boxed_slice::<u64, _>(usize::MAX, || {
+            called = true;
+            0
+        });
+
+        assert!(res.is_err());
+        assert!(!called);
+    }
+
+    #[test]
+    fn cache_reuses_stored() {
+        let mut cache = Cache::new();
+        assert_eq!(cache.take_or(|| 1), 1);
+
+        cache.store(2);
+        assert_eq!(cache.take_or(|| 3), 2);
+        assert_eq!(cache.take(), None);
+
+        cache.store(4);
+        assert_eq!(cache.try_take_or(|| Err::<i32, ()>(())), Ok(4));
+        assert_eq!(cache.try_take_or(|| Err::<i32, ()>(())), Err(()));
+    }
+}
@@ -3,4 +3,5 @@ pub use map::Map;
 pub use queue::Queue;
 pub use set::Set;
 pub use stack::Stack;
+#[cfg(feature = "std")]
 pub use tls::ThreadLocal;
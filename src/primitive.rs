@@ -0,0 +1,44 @@
+// The atomics raced on by the buckets and tables of a map, and the pause
+// counter and shared garbage list of the incinerator. With the `loom` cfg,
+// they are the atomics of `loom`, so the tests in `tests/loom.rs` can
+// model-check the interleavings of those operations. Other atomics are not
+// modeled, since they only hold hints and counters.
+
+#[cfg(not(loom))]
+pub use core::sync::atomic::{AtomicPtr, AtomicUsize};
+#[cfg(loom)]
+pub use loom::sync::atomic::{AtomicPtr, AtomicUsize};
+
+// Plain access to an atomic pointer through an exclusive reference. The
+// atomics of `loom` have no `get_mut`, so this is used instead.
+pub trait PtrMut<T> {
+    // Reads the pointer.
+    fn read_mut(&mut self) -> *mut T;
+
+    // Replaces the pointer, returning the old one.
+    fn replace_mut(&mut self, ptr: *mut T) -> *mut T;
+}
+
+#[cfg(not(loom))]
+impl<T> PtrMut<T> for AtomicPtr<T> {
+    #[inline(always)]
+    fn read_mut(&mut self) -> *mut T {
+        *self.get_mut()
+    }
+
+    #[inline(always)]
+    fn replace_mut(&mut self, ptr: *mut T) -> *mut T {
+        core::mem::replace(self.get_mut(), ptr)
+    }
+}
+
+#[cfg(loom)]
+impl<T> PtrMut<T> for AtomicPtr<T> {
+    fn read_mut(&mut self) -> *mut T {
+        self.with_mut(|loaded| *loaded)
+    }
+
+    fn replace_mut(&mut self, ptr: *mut T) -> *mut T {
+        self.with_mut(|loaded| core::mem::replace(loaded, ptr))
+    }
+}
@@ -1,11 +1,14 @@
-use std::{
+use core::{
     mem::align_of,
     ptr::{null_mut, NonNull},
 };
 
 static _NON_NULL: u8 = /* dummy value */ 1;
 
-// don't use with bit flags
+// A sentinel pointer, e.g. the pair of the root entry of a bucket. It is the
+// address of a static, which no allocation can share, taken from a shared
+// reference, so it has valid provenance. It must only be compared, never
+// dereferenced. Don't use with bit flags, since the static is not aligned.
 #[inline(always)]
 pub fn non_zero_null<T>() -> NonNull<T> {
     NonNull::from(&_NON_NULL).cast()
@@ -1,13 +1,13 @@
-use incin::Pause;
-use owned_alloc::OwnedAlloc;
-use ptr::{bypass_null, check_null_align};
-use removable::Removable;
-use std::{
+use core::{
     fmt,
     iter::FromIterator,
     ptr::{null_mut, NonNull},
     sync::atomic::{AtomicPtr, Ordering::*},
 };
+use incin::Pause;
+use owned_alloc::OwnedAlloc;
+use ptr::{bypass_null, check_null_align};
+use removable::Removable;
 
 /// A lock-free general-purpouse queue. FIFO semanthics are fully respected.
 /// It can be used as multi-producer and multi-consumer channel.
@@ -290,6 +290,7 @@ mod test {
         sync::{atomic::AtomicUsize, Arc},
         thread,
     };
+    use std::prelude::v1::*;
 
     #[test]
     fn on_empty_first_pop_is_none() {
@@ -1,4 +1,4 @@
-use std::{
+use core::{
     fmt,
     mem::{replace, MaybeUninit},
     sync::atomic::{
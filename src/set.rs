@@ -1,4 +1,14 @@
+#[cfg(feature = "std")]
 pub use map::RandomState;
+pub use map::{DefaultHashBuilder, FixedState};
+use core::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::FromIterator,
+    ops::Deref,
+};
 use map::{
     Insertion as MapInsertion,
     IntoIter as MapIntoIter,
@@ -9,18 +19,22 @@ use map::{
     Removed as MapRemoved,
     SharedIncin as MapIncin,
 };
-use std::{
-    borrow::Borrow,
-    cmp::Ordering,
-    fmt,
-    hash::{BuildHasher, Hash},
-    iter::FromIterator,
-    ops::Deref,
-};
+
+/// A [`Set`] hashing its elements with [aHash](::ahash), through
+/// [`ahash::RandomState`]. Create it with
+/// [`default`](Default::default) or [`with_hasher`](Set::with_hasher).
+#[cfg(feature = "ahash")]
+pub type AHashSet<T> = Set<T, ::ahash::RandomState>;
+
+/// A [`Set`] hashing its elements with [FxHash](::fxhash), through
+/// [`FxBuildHasher`](::fxhash::FxBuildHasher). Create it with
+/// [`default`](Default::default) or [`with_hasher`](Set::with_hasher).
+#[cfg(feature = "fxhash")]
+pub type FxSet<T> = Set<T, ::fxhash::FxBuildHasher>;
 
 /// A lock-free set. This is currently implemented on top of
 /// [`Map`](::map::Map). To check more details about it, please see `Map` docs.
-pub struct Set<T, H = RandomState> {
+pub struct Set<T, H = DefaultHashBuilder> {
     inner: Map<T, (), H>,
 }
 
@@ -74,9 +88,9 @@ where
         self.inner.optimize_space();
     }
 
-    /// Removes all elements. This method cannot be performed in a shared
-    /// context.
-    pub fn clear(&mut self) {
+    /// Removes all elements. This method can be performed in a shared context,
+    /// and elements inserted concurrently might either survive or be removed.
+    pub fn clear(&self) {
         self.inner.clear();
     }
 
@@ -89,7 +103,7 @@ where
         U: Hash + Ord,
         T: Borrow<U>,
     {
-        self.inner.get(elem).is_some()
+        self.inner.contains_key(elem)
     }
 
     /// Returns a guarded reference to the given element in the [`Set`]. This
@@ -265,7 +279,7 @@ where
 
 impl<T, H> fmt::Debug for Set<T, H>
 where
-    H: fmt::Debug,
+    T: fmt::Debug,
 {
     fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
         write!(fmtr, "Set {} inner_map: {:?} {}", '{', self.inner, '}')
@@ -615,6 +629,7 @@ mod test {
         cmp::Ordering,
         hash::{Hash, Hasher},
     };
+    use std::prelude::v1::*;
 
     #[derive(Debug, Clone, Copy)]
     struct EqI {
@@ -667,6 +682,22 @@ mod test {
         assert!(set.contains(&5));
     }
 
+    #[cfg(all(feature = "ahash", feature = "fxhash"))]
+    #[test]
+    fn hasher_aliases() {
+        let ahash_set = AHashSet::default();
+        let fx_set = FxSet::default();
+        for i in 0 .. 100u32 {
+            ahash_set.insert(i).unwrap();
+            fx_set.insert(i << 16).unwrap();
+        }
+        for i in 0 .. 100u32 {
+            assert!(ahash_set.contains(&i));
+            assert!(fx_set.contains(&(i << 16)));
+            assert!(!fx_set.contains(&(i << 16 | 1)));
+        }
+    }
+
     #[test]
     fn inserts_and_removes() {
         let set = Set::new();
@@ -1,11 +1,11 @@
-use owned_alloc::OwnedAlloc;
-use std::{
+use core::{
     fmt,
     iter::FromIterator,
     mem::ManuallyDrop,
     ptr::{null_mut, NonNull},
     sync::atomic::{AtomicPtr, Ordering::*},
 };
+use owned_alloc::OwnedAlloc;
 
 /// A lock-free stack. LIFO/FILO semanthics are fully respected.
 pub struct Stack<T> {
@@ -230,6 +230,7 @@ impl<T> Node<T> {
 mod test {
     use super::*;
     use std::{sync::Arc, thread};
+    use std::prelude::v1::*;
 
     #[test]
     fn on_empty_first_pop_is_none() {
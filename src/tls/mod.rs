@@ -2,15 +2,16 @@ mod tid;
 
 pub use self::tid::ThreadId;
 
-use owned_alloc::{Cache, OwnedAlloc, UninitAlloc};
-use ptr::check_null_align;
-use std::{
+use alloc::vec::Vec;
+use core::{
     fmt,
     marker::PhantomData,
     mem::{forget, replace},
-    ptr::{null_mut, NonNull},
+    ptr::{addr_of_mut, null_mut, NonNull},
     sync::atomic::{AtomicPtr, Ordering::*},
 };
+use owned_alloc::{Cache, OwnedAlloc, UninitAlloc};
+use ptr::check_null_align;
 
 const BITS: usize = 8;
 
@@ -668,16 +669,20 @@ struct Table<T> {
 impl<T> Table<T> {
     #[inline]
     fn new_alloc() -> OwnedAlloc<Self> {
-        // Safe because it calls a correctly a function which correctly
-        // initializes uninitialized memory with, indeed, uninitialized memory.
-        unsafe { UninitAlloc::<Self>::new().init_in_place(|this| this.init()) }
+        // Safe because `init` initializes every node of the table.
+        unsafe {
+            UninitAlloc::<Self>::new().init_in_place(|this| Self::init(this))
+        }
     }
 
-    // Unsafe because passing ininitialized memory may cause leaks.
+    // Writes every node of a table through a raw pointer, so no reference to
+    // the uninitialized memory is made. Unsafe because the pointer must be
+    // valid, and passing initialized memory may cause leaks.
     #[inline]
-    unsafe fn init(&mut self) {
-        for node_ref in &mut self.nodes as &mut [_] {
-            (node_ref as *mut Node<T>).write(Node {
+    unsafe fn init(this: *mut Self) {
+        let nodes = addr_of_mut!((*this).nodes) as *mut Node<T>;
+        for i in 0 .. 1 << BITS {
+            nodes.add(i).write(Node {
                 atomic: AtomicPtr::new(null_mut()),
                 _marker: PhantomData,
             })
@@ -758,6 +763,7 @@ mod test {
         sync::{Arc, Barrier},
         thread,
     };
+    use std::prelude::v1::*;
 
     #[test]
     fn threads_with_their_id() {
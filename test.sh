@@ -17,3 +17,5 @@ test_with_toolchain +nightly --lib
 export RUSTFLAGS='-C debuginfo=2'
 
 test_with_toolchain +stable
+test_with_toolchain +stable --no-default-features
+test_with_toolchain +stable "--features ahash,fxhash"
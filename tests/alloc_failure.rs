@@ -0,0 +1,282 @@
+extern crate lockfree;
+
+use lockfree::map::Map;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    hash::{BuildHasher, Hasher},
+    ptr::null_mut,
+};
+
+// Once armed, lets a given number of allocations of the current thread
+// succeed and fails every one after them, just like an exhausted allocator.
+// Allocations of the current thread still alive are counted, so leaks can be
+// told.
+struct Failing;
+
+thread_local! {
+    // How many allocations may still succeed, if armed.
+    static BUDGET: Cell<Option<usize>> = const { Cell::new(None) };
+    static FAILED: Cell<bool> = const { Cell::new(false) };
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Failing {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let fail = BUDGET
+            .try_with(|budget| match budget.get() {
+                Some(0) => true,
+                Some(left) => {
+                    budget.set(Some(left - 1));
+                    false
+                },
+                None => false,
+            })
+            .unwrap_or(false);
+        if fail {
+            FAILED.with(|failed| failed.set(true));
+            return null_mut();
+        }
+
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let _ = LIVE.try_with(|live| live.set(live.get() + 1));
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = LIVE.try_with(|live| live.set(live.get() - 1));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Failing = Failing;
+
+fn arm(budget: usize) {
+    FAILED.with(|failed| failed.set(false));
+    BUDGET.with(|cell| cell.set(Some(budget)));
+}
+
+// Returns whether some allocation failed since armed.
+fn disarm() -> bool {
+    BUDGET.with(|cell| cell.set(None));
+    FAILED.with(Cell::get)
+}
+
+fn live() -> isize {
+    LIVE.with(Cell::get)
+}
+
+// The hash of a key is its first word, so tests choose which keys share a
+// bucket or a node.
+type Key = (u64, u64);
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FirstWord;
+
+impl BuildHasher for FirstWord {
+    type Hasher = FirstWordHasher;
+
+    fn build_hasher(&self) -> FirstWordHasher {
+        FirstWordHasher { hash: None }
+    }
+}
+
+struct FirstWordHasher {
+    hash: Option<u64>,
+}
+
+impl Hasher for FirstWordHasher {
+    fn finish(&self) -> u64 {
+        self.hash.unwrap_or(0)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let mut word = [0; 8];
+        let len = bytes.len().min(8);
+        word[.. len].copy_from_slice(&bytes[.. len]);
+        self.write_u64(u64::from_ne_bytes(word));
+    }
+
+    fn write_u64(&mut self, word: u64) {
+        self.hash.get_or_insert(word);
+    }
+}
+
+type TestMap = Map<Key, u64, FirstWord>;
+
+fn contents(map: &TestMap) -> Vec<(Key, u64)> {
+    let mut pairs = map
+        .iter()
+        .map(|guard| (*guard.key(), *guard.val()))
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs
+}
+
+#[cfg(feature = "debug-validate")]
+fn validate(map: &TestMap) {
+    map.validate();
+}
+
+#[cfg(not(feature = "debug-validate"))]
+fn validate(_: &TestMap) {}
+
+// Inserts the given pair into maps made by `setup`, failing every allocation
+// after the first `n` ones, for every `n` until the insertion allocates
+// without failing. A failed insertion must give the pair back and leave the
+// map just as it was, and nothing may be leaked either way.
+fn check<F>(setup: F, key: Key, val: u64)
+where
+    F: Fn() -> TestMap,
+{
+    // Whatever the thread allocates for good on its first access to a map
+    // must not be taken as leaked.
+    drop(setup());
+
+    for n in 0 .. {
+        let baseline = live();
+        let map = setup();
+        let before = contents(&map);
+
+        arm(n);
+        let res = map.try_insert_alloc(key, val);
+        let failed = disarm();
+
+        let done = match res {
+            Err(err) => {
+                assert!(failed);
+                assert_eq!((err.key, err.val), (key, val));
+                assert_eq!(map.len(), before.len());
+                assert_eq!(contents(&map), before);
+                false
+            },
+
+            Ok(old) => {
+                let expected = before
+                    .iter()
+                    .find(|(found, _)| *found == key)
+                    .map(|&(_, found)| found);
+                assert_eq!(old.map(|old| *old.val()), expected);
+                let mut after = before.clone();
+                after.retain(|(found, _)| *found != key);
+                after.push((key, val));
+                after.sort();
+                assert_eq!(contents(&map), after);
+                assert_eq!(map.len(), after.len());
+                !failed
+            },
+        };
+
+        validate(&map);
+        drop(map);
+        drop(before);
+        assert_eq!(live(), baseline, "leaked with {} allocations", n);
+
+        if done {
+            break;
+        }
+    }
+}
+
+#[test]
+fn insert_into_empty_map() {
+    check(TestMap::default, (1, 0), 1);
+}
+
+#[test]
+fn replace_value() {
+    let setup = || {
+        let map = TestMap::default();
+        map.insert((1, 0), 0);
+        map
+    };
+    check(setup, (1, 0), 1);
+}
+
+#[test]
+fn insert_into_existing_bucket() {
+    let setup = || {
+        let map = TestMap::default();
+        map.insert((1, 0), 0);
+        map.insert((1, 2), 0);
+        map
+    };
+    check(setup, (1, 1), 1);
+}
+
+#[test]
+fn insert_branching_table() {
+    let setup = || {
+        let map = TestMap::default();
+        map.insert((1, 0), 0);
+        map
+    };
+    // Both hashes index the same node of the top table.
+    check(setup, (1 + 256, 0), 1);
+}
+
+// Hashes spread over few nodes of a compact top table, so its upgrade needs
+// sub-tables. The upgrade follows the insertion of the `65`th entry.
+fn compact_with(len: u64) -> TestMap {
+    let map = TestMap::with_fanout_compact(FirstWord);
+    for i in 0 .. len {
+        map.insert((i * 17, 0), i);
+    }
+    map
+}
+
+#[test]
+fn insert_upgrading_compact_map() {
+    check(|| compact_with(64), (64 * 17, 0), 1);
+}
+
+// A compact map whose upgrade failed to allocate once its last entry was
+// inserted, so its tree is left frozen.
+fn frozen_compact() -> TestMap {
+    for n in 0 .. {
+        let map = compact_with(64);
+        arm(n);
+        let res = map.try_insert_alloc((64 * 17, 0), 64);
+        assert!(disarm());
+        // The first budget enough for the insertion is not enough for the
+        // upgrade following it.
+        if res.is_ok() {
+            return map;
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn insert_finishing_upgrade() {
+    check(frozen_compact, (65 * 17, 0), 1);
+}
+
+#[test]
+fn frozen_compact_map_is_usable() {
+    drop(frozen_compact());
+    let baseline = live();
+
+    let map = frozen_compact();
+    for i in 0 .. 65 {
+        assert_eq!(map.get(&(i * 17, 0)).map(|guard| *guard.val()), Some(i));
+    }
+    assert_eq!(map.remove(&(0, 0)).map(|removed| *removed.val()), Some(0));
+    validate(&map);
+    drop(map);
+    assert_eq!(live(), baseline);
+
+    let mut map = frozen_compact();
+    assert!(map.insert_mut((65 * 17, 0), 65).is_none());
+    assert_eq!(map.iter_mut().count(), 66);
+    validate(&map);
+    drop(map);
+    assert_eq!(live(), baseline);
+
+    let map = frozen_compact();
+    assert_eq!(map.into_iter().count(), 65);
+    assert_eq!(live(), baseline);
+}
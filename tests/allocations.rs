@@ -0,0 +1,88 @@
+extern crate lockfree;
+
+use lockfree::map::Map;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+// Counts the allocations made by the current thread, so the test harness'
+// own threads do not disturb the count.
+struct Counting;
+
+thread_local! {
+    static ALLOCS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCS.try_with(|allocs| allocs.set(allocs.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocs() -> usize {
+    ALLOCS.with(Cell::get)
+}
+
+// Pins down that replacing the value of an existing key allocates only the new
+// pair, since the bucket is located before anything is allocated, and the
+// entry replacing the old one reuses the slot of an entry replaced before.
+#[test]
+fn replace_allocates_only_pair() {
+    const KEYS: usize = 1000;
+    const ROUNDS: usize = 10;
+
+    let map = Map::new();
+    for i in 0 .. KEYS {
+        map.insert(i, 0);
+    }
+
+    let before = allocs();
+    for round in 1 ..= ROUNDS {
+        for i in 0 .. KEYS {
+            assert!(map.insert(i, round).is_some());
+        }
+    }
+    let replaces = allocs() - before;
+    assert_eq!(replaces, KEYS * ROUNDS);
+
+    let before = allocs();
+    for i in 0 .. KEYS {
+        map.get(&i);
+    }
+    assert_eq!(allocs() - before, 0);
+}
+
+// Once the slabs of a map hold enough nodes, inserting and removing the same
+// keys over and over only allocates pairs, since removed buckets and their
+// nodes are recycled.
+#[test]
+fn churn_allocates_only_pairs() {
+    const KEYS: usize = 1000;
+    const ROUNDS: usize = 10;
+
+    let map = Map::new();
+    let churn = |map: &Map<usize, usize>| {
+        for i in 0 .. KEYS {
+            assert!(map.insert(i, i).is_none());
+        }
+        for i in 0 .. KEYS {
+            assert!(map.remove(&i).is_some());
+        }
+    };
+    churn(&map);
+
+    let before = allocs();
+    for _ in 0 .. ROUNDS {
+        churn(&map);
+    }
+    assert_eq!(allocs() - before, KEYS * ROUNDS);
+}
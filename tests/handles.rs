@@ -0,0 +1,8 @@
+extern crate trybuild;
+
+// Pins down that a read handle of a map gives no way to change it.
+#[test]
+fn read_handle_is_read_only() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/handles/fail/*.rs");
+}
@@ -0,0 +1,9 @@
+extern crate lockfree;
+
+use lockfree::map::Map;
+use std::sync::Arc;
+
+fn main() {
+    let (_, read) = Arc::new(Map::<u32, u32>::new()).handles();
+    read.insert(1, 2);
+}
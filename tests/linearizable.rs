@@ -0,0 +1,171 @@
+// Runs random operations on a map from many threads and checks that the
+// results they observed are linearizable, i.e. that they could be the results
+// of some sequential order of the operations on a `HashMap`. The randomized
+// test is ignored by default, since it is slow; run it with:
+//
+//     cargo test --release --test linearizable -- --ignored
+//
+// `LOCKFREE_ROUNDS` sets how many histories are checked, and `LOCKFREE_SEED`
+// sets the seed of the first one, the next ones using the following seeds.
+// Failures print the seed of their history. The operations are then the same,
+// but not the way the threads interleave.
+
+extern crate lockfree;
+
+mod support;
+
+use lockfree::map::{Insertion, Map, Removed};
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Barrier},
+    thread,
+};
+use support::{linearizable, show, Clock, Event, Log, Rng};
+
+const THREADS: usize = 4;
+const OPS: usize = 300;
+const KEYS: u8 = 4;
+
+// The operations on a single key. Values are unique across a history, so the
+// checker can tell which insertion a result comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Insert(u64),
+    Remove,
+    Get,
+    Reinsert(u64),
+}
+
+// The value of a key before the operation, or the one found by `Get`.
+type Res = Option<u64>;
+
+// The operation of `HashMap` matching the given one on a key whose value is
+// `state`.
+fn step(state: &Option<u64>, op: &Op) -> (Option<u64>, Res) {
+    match *op {
+        Op::Insert(val) | Op::Reinsert(val) => (Some(val), *state),
+        Op::Remove => (None, *state),
+        Op::Get => (*state, *state),
+    }
+}
+
+// Runs a single history from the given seed, returning the events of each key.
+fn run(seed: u64) -> HashMap<u8, Vec<Event<Op, Res>>> {
+    let map = Arc::new(Map::new());
+    let clock = Arc::new(Clock::new());
+    let barrier = Arc::new(Barrier::new(THREADS));
+
+    let threads = (0 .. THREADS)
+        .map(|thread| {
+            let map = map.clone();
+            let clock = clock.clone();
+            let barrier = barrier.clone();
+            let mut rng = Rng::new(seed ^ ((thread as u64 + 1) << 56));
+            thread::spawn(move || {
+                let mut log = Log::new(&clock);
+                let mut keys = Vec::with_capacity(OPS);
+                // Entries removed by this thread, reinserted later.
+                let mut stash = Vec::<Removed<u8, u64>>::new();
+                barrier.wait();
+
+                for i in 0 .. OPS {
+                    let fresh = ((thread as u64) << 32) | i as u64;
+                    let mut key = rng.below(KEYS as usize) as u8;
+                    let choice = rng.below(8);
+                    if choice == 0 && !stash.is_empty() {
+                        let removed = stash.swap_remove(rng.below(stash.len()));
+                        key = *removed.key();
+                        let op = Op::Reinsert(*removed.val());
+                        log.record(op, |_| match map.reinsert(removed) {
+                            Insertion::Created => None,
+                            Insertion::Updated(old) => Some(*old.val()),
+                            Insertion::Failed(_) => panic!("reinsert failed"),
+                        });
+                    } else if choice < 4 {
+                        log.record(Op::Insert(fresh), |_| {
+                            map.insert(key, fresh).map(|old| *old.val())
+                        });
+                    } else if choice < 6 {
+                        log.record(Op::Remove, |_| {
+                            map.remove(&key).map(|removed| {
+                                let val = *removed.val();
+                                stash.push(removed);
+                                val
+                            })
+                        });
+                    } else {
+                        log.record(Op::Get, |_| {
+                            map.get(&key).map(|guard| *guard.val())
+                        });
+                    }
+                    keys.push(key);
+                }
+
+                keys.into_iter().zip(log.into_events()).collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut histories = HashMap::<_, Vec<_>>::new();
+    for thread in threads {
+        for (key, event) in thread.join().expect("thread failed") {
+            histories.entry(key).or_default().push(event);
+        }
+    }
+    histories
+}
+
+fn check(seed: u64) {
+    for (key, events) in run(seed) {
+        if let Err(events) = linearizable(None, events, step) {
+            panic!(
+                "history of key {} is not linearizable, run the same \
+                 operations with LOCKFREE_SEED={} LOCKFREE_ROUNDS=1:\n{}",
+                key,
+                seed,
+                show(&events)
+            );
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn random_histories() {
+    let rounds = env::var("LOCKFREE_ROUNDS")
+        .map_or(1000, |var| var.parse().expect("invalid LOCKFREE_ROUNDS"));
+    let seed = support::seed();
+    for round in 0 .. rounds {
+        check(seed.wrapping_add(round));
+    }
+}
+
+fn event(op: Op, res: Res, call: usize, ret: usize) -> Event<Op, Res> {
+    Event { op, res, call, ret }
+}
+
+#[test]
+fn accepts_overlapping_history() {
+    // The removal must be linearized between both insertions, which is only
+    // possible because it overlaps both.
+    let events = vec![
+        event(Op::Insert(1), None, 0, 3),
+        event(Op::Remove, Some(1), 1, 6),
+        event(Op::Insert(2), None, 4, 5),
+        event(Op::Get, Some(2), 7, 8),
+    ];
+    assert!(linearizable(None, events, step).is_ok());
+}
+
+#[test]
+fn rejects_lost_insertion() {
+    // The insertion returned before the lookup was called, so the lookup
+    // cannot miss it.
+    let events = vec![
+        event(Op::Insert(1), None, 0, 1),
+        event(Op::Get, None, 2, 3),
+        event(Op::Remove, Some(1), 4, 5),
+    ];
+    assert!(linearizable(None, events, step).is_err());
+}
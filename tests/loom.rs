@@ -0,0 +1,148 @@
+// Model-checks the bucket algorithm of the map with `loom`, exploring every
+// interleaving of the compare-and-swaps on the nodes of the tables and on the
+// lists of the buckets. Run with:
+//
+//     RUSTFLAGS="--cfg loom" cargo test --test loom --release \
+//         --no-default-features
+//
+// The threads of a model all run on the same OS thread, so the thread-local
+// garbage lists of the `std` incinerator would be shared between them; the
+// shared garbage list used without `std` is modeled instead.
+//
+// Every key hashes to the same code, so the keys of a test always share a
+// single bucket, and the tables have two nodes, so the models stay small.
+#![cfg(loom)]
+
+#[cfg(feature = "std")]
+compile_error!("the loom tests must run with `--no-default-features`");
+
+extern crate lockfree;
+extern crate loom;
+
+use lockfree::map::Map;
+use loom::{sync::Arc, thread};
+use std::hash::{BuildHasher, Hasher};
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ConstState;
+
+impl BuildHasher for ConstState {
+    type Hasher = ConstHasher;
+
+    fn build_hasher(&self) -> ConstHasher {
+        ConstHasher
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ConstHasher;
+
+impl Hasher for ConstHasher {
+    fn write(&mut self, _bytes: &[u8]) {}
+
+    fn finish(&self) -> u64 {
+        0
+    }
+}
+
+type TestMap = Map<u8, u8, ConstState, 1>;
+
+// The stack of the threads of a model, since the default one of `loom` is too
+// small for the operations of the map.
+const STACK_SIZE: usize = 1 << 20;
+
+// Runs the given test for every interleaving of its threads with at most three
+// preemptions, unless `LOOM_MAX_PREEMPTIONS` says otherwise. The test itself
+// runs in a spawned thread as well, so it gets a bigger stack.
+fn model(test: fn()) {
+    let mut builder = loom::model::Builder::new();
+    if builder.preemption_bound.is_none() {
+        builder.preemption_bound = Some(3);
+    }
+    builder.max_branches = builder.max_branches.max(10_000);
+    builder.check(move || spawn(test).join().unwrap());
+}
+
+fn spawn<F, T>(thread: F) -> thread::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    thread::Builder::new().stack_size(STACK_SIZE).spawn(thread).unwrap()
+}
+
+fn new_map() -> Arc<TestMap> {
+    Arc::new(Map::with_fanout(ConstState))
+}
+
+#[test]
+fn insert_same_key() {
+    model(|| {
+        let map = new_map();
+        let other = map.clone();
+        let thread = spawn(move || other.insert(0, 1).map(|x| *x.val()));
+        let here = map.insert(0, 2).map(|x| *x.val());
+        let there = thread.join().unwrap();
+
+        // Exactly one insertion saw the value of the other one.
+        let val = *map.get(&0).unwrap().val();
+        match (here, there) {
+            (None, Some(2)) => assert_eq!(val, 1),
+            (Some(1), None) => assert_eq!(val, 2),
+            res => panic!("unexpected insertions {:?}", res),
+        }
+    });
+}
+
+#[test]
+fn insert_races_remove() {
+    model(|| {
+        let map = new_map();
+        map.insert(0, 0);
+        let other = map.clone();
+        let thread = spawn(move || other.remove(&1).map(|x| *x.val()));
+        assert!(map.insert(1, 1).is_none());
+        let removed = thread.join().unwrap();
+
+        assert_eq!(*map.get(&0).unwrap().val(), 0);
+        match removed {
+            Some(1) => assert!(map.get(&1).is_none()),
+            None => assert_eq!(*map.get(&1).unwrap().val(), 1),
+            res => panic!("unexpected removal {:?}", res),
+        }
+    });
+}
+
+#[test]
+fn remove_races_remove() {
+    model(|| {
+        let map = new_map();
+        map.insert(0, 0);
+        map.insert(1, 1);
+        let other = map.clone();
+        let thread = spawn(move || other.remove(&0).map(|x| *x.val()));
+        assert_eq!(map.remove(&1).map(|x| *x.val()), Some(1));
+        assert_eq!(thread.join().unwrap(), Some(0));
+
+        assert!(map.get(&0).is_none());
+        assert!(map.get(&1).is_none());
+        assert_eq!(map.iter().count(), 0);
+    });
+}
+
+#[test]
+fn insert_races_collapse() {
+    model(|| {
+        let map = new_map();
+        map.insert(0, 0);
+        let other = map.clone();
+        // Removing the last key of the bucket removes the bucket as well,
+        // which must not lose the key inserted meanwhile.
+        let thread = spawn(move || other.remove(&0).map(|x| *x.val()));
+        assert!(map.insert(1, 1).is_none());
+        assert_eq!(thread.join().unwrap(), Some(0));
+
+        assert!(map.get(&0).is_none());
+        assert_eq!(*map.get(&1).unwrap().val(), 1);
+    });
+}
@@ -0,0 +1,10 @@
+extern crate trybuild;
+
+// Pins down which combinations of `Send` and `Sync` keys and values make the
+// map types `Send` and `Sync`.
+#[test]
+fn send_sync_bounds() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/send_sync/pass/*.rs");
+    cases.compile_fail("tests/send_sync/fail/*.rs");
+}
@@ -0,0 +1,10 @@
+extern crate lockfree;
+
+use lockfree::map::Map;
+use std::rc::Rc;
+
+fn assert_send<T: Send>() {}
+
+fn main() {
+    assert_send::<Map<u32, Rc<u32>>>();
+}
@@ -0,0 +1,10 @@
+extern crate lockfree;
+
+use lockfree::map::Map;
+use std::sync::MutexGuard;
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    assert_sync::<Map<u32, MutexGuard<'static, u32>>>();
+}
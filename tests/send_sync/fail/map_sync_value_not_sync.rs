@@ -0,0 +1,10 @@
+extern crate lockfree;
+
+use lockfree::map::Map;
+use std::cell::Cell;
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    assert_sync::<Map<u32, Cell<u32>>>();
+}
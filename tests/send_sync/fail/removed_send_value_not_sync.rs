@@ -0,0 +1,10 @@
+extern crate lockfree;
+
+use lockfree::map::Removed;
+use std::cell::Cell;
+
+fn assert_send<T: Send>() {}
+
+fn main() {
+    assert_send::<Removed<u32, Cell<u32>>>();
+}
@@ -0,0 +1,33 @@
+extern crate lockfree;
+
+use lockfree::map::{Map, ReadGuard, Removed};
+use std::{cell::Cell, sync::MutexGuard, thread};
+
+fn assert_send<T: Send>() {}
+
+fn assert_sync<T: Sync>() {}
+
+fn main() {
+    // Values which are `Send` but not `Sync`.
+    assert_send::<Map<u32, Cell<u32>>>();
+
+    // Values which are `Sync` but not `Send`.
+    assert_sync::<Removed<u32, MutexGuard<'static, u32>>>();
+    assert_sync::<ReadGuard<'static, u32, MutexGuard<'static, u32>>>();
+    assert_send::<ReadGuard<'static, u32, MutexGuard<'static, u32>>>();
+
+    // Values which are both.
+    assert_send::<Map<u32, Vec<u32>>>();
+    assert_sync::<Map<u32, Vec<u32>>>();
+    assert_send::<Removed<u32, Vec<u32>>>();
+    assert_sync::<Removed<u32, Vec<u32>>>();
+
+    let map = Map::new();
+    map.insert(1, Cell::new(2));
+    let handle = thread::spawn(move || {
+        map.get(&1).unwrap().val().set(3);
+        map
+    });
+    let map = handle.join().unwrap();
+    assert_eq!(map.get(&1).unwrap().val().get(), 3);
+}
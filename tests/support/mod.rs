@@ -0,0 +1,208 @@
+// Infrastructure for randomized tests: a seeded random generator, a log of the
+// operations each thread made, and a linearizability checker for such logs.
+
+use std::{
+    collections::HashSet,
+    env,
+    fmt::Debug,
+    hash::Hash,
+    sync::atomic::{AtomicUsize, Ordering::*},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// The seed of a test, taken from `LOCKFREE_SEED` if set, so a failure can be
+// reproduced.
+pub fn seed() -> u64 {
+    match env::var("LOCKFREE_SEED") {
+        Ok(var) => var.parse().expect("invalid LOCKFREE_SEED"),
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64),
+    }
+}
+
+// A small and fast random generator (SplitMix64). Not for cryptography, but
+// fully determined by its seed.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut mixed = self.state;
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        mixed ^ (mixed >> 31)
+    }
+
+    // A number in `0 .. bound`.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+// A logical clock shared by the threads of a history. Stamps are unique, and
+// if an operation returned before another one was called, its return stamp is
+// smaller than the call stamp of the other.
+#[derive(Debug, Default)]
+pub struct Clock {
+    now: AtomicUsize,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tick(&self) -> usize {
+        self.now.fetch_add(1, SeqCst)
+    }
+}
+
+// A complete operation of a history: what was done, what was observed, and
+// when it was called and when it returned.
+#[derive(Debug, Clone)]
+pub struct Event<O, R> {
+    pub op: O,
+    pub res: R,
+    pub call: usize,
+    pub ret: usize,
+}
+
+// The operations made by a single thread, in order.
+#[derive(Debug)]
+pub struct Log<'clock, O, R> {
+    clock: &'clock Clock,
+    events: Vec<Event<O, R>>,
+}
+
+impl<'clock, O, R> Log<'clock, O, R> {
+    pub fn new(clock: &'clock Clock) -> Self {
+        Self { clock, events: Vec::new() }
+    }
+
+    // Runs the operation, recording it along with its result.
+    pub fn record<F>(&mut self, op: O, run: F)
+    where
+        F: FnOnce(&O) -> R,
+    {
+        let call = self.clock.tick();
+        let res = run(&op);
+        let ret = self.clock.tick();
+        self.events.push(Event { op, res, call, ret });
+    }
+
+    pub fn into_events(self) -> Vec<Event<O, R>> {
+        self.events
+    }
+}
+
+// Checks whether the given history is linearizable, i.e. whether there is a
+// sequential order of its events which respects their real time order and in
+// which each result is the one `step` gives for the state left by the events
+// before it. `step` returns the next state and the expected result. On
+// failure, the events are returned sorted by call.
+//
+// This is the search of Wing and Gong, remembering which sets of linearized
+// events and states were already explored. It is exponential in the worst
+// case, so histories should be split into independent ones, e.g. per key.
+pub fn linearizable<S, O, R, F>(
+    init: S,
+    mut events: Vec<Event<O, R>>,
+    step: F,
+) -> Result<(), Vec<Event<O, R>>>
+where
+    S: Clone + Eq + Hash,
+    R: PartialEq,
+    F: Fn(&S, &O) -> (S, R),
+{
+    events.sort_by_key(|event| event.call);
+    let mut search = Search {
+        events: &events,
+        step,
+        done: vec![false; events.len()],
+        seen: HashSet::new(),
+    };
+    if search.explore(init, 0) {
+        Ok(())
+    } else {
+        Err(events)
+    }
+}
+
+struct Search<'events, S, O, R, F> {
+    events: &'events [Event<O, R>],
+    step: F,
+    // Which events are already linearized.
+    done: Vec<bool>,
+    seen: HashSet<(Vec<bool>, S)>,
+}
+
+impl<'events, S, O, R, F> Search<'events, S, O, R, F>
+where
+    S: Clone + Eq + Hash,
+    R: PartialEq,
+    F: Fn(&S, &O) -> (S, R),
+{
+    fn explore(&mut self, state: S, count: usize) -> bool {
+        if count == self.events.len() {
+            return true;
+        }
+        if !self.seen.insert((self.done.clone(), state.clone())) {
+            return false;
+        }
+
+        // An event can only come next if no pending event returned before it
+        // was called.
+        let first_ret = self
+            .pending()
+            .map(|index| self.events[index].ret)
+            .min()
+            .unwrap();
+        let candidates = self
+            .pending()
+            .take_while(|&index| self.events[index].call < first_ret)
+            .collect::<Vec<_>>();
+
+        for index in candidates {
+            let event = &self.events[index];
+            let (next, res) = (self.step)(&state, &event.op);
+            if res == event.res {
+                self.done[index] = true;
+                if self.explore(next, count + 1) {
+                    return true;
+                }
+                self.done[index] = false;
+            }
+        }
+
+        false
+    }
+
+    fn pending<'this>(&'this self) -> impl Iterator<Item = usize> + 'this {
+        (0 .. self.events.len()).filter(move |&index| !self.done[index])
+    }
+}
+
+// Formats a history, one event per line.
+pub fn show<O, R>(events: &[Event<O, R>]) -> String
+where
+    O: Debug,
+    R: Debug,
+{
+    events
+        .iter()
+        .map(|event| {
+            format!(
+                "[{} .. {}] {:?} -> {:?}\n",
+                event.call, event.ret, event.op, event.res
+            )
+        })
+        .collect()
+}